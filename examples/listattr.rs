@@ -2,7 +2,6 @@ extern crate haiku;
 extern crate libc;
 
 use std::env::args;
-use std::fmt::Write;
 use std::path::Path;
 
 use libc::{
@@ -13,8 +12,8 @@ use libc::{
 
 use haiku::storage::{AttributeDescriptor, AttributeExt};
 
-fn get_type(type_code: u32) -> String {
-	match type_code {
+fn get_type(attribute: &AttributeDescriptor) -> String {
+	match attribute.raw_attribute_type {
 		B_MIME_STRING_TYPE => "MIME String".to_string(),
 		B_STRING_TYPE => "Text".to_string(),
 		B_BOOL_TYPE => "Boolean".to_string(),
@@ -28,109 +27,24 @@ fn get_type(type_code: u32) -> String {
 		B_UINT16_TYPE => "Uint-16".to_string(),
 		B_UINT32_TYPE => "Uint-32".to_string(),
 		B_UINT64_TYPE => "Uint-64".to_string(),
-		_ => "Other".to_string(), // TODO: convert into character string
+		_ => format!(
+			"'{}'",
+			attribute
+				.raw_attribute_type_chars()
+				.iter()
+				.collect::<String>()
+		),
 	}
 }
 
 fn print_attribute_contents(path: &Path, attribute: &AttributeDescriptor) -> String {
-	let mut output = String::new();
-	match attribute.raw_attribute_type {
-		B_INT8_TYPE => {
-			let value = path.read_attribute::<i8>(attribute).unwrap();
-			write!(&mut output, "{}", value).unwrap();
-		}
-		B_UINT8_TYPE => {
-			let value = path.read_attribute::<u8>(attribute).unwrap();
-			write!(&mut output, "{}", value).unwrap();
-		}
-		B_INT16_TYPE => {
-			let value = path.read_attribute::<i16>(attribute).unwrap();
-			write!(&mut output, "{}", value).unwrap();
-		}
-		B_UINT16_TYPE => {
-			let value = path.read_attribute::<u16>(attribute).unwrap();
-			write!(&mut output, "{}", value).unwrap();
-		}
-		B_INT32_TYPE => {
-			let value = path.read_attribute::<i32>(attribute).unwrap();
-			write!(&mut output, "{}", value).unwrap();
-		}
-		B_UINT32_TYPE => {
-			let value = path.read_attribute::<u32>(attribute).unwrap();
-			write!(&mut output, "{}", value).unwrap();
-		}
-		B_INT64_TYPE => {
-			let value = path.read_attribute::<i64>(attribute).unwrap();
-			write!(&mut output, "{}", value).unwrap();
-		}
-		B_UINT64_TYPE => {
-			let value = path.read_attribute::<u64>(attribute).unwrap();
-			write!(&mut output, "{}", value).unwrap();
-		}
-		B_FLOAT_TYPE => {
-			let value = path.read_attribute::<f32>(attribute).unwrap();
-			write!(&mut output, "{}", value).unwrap();
-		}
-		B_DOUBLE_TYPE => {
-			let value = path.read_attribute::<f64>(attribute).unwrap();
-			write!(&mut output, "{}", value).unwrap();
-		}
-		B_BOOL_TYPE => {
-			let value = path.read_attribute::<bool>(attribute).unwrap();
-			write!(&mut output, "{}", value).unwrap();
-		}
-		B_STRING_TYPE => {
-			let value = path.read_attribute::<String>(attribute).unwrap();
-			write!(&mut output, "{}", value).unwrap();
-		}
-		_ => write!(&mut output, "{}", dump_raw_data(&path, &attribute)).unwrap(),
-	}
-	output
-}
-
-fn dump_raw_data(path: &Path, attribute: &AttributeDescriptor) -> String {
-	const CHUNK_SIZE: u32 = 16;
-	const PRINT_LIMIT: i64 = 256;
-	let mut dump_position: usize = 0;
-	let mut output = String::from("\n");
-
-	let buffer = path
-		.read_attribute_raw(
-			&attribute.name,
-			attribute.raw_attribute_type,
-			0,
-			PRINT_LIMIT,
-		)
-		.unwrap();
-
-	while dump_position < buffer.len() {
-		write!(&mut output, "\t{:>4}: ", dump_position).unwrap();
-		for i in 0..CHUNK_SIZE as usize {
-			if dump_position + i < buffer.len() {
-				write!(&mut output, "{:2x} ", buffer[dump_position + i]).unwrap();
-			} else {
-				write!(&mut output, "   ").unwrap();
-			}
-		}
-
-		// Print the byte in the form of a printable character
-		output.push(' ');
-		for _ in 0..CHUNK_SIZE {
-			if dump_position < buffer.len() {
-				let c = buffer[dump_position] as char;
-				if c.is_ascii_graphic() {
-					write!(&mut output, "{}", c).unwrap();
-				} else {
-					output.push('.');
-				}
-			} else {
-				output.push(' ');
-			}
-			dump_position += 1;
-		}
-		output.push('\n');
+	let output = path.read_attribute_display(attribute).unwrap();
+	// Multi-line output (like a hex dump) starts on a new line
+	if output.contains('\n') {
+		format!("\n{}", output)
+	} else {
+		output
 	}
-	output
 }
 
 fn main() {
@@ -179,7 +93,7 @@ fn main() {
 				};
 				println!(
 					"{0: >1$} {2: >3$}  {4: <5$} {6}",
-					get_type(attribute.raw_attribute_type),
+					get_type(&attribute),
 					TYPE_WIDTH,
 					attribute.size,
 					SIZE_WIDTH,
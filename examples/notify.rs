@@ -6,6 +6,8 @@
 extern crate getopts;
 extern crate haiku;
 
+use std::process;
+
 use getopts::Options;
 use haiku::app::{
 	Application, ApplicationDelegate, ApplicationHooks, Notification, NotificationType,
@@ -48,7 +50,13 @@ impl ApplicationHooks for NotifyApp {
 		}
 
 		// Start building the notification based on the options
-		let mut notification = Notification::default();
+		let mut notification = match Notification::try_default() {
+			Ok(notification) => notification,
+			Err(e) => {
+				println!("cannot create a notification: {}", e);
+				return;
+			}
+		};
 
 		// Make sure there is a message
 		if matches.free.len() != 1 {
@@ -155,6 +163,12 @@ fn main() {
 		options: build_options(),
 		notification: None,
 	};
-	let app = Application::new(SIGNATURE, state);
+	let app = match Application::new(SIGNATURE, state) {
+		Ok(app) => app,
+		Err(e) => {
+			eprintln!("Cannot start the application: {}", e);
+			process::exit(1);
+		}
+	};
 	app.run().unwrap();
 }
@@ -39,13 +39,21 @@ fn main() {
 		.expect("Unexpected error getting the list of teams");
 	for team in team_list {
 		let app_info = match ROSTER.get_running_app_info(&team) {
-			Some(info) => info,
+			Ok(info) => info,
+			Err(_) => continue,
+		};
+		let app_team = match app_info.team() {
+			Some(team) => team,
 			None => continue,
 		};
 		let path = truncate_string(&app_info.path, NAME_FIELD_WIDTH);
 		println!(
 			"{:>6} {:>34}{:>5} {:>5} ({})",
-			app_info.team, path, app_info.port, app_info.flags, app_info.signature
+			app_team.get_team_id(),
+			path,
+			app_info.port,
+			app_info.flags,
+			app_info.signature
 		);
 	}
 }
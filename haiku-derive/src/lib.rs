@@ -0,0 +1,308 @@
+//
+// Copyright 2024, Niels Sascha Reedijk <niels.reedijk@gmail.com>
+// All rights reserved. Distributed under the terms of the MIT License.
+//
+
+//! Derive macros for the haiku crate
+//!
+//! This crate is re-exported by the haiku crate, use the macros from there.
+
+use proc_macro::{Delimiter, TokenStream, TokenTree};
+
+// The type code of derived types that do not set one
+const DEFAULT_TYPE_CODE: &str = "RAWT";
+
+/// Derive `haiku::support::Flattenable` for a struct
+///
+/// The struct is flattened by concatenating the flattened fields, in the
+/// order in which they are declared. All the fields must implement
+/// `Flattenable` themselves.
+///
+/// Unflattening needs to know where each field ends. Therefore all fields,
+/// except the last one, must have a fixed flattened size, which their
+/// `Flattenable::FLATTENED_SIZE` states. This is the case for the numeric
+/// types, for the geometry types of the interface kit, and for derived
+/// structs that only have such fields. A variable size field that is not
+/// the last one fails the build. The last field may have a variable size,
+/// like a `String`.
+///
+/// The type code is set with the `flattenable` attribute. Without it, the
+/// type code is `B_RAW_TYPE`.
+///
+/// ```ignore
+/// use haiku::support::Flattenable;
+///
+/// #[derive(Flattenable)]
+/// #[flattenable(type_code = "SCOR")]
+/// #[repr(C)]
+/// struct Score {
+///     points: u32,
+///     player: String,
+/// }
+/// ```
+#[proc_macro_derive(Flattenable, attributes(flattenable))]
+pub fn derive_flattenable(input: TokenStream) -> TokenStream {
+	match parse_struct(input) {
+		Ok(definition) => generate_flattenable(&definition).parse().unwrap(),
+		Err(message) => format!("compile_error!({:?});", message).parse().unwrap(),
+	}
+}
+
+struct Struct {
+	name: String,
+	type_code: u32,
+	// The name and the type of each field
+	fields: Vec<(String, String)>,
+}
+
+fn parse_struct(input: TokenStream) -> Result<Struct, String> {
+	let mut type_code = None;
+	let mut tokens = input.into_iter().peekable();
+
+	// The attributes and the visibility precede the struct keyword
+	loop {
+		match tokens.next() {
+			Some(TokenTree::Punct(ref p)) if p.as_char() == '#' => {
+				if let Some(TokenTree::Group(attribute)) = tokens.next() {
+					if let Some(code) = parse_type_code(attribute.stream())? {
+						type_code = Some(code);
+					}
+				}
+			}
+			Some(TokenTree::Ident(ref i)) if i.to_string() == "struct" => break,
+			Some(TokenTree::Ident(ref i)) if i.to_string() == "pub" => {}
+			Some(TokenTree::Group(ref g)) if g.delimiter() == Delimiter::Parenthesis => {}
+			_ => return Err(String::from("Flattenable can only be derived for structs")),
+		}
+	}
+
+	let name = match tokens.next() {
+		Some(TokenTree::Ident(name)) => name.to_string(),
+		_ => return Err(String::from("expected the name of the struct")),
+	};
+
+	let body = match tokens.next() {
+		Some(TokenTree::Group(ref g)) if g.delimiter() == Delimiter::Brace => g.stream(),
+		Some(TokenTree::Punct(ref p)) if p.as_char() == '<' => {
+			return Err(String::from(
+				"Flattenable cannot be derived for generic structs",
+			))
+		}
+		_ => {
+			return Err(String::from(
+				"Flattenable can only be derived for structs with named fields",
+			))
+		}
+	};
+
+	Ok(Struct {
+		name,
+		type_code: match type_code {
+			Some(code) => code,
+			None => four_character_code(DEFAULT_TYPE_CODE)?,
+		},
+		fields: parse_fields(body)?,
+	})
+}
+
+// Parse the contents of an attribute, and return the type code if it is a
+// `flattenable(type_code = "...")` attribute
+fn parse_type_code(attribute: TokenStream) -> Result<Option<u32>, String> {
+	let mut tokens = attribute.into_iter();
+	match tokens.next() {
+		Some(TokenTree::Ident(ref i)) if i.to_string() == "flattenable" => {}
+		_ => return Ok(None),
+	}
+	let arguments: Vec<TokenTree> = match tokens.next() {
+		Some(TokenTree::Group(ref g)) if g.delimiter() == Delimiter::Parenthesis => {
+			g.stream().into_iter().collect()
+		}
+		_ => return Err(String::from("expected #[flattenable(type_code = \"...\")]")),
+	};
+	match arguments.as_slice() {
+		[TokenTree::Ident(key), TokenTree::Punct(equals), TokenTree::Literal(value)]
+			if key.to_string() == "type_code" && equals.as_char() == '=' =>
+		{
+			let value = value.to_string();
+			match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+				Some(code) => Ok(Some(four_character_code(code)?)),
+				None => Err(String::from("the type code must be a string")),
+			}
+		}
+		_ => Err(String::from("expected #[flattenable(type_code = \"...\")]")),
+	}
+}
+
+// Convert a code like "RAWT" in the same way as the haiku_constant! macro
+fn four_character_code(code: &str) -> Result<u32, String> {
+	if code.len() != 4 || !code.is_ascii() {
+		return Err(format!(
+			"the type code must be four ASCII characters, not {:?}",
+			code
+		));
+	}
+	Ok(code.bytes().fold(0, |acc, b| (acc << 8) | b as u32))
+}
+
+fn parse_fields(body: TokenStream) -> Result<Vec<(String, String)>, String> {
+	let mut fields = Vec::new();
+	let mut tokens = body.into_iter().peekable();
+	while tokens.peek().is_some() {
+		// Skip the attributes and the visibility of the field
+		let name = loop {
+			match tokens.next() {
+				Some(TokenTree::Punct(ref p)) if p.as_char() == '#' => {
+					tokens.next();
+				}
+				Some(TokenTree::Ident(ref i)) if i.to_string() == "pub" => {
+					if let Some(TokenTree::Group(_)) = tokens.peek() {
+						tokens.next();
+					}
+				}
+				Some(TokenTree::Ident(name)) => break name.to_string(),
+				_ => return Err(String::from("expected the name of a field")),
+			}
+		};
+		match tokens.next() {
+			Some(TokenTree::Punct(ref p)) if p.as_char() == ':' => {}
+			_ => return Err(format!("expected the type of field {}", name)),
+		}
+
+		// The type ends at the first comma that is not between angle
+		// brackets
+		let mut field_type = String::new();
+		let mut depth = 0;
+		for token in tokens.by_ref() {
+			if let TokenTree::Punct(ref p) = token {
+				match p.as_char() {
+					'<' => depth += 1,
+					'>' => depth -= 1,
+					',' if depth == 0 => break,
+					_ => {}
+				}
+			}
+			field_type.push_str(&token.to_string());
+			field_type.push(' ');
+		}
+		fields.push((name, field_type));
+	}
+	Ok(fields)
+}
+
+fn generate_flattenable(definition: &Struct) -> String {
+	let name = &definition.name;
+	let trait_for = |field_type: &str| {
+		format!(
+			"<{} as ::haiku::support::Flattenable<{}>>",
+			field_type, field_type
+		)
+	};
+
+	let mut is_fixed_size = String::from("true");
+	let mut fixed_flattened_size = String::from("Some(0)");
+	let mut flattened_size = String::from("0");
+	let mut flatten = String::new();
+	let mut unflatten = String::new();
+	for (index, (field, field_type)) in definition.fields.iter().enumerate() {
+		let flattenable = trait_for(field_type);
+		is_fixed_size.push_str(&format!(" && {}::is_fixed_size()", flattenable));
+		fixed_flattened_size = format!(
+			"match ({}, {}::FLATTENED_SIZE) {{
+				(Some(size), Some(field_size)) => Some(size + field_size),
+				_ => None,
+			}}",
+			fixed_flattened_size, flattenable
+		);
+		flattened_size.push_str(&format!(
+			" + {}::flattened_size(&self.{})",
+			flattenable, field
+		));
+		flatten.push_str(&format!(
+			"data.extend({}::flatten(&self.{}));\n",
+			flattenable, field
+		));
+		if index + 1 == definition.fields.len() {
+			// The last field gets the rest of the buffer
+			unflatten.push_str(&format!(
+				"let {} = {}::unflatten(&buffer[offset..])?;\n",
+				field, flattenable
+			));
+		} else {
+			// The size of the other fields is known at compile time, and a
+			// field with a variable size fails the build
+			unflatten.push_str(&format!(
+				"let {field} = {{
+					const SIZE: usize = match {flattenable}::FLATTENED_SIZE {{
+						Some(size) => size,
+						None => panic!(\"only the last field of {name} can have a variable size\"),
+					}};
+					let data = buffer.get(offset..offset + SIZE).ok_or_else(|| {{
+						::haiku::support::HaikuError::new(
+							::haiku::support::ErrorKind::InvalidData,
+							\"the buffer does not contain a flattened {name}\",
+						)
+					}})?;
+					offset += SIZE;
+					{flattenable}::unflatten(data)?
+				}};\n"
+			));
+		}
+	}
+	if definition.fields.is_empty() {
+		unflatten.push_str(&format!(
+			"if !buffer.is_empty() {{
+				return Err(::haiku::support::HaikuError::new(
+					::haiku::support::ErrorKind::InvalidData,
+					\"the buffer does not contain a flattened {}\",
+				));
+			}}\n",
+			name
+		));
+	}
+	let field_names: Vec<&str> = definition
+		.fields
+		.iter()
+		.map(|(field, _)| field.as_str())
+		.collect();
+
+	format!(
+		"impl ::haiku::support::Flattenable<{name}> for {name} {{
+			const FLATTENED_SIZE: ::std::option::Option<usize> = {fixed_flattened_size};
+
+			fn type_code() -> u32 {{
+				{type_code}
+			}}
+
+			fn is_fixed_size() -> bool {{
+				{is_fixed_size}
+			}}
+
+			fn flattened_size(&self) -> usize {{
+				{flattened_size}
+			}}
+
+			fn flatten(&self) -> ::std::vec::Vec<u8> {{
+				let mut data: ::std::vec::Vec<u8> = ::std::vec::Vec::with_capacity(
+					<{name} as ::haiku::support::Flattenable<{name}>>::flattened_size(self),
+				);
+				{flatten}
+				data
+			}}
+
+			#[allow(unused_mut, unused_variables)]
+			fn unflatten(buffer: &[u8]) -> ::haiku::support::Result<{name}> {{
+				let mut offset: usize = 0;
+				{unflatten}
+				Ok({name} {{ {fields} }})
+			}}
+		}}",
+		name = name,
+		type_code = definition.type_code,
+		is_fixed_size = is_fixed_size,
+		fixed_flattened_size = fixed_flattened_size,
+		flattened_size = flattened_size,
+		flatten = flatten,
+		unflatten = unflatten,
+		fields = field_names.join(", "),
+	)
+}
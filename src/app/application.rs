@@ -3,27 +3,35 @@
 // All rights reserved. Distributed under the terms of the MIT License.
 //
 
-use std::collections::{HashMap, VecDeque};
-use std::env::args;
+use std::env::{args, current_dir};
 use std::mem;
-use std::sync::{atomic, Arc, Mutex};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use libc::{find_thread, get_thread_info, port_id, team_id, thread_id, thread_info};
+use libc::{
+	find_thread, get_thread_info, port_id, team_id, thread_id, thread_info, B_ALREADY_RUNNING,
+};
 
-use crate::app::looper::{HandlerType, Looper, LooperDelegate, NEXT_HANDLER_TOKEN};
-use crate::app::roster::{ApplicationRegistrationStatus, ROSTER};
-use crate::app::serverlink::{server_protocol, ServerLink};
+use crate::app::looper::{Looper, LooperDelegate, LooperRegistry};
+use crate::app::roster::{Registration, B_MULTIPLE_LAUNCH, ROSTER};
+use crate::app::serverlink::{server_protocol, DesktopConnectionOptions, ServerLink};
 use crate::app::sys::{
-	get_app_path, B_ARGV_RECEIVED, B_PREFERRED_TOKEN, B_QUIT_REQUESTED, B_READY_TO_RUN, QUIT,
+	get_app_path, B_ARGV_RECEIVED, B_PREFERRED_TOKEN, B_QUIT_REQUESTED, B_READY_TO_RUN,
+	B_REFS_RECEIVED, B_SILENT_RELAUNCH, QUIT,
 };
 use crate::app::{Handler, Message, Messenger};
+use crate::haiku_constant;
 use crate::kernel::ports::Port;
+use crate::kernel::teams::Team;
 use crate::kernel::INFINITE_TIMEOUT;
 use crate::storage::sys::entry_ref;
+use crate::storage::AttributeExt;
 use crate::storage::MimeType;
-use crate::support::Result;
+use crate::support::{ErrorKind, HaikuError, Result};
 
 const LOOPER_PORT_DEFAULT_CAPACITY: i32 = 200;
+const LOOPER_QUIT_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// Main entrypoint into a Haiku Application
 ///
@@ -36,6 +44,11 @@ const LOOPER_PORT_DEFAULT_CAPACITY: i32 = 200;
 /// then set up additional loopers using the `create_looper()` function, and
 /// when you are ready, you call the `run()` function to start the message
 /// loop. Control is returned to you when the message loop is quit.
+///
+/// When the application is dropped, whether or not `run()` was called, the
+/// loopers are stopped, the application is unregistered from the registrar
+/// and the app_server, and the port of the application is closed. Any
+/// messages that were not yet processed are discarded.
 pub struct Application<A>
 where
 	A: ApplicationHooks + Send + 'static,
@@ -43,6 +56,7 @@ where
 	state: Arc<Mutex<A>>,
 	inner_looper: Looper<A>,
 	link: ServerLink,
+	loopers: Arc<LooperRegistry>,
 }
 
 impl<A> Application<A>
@@ -61,83 +75,106 @@ where
 	/// sent to the application. Secondly, the state is shared among all the
 	/// loopers (and handlers) through the `Context<A>` instances that are
 	/// passed as arguments to the message processors.
-	pub fn new(signature: &str, initial_state: A) -> Self {
+	///
+	/// If the application is a single or exclusive launch application (as
+	/// set in its `BEOS:APP_FLAGS` attribute), and another instance is already
+	/// running, then the arguments of this instance are sent to the running
+	/// instance, where they are delivered to its `argv_received()` hook. If
+	/// there are no arguments, the running instance gets a
+	/// `B_SILENT_RELAUNCH` message instead. Nothing is sent if the running
+	/// instance has the `B_ARGV_ONLY` flag. This instance is not registered,
+	/// and an error with the raw OS error `B_ALREADY_RUNNING` is returned.
+	/// Usually, the caller then exits. If the message cannot be sent, that
+	/// error is returned instead.
+	///
+	/// This returns an error with `ErrorKind::InvalidInput` if the signature
+	/// is not an application mime type. Errors that occur while registering
+	/// the application with the registrar or the app_server are passed on.
+	/// This is the case when the program does not run in a graphical
+	/// session.
+	pub fn new(signature: &str, initial_state: A) -> Result<Self> {
+		Self::new_with_desktop_options(
+			signature,
+			initial_state,
+			&DesktopConnectionOptions::default(),
+		)
+	}
+
+	/// Create a new application object that connects to the desktop with
+	/// explicit `options`
+	///
+	/// This behaves like `Application::new()`, except that the connection to
+	/// the app_server is made with the given options, for example to connect
+	/// to a different screen than the one in the `TARGET_SCREEN` environment
+	/// variable, or to wait longer for the app_server to reply.
+	pub fn new_with_desktop_options(
+		signature: &str,
+		initial_state: A,
+		options: &DesktopConnectionOptions,
+	) -> Result<Self> {
 		// Check the signature
-		let mime_type = match MimeType::new(signature) {
-			Some(t) => t,
-			None => panic!("Invalid MimeType"),
+		let invalid_signature = || {
+			HaikuError::new(
+				ErrorKind::InvalidInput,
+				"the signature is not a valid application mime type",
+			)
 		};
-
+		let mime_type = MimeType::new(signature).ok_or_else(invalid_signature)?;
 		if mime_type.is_supertype_only()
-			|| (mime_type.get_supertype() != MimeType::new("application").unwrap())
+			|| MimeType::new("application") != Some(mime_type.get_supertype())
 		{
-			panic!("Invalid MimeType");
+			return Err(invalid_signature());
 		}
 
 		// Get an entry_ref for this path
-		let path = get_app_path(0).expect("Cannot get the path for this executable");
-		let entry =
-			entry_ref::from_path(&path).expect("Cannot get the entry_ref for this executable");
+		let path = get_app_path(0)?;
+		let entry = entry_ref::from_path(&path)?;
 
-		// To do: see if the application file has any attributes set
-		let app_flags: u32 = 1; //B_MULTIPLE_LAUNCH as B_REG_DEFAULT_APP_FLAGS
+		let app_flags = get_app_flags(&path);
 
-		// Register at the app server
-		let port = Port::create("application", LOOPER_PORT_DEFAULT_CAPACITY).unwrap();
+		// Register at the registrar
+		let port = Port::create("application", LOOPER_PORT_DEFAULT_CAPACITY)?;
 		let (team, thread) = get_current_team_and_thread();
-		let registration = match ROSTER.is_application_registered(&entry, team, 0) {
-			Ok(r) => r,
-			Err(_) => {
-				panic!("Error communicating with the registrar about the registration status")
-			}
-		};
-		match registration {
-			ApplicationRegistrationStatus::Registered(_) => (), //Ignored by the C++ implementation as well
-			ApplicationRegistrationStatus::PreRegistered(_) => {
-				panic!("Pre registered applications are not implemented")
-			}
-			ApplicationRegistrationStatus::NotRegistered => (), //Ignored, now register
-		};
+		let port =
+			match ROSTER.register_application(signature, &entry, app_flags, team, thread, port) {
+				Ok(Registration::Registered(port)) => port,
+				Ok(Registration::OtherInstance(other_team)) => {
+					// Hand over the arguments to the running instance, and
+					// leave it to the caller to end this instance
+					let argv: Vec<String> = args().collect();
+					relaunch_in_team(other_team, &argv)?;
+					return Err(HaikuError::from_raw_os_error(B_ALREADY_RUNNING));
+				}
+				Err(e) => return Err(e),
+			};
 
-		match ROSTER.add_application(
-			&String::from(signature),
-			&entry,
-			app_flags,
-			team,
-			thread,
-			port.get_port_id(),
-			true,
-		) {
-			Ok(_) => (),
-			Err(_) => panic!("Error registering with the registrar"),
-		};
+		// Make sure the signature is known in the MIME database, so that the
+		// system can show the application properly. Like the C++
+		// implementation, failing to do so is not fatal.
+		if !mime_type.is_installed().unwrap_or(true) {
+			mime_type.install().ok();
+		}
 
 		// Set up some defaults
 		let state = Arc::new(Mutex::new(initial_state));
-		let default_looper_state = Box::new(ApplicationLooperState {});
-		let context = Context {
-			handler_messenger: Messenger::from_port(&port).unwrap(),
-			looper: LooperDelegate {
-				messenger: Messenger::from_port(&port).unwrap(),
-			},
-			application: ApplicationDelegate {
-				messenger: Messenger::from_port(&port).unwrap(),
-			},
-			application_state: state.clone(),
-		};
-		let mut handlers = HashMap::new();
-		let handler_token = NEXT_HANDLER_TOKEN.fetch_add(1, atomic::Ordering::Relaxed);
-		handlers.insert(handler_token, HandlerType::LooperState);
-		let mut inner_looper = Looper {
-			name: String::from("application"),
-			port: port,
-			message_queue: VecDeque::new(),
-			handlers: handlers,
-			preferred_handler: handler_token,
-			context: context,
-			state: default_looper_state,
-			terminating: false,
+		let application_messenger = match Messenger::from_port(&port) {
+			Some(messenger) => messenger,
+			None => {
+				let _ = ROSTER.remove_application(team);
+				return Err(HaikuError::new(
+					ErrorKind::NotFound,
+					"cannot create a messenger for the application port",
+				));
+			}
 		};
+		let mut inner_looper = Looper::new(
+			"application",
+			port,
+			application_messenger,
+			state.clone(),
+			Box::new(ApplicationLooperState {}),
+			None,
+		);
 
 		// Add the ARGV_RECEIVED message to the queue
 		let mut argv_message = Message::new(B_ARGV_RECEIVED);
@@ -150,41 +187,28 @@ where
 		ready_message.header.target = B_PREFERRED_TOKEN;
 		inner_looper.message_queue.push_back(ready_message);
 
-		// Connect to the app_server
-		let mut link = ServerLink::create_desktop_connection().unwrap();
-		// AS_CREATE_APP:
-		// Data: 1) port_id - receiver port of the serverlink
-		//       2) port_id - looper port for this BApplication
-		//       3) team_id - the team id for this application
-		//       4) i32 - the handler ID token of this app
-		//       5) &str - signature of this app
-		link.sender
-			.start_message(server_protocol::AS_CREATE_APP, 0)
-			.unwrap();
-		link.sender
-			.attach(&link.receiver.port.get_port_id())
-			.unwrap();
-		link.sender
-			.attach(&inner_looper.port.get_port_id())
-			.unwrap();
-		link.sender.attach(&team).unwrap();
-		link.sender.attach(&handler_token).unwrap();
-		link.sender.attach_string(signature).unwrap();
-		link.sender.flush(true).unwrap();
-		let message = link.receiver.get_next_message(INFINITE_TIMEOUT).unwrap();
-		if message.0 != 0 {
-			panic!("Cannot register the application at the app_server");
-		}
-		let server_port: port_id = link.receiver.read(0).unwrap();
-		let _: i32 = link.receiver.read(0).unwrap(); // area id, ignore for now
-		let _: i32 = link.receiver.read(0).unwrap(); // team id, ignore for now
-		link.sender.set_port(Port::from_id(server_port).unwrap());
+		// Register at the app_server. If that fails, the application is
+		// removed from the registrar again.
+		let link = match register_at_app_server(
+			inner_looper.port.get_port_id(),
+			team,
+			inner_looper.preferred_handler,
+			signature,
+			options,
+		) {
+			Ok(link) => link,
+			Err(e) => {
+				let _ = ROSTER.remove_application(team);
+				return Err(e);
+			}
+		};
 
-		Self {
+		Ok(Self {
 			state: state,
 			inner_looper: inner_looper,
 			link: link,
-		}
+			loopers: Arc::new(LooperRegistry::default()),
+		})
 	}
 
 	/// Create a new looper for this application
@@ -197,35 +221,23 @@ where
 	/// The created loopers will not automatically start running; instead they
 	/// will be in a suspended state. See the Looper documentation on how to
 	/// start running them.
+	///
+	/// This returns an error if the port of the looper cannot be created, for
+	/// example when the system runs out of ports.
 	pub fn create_looper(
 		&mut self,
 		name: &str,
 		initial_state: Box<dyn Handler<A> + Send>,
-	) -> Looper<A> {
-		let port = Port::create(name, LOOPER_PORT_DEFAULT_CAPACITY).unwrap();
-		let mut handlers = HashMap::new();
-		let token = NEXT_HANDLER_TOKEN.fetch_add(1, atomic::Ordering::Relaxed);
-		handlers.insert(token, HandlerType::LooperState);
-		let context = Context {
-			handler_messenger: Messenger::from_port(&port).unwrap(),
-			looper: LooperDelegate {
-				messenger: Messenger::from_port(&port).unwrap(),
-			},
-			application: ApplicationDelegate {
-				messenger: self.inner_looper.get_messenger(),
-			},
-			application_state: self.state.clone(),
-		};
-		Looper {
-			name: String::from(name),
-			port: port,
-			message_queue: VecDeque::new(),
-			handlers: handlers,
-			preferred_handler: token,
-			context: context,
-			state: initial_state,
-			terminating: false,
-		}
+	) -> Result<Looper<A>> {
+		let port = Port::create(name, LOOPER_PORT_DEFAULT_CAPACITY)?;
+		Ok(Looper::new(
+			name,
+			port,
+			self.inner_looper.get_messenger(),
+			self.state.clone(),
+			initial_state,
+			Some(self.loopers.clone()),
+		))
 	}
 
 	/// Run the application
@@ -236,8 +248,21 @@ where
 	/// This method consumes the application instance, meaning that you won't be
 	/// able to use it after the loop has finished.
 	pub fn run(mut self) -> Result<()> {
-		self.inner_looper.looper_task();
-		Ok(())
+		self.inner_looper.run_in_current_thread()
+	}
+
+	/// Quit all the loopers that were created by this application
+	///
+	/// This sends a request to quit to every looper that was created with
+	/// `create_looper()` and is running, and then waits for their threads to
+	/// finish. If not all the loopers have stopped before the `timeout`, an
+	/// error with `ErrorKind::TimedOut` is returned.
+	///
+	/// This is done automatically when the application is dropped, but you
+	/// can use this method to shut down the loopers at a moment of your
+	/// choosing.
+	pub fn quit_all_loopers(&self, timeout: Duration) -> Result<()> {
+		self.loopers.quit_all(timeout)
 	}
 
 	/// Get a messenger to the application
@@ -254,16 +279,31 @@ where
 	A: ApplicationHooks + Send + 'static,
 {
 	fn drop(&mut self) {
+		// Stop the loopers, so that their threads do not outlive the
+		// application
+		let _ = self.loopers.quit_all(LOOPER_QUIT_TIMEOUT);
+
 		// Unregister from Registrar
 		let (team, _) = get_current_team_and_thread();
 		let _ = ROSTER.remove_application(team);
 
 		// Unregister from the app_server
-		self.link
+		if self
+			.link
 			.sender
 			.start_message(B_QUIT_REQUESTED as i32, 0)
-			.unwrap();
-		self.link.sender.flush(false).unwrap();
+			.is_ok()
+		{
+			let _ = self.link.sender.flush(false);
+		}
+
+		// Close the port, so that no new messages are accepted, and discard
+		// the messages that were not processed. This is the case for the
+		// initial messages when `run()` was never called. The port is deleted
+		// when the inner looper is dropped.
+		let _ = self.inner_looper.port.close();
+		let _ = self.inner_looper.port.read_all_messages();
+		self.inner_looper.message_queue.clear();
 	}
 }
 
@@ -343,8 +383,9 @@ where
 ///
 /// In order to create an Application object, you will need to provide an
 /// application state. Each application state will need to implement this
-/// trait. Currently there are three hook methods, all of which have a
-/// default implementation (which does nothing).
+/// trait. All the hook methods have a default implementation, which does
+/// nothing, or in the case of `quit_requested()`, allows the application to
+/// quit.
 ///
 /// The application state acts like a `Handler<A>`, and can be targeted by
 /// messages. There is a variety of the `Handler<A>::message_received()`
@@ -383,6 +424,28 @@ pub trait ApplicationHooks {
 	/// Single Launch, and the user tried to launch another instance. In that
 	/// case the arguments will be sent to this instance.
 	fn argv_received(&mut self, _application: &ApplicationDelegate, _argv: Vec<String>) {}
+
+	/// Called when your application is asked to open files
+	///
+	/// This hook is called when files are dropped on the application, or
+	/// when the application is launched to open documents. The `refs` are
+	/// the paths of the files. References that no longer resolve to a path
+	/// are left out.
+	fn refs_received(&mut self, _application: &ApplicationDelegate, _refs: Vec<PathBuf>) {}
+
+	/// Called when the application is asked to quit
+	///
+	/// This hook is called when the application receives a
+	/// `B_QUIT_REQUESTED` message. Return `true` to let the application
+	/// quit, or `false` to keep it running, for example because the user
+	/// still needs to save changes. The default implementation returns
+	/// `true`.
+	///
+	/// Note that `ApplicationDelegate::quit()` does not ask this hook, it
+	/// always quits the application.
+	fn quit_requested(&mut self, _application: &ApplicationDelegate) -> bool {
+		true
+	}
 }
 
 struct ApplicationLooperState {}
@@ -402,11 +465,121 @@ where
 				}
 			}
 			B_READY_TO_RUN => application_state.ready_to_run(&context.application),
+			B_REFS_RECEIVED => {
+				let refs = parse_refs(message);
+				application_state.refs_received(&context.application, refs);
+			}
+			B_QUIT_REQUESTED => {
+				if application_state.quit_requested(&context.application) {
+					context.application.quit();
+				}
+			}
 			_ => application_state.message_received(&context.application, message),
 		}
 	}
 }
 
+// The type of the BEOS:APP_FLAGS attribute
+const B_APP_FLAGS_TYPE: u32 = haiku_constant!('A', 'P', 'P', 'F');
+
+// Connect to the app_server, and register the application with its looper
+// port and the token of its preferred handler
+fn register_at_app_server(
+	looper_port: port_id,
+	team: team_id,
+	handler_token: i32,
+	signature: &str,
+	options: &DesktopConnectionOptions,
+) -> Result<ServerLink> {
+	let mut link = ServerLink::create_desktop_connection_with_options(options)?;
+	// AS_CREATE_APP:
+	// Data: 1) port_id - receiver port of the serverlink
+	//       2) port_id - looper port for this BApplication
+	//       3) team_id - the team id for this application
+	//       4) i32 - the handler ID token of this app
+	//       5) &str - signature of this app
+	link.sender
+		.start_message(server_protocol::AS_CREATE_APP, 0)?;
+	link.sender.attach(&link.receiver.port.get_port_id())?;
+	link.sender.attach(&looper_port)?;
+	link.sender.attach(&team)?;
+	link.sender.attach(&handler_token)?;
+	link.sender.attach_string(signature)?;
+	link.sender.flush(true)?;
+	// Note that the LinkReceiver retries reading when it is interrupted
+	let (code, _, _) = link
+		.receiver
+		.get_next_message(INFINITE_TIMEOUT)
+		.ok_or_else(|| {
+			HaikuError::new(
+				ErrorKind::InvalidData,
+				"cannot read the reply of the app_server",
+			)
+		})?;
+	if code != 0 {
+		return Err(HaikuError::from_raw_os_error(code as i32));
+	}
+	let server_port: port_id = link.receiver.read(0)?;
+	let _: i32 = link.receiver.read(0)?; // area id, ignore for now
+	let _: i32 = link.receiver.read(0)?; // team id, ignore for now
+	let server_port = Port::from_id(server_port).ok_or_else(|| {
+		HaikuError::new(
+			ErrorKind::NotFound,
+			"the port of the app_server does not exist",
+		)
+	})?;
+	link.sender.set_port(server_port);
+	Ok(link)
+}
+
+// Read the launch flags from the BEOS:APP_FLAGS attribute of the executable.
+// Applications without the attribute may be launched multiple times.
+pub(crate) fn get_app_flags(path: &Path) -> u32 {
+	match path.read_attribute_raw("BEOS:APP_FLAGS", B_APP_FLAGS_TYPE, 0, 4) {
+		Ok(data) if data.len() == 4 => u32::from_ne_bytes([data[0], data[1], data[2], data[3]]),
+		_ => B_MULTIPLE_LAUNCH,
+	}
+}
+
+// Build the B_ARGV_RECEIVED message that is sent to a running instance. The
+// first argument is replaced by the path of the running executable.
+pub(crate) fn argv_message(argv: &[String], app_path: &str) -> Result<Message> {
+	let mut message = Message::new(B_ARGV_RECEIVED);
+	message.add_data("argc", &(argv.len() as i32))?;
+	for (i, arg) in argv.iter().enumerate() {
+		if i == 0 {
+			message.add_string("argv", app_path)?;
+		} else {
+			message.add_data("argv", arg)?;
+		}
+	}
+	if let Ok(cwd) = current_dir() {
+		message.add_data("cwd", &cwd.to_string_lossy().into_owned())?;
+	}
+	Ok(message)
+}
+
+// Send the arguments to the running instance of this application in `team`,
+// or a B_SILENT_RELAUNCH message if there are none. Like BApplication, an
+// instance with the B_ARGV_ONLY flag does not get any message.
+fn relaunch_in_team(team: team_id, argv: &[String]) -> Result<()> {
+	let team = Team::from(team)
+		.ok_or_else(|| HaikuError::new(ErrorKind::NotFound, "the running instance is gone"))?;
+	let info = ROSTER.get_running_app_info(&team)?;
+	if info.is_argv_only() {
+		return Ok(());
+	}
+	let messenger = Messenger::from_port_id(info.port)
+		.ok_or_else(|| HaikuError::new(ErrorKind::NotFound, "the running instance has no port"))?;
+	let message = if argv.len() > 1 {
+		let app_path = get_app_path(team.get_team_id())?;
+		argv_message(argv, &app_path.to_string_lossy())?
+	} else {
+		Message::new(B_SILENT_RELAUNCH)
+	};
+	messenger.send(message, &messenger)
+}
+
 // Convert a B_ARGV_RECEIVED message into a Vector with strings
 fn parse_argv(message: &Message) -> Vec<String> {
 	let internal = message.find_data::<bool>("_internal", 0).unwrap_or(false);
@@ -428,6 +601,18 @@ fn parse_argv(message: &Message) -> Vec<String> {
 	argv
 }
 
+// Get the paths of the entry refs in the refs field of a B_REFS_RECEIVED
+// message
+fn parse_refs(message: &Message) -> Vec<PathBuf> {
+	let count = match message.get_info("refs") {
+		Some((_, count, _)) => count,
+		None => 0,
+	};
+	(0..count)
+		.filter_map(|index| message.find_path("refs", index).ok())
+		.collect()
+}
+
 /// Get the current team id and thread id
 // TODO: some caching
 pub(crate) fn get_current_team_and_thread() -> (team_id, thread_id) {
@@ -453,6 +638,20 @@ mod tests {
 	const ADD_TO_COUNTER: u32 = haiku_constant!('C', 'O', '+', '+');
 	const INFORM_APP_ABOUT_COUNTER: u32 = haiku_constant!('I', 'A', 'A', 'C');
 
+	// Build a context where the handler, the looper and the application all
+	// send their messages to `port`
+	fn test_context<S: Send>(port: &Port, state: Arc<Mutex<S>>) -> Context<S> {
+		let messenger = Messenger::from_port(port).unwrap();
+		Context {
+			handler_messenger: messenger.clone(),
+			looper: LooperDelegate {
+				messenger: messenger.clone(),
+			},
+			application: ApplicationDelegate { messenger },
+			application_state: state,
+		}
+	}
+
 	struct CountLooperState {
 		count: u32,
 	}
@@ -517,14 +716,19 @@ mod tests {
 		let looper_state_2 = Box::new(CountLooperState { count: 0 });
 		let application_state = ApplicationState { total_count: 0 };
 
-		let mut application = Application::new("application/looper_test", application_state);
+		let mut application =
+			Application::new("application/looper_test", application_state).unwrap();
 
-		let looper_1 = application.create_looper("looper 1", looper_state_1);
+		let looper_1 = application
+			.create_looper("looper 1", looper_state_1)
+			.unwrap();
 		let messenger_1 = looper_1.get_messenger();
-		let looper_2 = application.create_looper("looper 2", looper_state_2);
+		let looper_2 = application
+			.create_looper("looper 2", looper_state_2)
+			.unwrap();
 		let messenger_2 = looper_2.get_messenger();
-		assert!(looper_1.run().is_ok());
-		assert!(looper_2.run().is_ok());
+		let thread_1 = looper_1.run().unwrap();
+		let thread_2 = looper_2.run().unwrap();
 
 		// Create four count messages, two for each counter
 		let app_messenger = application.get_messenger();
@@ -546,5 +750,139 @@ mod tests {
 			.unwrap();
 
 		application.run().unwrap();
+		// The application has asked the loopers to quit
+		thread_1.join().unwrap();
+		thread_2.join().unwrap();
+	}
+
+	struct ArgvState {
+		argv: Vec<String>,
+	}
+
+	impl ApplicationHooks for ArgvState {
+		fn argv_received(&mut self, _application: &ApplicationDelegate, argv: Vec<String>) {
+			self.argv = argv;
+		}
+	}
+
+	#[test]
+	fn test_relaunch_argv_received() {
+		// A relaunched instance sends its arguments to the running instance
+		let argv = vec![
+			String::from("/boot/home/relaunch"),
+			String::from("--open"),
+			String::from("file.txt"),
+		];
+		let message = argv_message(&argv, "/boot/home/app").unwrap();
+		let port = Port::create("mock application", 10).unwrap();
+		let messenger = Messenger::from_port(&port).unwrap();
+		messenger.send(message, &messenger).unwrap();
+
+		// The running instance delivers them to the argv_received hook
+		let (_, buffer) = port.read().unwrap();
+		let message = Message::try_from(buffer.as_slice()).unwrap();
+		let state = Arc::new(Mutex::new(ArgvState { argv: Vec::new() }));
+		let context = test_context(&port, state.clone());
+		let mut looper_state = ApplicationLooperState {};
+		Handler::message_received(&mut looper_state, &context, &message);
+		assert_eq!(
+			state.lock().unwrap().argv,
+			vec!["/boot/home/app", "--open", "file.txt"]
+		);
+		assert_eq!(message.find_data::<i32>("argc", 0).unwrap(), 3);
+	}
+
+	struct RefsState {
+		refs: Vec<PathBuf>,
+	}
+
+	impl ApplicationHooks for RefsState {
+		fn refs_received(&mut self, _application: &ApplicationDelegate, refs: Vec<PathBuf>) {
+			self.refs = refs;
+		}
+	}
+
+	#[test]
+	fn test_refs_received() {
+		extern crate tempfile;
+		use std::fs;
+
+		let directory = tempfile::tempdir().unwrap();
+		let first = directory.path().join("first");
+		let second = directory.path().join("second");
+		fs::write(&first, "first").unwrap();
+		fs::write(&second, "second").unwrap();
+		let message = Message::refs_received(&[&first, &second]).unwrap();
+
+		let port = Port::create("mock application", 10).unwrap();
+		let state = Arc::new(Mutex::new(RefsState { refs: Vec::new() }));
+		let context = test_context(&port, state.clone());
+		let mut looper_state = ApplicationLooperState {};
+		Handler::message_received(&mut looper_state, &context, &message);
+		assert_eq!(
+			state.lock().unwrap().refs,
+			vec![
+				first.canonicalize().unwrap(),
+				second.canonicalize().unwrap()
+			]
+		);
+	}
+
+	struct QuitState {
+		requests: u32,
+	}
+
+	impl ApplicationHooks for QuitState {
+		fn quit_requested(&mut self, _application: &ApplicationDelegate) -> bool {
+			self.requests += 1;
+			self.requests > 1
+		}
+	}
+
+	#[test]
+	fn test_quit_requested() {
+		let port = Port::create("mock application", 10).unwrap();
+		let state = Arc::new(Mutex::new(QuitState { requests: 0 }));
+		let context = test_context(&port, state.clone());
+		let mut looper_state = ApplicationLooperState {};
+		let request = Message::new(B_QUIT_REQUESTED);
+
+		// The first request is vetoed, so the application is not told to quit
+		Handler::message_received(&mut looper_state, &context, &request);
+		assert_eq!(port.get_count().unwrap(), 0);
+
+		// The second request is accepted
+		Handler::message_received(&mut looper_state, &context, &request);
+		assert_eq!(state.lock().unwrap().requests, 2);
+		let (_, buffer) = port.read().unwrap();
+		assert_eq!(Message::try_from(buffer.as_slice()).unwrap().what(), QUIT);
+	}
+
+	#[test]
+	fn test_application_drop_without_run() {
+		let application = Application::new(
+			"application/x-vnd.haiku-rs-drop-test",
+			ArgvState { argv: Vec::new() },
+		)
+		.unwrap();
+		let port_id = application.inner_looper.port.get_port_id();
+		assert!(ROSTER.is_running("application/x-vnd.haiku-rs-drop-test"));
+		drop(application);
+
+		// The port is gone, and the application is no longer registered.
+		// Other tests register applications in the same team, so check the
+		// signature of this test rather than the team.
+		assert!(Port::from_id(port_id).is_none());
+		assert!(!ROSTER.is_running("application/x-vnd.haiku-rs-drop-test"));
+	}
+
+	#[test]
+	fn test_application_invalid_signature() {
+		for signature in ["not a mime type", "application", "text/x-vnd.haiku-rs-test"] {
+			let error = Application::new(signature, ArgvState { argv: Vec::new() })
+				.err()
+				.unwrap();
+			assert!(matches!(error.kind(), ErrorKind::InvalidInput));
+		}
 	}
 }
@@ -0,0 +1,67 @@
+//
+// Copyright 2024, Niels Sascha Reedijk <niels.reedijk@gmail.com>
+// All rights reserved. Distributed under the terms of the MIT License.
+//
+
+//! Message codes that are used by the application kit
+//!
+//! These are the `what` codes of messages that the crate sends or receives
+//! when it communicates with the system services. They are useful when you
+//! want to recognize these messages in your own handlers, or when you need to
+//! talk to the services directly.
+
+use crate::haiku_constant;
+
+// os/app/AppDefs.h
+
+/// The message that contains the command line arguments of an application
+pub const B_ARGV_RECEIVED: u32 = haiku_constant!('_', 'A', 'R', 'G');
+/// The message that is sent when an application is ready to run
+pub const B_READY_TO_RUN: u32 = haiku_constant!('_', 'R', 'T', 'R');
+/// The request to quit a looper or an application
+pub const B_QUIT_REQUESTED: u32 = haiku_constant!('_', 'Q', 'R', 'Q');
+/// The message with references to files that an application should open
+pub const B_REFS_RECEIVED: u32 = haiku_constant!('_', 'R', 'R', 'C');
+/// The message that a looper sends to its preferred handler at the pulse rate
+pub const B_PULSE: u32 = haiku_constant!('_', 'P', 'U', 'L');
+/// The message to a running application when it is launched again without
+/// arguments
+pub const B_SILENT_RELAUNCH: u32 = haiku_constant!('A', 'R', 'E', 'L');
+
+// os/app/Roster.h
+
+/// The message to roster watchers when an application is launched
+pub const B_SOME_APP_LAUNCHED: u32 = haiku_constant!('B', 'R', 'A', 'S');
+/// The message to roster watchers when an application quits
+pub const B_SOME_APP_QUIT: u32 = haiku_constant!('B', 'R', 'A', 'Q');
+/// The message to roster watchers when an application is activated
+pub const B_SOME_APP_ACTIVATED: u32 = haiku_constant!('B', 'R', 'A', 'W');
+
+// private/app/RegistrarDefs.h
+
+/// The request to the registrar for the list of running teams
+pub const B_REG_GET_APP_LIST: u32 = haiku_constant!('r', 'g', 'a', 'l');
+/// The request to the registrar for the information of a running application
+pub const B_REG_GET_APP_INFO: u32 = haiku_constant!('r', 'g', 'a', 'i');
+/// The reply of the registrar when a request succeeded
+pub const B_REG_SUCCESS: u32 = haiku_constant!('r', 'g', 's', 'u');
+/// The reply of the registrar with the status of a request in `result`
+pub const B_REG_RESULT: u32 = haiku_constant!('r', 'g', 'r', 'z');
+/// The request to the registrar for the messenger of the MIME database
+pub const B_REG_GET_MIME_MESSENGER: u32 = haiku_constant!('r', 'g', 'm', 'm');
+/// The request to the MIME database to install a type
+pub const B_REG_MIME_INSTALL: u32 = haiku_constant!('r', 'g', 'i', 'n');
+/// The request to the MIME database to delete a type
+pub const B_REG_MIME_DELETE: u32 = haiku_constant!('r', 'g', 'd', 'l');
+/// The request to the MIME database to set a property of a type
+pub const B_REG_MIME_SET_PARAM: u32 = haiku_constant!('r', 'g', 's', 'p');
+
+// private/app/LaunchDaemonDefs.h
+
+/// The request to the launch_daemon for the data of a job or a service
+pub const B_GET_LAUNCH_DATA: u32 = haiku_constant!('l', 'n', 'd', 'a');
+
+// private/notification/Notifications.h
+
+/// The message that delivers a notification to the notification_server
+pub const NOTIFICATION_MESSAGE: u32 = haiku_constant!('n', 's', 's', 'm');
@@ -5,15 +5,24 @@
 
 use std::collections::{HashMap, VecDeque};
 use std::marker::Send;
+use std::mem;
 use std::sync::atomic;
-use std::sync::atomic::AtomicI32;
+use std::sync::atomic::{AtomicBool, AtomicI32};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
+use std::thread::JoinHandle;
 use std::time::Duration;
 
-use crate::app::sys::{B_PREFERRED_TOKEN, B_QUIT_REQUESTED, QUIT};
+use libc::{B_TIMED_OUT, B_WOULD_BLOCK};
+
+use crate::app::application::ApplicationDelegate;
+use crate::app::constants::B_PULSE;
+use crate::app::sys::{B_NULL_TOKEN, B_PREFERRED_TOKEN, QUIT};
+use crate::app::timer::Timer;
 use crate::app::{Context, Message, Messenger};
 use crate::kernel::ports::Port;
-use crate::kernel::INFINITE_TIMEOUT;
+use crate::kernel::{system_time, INFINITE_TIMEOUT};
 use crate::support::{ErrorKind, Flattenable, HaikuError, Result};
 
 /// A trait for the ability to process messages in the context of a looper
@@ -32,6 +41,75 @@ where
 	fn message_received(&mut self, context: &Context<A>, message: &Message);
 }
 
+/// A Handler that calls a closure for every message
+///
+/// This is useful for small Handlers that do not need a type of their own.
+/// The closure gets the same arguments as `Handler::message_received()`.
+///
+/// # Example
+///
+/// ```norun
+/// # extern crate haiku;
+/// # use haiku::app::{FnHandler, Looper};
+/// # fn add(looper: &mut Looper<()>) {
+/// let handler = FnHandler::new(|_context, message| println!("{:?}", message));
+/// looper.add_handler(Box::new(handler));
+/// # }
+/// ```
+pub struct FnHandler<A>
+where
+	A: Send + 'static,
+{
+	handler: HandlerFn<A>,
+}
+
+type HandlerFn<A> = Box<dyn FnMut(&Context<A>, &Message) + Send>;
+
+impl<A> FnHandler<A>
+where
+	A: Send + 'static,
+{
+	/// Create a Handler that calls `handler` for every message
+	pub fn new<F>(handler: F) -> FnHandler<A>
+	where
+		F: FnMut(&Context<A>, &Message) + Send + 'static,
+	{
+		FnHandler {
+			handler: Box::new(handler),
+		}
+	}
+}
+
+impl<A> Handler<A> for FnHandler<A>
+where
+	A: Send + 'static,
+{
+	fn message_received(&mut self, context: &Context<A>, message: &Message) {
+		(self.handler)(context, message);
+	}
+}
+
+/// The decision of a `MessageFilter` about a message
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilterResult {
+	/// Pass the message on to the next filter, and then to its Handler
+	Dispatch,
+	/// Drop the message, it is not dispatched to a Handler
+	Skip,
+}
+
+/// A trait for objects that inspect messages before they are dispatched
+///
+/// Filters are added to a Looper with `Looper::add_common_filter()`. The
+/// Looper passes every message to its filters, in the order in which they
+/// were added, before it dispatches the message to a Handler. A filter may
+/// change the message, or it may drop it by returning `FilterResult::Skip`.
+/// The remaining filters do not see a message that is skipped.
+pub trait MessageFilter {
+	/// Inspect, and possibly change, a message before it is dispatched
+	fn filter(&mut self, message: &mut Message) -> FilterResult;
+}
+
 pub(crate) enum HandlerType<A>
 where
 	A: Send + 'static,
@@ -40,6 +118,9 @@ where
 	LooperState,
 }
 
+pub(crate) type BeforeDispatchObserver = Box<dyn FnMut(&Message) + Send>;
+pub(crate) type AfterDispatchObserver = Box<dyn FnMut(&Message, Duration) + Send>;
+
 /// A system that receives and processes messages in a separate thread
 ///
 /// Loopers are a core Haiku concept. Haiku embraces the multithreaded
@@ -74,8 +155,19 @@ where
 /// system.
 ///
 /// A Looper will continue to run until the it gets a request to quit. This
-/// can be done by sending the B_QUIT_REQUESTED message. Additionally, a
-/// Looper will quit when the Application is quitting.
+/// can be done with the `LooperDelegate::quit()` method. Additionally, a
+/// Looper will quit when the Application is quitting. A `B_QUIT_REQUESTED`
+/// message is dispatched to the Handler like any other message, so that the
+/// Handler can decide whether to quit.
+///
+/// For tracing and metrics, it is possible to observe every message that is
+/// dispatched to a Handler, using the `set_before_dispatch()` and
+/// `set_after_dispatch()` methods. Observers cannot change or drop messages,
+/// but the filters that are added with `add_common_filter()` can.
+///
+/// By default, a Looper processes its messages one at a time on its own
+/// thread. Loopers with many independent Handlers can spread the work over
+/// more threads with the `set_worker_count()` method.
 pub struct Looper<A>
 where
 	A: Send + 'static,
@@ -88,17 +180,76 @@ where
 	pub(crate) context: Context<A>,
 	pub(crate) state: Box<dyn Handler<A> + Send>,
 	pub(crate) terminating: bool,
+	pub(crate) before_dispatch: Option<BeforeDispatchObserver>,
+	pub(crate) after_dispatch: Option<AfterDispatchObserver>,
+	pub(crate) registry: Option<Arc<LooperRegistry>>,
+	pub(crate) worker_count: usize,
+	pub(crate) pulse_rate: Option<Duration>,
+	pub(crate) filters: Vec<Box<dyn MessageFilter + Send>>,
+	pub(crate) started: bool,
 }
 
 impl<A> Looper<A>
 where
 	A: Send + 'static,
 {
+	// Create a looper that receives its messages on `port`, with `state` as
+	// its preferred handler. The `application` messenger and the shared
+	// `application_state` are made available to the handlers through their
+	// context.
+	pub(crate) fn new(
+		name: &str,
+		port: Port,
+		application: Messenger,
+		application_state: Arc<Mutex<A>>,
+		state: Box<dyn Handler<A> + Send>,
+		registry: Option<Arc<LooperRegistry>>,
+	) -> Looper<A> {
+		let context = Context {
+			handler_messenger: Messenger::from_port(&port).unwrap(),
+			looper: LooperDelegate {
+				messenger: Messenger::from_port(&port).unwrap(),
+			},
+			application: ApplicationDelegate {
+				messenger: application,
+			},
+			application_state: application_state,
+		};
+		let token = NEXT_HANDLER_TOKEN.fetch_add(1, atomic::Ordering::Relaxed);
+		let mut handlers = HashMap::new();
+		handlers.insert(token, HandlerType::LooperState);
+		Looper {
+			name: String::from(name),
+			port: port,
+			message_queue: VecDeque::new(),
+			handlers: handlers,
+			preferred_handler: token,
+			context: context,
+			state: state,
+			terminating: false,
+			before_dispatch: None,
+			after_dispatch: None,
+			registry: registry,
+			worker_count: 1,
+			pulse_rate: None,
+			filters: Vec::new(),
+			started: false,
+		}
+	}
+
 	/// Get the name for this Looper
 	pub fn name(&self) -> &str {
 		&self.name
 	}
 
+	/// Change the name of this Looper
+	///
+	/// The name is also used for the thread of the Looper, so it should be
+	/// set before the Looper is run.
+	pub fn set_name(&mut self, name: &str) {
+		self.name = String::from(name);
+	}
+
 	/// Get a Messenger for this looper
 	///
 	/// This Messenger by default points to the preferred Handler.
@@ -106,27 +257,73 @@ where
 		Messenger::from_port(&self.port).unwrap()
 	}
 
+	/// Get a Messenger for a specific Handler of this looper
+	///
+	/// The `token` is the value that `add_handler()` or
+	/// `add_preferred_handler()` returned. Messages that are sent with this
+	/// Messenger are dispatched to that Handler, even if it is not the
+	/// preferred Handler.
+	pub fn messenger_for(&self, token: i32) -> Messenger {
+		let mut messenger = self.get_messenger();
+		messenger.set_token(token);
+		messenger
+	}
+
 	/// Start the message loop
 	///
 	/// When you use this method, the Looper ownership of the Looper object
 	/// will be transferred to the Looper's thread. The message processing
 	/// will start, until the Looper is requested to quit.
-	pub fn run(mut self) -> Result<()> {
-		let _child = thread::spawn(move || {
-			self.looper_task();
-		});
-		Ok(())
+	///
+	/// The thread of the Looper has the same name as the Looper, which makes
+	/// it easier to identify in a debugger.
+	///
+	/// A Looper can only be started once. Since this method takes ownership
+	/// of the Looper, this is usually enforced by the compiler, but a Looper
+	/// that has already been started returns an error with
+	/// `ErrorKind::NotAllowed`.
+	///
+	/// The handle to the thread of the Looper is returned. Joining it waits
+	/// until the Looper has quit, and reports whether the Looper panicked.
+	/// An Application waits for its Loopers when it quits, so the handle
+	/// may also be dropped.
+	pub fn run(mut self) -> Result<JoinHandle<()>> {
+		self.mark_started()?;
+		let messenger = self.get_messenger();
+		let registry = self.registry.take();
+		let finished = Arc::new(AtomicBool::new(false));
+		let flag = FinishedFlag(finished.clone(), registry.clone());
+		let thread = thread::Builder::new()
+			.name(self.name.clone())
+			.spawn(move || {
+				// The flag is dropped last, after the looper has deleted its
+				// port
+				let _flag = flag;
+				let mut looper = self;
+				looper.looper_task();
+			})
+			.map_err(|e| HaikuError::new(ErrorKind::Other, e))?;
+		if let Some(registry) = registry {
+			registry.add(LooperController {
+				messenger,
+				finished,
+			});
+		}
+		Ok(thread)
 	}
 
 	/// Add a Handler to the message queue
 	///
 	/// The handler may be any object that implements the Handler trait. The
 	/// object should be created on the heap (as a Box).
-	pub fn add_handler(&mut self, handler: Box<dyn Handler<A> + Send>) {
-		self.handlers.insert(
-			NEXT_HANDLER_TOKEN.fetch_add(1, atomic::Ordering::Relaxed),
-			HandlerType::OwnedHandler(handler),
-		);
+	///
+	/// The returned token identifies the Handler. Use it with
+	/// `messenger_for()` to send messages to this Handler.
+	pub fn add_handler(&mut self, handler: Box<dyn Handler<A> + Send>) -> i32 {
+		let token = NEXT_HANDLER_TOKEN.fetch_add(1, atomic::Ordering::Relaxed);
+		self.handlers
+			.insert(token, HandlerType::OwnedHandler(handler));
+		token
 	}
 
 	/// Add a preferred Handler to the message queue
@@ -134,15 +331,153 @@ where
 	/// Like the add_handler() method, this method takes ownership of any
 	/// Handler. In addition, this method will also set the Handler as the
 	/// preferred Handler of this Looper. This will overwrite the previously
-	/// set preferred Handler.
-	pub fn add_preferred_handler(&mut self, handler: Box<dyn Handler<A> + Send>) {
-		let token = NEXT_HANDLER_TOKEN.fetch_add(1, atomic::Ordering::Relaxed);
-		self.handlers
-			.insert(token, HandlerType::OwnedHandler(handler));
+	/// set preferred Handler. The token of the Handler is returned.
+	pub fn add_preferred_handler(&mut self, handler: Box<dyn Handler<A> + Send>) -> i32 {
+		let token = self.add_handler(handler);
 		self.preferred_handler = token;
+		token
+	}
+
+	/// Remove a Handler from the message queue
+	///
+	/// The Handler with the `token` is removed, and returned to the caller.
+	/// Messages that are sent to the token are no longer dispatched. If the
+	/// Handler was the preferred Handler, the state of the Looper becomes the
+	/// preferred Handler again.
+	///
+	/// The state of the Looper cannot be removed. This method returns `None`
+	/// if the `token` belongs to the state, or to no Handler at all.
+	pub fn remove_handler(&mut self, token: i32) -> Option<Box<dyn Handler<A> + Send>> {
+		match self.handlers.remove(&token)? {
+			HandlerType::OwnedHandler(handler) => {
+				if self.preferred_handler == token {
+					self.preferred_handler = self.state_token();
+				}
+				Some(handler)
+			}
+			HandlerType::LooperState => {
+				self.handlers.insert(token, HandlerType::LooperState);
+				None
+			}
+		}
+	}
+
+	/// Add a filter for all the messages of this Looper
+	///
+	/// The filter sees every message before it is dispatched, whichever
+	/// Handler it is for. Filters run in the order in which they are added.
+	pub fn add_common_filter(&mut self, filter: Box<dyn MessageFilter + Send>) {
+		self.filters.push(filter);
+	}
+
+	/// Set an observer that is called before a message is dispatched
+	///
+	/// The observer is called from the Looper's thread, right before the
+	/// message is passed to the Handler. Setting a new observer replaces the
+	/// previous one.
+	pub fn set_before_dispatch<F>(&mut self, observer: F)
+	where
+		F: FnMut(&Message) + Send + 'static,
+	{
+		self.before_dispatch = Some(Box::new(observer));
+	}
+
+	/// Set an observer that is called after a message is dispatched
+	///
+	/// The observer is called from the Looper's thread, after the Handler
+	/// has processed the message. It receives the message and the time the
+	/// Handler spent on it. Setting a new observer replaces the previous one.
+	pub fn set_after_dispatch<F>(&mut self, observer: F)
+	where
+		F: FnMut(&Message, Duration) + Send + 'static,
+	{
+		self.after_dispatch = Some(Box::new(observer));
+	}
+
+	/// Get the number of threads that process the messages of this Looper
+	pub fn worker_count(&self) -> usize {
+		self.worker_count
+	}
+
+	/// Set the number of threads that process the messages of this Looper
+	///
+	/// By default, the Looper calls its Handlers from its own thread, one
+	/// message at a time. With a `count` larger than one, the Looper starts
+	/// that many worker threads when it runs. The Looper's thread then only
+	/// reads the messages from the port and passes them on to the workers.
+	///
+	/// Each Handler is assigned to one of the workers. This guarantees that
+	/// the messages for a Handler are processed in the order in which they
+	/// arrived, and that a Handler is never called from two threads at the
+	/// same time. Messages for different Handlers may be processed at the
+	/// same time, so there is no ordering between them. The dispatch
+	/// observers are called from the workers, one call at a time.
+	///
+	/// A `count` of zero is treated as one. The count should be set before
+	/// the Looper is run. If the worker threads cannot be started, the
+	/// Looper calls its Handlers from its own thread.
+	pub fn set_worker_count(&mut self, count: usize) {
+		self.worker_count = count.max(1);
+	}
+
+	/// Get the interval at which the Looper sends `B_PULSE` messages
+	pub fn pulse_rate(&self) -> Option<Duration> {
+		self.pulse_rate
+	}
+
+	/// Set the interval at which the Looper sends `B_PULSE` messages
+	///
+	/// When a pulse rate is set, the preferred Handler receives a `B_PULSE`
+	/// message whenever the Looper has been waiting for messages for that
+	/// long. This can be used for animations, or to poll for changes. A
+	/// Looper that is busy with other messages does not send pulses, so the
+	/// rate is the shortest interval between pulses, not a guarantee.
+	///
+	/// Set the rate to `None` to stop the pulses. The rate should be set
+	/// before the Looper is run.
+	pub fn set_pulse_rate(&mut self, rate: Option<Duration>) {
+		self.pulse_rate = rate;
+	}
+
+	// Run the message loop in the current thread, until the looper quits
+	//
+	// This is used by the Application, which runs its looper in the main
+	// thread. Like run(), this fails if the looper has already been started.
+	pub(crate) fn run_in_current_thread(&mut self) -> Result<()> {
+		self.mark_started()?;
+		self.looper_task();
+		Ok(())
+	}
+
+	// Get the token of the state of the looper
+	fn state_token(&self) -> i32 {
+		self.handlers
+			.iter()
+			.find(|(_, handler)| matches!(handler, HandlerType::LooperState))
+			.map(|(token, _)| *token)
+			.unwrap_or(B_NULL_TOKEN)
+	}
+
+	fn mark_started(&mut self) -> Result<()> {
+		if self.started {
+			return Err(HaikuError::new(
+				ErrorKind::NotAllowed,
+				"the looper has already been started",
+			));
+		}
+		self.started = true;
+		Ok(())
 	}
 
 	pub(crate) fn looper_task(&mut self) {
+		// If the workers cannot be started, the looper keeps its handlers
+		// and dispatches the messages from its own thread
+		let mut pool = if self.worker_count > 1 {
+			WorkerPool::start(self).ok()
+		} else {
+			None
+		};
+		let mut next_pulse = self.pulse_rate.map(|rate| system_time() + rate);
 		loop {
 			// Try to read the first message from the port
 			// This will block until there is a message
@@ -150,9 +485,26 @@ where
 			// Application object puts a READY_TO_RUN in the queue, and
 			// we want to guarantee that that one is processed, without
 			// getting stuck on waiting for messages in the port.
+			// With a pulse rate, the wait ends when the next pulse is due.
 			if self.message_queue.len() == 0 {
-				match self.read_message_from_port(INFINITE_TIMEOUT) {
+				let timeout = match next_pulse {
+					Some(pulse) => pulse.saturating_sub(system_time()),
+					None => INFINITE_TIMEOUT,
+				};
+				match self.read_message_from_port(timeout) {
 					Ok(message) => self.message_queue.push_back(message),
+					Err(ref e)
+						if next_pulse.is_some()
+							&& matches!(
+								e.raw_os_error(),
+								Some(B_TIMED_OUT) | Some(B_WOULD_BLOCK)
+							) =>
+					{
+						let mut pulse = Message::new(B_PULSE);
+						pulse.header.target = B_PREFERRED_TOKEN;
+						self.message_queue.push_back(pulse);
+						next_pulse = self.pulse_rate.map(|rate| system_time() + rate);
+					}
 					Err(e) => {
 						println!("[{}] Error getting message: {:?}", self.name(), e);
 						continue;
@@ -177,39 +529,34 @@ where
 			// queue, this is the inner loop
 			let mut dispatch_next_message = true;
 			while dispatch_next_message && !self.terminating {
-				let message = self.message_queue.pop_front();
-
-				if message.is_none() {
-					dispatch_next_message = false;
-				} else {
-					let message = message.unwrap();
+				if let Some(mut message) = self.message_queue.pop_front() {
+					if message.what() != QUIT && !self.filter(&mut message) {
+						continue;
+					}
 					let mut handler_token = message.header.target;
 					if handler_token == B_PREFERRED_TOKEN {
 						handler_token = self.preferred_handler;
 					}
 
-					let handler = match self.handlers.get_mut(&handler_token) {
-						Some(handler) => handler,
-						None => continue, //If we are not the addressee, continue next
+					let is_addressee = match pool {
+						Some(ref pool) => pool.routes.contains_key(&handler_token),
+						None => self.handlers.contains_key(&handler_token),
 					};
+					if !is_addressee {
+						continue; //If we are not the addressee, continue next
+					}
 
 					match message.what() {
-						B_QUIT_REQUESTED => {}
 						QUIT => {
 							self.terminating = true;
 						}
-						_ => {
-							self.context.handler_messenger.set_token(handler_token);
-							match handler {
-								HandlerType::OwnedHandler(h) => {
-									h.message_received(&self.context, &message);
-								}
-								HandlerType::LooperState => {
-									self.state.message_received(&self.context, &message);
-								}
-							}
-						}
+						_ => match pool {
+							Some(ref pool) => pool.dispatch(handler_token, message),
+							None => self.dispatch(handler_token, &message),
+						},
 					}
+				} else {
+					dispatch_next_message = false;
 				}
 
 				if self.terminating {
@@ -229,14 +576,39 @@ where
 				break;
 			}
 		}
+		if let Some(pool) = pool.take() {
+			pool.stop(self);
+		}
+	}
+
+	// Run the filters, and return whether the message should be dispatched
+	fn filter(&mut self, message: &mut Message) -> bool {
+		self.filters
+			.iter_mut()
+			.all(|filter| filter.filter(message) == FilterResult::Dispatch)
+	}
+
+	fn dispatch(&mut self, handler_token: i32, message: &Message) {
+		self.context.handler_messenger.set_token(handler_token);
+		let handler = match self.handlers.get_mut(&handler_token) {
+			Some(HandlerType::OwnedHandler(h)) => h,
+			Some(HandlerType::LooperState) => &mut self.state,
+			None => return,
+		};
+		dispatch_to_handler(
+			handler.as_mut(),
+			&self.context,
+			message,
+			&mut self.before_dispatch,
+			&mut self.after_dispatch,
+		);
 	}
 
 	fn read_message_from_port(&self, timeout: Duration) -> Result<Message> {
 		// TODO: handle B_INTERRUPTED?
 		let (type_code, buffer) = self.port.try_read(timeout)?;
 		if type_code as u32 == Message::type_code() {
-			let message = Message::unflatten(&buffer)?;
-			Ok(message)
+			Message::unflatten_received(buffer.as_slice())
 		} else {
 			Err(HaikuError::new(
 				ErrorKind::InvalidData,
@@ -246,6 +618,209 @@ where
 	}
 }
 
+// Pass a message to a handler, and notify the observers
+fn dispatch_to_handler<A>(
+	handler: &mut (dyn Handler<A> + Send),
+	context: &Context<A>,
+	message: &Message,
+	before_dispatch: &mut Option<BeforeDispatchObserver>,
+	after_dispatch: &mut Option<AfterDispatchObserver>,
+) where
+	A: Send + 'static,
+{
+	if let Some(ref mut observer) = before_dispatch {
+		observer(message);
+	}
+	let start_time = system_time();
+	handler.message_received(context, message);
+	if let Some(ref mut observer) = after_dispatch {
+		observer(message, system_time() - start_time);
+	}
+}
+
+// The number of messages that may wait for a worker, before the looper's
+// thread blocks on passing on the next one
+const WORKER_QUEUE_LENGTH: usize = 64;
+
+type WorkerHandlers<A> = HashMap<i32, Box<dyn Handler<A> + Send>>;
+type DispatchObservers = (
+	Option<BeforeDispatchObserver>,
+	Option<AfterDispatchObserver>,
+);
+
+// A stand-in for the state of a looper while it is owned by a worker
+struct DetachedState {}
+
+impl<A> Handler<A> for DetachedState
+where
+	A: Send + 'static,
+{
+	fn message_received(&mut self, _context: &Context<A>, _message: &Message) {}
+}
+
+// The worker threads of a looper with a worker count larger than one
+//
+// The handlers of the looper are divided over the workers, and every worker
+// owns its handlers while it runs. The looper's thread sends each message to
+// the worker that owns the target handler, so that the messages for one
+// handler keep their order.
+struct WorkerPool<A>
+where
+	A: Send + 'static,
+{
+	routes: HashMap<i32, usize>,
+	senders: Vec<SyncSender<Message>>,
+	threads: Vec<JoinHandle<WorkerHandlers<A>>>,
+	state_token: Option<i32>,
+	observers: Arc<Mutex<DispatchObservers>>,
+}
+
+impl<A> WorkerPool<A>
+where
+	A: Send + 'static,
+{
+	fn start(looper: &mut Looper<A>) -> Result<WorkerPool<A>> {
+		let count = looper.worker_count;
+
+		// Start the workers before the handlers are taken from the looper,
+		// so that the looper keeps its handlers if a thread cannot be
+		// started. Each worker receives its handlers once all are running.
+		let observers = Arc::new(Mutex::new((None, None)));
+		let mut handler_senders = Vec::new();
+		let mut senders = Vec::new();
+		let mut threads = Vec::new();
+		for index in 0..count {
+			let (handler_sender, handler_receiver) = sync_channel::<WorkerHandlers<A>>(1);
+			let (sender, receiver) = sync_channel::<Message>(WORKER_QUEUE_LENGTH);
+			let mut context = Context {
+				handler_messenger: looper.context.handler_messenger.clone(),
+				looper: LooperDelegate {
+					messenger: looper.context.looper.messenger.clone(),
+				},
+				application: ApplicationDelegate {
+					messenger: looper.context.application.messenger.clone(),
+				},
+				application_state: looper.context.application_state.clone(),
+			};
+			let observers: Arc<Mutex<DispatchObservers>> = observers.clone();
+			let spawned = thread::Builder::new()
+				.name(format!("{} worker {}", looper.name, index))
+				.spawn(move || {
+					let mut handlers = match handler_receiver.recv() {
+						Ok(handlers) => handlers,
+						Err(_) => return HashMap::new(),
+					};
+					for message in receiver {
+						let token = message.header.target;
+						if let Some(handler) = handlers.get_mut(&token) {
+							context.handler_messenger.set_token(token);
+							// Only hold the lock while calling the observers,
+							// so that the handlers of other workers can run
+							if let Some(ref mut observer) = observers.lock().unwrap().0 {
+								observer(&message);
+							}
+							let start_time = system_time();
+							handler.message_received(&context, &message);
+							if let Some(ref mut observer) = observers.lock().unwrap().1 {
+								observer(&message, system_time() - start_time);
+							}
+						}
+					}
+					handlers
+				});
+			match spawned {
+				Ok(thread) => {
+					handler_senders.push(handler_sender);
+					senders.push(sender);
+					threads.push(thread);
+				}
+				Err(e) => {
+					// Dropping the channels lets the started workers exit
+					drop(handler_senders);
+					drop(senders);
+					for thread in threads {
+						let _ = thread.join();
+					}
+					return Err(HaikuError::new(ErrorKind::Other, e));
+				}
+			}
+		}
+
+		// Assign the handlers to the workers in the order of their tokens
+		let mut tokens: Vec<i32> = looper.handlers.keys().copied().collect();
+		tokens.sort();
+		let mut routes = HashMap::new();
+		let mut state_token = None;
+		let mut assignments: Vec<WorkerHandlers<A>> = (0..count).map(|_| HashMap::new()).collect();
+		for (index, token) in tokens.into_iter().enumerate() {
+			let handler = match looper.handlers.remove(&token).unwrap() {
+				HandlerType::OwnedHandler(h) => h,
+				HandlerType::LooperState => {
+					state_token = Some(token);
+					mem::replace(&mut looper.state, Box::new(DetachedState {}))
+				}
+			};
+			routes.insert(token, index % count);
+			assignments[index % count].insert(token, handler);
+		}
+		*observers.lock().unwrap() = (looper.before_dispatch.take(), looper.after_dispatch.take());
+		for (handler_sender, handlers) in handler_senders.iter().zip(assignments) {
+			// The channel has room for the handlers, and the worker waits
+			// for them, so this does not fail
+			let _ = handler_sender.send(handlers);
+		}
+
+		Ok(WorkerPool {
+			routes,
+			senders,
+			threads,
+			state_token,
+			observers,
+		})
+	}
+
+	// Send a message to the worker that owns the handler
+	fn dispatch(&self, handler_token: i32, mut message: Message) {
+		if let Some(index) = self.routes.get(&handler_token) {
+			message.header.target = handler_token;
+			// A worker only stops early when a handler panicked; that is
+			// reported when the pool is stopped
+			let _ = self.senders[*index].send(message);
+		}
+	}
+
+	// Let the workers finish their messages, and return the handlers and the
+	// observers to the looper
+	//
+	// The handlers of a worker that panicked are lost. The panic is not
+	// caught, so it has already been reported by the panic hook.
+	fn stop(self, looper: &mut Looper<A>) {
+		drop(self.senders);
+		for thread in self.threads {
+			let handlers = match thread.join() {
+				Ok(handlers) => handlers,
+				Err(_) => continue,
+			};
+			for (token, handler) in handlers {
+				if Some(token) == self.state_token {
+					looper.state = handler;
+					looper.handlers.insert(token, HandlerType::LooperState);
+				} else {
+					looper
+						.handlers
+						.insert(token, HandlerType::OwnedHandler(handler));
+				}
+			}
+		}
+		// The workers are gone, so this is the last reference
+		if let Ok(observers) = Arc::try_unwrap(self.observers) {
+			let (before_dispatch, after_dispatch) = observers.into_inner().unwrap();
+			looper.before_dispatch = before_dispatch;
+			looper.after_dispatch = after_dispatch;
+		}
+	}
+}
+
 /// Interact with the associated looper
 ///
 /// The looper controls the message flow. This delegate allows you to access
@@ -266,6 +841,118 @@ impl LooperDelegate {
 		let message = Message::new(QUIT);
 		self.messenger.send(message, &self.messenger).unwrap();
 	}
+
+	/// Send a message to the looper after a delay
+	///
+	/// The message is delivered to the looper once the `delay` has passed.
+	/// It is not delivered if the looper has quit in the mean time.
+	pub fn post_delayed(&self, message: Message, delay: Duration) {
+		Timer::start(
+			self.messenger.clone(),
+			&message,
+			delay,
+			Duration::ZERO,
+			Some(1),
+		)
+		.detach();
+	}
+}
+
+// A handle to a Looper that is running in its own thread
+//
+// The caller of Looper::run() owns the handle to the thread, so the
+// controller knows that the thread has ended by a flag that is set when the
+// thread finishes, or panics.
+pub(crate) struct LooperController {
+	messenger: Messenger,
+	finished: Arc<AtomicBool>,
+}
+
+impl LooperController {
+	fn is_finished(&self) -> bool {
+		self.finished.load(atomic::Ordering::Acquire)
+	}
+}
+
+// Sets the finished flag of a looper thread when it is dropped, and tells
+// the registry of the looper
+struct FinishedFlag(Arc<AtomicBool>, Option<Arc<LooperRegistry>>);
+
+impl Drop for FinishedFlag {
+	fn drop(&mut self) {
+		self.0.store(true, atomic::Ordering::Release);
+		if let Some(ref registry) = self.1 {
+			registry.notify_finished();
+		}
+	}
+}
+
+// The running Loopers of an Application
+//
+// Loopers that are created by an Application register themselves when they
+// are run, so that the Application can stop them when it quits.
+#[derive(Default)]
+pub(crate) struct LooperRegistry {
+	controllers: Mutex<Vec<LooperController>>,
+	// Signalled whenever the thread of a registered looper finishes
+	finished: Condvar,
+}
+
+impl LooperRegistry {
+	fn add(&self, controller: LooperController) {
+		let mut controllers = self.controllers.lock().unwrap();
+		// Forget the loopers that have quit by themselves
+		controllers.retain(|controller| !controller.is_finished());
+		controllers.push(controller);
+	}
+
+	fn notify_finished(&self) {
+		// Hold the lock, so that quit_all() cannot miss the notification
+		// between checking the loopers and starting to wait
+		let _controllers = self.controllers.lock().unwrap();
+		self.finished.notify_all();
+	}
+
+	// Ask all loopers to quit, and wait for their threads to finish
+	//
+	// Loopers that do not finish before the timeout stay in the registry.
+	pub(crate) fn quit_all(&self, timeout: Duration) -> Result<()> {
+		// Send the requests without holding the lock, as a looper with a
+		// full port may need to register a new looper before it gets to
+		// the request
+		let messengers: Vec<Messenger> = self
+			.controllers
+			.lock()
+			.unwrap()
+			.iter()
+			.map(|controller| controller.messenger.clone())
+			.collect();
+		for messenger in messengers.iter() {
+			// The looper may already have quit by itself, which closes its
+			// port, so ignore any errors
+			let _ = messenger.send(Message::new(QUIT), messenger);
+		}
+
+		let controllers = self.controllers.lock().unwrap();
+		let (controllers, _) = self
+			.finished
+			.wait_timeout_while(controllers, timeout, |controllers| {
+				controllers.retain(|controller| !controller.is_finished());
+				!controllers.is_empty()
+			})
+			.unwrap();
+		if controllers.is_empty() {
+			Ok(())
+		} else {
+			Err(HaikuError::new(
+				ErrorKind::TimedOut,
+				format!(
+					"{} looper(s) did not quit before the timeout",
+					controllers.len()
+				),
+			))
+		}
+	}
 }
 
 /// The following global counter creates new unique tokens to identify handlers.
@@ -277,3 +964,599 @@ impl LooperDelegate {
 // Also: the token counter is part of the looper module here, but it might
 // as well be in the application object... to do.
 pub(crate) static NEXT_HANDLER_TOKEN: AtomicI32 = AtomicI32::new(2);
+
+// Create a looper outside of an Application
+#[cfg(test)]
+pub(crate) fn create_test_looper(name: &str, state: Box<dyn Handler<()> + Send>) -> Looper<()> {
+	let port = Port::create(name, 10).unwrap();
+	let application = Messenger::from_port(&port).unwrap();
+	Looper::new(
+		name,
+		port,
+		application,
+		Arc::new(Mutex::new(())),
+		state,
+		None,
+	)
+}
+
+#[test]
+fn test_looper_dispatch_observers() {
+	use crate::haiku_constant;
+
+	const WORK: u32 = haiku_constant!('w', 'o', 'r', 'k');
+
+	struct WorkState {}
+
+	impl Handler<()> for WorkState {
+		fn message_received(&mut self, _context: &Context<()>, _message: &Message) {
+			thread::sleep(Duration::from_millis(5));
+		}
+	}
+
+	let mut looper = create_test_looper("dispatch observer test", Box::new(WorkState {}));
+
+	let dispatched = Arc::new(Mutex::new(0));
+	let total_time = Arc::new(Mutex::new(Duration::new(0, 0)));
+	let observed_dispatched = dispatched.clone();
+	looper.set_before_dispatch(move |message| {
+		assert_eq!(message.what(), WORK);
+		*observed_dispatched.lock().unwrap() += 1;
+	});
+	let observed_total_time = total_time.clone();
+	looper.set_after_dispatch(move |_message, duration| {
+		*observed_total_time.lock().unwrap() += duration;
+	});
+
+	// Queue three messages for the handler, and a message to quit the
+	// looper, which is not dispatched to the handler
+	for _ in 0..3 {
+		let mut message = Message::new(WORK);
+		message.header.target = B_PREFERRED_TOKEN;
+		looper.message_queue.push_back(message);
+	}
+	let mut quit_message = Message::new(QUIT);
+	quit_message.header.target = B_PREFERRED_TOKEN;
+	looper.message_queue.push_back(quit_message);
+	looper.looper_task();
+
+	assert_eq!(*dispatched.lock().unwrap(), 3);
+	assert!(*total_time.lock().unwrap() >= Duration::from_millis(15));
+}
+
+#[test]
+fn test_looper_thread_name() {
+	use std::sync::mpsc::{channel, Sender};
+
+	use crate::haiku_constant;
+
+	const GET_THREAD_NAME: u32 = haiku_constant!('g', 't', 'n', 'm');
+
+	struct ThreadNameState {
+		sender: Sender<Option<String>>,
+	}
+
+	impl Handler<()> for ThreadNameState {
+		fn message_received(&mut self, _context: &Context<()>, _message: &Message) {
+			let name = thread::current().name().map(String::from);
+			self.sender.send(name).unwrap();
+		}
+	}
+
+	let (sender, receiver) = channel();
+	let state = ThreadNameState { sender };
+	let mut looper = create_test_looper("thread name test", Box::new(state));
+	looper.set_name("renamed looper");
+	assert_eq!(looper.name(), "renamed looper");
+	let messenger = looper.get_messenger();
+	looper.run().unwrap();
+
+	messenger
+		.send(Message::new(GET_THREAD_NAME), &messenger)
+		.unwrap();
+	let name = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+	assert_eq!(name.as_deref(), Some("renamed looper"));
+	messenger.send(Message::new(QUIT), &messenger).unwrap();
+}
+
+#[test]
+fn test_looper_registry_quit_all() {
+	struct IdleState {}
+
+	impl Handler<()> for IdleState {
+		fn message_received(&mut self, _context: &Context<()>, _message: &Message) {}
+	}
+
+	let registry = Arc::new(LooperRegistry::default());
+	let mut ports = Vec::new();
+	let mut threads = Vec::new();
+	for index in 0..3 {
+		let mut looper =
+			create_test_looper(&format!("registry test {}", index), Box::new(IdleState {}));
+		looper.registry = Some(registry.clone());
+		ports.push(looper.port.get_port_id());
+		threads.push(looper.run().unwrap());
+	}
+	assert_eq!(registry.controllers.lock().unwrap().len(), 3);
+
+	// A looper that quits by itself is forgotten when the next one is added
+	let messenger = registry.controllers.lock().unwrap()[0].messenger.clone();
+	messenger.send(Message::new(QUIT), &messenger).unwrap();
+	threads.remove(0).join().unwrap();
+	let mut looper = create_test_looper("registry test 3", Box::new(IdleState {}));
+	looper.registry = Some(registry.clone());
+	ports.push(looper.port.get_port_id());
+	looper.run().unwrap();
+	assert_eq!(registry.controllers.lock().unwrap().len(), 3);
+
+	registry.quit_all(Duration::from_secs(5)).unwrap();
+	assert!(registry.controllers.lock().unwrap().is_empty());
+	// The ports of the loopers are deleted when their threads end
+	for port in ports {
+		assert!(Port::from_id(port).is_none());
+	}
+}
+
+#[test]
+fn test_looper_worker_ordering() {
+	use std::thread::ThreadId;
+
+	use crate::haiku_constant;
+
+	const WORK: u32 = haiku_constant!('w', 'o', 'r', 'k');
+
+	type Log = Arc<Mutex<Vec<(usize, i32, ThreadId)>>>;
+
+	struct OrderedState {
+		id: usize,
+		log: Log,
+	}
+
+	impl Handler<()> for OrderedState {
+		fn message_received(&mut self, _context: &Context<()>, message: &Message) {
+			let sequence: i32 = message.find_data("sequence", 0).unwrap();
+			// Vary the time of the work, so that the workers interleave
+			thread::sleep(Duration::from_micros((sequence as u64 * 37) % 500));
+			let thread = thread::current().id();
+			self.log.lock().unwrap().push((self.id, sequence, thread));
+		}
+	}
+
+	let log: Log = Arc::new(Mutex::new(Vec::new()));
+	let state = OrderedState {
+		id: 0,
+		log: log.clone(),
+	};
+	let mut looper = create_test_looper("worker ordering test", Box::new(state));
+	for id in 1..4 {
+		looper.add_handler(Box::new(OrderedState {
+			id,
+			log: log.clone(),
+		}));
+	}
+	looper.set_worker_count(0);
+	assert_eq!(looper.worker_count(), 1);
+	looper.set_worker_count(4);
+	assert_eq!(looper.worker_count(), 4);
+
+	let dispatched = Arc::new(Mutex::new(0));
+	let observed_dispatched = dispatched.clone();
+	looper.set_before_dispatch(move |_message| {
+		*observed_dispatched.lock().unwrap() += 1;
+	});
+
+	let mut tokens: Vec<i32> = looper.handlers.keys().copied().collect();
+	tokens.sort();
+	for sequence in 0..100 {
+		for token in tokens.iter() {
+			let mut message = Message::new(WORK);
+			message.header.target = *token;
+			message.add_data("sequence", &sequence).unwrap();
+			looper.message_queue.push_back(message);
+		}
+	}
+	let mut quit_message = Message::new(QUIT);
+	quit_message.header.target = B_PREFERRED_TOKEN;
+	looper.message_queue.push_back(quit_message);
+	looper.looper_task();
+
+	// Every handler received its messages in order, on a single thread
+	let log = log.lock().unwrap();
+	assert_eq!(log.len(), 400);
+	let mut threads = Vec::new();
+	for id in 0..4 {
+		let entries: Vec<&(usize, i32, ThreadId)> = log.iter().filter(|e| e.0 == id).collect();
+		let sequences: Vec<i32> = entries.iter().map(|e| e.1).collect();
+		assert_eq!(sequences, (0..100).collect::<Vec<i32>>());
+		assert!(entries.iter().all(|e| e.2 == entries[0].2));
+		threads.push(entries[0].2);
+	}
+	assert_ne!(threads[0], thread::current().id());
+	threads.sort_by_key(|t| format!("{:?}", t));
+	threads.dedup();
+	assert_eq!(threads.len(), 4);
+
+	// The handlers and the observers are returned to the looper
+	assert_eq!(*dispatched.lock().unwrap(), 400);
+	assert_eq!(looper.handlers.len(), 4);
+	assert!(looper.before_dispatch.is_some());
+}
+
+#[test]
+fn test_looper_single_start() {
+	struct IdleState {}
+
+	impl Handler<()> for IdleState {
+		fn message_received(&mut self, _context: &Context<()>, _message: &Message) {}
+	}
+
+	let mut looper = create_test_looper("single start test", Box::new(IdleState {}));
+	let mut quit_message = Message::new(QUIT);
+	quit_message.header.target = B_PREFERRED_TOKEN;
+	looper.message_queue.push_back(quit_message);
+	looper.run_in_current_thread().unwrap();
+
+	// Starting the looper again, in any way, is rejected
+	let error = looper.run_in_current_thread().unwrap_err();
+	assert!(matches!(error.kind(), ErrorKind::NotAllowed));
+	let error = looper.run().unwrap_err();
+	assert!(matches!(error.kind(), ErrorKind::NotAllowed));
+}
+
+#[test]
+fn test_fn_handler() {
+	use std::sync::mpsc::channel;
+
+	use crate::haiku_constant;
+
+	const PING: u32 = haiku_constant!('p', 'i', 'n', 'g');
+
+	let (sender, receiver) = channel();
+	let state_sender = sender.clone();
+	let state = FnHandler::new(move |_context: &Context<()>, message: &Message| {
+		state_sender.send(("state", message.what())).unwrap();
+	});
+	let mut looper = create_test_looper("fn handler test", Box::new(state));
+	let mut count: u32 = 0;
+	looper.add_handler(Box::new(FnHandler::new(move |_context, message| {
+		assert_eq!(message.what(), PING);
+		count += 1;
+		sender.send(("handler", count)).unwrap();
+	})));
+	let handler_token = *looper
+		.handlers
+		.keys()
+		.find(|token| **token != looper.preferred_handler)
+		.unwrap();
+
+	let mut message = Message::new(PING);
+	message.header.target = B_PREFERRED_TOKEN;
+	looper.message_queue.push_back(message);
+	for _ in 0..2 {
+		let mut message = Message::new(PING);
+		message.header.target = handler_token;
+		looper.message_queue.push_back(message);
+	}
+	let mut quit_message = Message::new(QUIT);
+	quit_message.header.target = B_PREFERRED_TOKEN;
+	looper.message_queue.push_back(quit_message);
+	looper.looper_task();
+
+	assert_eq!(receiver.try_recv().unwrap(), ("state", PING));
+	assert_eq!(receiver.try_recv().unwrap(), ("handler", 1));
+	assert_eq!(receiver.try_recv().unwrap(), ("handler", 2));
+	assert!(receiver.try_recv().is_err());
+}
+
+#[test]
+fn test_looper_request_and_reply() {
+	use std::sync::mpsc::channel;
+
+	use libc::B_DUPLICATE_REPLY;
+
+	use crate::haiku_constant;
+
+	const REQUEST: u32 = haiku_constant!('r', 'q', 's', 't');
+	const REPLY: u32 = haiku_constant!('r', 'p', 'l', 'y');
+
+	// The server answers every request, and reports whether that worked
+	let (result_sender, results) = channel();
+	let (reply_sender, replies) = channel();
+	let server = FnHandler::new(move |_context: &Context<()>, message: &Message| {
+		let mut message = message.clone();
+		let value: i32 = message.find_data("value", 0).unwrap();
+		let mut reply = Message::new(REPLY);
+		reply.add_data("value", &(value * 2)).unwrap();
+		let result = message.reply(reply).map_err(|e| e.kind());
+		// A second reply to the same message is rejected
+		let duplicate = message
+			.reply(Message::new(REPLY))
+			.err()
+			.and_then(|e| e.raw_os_error());
+		result_sender.send((result, duplicate)).unwrap();
+	});
+	let client = FnHandler::new(move |_context: &Context<()>, message: &Message| {
+		assert!(message.is_reply());
+		let value: i32 = message.find_data("value", 0).unwrap();
+		reply_sender.send(value).unwrap();
+	});
+	let server = create_test_looper("reply server", Box::new(server));
+	let client = create_test_looper("reply client", Box::new(client));
+	let server_messenger = server.get_messenger();
+	let client_messenger = client.get_messenger();
+	server.run().unwrap();
+	client.run().unwrap();
+
+	let mut request = Message::new(REQUEST);
+	request.add_data("value", &21).unwrap();
+	server_messenger
+		.send_and_ask_reply(request, &client_messenger)
+		.unwrap();
+	let timeout = Duration::from_secs(5);
+	let (result, duplicate) = results.recv_timeout(timeout).unwrap();
+	assert!(result.is_ok());
+	assert_eq!(duplicate, Some(B_DUPLICATE_REPLY));
+	assert_eq!(replies.recv_timeout(timeout).unwrap(), 42);
+	assert!(replies.recv_timeout(Duration::from_millis(100)).is_err());
+
+	// A message that does not ask for a reply cannot be answered
+	let mut request = Message::new(REQUEST);
+	request.add_data("value", &1).unwrap();
+	server_messenger.send(request, &client_messenger).unwrap();
+	assert!(matches!(
+		results.recv_timeout(timeout).unwrap(),
+		(Err(ErrorKind::NotAllowed), None)
+	));
+
+	for messenger in [server_messenger, client_messenger] {
+		messenger.send(Message::new(QUIT), &messenger).unwrap();
+	}
+}
+
+#[test]
+fn test_looper_messenger_for() {
+	use std::sync::mpsc::channel;
+
+	use crate::haiku_constant;
+
+	const PING: u32 = haiku_constant!('p', 'i', 'n', 'g');
+
+	let (sender, receiver) = channel();
+	let state_sender = sender.clone();
+	let state = FnHandler::new(move |_context: &Context<()>, _message: &Message| {
+		state_sender.send("state").unwrap();
+	});
+	let mut looper = create_test_looper("messenger for test", Box::new(state));
+	let first_sender = sender.clone();
+	let first = looper.add_handler(Box::new(FnHandler::new(move |_context, _message| {
+		first_sender.send("first").unwrap();
+	})));
+	let second = looper.add_handler(Box::new(FnHandler::new(move |_context, _message| {
+		sender.send("second").unwrap();
+	})));
+	assert_ne!(first, second);
+	let first_messenger = looper.messenger_for(first);
+	let second_messenger = looper.messenger_for(second);
+	let messenger = looper.get_messenger();
+	looper.run().unwrap();
+
+	// Each message reaches the addressed handler
+	let timeout = Duration::from_secs(5);
+	second_messenger
+		.send(Message::new(PING), &messenger)
+		.unwrap();
+	assert_eq!(receiver.recv_timeout(timeout).unwrap(), "second");
+	first_messenger
+		.send(Message::new(PING), &messenger)
+		.unwrap();
+	assert_eq!(receiver.recv_timeout(timeout).unwrap(), "first");
+	messenger.send(Message::new(PING), &messenger).unwrap();
+	assert_eq!(receiver.recv_timeout(timeout).unwrap(), "state");
+	messenger.send(Message::new(QUIT), &messenger).unwrap();
+}
+
+#[test]
+fn test_looper_workers_run_concurrently() {
+	use std::sync::Condvar;
+
+	use crate::haiku_constant;
+
+	const WORK: u32 = haiku_constant!('w', 'o', 'r', 'k');
+
+	type Rendezvous = Arc<(Mutex<usize>, Condvar)>;
+
+	// Each handler waits until the other handler has arrived as well, which
+	// only succeeds when both run at the same time
+	struct WaitingState {
+		rendezvous: Rendezvous,
+		met: Arc<Mutex<Vec<bool>>>,
+	}
+
+	impl Handler<()> for WaitingState {
+		fn message_received(&mut self, _context: &Context<()>, _message: &Message) {
+			let (ref arrived, ref condvar) = *self.rendezvous;
+			let mut arrived = arrived.lock().unwrap();
+			*arrived += 1;
+			condvar.notify_all();
+			let (_arrived, result) = condvar
+				.wait_timeout_while(arrived, Duration::from_secs(5), |arrived| *arrived < 2)
+				.unwrap();
+			self.met.lock().unwrap().push(!result.timed_out());
+		}
+	}
+
+	let rendezvous: Rendezvous = Arc::new((Mutex::new(0), Condvar::new()));
+	let met = Arc::new(Mutex::new(Vec::new()));
+	let state = WaitingState {
+		rendezvous: rendezvous.clone(),
+		met: met.clone(),
+	};
+	let mut looper = create_test_looper("worker concurrency test", Box::new(state));
+	let token = looper.add_handler(Box::new(WaitingState {
+		rendezvous,
+		met: met.clone(),
+	}));
+	looper.set_worker_count(2);
+	// The observers do not serialize the handlers
+	looper.set_before_dispatch(|_message| {});
+	looper.set_after_dispatch(|_message, _duration| {});
+
+	for target in [B_PREFERRED_TOKEN, token] {
+		let mut message = Message::new(WORK);
+		message.header.target = target;
+		looper.message_queue.push_back(message);
+	}
+	let mut quit_message = Message::new(QUIT);
+	quit_message.header.target = B_PREFERRED_TOKEN;
+	looper.message_queue.push_back(quit_message);
+	looper.looper_task();
+
+	assert_eq!(*met.lock().unwrap(), vec![true, true]);
+}
+
+#[test]
+fn test_looper_remove_handler() {
+	use std::sync::mpsc::channel;
+
+	use crate::haiku_constant;
+
+	const PING: u32 = haiku_constant!('p', 'i', 'n', 'g');
+
+	let (sender, receiver) = channel();
+	let state_sender = sender.clone();
+	let state = FnHandler::new(move |_context: &Context<()>, _message: &Message| {
+		state_sender.send("state").unwrap();
+	});
+	let mut looper = create_test_looper("remove handler test", Box::new(state));
+	let state_token = looper.preferred_handler;
+	let token =
+		looper.add_preferred_handler(Box::new(FnHandler::new(move |_context, _message| {
+			sender.send("handler").unwrap();
+		})));
+	assert_eq!(looper.preferred_handler, token);
+
+	// The removed handler is returned, and the state is preferred again
+	assert!(looper.remove_handler(token).is_some());
+	assert!(looper.remove_handler(token).is_none());
+	assert_eq!(looper.preferred_handler, state_token);
+	// The state cannot be removed
+	assert!(looper.remove_handler(state_token).is_none());
+	assert_eq!(looper.handlers.len(), 1);
+
+	// Messages to the token of the removed handler are not dispatched
+	let mut message = Message::new(PING);
+	message.header.target = token;
+	looper.message_queue.push_back(message);
+	let mut message = Message::new(PING);
+	message.header.target = B_PREFERRED_TOKEN;
+	looper.message_queue.push_back(message);
+	let mut quit_message = Message::new(QUIT);
+	quit_message.header.target = B_PREFERRED_TOKEN;
+	looper.message_queue.push_back(quit_message);
+	looper.looper_task();
+
+	assert_eq!(receiver.try_recv().unwrap(), "state");
+	assert!(receiver.try_recv().is_err());
+}
+
+#[test]
+fn test_looper_pulse() {
+	use std::sync::mpsc::channel;
+
+	let (sender, receiver) = channel();
+	let state = FnHandler::new(move |_context: &Context<()>, message: &Message| {
+		sender.send(message.what()).unwrap();
+	});
+	let mut looper = create_test_looper("pulse test", Box::new(state));
+	assert!(looper.pulse_rate().is_none());
+	looper.set_pulse_rate(Some(Duration::from_millis(10)));
+	assert_eq!(looper.pulse_rate(), Some(Duration::from_millis(10)));
+	let messenger = looper.get_messenger();
+	let thread = looper.run().unwrap();
+
+	// The pulses are at least 10 ms apart
+	let start = system_time();
+	for _ in 0..5 {
+		let what = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+		assert_eq!(what, B_PULSE);
+	}
+	assert!(system_time() - start >= Duration::from_millis(40));
+	messenger.send(Message::new(QUIT), &messenger).unwrap();
+	thread.join().unwrap();
+}
+
+#[test]
+fn test_looper_common_filter() {
+	use std::sync::mpsc::channel;
+
+	use crate::haiku_constant;
+
+	const KEEP: u32 = haiku_constant!('k', 'e', 'e', 'p');
+	const DROP: u32 = haiku_constant!('d', 'r', 'o', 'p');
+
+	struct DropFilter {}
+
+	impl MessageFilter for DropFilter {
+		fn filter(&mut self, message: &mut Message) -> FilterResult {
+			if message.what() == DROP {
+				FilterResult::Skip
+			} else {
+				FilterResult::Dispatch
+			}
+		}
+	}
+
+	// The second filter marks the messages that it sees
+	struct MarkFilter {}
+
+	impl MessageFilter for MarkFilter {
+		fn filter(&mut self, message: &mut Message) -> FilterResult {
+			message.add_data("marked", &true).unwrap();
+			FilterResult::Dispatch
+		}
+	}
+
+	let (sender, receiver) = channel();
+	let state = FnHandler::new(move |_context: &Context<()>, message: &Message| {
+		let marked = message.find_data::<bool>("marked", 0).unwrap_or(false);
+		sender.send((message.what(), marked)).unwrap();
+	});
+	let mut looper = create_test_looper("common filter test", Box::new(state));
+	looper.add_common_filter(Box::new(DropFilter {}));
+	looper.add_common_filter(Box::new(MarkFilter {}));
+
+	for what in [KEEP, DROP, KEEP, DROP, QUIT] {
+		let mut message = Message::new(what);
+		message.header.target = B_PREFERRED_TOKEN;
+		looper.message_queue.push_back(message);
+	}
+	looper.looper_task();
+
+	assert_eq!(receiver.try_recv().unwrap(), (KEEP, true));
+	assert_eq!(receiver.try_recv().unwrap(), (KEEP, true));
+	assert!(receiver.try_recv().is_err());
+}
+
+#[test]
+fn test_post_delayed() {
+	use std::time::Instant;
+
+	use crate::haiku_constant;
+
+	const DELAYED: u32 = haiku_constant!('d', 'l', 'a', 'y');
+	let port = Port::create("post_delayed_test", 10).unwrap();
+	let looper = LooperDelegate {
+		messenger: Messenger::from_port(&port).unwrap(),
+	};
+
+	let start = Instant::now();
+	let delay = Duration::from_millis(50);
+	looper.post_delayed(Message::new(DELAYED), delay);
+	assert_eq!(port.get_count().unwrap(), 0);
+	let (_, buffer) = port.read().unwrap();
+	assert!(start.elapsed() >= delay);
+	assert_eq!(
+		Message::try_from(buffer.as_slice()).unwrap().what(),
+		DELAYED
+	);
+}
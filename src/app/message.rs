@@ -5,16 +5,29 @@
 
 use std::char;
 use std::fmt;
-use std::mem::{size_of, transmute_copy, MaybeUninit};
+use std::mem::{size_of, MaybeUninit};
+use std::path::{Path, PathBuf};
 use std::ptr;
 use std::slice::from_raw_parts;
 use std::str;
 
-use libc::{find_thread, get_thread_info, thread_info, B_ANY_TYPE, B_MESSAGE_TYPE, B_OK};
+use libc::{
+	find_thread, get_thread_info, port_id, thread_info, B_ANY_TYPE, B_BOOL_TYPE, B_DOUBLE_TYPE,
+	B_DUPLICATE_REPLY, B_FLOAT_TYPE, B_INT16_TYPE, B_INT32_TYPE, B_INT64_TYPE, B_INT8_TYPE,
+	B_MESSAGE_TYPE, B_OBJECT_TYPE, B_OK, B_PAGE_SIZE, B_POINTER_TYPE, B_RAW_TYPE, B_READ_AREA,
+	B_STRING_TYPE, B_UINT16_TYPE, B_UINT32_TYPE, B_UINT64_TYPE, B_UINT8_TYPE, B_WRITE_AREA,
+};
 
+use crate::app::application::get_current_team_and_thread;
+use crate::app::constants::B_REFS_RECEIVED;
 use crate::app::sys::*;
 use crate::app::Messenger;
-use crate::support::{ErrorKind, Flattenable, HaikuError, Result};
+use crate::kernel::areas::Area;
+use crate::kernel::helpers::get_path_for_entry_ref;
+use crate::kernel::teams::Team;
+use crate::storage::dump_raw_data;
+use crate::storage::sys::entry_ref;
+use crate::support::{swap_data, ErrorKind, Flattenable, HaikuError, Result};
 
 /// A rustean representation of a BMessage
 ///
@@ -32,12 +45,96 @@ use crate::support::{ErrorKind, Flattenable, HaikuError, Result};
 ///
 /// In order to read the data, you may use the `find_data()` method
 ///
+/// Messages cannot carry open file descriptors. To hand a file to another
+/// team, add a reference to it with `add_path()`, and let the receiver open
+/// the path that it gets from `find_path()`.
+///
 /// Further manipulation of the data can be done with the `remove_data()` and
 /// `remove_field()` methods.
+#[derive(Clone)]
 pub struct Message {
 	pub(crate) header: message_header,
 	fields: Vec<field_header>,
 	data: Vec<u8>,
+	index: FieldIndex,
+}
+
+// Messages with more data than this are sent with their fields and data in
+// an area, like BMessage does
+const PASS_BY_AREA_THRESHOLD: usize = B_PAGE_SIZE * 10;
+// The name of the area that carries the body of a message that is passed by
+// area. This is the name that BMessage::_FlattenToArea() gives it, so that
+// messages from native senders can be read.
+const MESSAGE_AREA_NAME: &str = "BMessage data";
+
+// The number of fields per bucket at which the field index grows
+const FIELD_INDEX_LOAD_FACTOR: usize = 2;
+
+// A hash table to find the fields of a message by name
+//
+// The header of a message also contains a hash table, but its size of five
+// buckets is part of the wire format, so with many fields, the chains in that
+// table become long. The header table is still maintained for the receivers
+// of the message, but lookups use this table, which grows with the number of
+// fields. Every field has an entry with its hash and the next field in its
+// bucket.
+#[derive(Clone)]
+struct FieldIndex {
+	buckets: Vec<i32>,
+	entries: Vec<(u32, i32)>,
+}
+
+impl FieldIndex {
+	fn new() -> FieldIndex {
+		FieldIndex {
+			buckets: vec![-1; MESSAGE_BODY_HASH_TABLE_SIZE],
+			entries: Vec::new(),
+		}
+	}
+
+	// Add the next field, and grow the table when it gets too full
+	fn push(&mut self, hash: u32) {
+		if self.entries.len() >= self.buckets.len() * FIELD_INDEX_LOAD_FACTOR {
+			let size = self.buckets.len() * 2 + 1;
+			self.rehash(size);
+		}
+		let bucket = hash as usize % self.buckets.len();
+		self.entries.push((hash, self.buckets[bucket]));
+		self.buckets[bucket] = (self.entries.len() - 1) as i32;
+	}
+
+	fn rehash(&mut self, size: usize) {
+		self.buckets = vec![-1; size];
+		for (index, entry) in self.entries.iter_mut().enumerate() {
+			let bucket = entry.0 as usize % size;
+			entry.1 = self.buckets[bucket];
+			self.buckets[bucket] = index as i32;
+		}
+	}
+
+	// Rebuild the table for a new list of hashes
+	fn rebuild(&mut self, hashes: impl Iterator<Item = u32>) {
+		*self = FieldIndex::new();
+		for hash in hashes {
+			self.push(hash);
+		}
+	}
+
+	// Iterate over the indexes of the fields that may have this hash
+	fn candidates(&self, hash: u32) -> impl Iterator<Item = usize> + '_ {
+		let mut next = self.buckets[hash as usize % self.buckets.len()];
+		std::iter::from_fn(move || {
+			while next >= 0 {
+				let index = next as usize;
+				let entry = self.entries[index];
+				next = entry.1;
+				if entry.0 == hash {
+					return Some(index);
+				}
+			}
+			None
+		})
+	}
 }
 
 impl Message {
@@ -61,7 +158,36 @@ impl Message {
 			},
 			fields: Vec::new(),
 			data: Vec::new(),
+			index: FieldIndex::new(),
+		}
+	}
+
+	/// Create a new message with the signature `what` and a list of fields
+	///
+	/// The fields are added in order, so a name that occurs more than once
+	/// gets multiple values. The error of the first value that could not be
+	/// added is returned. This is the implementation of the `message!` macro,
+	/// which is the public interface.
+	#[doc(hidden)]
+	pub fn with_fields(what: u32, fields: &[(&str, &dyn MessageValue)]) -> Result<Self> {
+		let mut message = Message::new(what);
+		for (name, value) in fields {
+			value.add_to(&mut message, name)?;
+		}
+		Ok(message)
+	}
+
+	/// Create a `B_REFS_RECEIVED` message with references to files
+	///
+	/// This is the message that asks an application to open files. Every
+	/// path is added as an entry ref to the `refs` field. The parent
+	/// directory of each path must exist, otherwise an error is returned.
+	pub fn refs_received(paths: &[&Path]) -> Result<Message> {
+		let mut message = Message::new(B_REFS_RECEIVED);
+		for path in paths {
+			message.add_data("refs", &entry_ref::from_path(path)?)?;
 		}
+		Ok(message)
 	}
 
 	/// Get the current identifier of the message
@@ -84,26 +210,41 @@ impl Message {
 	/// This method will return an error of `ErrorKind::InvalidInput` when you
 	/// are trying to add data to an existing identifier, with a different type.
 	pub fn add_data<T: Flattenable<T>>(&mut self, name: &str, data: &T) -> Result<()> {
-		if self.header.message_area > 0 {
-			// Todo: implement support for messages with areas
-			unimplemented!()
-		}
+		self.add_flattened_data(name, T::type_code(), T::is_fixed_size(), &data.flatten())
+	}
 
-		let field_index = match self.find_field(name, T::type_code()) {
+	/// Add a buffer of bytes to the message as `B_RAW_TYPE` data
+	///
+	/// This method takes ownership of the `data`, and stores it under `name`
+	/// as variable sized raw data. The resulting message is identical to
+	/// adding a `Vec<u8>` through `add_data()`, but it skips flattening
+	/// the data into an intermediate buffer. Note that the bytes are still
+	/// copied once, into the internal buffer of the message.
+	///
+	/// Like `add_data()`, this will return an error of `ErrorKind::InvalidInput`
+	/// when `name` already contains data of another type.
+	pub fn adopt_raw(&mut self, name: &str, data: Vec<u8>) -> Result<()> {
+		self.add_flattened_data(name, B_RAW_TYPE, false, &data)
+	}
+
+	fn add_flattened_data(
+		&mut self,
+		name: &str,
+		type_code: u32,
+		is_fixed_size: bool,
+		data: &[u8],
+	) -> Result<()> {
+		let field_index = match self.find_field(name, type_code) {
 			Ok(index) => index,
 			Err(err) => match err.kind() {
-				ErrorKind::NotFound => self.add_field(name, T::type_code(), T::is_fixed_size()),
+				ErrorKind::NotFound => self.add_field(name, type_code, is_fixed_size),
 				_ => return Err(err),
 			},
 		};
 
 		// Prepare the buffer for the copying of data
-		let data_size = data.flattened_size();
-		let data_size_info = if T::is_fixed_size() {
-			0
-		} else {
-			size_of::<u32>()
-		};
+		let data_size = data.len();
+		let data_size_info = if is_fixed_size { 0 } else { size_of::<u32>() };
 		let mut offset = {
 			// Don't get a mutable field_header here just yet, as update_offsets
 			// needs mutable references
@@ -115,17 +256,19 @@ impl Message {
 		self.update_offsets(offset, (data_size + data_size_info) as isize);
 
 		// Actually copy the data
-		// Note that there might be room for optimization by using ptr::copy
-		// instead of the vector functions, especially when the field has a
-		// variable size, as that now does two moves.
-		if !T::is_fixed_size() {
+		// If the field is the last one, the data can be appended directly,
+		// otherwise it will have to be inserted in between the existing data.
+		if !is_fixed_size {
 			let data_size_vec = (data_size as u32).flatten();
 			self.data
 				.splice(offset..offset, data_size_vec.iter().cloned());
 			offset += size_of::<u32>();
 		}
-		let data = data.flatten();
-		self.data.splice(offset..offset, data.iter().cloned());
+		if offset == self.data.len() {
+			self.data.extend_from_slice(data);
+		} else {
+			self.data.splice(offset..offset, data.iter().cloned());
+		}
 
 		// Update the headers
 		let field_header = self.fields.get_mut(field_index).unwrap();
@@ -147,7 +290,130 @@ impl Message {
 	/// Additionally, if the `index` is out of range, it will return
 	/// `ErrorKind::InvalidInput`.
 	pub fn find_data<T: Flattenable<T>>(&self, name: &str, index: usize) -> Result<T> {
-		let field_index = match self.find_field(name, T::type_code()) {
+		T::unflatten(self.find_item(name, T::type_code(), index)?)
+	}
+
+	/// Borrow the raw bytes of an object that is stored in the message
+	///
+	/// This is the zero-copy variant of `find_data()`. The returned slice
+	/// contains the flattened data of the item at `index`, without the size
+	/// prefix that variable sized items have. The type of the field is not
+	/// checked, use `get_info()` if you need to know it.
+	///
+	/// The errors are the same as the errors of `find_data()`.
+	pub fn find_data_bytes(&self, name: &str, index: usize) -> Result<&[u8]> {
+		self.find_item(name, B_ANY_TYPE, index)
+	}
+
+	/// Borrow the bytes of raw data that is stored in the message
+	///
+	/// This is the zero-copy variant of `find_data::<Vec<u8>>()`. Unlike
+	/// `find_data_bytes()`, the field must contain `B_RAW_TYPE` data, like
+	/// the data that is added with `adopt_raw()`.
+	///
+	/// The errors are the same as the errors of `find_data()`.
+	pub fn find_data_raw(&self, name: &str, index: usize) -> Result<&[u8]> {
+		self.find_item(name, B_RAW_TYPE, index)
+	}
+
+	/// Add a reference to a file to the message
+	///
+	/// Haiku cannot pass open file descriptors between teams through
+	/// messages. Instead, the file is added as an entry ref, which the
+	/// receiver can turn back into a path with `find_path()`, and then open
+	/// it again. The receiver needs to have the permissions to open the file.
+	///
+	/// The parent directory of the `path` must exist, the file itself does
+	/// not need to exist.
+	pub fn add_path(&mut self, name: &str, path: &Path) -> Result<()> {
+		self.add_data(name, &entry_ref::from_path(path)?)
+	}
+
+	/// Get the path of a file reference that is stored in the message
+	///
+	/// This works for references that are added with `add_path()`, as well
+	/// as entry refs that are sent by other applications, such as the `refs`
+	/// in a `B_REFS_RECEIVED` message.
+	pub fn find_path(&self, name: &str, index: usize) -> Result<PathBuf> {
+		let entry: entry_ref = self.find_data(name, index)?;
+		let path = get_path_for_entry_ref(entry.device, entry.directory, entry.name.as_ptr())?;
+		Ok(PathBuf::from(path))
+	}
+
+	/// Add a string to the message
+	///
+	/// This is a shortcut for adding a `String` with `add_data()`, without
+	/// the need to create a `String` first. Haiku stores strings as C
+	/// strings, so this returns an error of `ErrorKind::InvalidInput` if the
+	/// `string` contains a nul character.
+	pub fn add_string(&mut self, name: &str, string: &str) -> Result<()> {
+		if string.as_bytes().contains(&0) {
+			return Err(HaikuError::new(
+				ErrorKind::InvalidInput,
+				"the string contains a nul character",
+			));
+		}
+		let mut data = Vec::with_capacity(string.len() + 1);
+		data.extend_from_slice(string.as_bytes());
+		data.push(0);
+		self.add_flattened_data(name, B_STRING_TYPE, false, &data)
+	}
+
+	/// Get a string from the message
+	///
+	/// This is a shortcut for `find_data::<String>()`.
+	pub fn find_string(&self, name: &str, index: usize) -> Result<String> {
+		self.find_data(name, index)
+	}
+
+	/// Add a 32 bit integer to the message
+	pub fn add_int32(&mut self, name: &str, value: i32) -> Result<()> {
+		self.add_data(name, &value)
+	}
+
+	/// Get a 32 bit integer from the message
+	pub fn find_int32(&self, name: &str, index: usize) -> Result<i32> {
+		self.find_data(name, index)
+	}
+
+	/// Add a boolean to the message
+	pub fn add_bool(&mut self, name: &str, value: bool) -> Result<()> {
+		self.add_data(name, &value)
+	}
+
+	/// Get a boolean from the message
+	pub fn find_bool(&self, name: &str, index: usize) -> Result<bool> {
+		self.find_data(name, index)
+	}
+
+	/// Add a float to the message
+	pub fn add_float(&mut self, name: &str, value: f32) -> Result<()> {
+		self.add_data(name, &value)
+	}
+
+	/// Get a float from the message
+	pub fn find_float(&self, name: &str, index: usize) -> Result<f32> {
+		self.find_data(name, index)
+	}
+
+	/// Add a message to the message
+	///
+	/// The `message` is flattened and stored under `name` with the type
+	/// `B_MESSAGE_TYPE`. Like other data, more messages can be added to the
+	/// same `name`.
+	pub fn add_message(&mut self, name: &str, message: &Message) -> Result<()> {
+		self.add_data(name, message)
+	}
+
+	/// Get a message that is stored in the message
+	///
+	/// This is a shortcut for `find_data::<Message>()`.
+	pub fn find_message(&self, name: &str, index: usize) -> Result<Message> {
+		self.find_data(name, index)
+	}
+
+	fn find_item(&self, name: &str, type_code: u32, index: usize) -> Result<&[u8]> {
+		let field_index = match self.find_field(name, type_code) {
 			Ok(index) => index,
 			Err(_) => return Err(HaikuError::from(ErrorKind::NotFound)),
 		};
@@ -159,30 +425,34 @@ impl Message {
 			));
 		}
 
+		// Pointers are only valid within the team that added them
+		if (field_header.field_type == B_POINTER_TYPE || field_header.field_type == B_OBJECT_TYPE)
+			&& self.is_from_other_team()
+		{
+			return Err(HaikuError::new(
+				ErrorKind::NotAllowed,
+				"pointer types are not transferable between teams",
+			));
+		}
+
 		if (field_header.flags & FIELD_FLAG_FIXED_SIZE) != 0 {
 			let item_size: usize = (field_header.data_size / field_header.count) as usize;
 			let offset: usize = (field_header.offset + field_header.name_length as u32) as usize
 				+ index * item_size;
-			T::unflatten(&self.data[offset..offset + item_size])
+			Ok(&self.data[offset..offset + item_size])
 		} else {
-			let mut offset: usize =
-				(field_header.offset + field_header.name_length as u32) as usize;
-			let mut item_size: usize = 0;
-			for _ in 0..index {
-				// this loop will set offset to the beginning of the data that we want to read.
-				// with index 0 it should at least skip the first 4 bytes (u32) that show the item size
-				offset += item_size;
-				item_size =
-					u32::unflatten(&self.data[offset..offset + size_of::<u32>()]).unwrap() as usize;
-				offset += size_of::<u32>();
-			}
+			let (offset, item_size) = variable_size_item(
+				&self.data,
+				(field_header.offset + field_header.name_length as u32) as usize,
+				index,
+			);
 			if item_size == 0 {
 				return Err(HaikuError::new(
 					ErrorKind::InvalidData,
 					"item size at index is garbage",
 				));
 			}
-			T::unflatten(&self.data[offset..offset + item_size])
+			Ok(&self.data[offset..offset + item_size])
 		}
 	}
 
@@ -201,11 +471,6 @@ impl Message {
 		index: usize,
 		data: &T,
 	) -> Result<()> {
-		if self.header.message_area > 0 {
-			// Todo: implement support for messages with areas
-			unimplemented!()
-		}
-
 		let field_index = match self.find_field(name, T::type_code()) {
 			Ok(index) => index,
 			Err(_) => return Err(HaikuError::from(ErrorKind::NotFound)),
@@ -228,17 +493,11 @@ impl Message {
 					.splice(offset..(offset + item_size), data_vec.iter().cloned());
 				(offset, 0)
 			} else {
-				let mut offset: usize =
-					(field_header.offset + field_header.name_length as u32) as usize;
-				let mut item_size: usize = 0;
-				for _ in 0..=index {
-					// this loop will set offset to the beginning of the data that we want to read.
-					// with index 0 it should at least skip the first 4 bytes (u32) that show the item size
-					offset += item_size;
-					item_size = u32::unflatten(&self.data[offset..offset + size_of::<u32>()])
-						.unwrap() as usize;
-					offset += size_of::<u32>();
-				}
+				let (offset, item_size) = variable_size_item(
+					&self.data,
+					(field_header.offset + field_header.name_length as u32) as usize,
+					index,
+				);
 				// replace the item size with the new item size
 				let new_data_size = data.flattened_size();
 				let data_size_vec = (new_data_size as u32).flatten();
@@ -272,11 +531,6 @@ impl Message {
 	/// This will return `ErrorKind::NotFound` when the identifier does not
 	/// exist, and `ErrorKind::InvalidInput` when the `index` is out of range.
 	pub fn remove_data(&mut self, name: &str, index: usize) -> Result<()> {
-		if self.header.message_area > 0 {
-			// Todo: implement support for messages with areas
-			unimplemented!()
-		}
-
 		let field_index = match self.find_field(name, B_ANY_TYPE) {
 			Ok(index) => index,
 			Err(_) => return Err(HaikuError::from(ErrorKind::NotFound)),
@@ -305,17 +559,11 @@ impl Message {
 				field_header.count -= 1;
 				(offset, offset + item_size)
 			} else {
-				let mut offset: usize =
-					(field_header.offset + field_header.name_length as u32) as usize;
-				let mut item_size: usize = 0;
-				for _ in 0..=index {
-					// this loop will set offset to the beginning of the data that we want to read.
-					// with index 0 it should at least skip the first 4 bytes (u32) that show the item size
-					offset += item_size;
-					item_size = u32::unflatten(&self.data[offset..offset + size_of::<u32>()])
-						.unwrap() as usize;
-					offset += size_of::<u32>();
-				}
+				let (offset, item_size) = variable_size_item(
+					&self.data,
+					(field_header.offset + field_header.name_length as u32) as usize,
+					index,
+				);
 
 				if item_size == 0 {
 					return Err(HaikuError::new(
@@ -343,10 +591,6 @@ impl Message {
 	/// This removes all data stored at the identfier `name`. It will return
 	/// `ErrorKind::NotFound` if there is no data stored at `name`.
 	pub fn remove_field(&mut self, name: &str) -> Result<()> {
-		if self.header.message_area > 0 {
-			// Todo: implement support for messages with areas
-			unimplemented!()
-		}
 		let field_index = match self.find_field(name, B_ANY_TYPE) {
 			Ok(index) => index,
 			Err(_) => return Err(HaikuError::from(ErrorKind::NotFound)),
@@ -408,7 +652,70 @@ impl Message {
 		// Update the header count
 		self.header.field_count = self.header.field_count - 1;
 		self.header.data_size = ((self.header.data_size as isize) + change) as u32;
+		self.rebuild_index();
+
+		Ok(())
+	}
+
+	/// Remove all the fields from the message
+	///
+	/// The `what` code and the flags of the message are kept. This makes it
+	/// possible to reuse a message, for example to send a similar message in
+	/// a loop.
+	pub fn make_empty(&mut self) {
+		self.fields.clear();
+		self.data.clear();
+		self.header.data_size = 0;
+		self.header.field_count = 0;
+		self.header.hash_table = [-1; MESSAGE_BODY_HASH_TABLE_SIZE];
+		self.index = FieldIndex::new();
+	}
+
+	/// Copy all the fields of `other` into this message
+	///
+	/// When this message does not have a field with the same name, it is
+	/// added. Otherwise, if `overwrite` is `true`, the existing field is
+	/// replaced by the one in `other`, and if it is `false`, the items of
+	/// `other` are appended to the existing field.
+	///
+	/// When appending, the fields with the same name must have the same type.
+	/// Otherwise, `ErrorKind::InvalidInput` is returned, and this message is
+	/// left unchanged.
+	pub fn merge_from(&mut self, other: &Message, overwrite: bool) -> Result<()> {
+		let mut fields = Vec::with_capacity(other.fields.len());
+		for field_header in other.fields.iter() {
+			let name = match str::from_utf8(other.field_name(field_header)) {
+				Ok(name) => name,
+				Err(_) => {
+					return Err(HaikuError::new(
+						ErrorKind::InvalidData,
+						"the name of a field contains invalid characters",
+					))
+				}
+			};
+			if !overwrite {
+				if let Some((type_code, _, _)) = self.get_info(name) {
+					if type_code != field_header.field_type {
+						return Err(HaikuError::new(
+							ErrorKind::InvalidInput,
+							"the messages contain a field with the same name, but a different type",
+						));
+					}
+				}
+			}
+			fields.push((name, field_header));
+		}
 
+		for (name, field_header) in fields {
+			if overwrite && self.get_info(name).is_some() {
+				self.remove_field(name)?;
+			}
+			let is_fixed_size = (field_header.flags & FIELD_FLAG_FIXED_SIZE) != 0;
+			for index in 0..field_header.count as usize {
+				let data = other.find_item(name, field_header.field_type, index)?;
+				self.add_flattened_data(name, field_header.field_type, is_fixed_size, data)?;
+			}
+		}
 		Ok(())
 	}
 
@@ -429,6 +736,106 @@ impl Message {
 		))
 	}
 
+	/// Iterate over the fields with a particular type
+	///
+	/// The iterator returns the name and the number of items of every field
+	/// that has the type `type_code`. Use `B_ANY_TYPE` to iterate over all
+	/// the fields. The fields are returned in the order in which they were
+	/// added to the message.
+	pub fn fields_of_type(&self, type_code: u32) -> impl Iterator<Item = (&str, usize)> + '_ {
+		self.names()
+			.filter(move |&(_, field_type, _)| type_code == B_ANY_TYPE || field_type == type_code)
+			.map(|(name, _, count)| (name, count))
+	}
+
+	/// Iterate over all the fields in the message
+	///
+	/// The iterator returns the name, the type code and the number of items
+	/// of every field, in the order in which they were added to the message.
+	/// This makes it possible to inspect a message without knowing what is
+	/// in it.
+	pub fn names(&self) -> impl Iterator<Item = (&str, u32, usize)> + '_ {
+		self.fields.iter().filter_map(move |field| {
+			str::from_utf8(self.field_name(field))
+				.ok()
+				.map(|name| (name, field.field_type, field.count as usize))
+		})
+	}
+
+	/// Flatten the message into an existing buffer
+	///
+	/// The buffer is cleared, and then filled with the flattened message.
+	/// This is the same data that `flatten()` returns, but it reuses the
+	/// allocation of the buffer. When a buffer is used to flatten many
+	/// messages, it only needs to grow when a message is bigger than any of
+	/// the previous ones.
+	pub fn flatten_into(&self, buffer: &mut Vec<u8>) {
+		buffer.clear();
+		buffer.reserve(self.flattened_size());
+		let message_header_bytes: &[u8] = unsafe {
+			from_raw_parts(
+				(&self.header as *const message_header) as *const u8,
+				size_of::<message_header>(),
+			)
+		};
+		buffer.extend_from_slice(message_header_bytes);
+		let field_header_bytes: &[u8] = unsafe {
+			from_raw_parts(
+				self.fields.as_ptr() as *const u8,
+				size_of::<field_header>() * self.fields.len(),
+			)
+		};
+		buffer.extend_from_slice(field_header_bytes);
+		buffer.extend_from_slice(&self.data);
+	}
+
+	// Check if the message is too big to be sent through a port
+	pub(crate) fn should_pass_by_area(&self) -> bool {
+		self.data.len() > PASS_BY_AREA_THRESHOLD
+	}
+
+	// Flatten the message for sending it to a port of the `target` team.
+	// The fields and the data are copied into an area, which is transferred
+	// to the target. The buffer is filled with only the header, which
+	// refers to the area. The receiver picks up the area in
+	// `unflatten_received()`.
+	pub(crate) fn flatten_to_area(&self, buffer: &mut Vec<u8>, target: &Team) -> Result<()> {
+		let field_header_bytes: &[u8] = unsafe {
+			from_raw_parts(
+				self.fields.as_ptr() as *const u8,
+				size_of::<field_header>() * self.fields.len(),
+			)
+		};
+		let body_size = field_header_bytes.len() + self.data.len();
+		let mut area = Area::create(MESSAGE_AREA_NAME, body_size, B_READ_AREA | B_WRITE_AREA)?;
+		let (area_fields, area_data) =
+			area.as_mut_slice()?[..body_size].split_at_mut(field_header_bytes.len());
+		area_fields.copy_from_slice(field_header_bytes);
+		area_data.copy_from_slice(&self.data);
+
+		let mut header = self.header;
+		header.message_area = area.transfer(target)?;
+		header.flags |= MESSAGE_FLAG_PASS_BY_AREA;
+		let message_header_bytes: &[u8] = unsafe {
+			from_raw_parts(
+				(&header as *const message_header) as *const u8,
+				size_of::<message_header>(),
+			)
+		};
+		buffer.clear();
+		buffer.extend_from_slice(message_header_bytes);
+		Ok(())
+	}
+
+	/// Get the size of the message when it is sent
+	///
+	/// This is the number of bytes that the flattened message will take. It
+	/// can be used to pre-size buffers, or to decide how to transport the
+	/// message, without flattening it first.
+	pub fn wire_size(&self) -> usize {
+		self.flattened_size()
+	}
+
 	/// Check if the message has data associated with it
 	pub fn is_empty(&self) -> bool {
 		self.fields.len() == 0
@@ -497,9 +904,115 @@ impl Message {
 		Messenger::from_port_id(self.header.reply_port)
 	}
 
-	fn hash_name(&self, name: &str) -> u32 {
+	/// Create a reply to this message, and a Messenger to send it with
+	///
+	/// The reply is a new message with the identifier `what`, that is
+	/// marked as a reply. The Messenger points to the Handler that the
+	/// sender asked the reply to go to. Add any data to the reply, and then
+	/// send it with the Messenger.
+	///
+	/// This returns `None` if the sender did not ask for a reply, or if the
+	/// port for the reply no longer exists.
+	pub fn make_reply(&self, what: u32) -> Option<(Message, Messenger)> {
+		if (self.header.flags & MESSAGE_FLAG_REPLY_REQUIRED) == 0
+			|| (self.header.flags & MESSAGE_FLAG_REPLY_DONE) != 0
+		{
+			return None;
+		}
+		let mut messenger = self.get_return_address()?;
+		if self.header.reply_target >= 0 {
+			messenger.set_token(self.header.reply_target);
+		}
+		let mut reply = Message::new(what);
+		reply.header.flags |= MESSAGE_FLAG_IS_REPLY;
+		Some((reply, messenger))
+	}
+
+	/// Send a reply to the sender of this message
+	///
+	/// The `reply` is sent to the Handler that the sender asked the reply to
+	/// go to, using `Messenger::send_reply()`. This is the usual way for a
+	/// Handler to answer a request in `message_received()`. Handlers get a
+	/// shared reference to the message, so they reply from a clone.
+	///
+	/// A message can be answered once. This returns an error of
+	/// `ErrorKind::NotAllowed` if the sender did not ask for a reply, an
+	/// error with the raw OS error `B_DUPLICATE_REPLY` if the message was
+	/// already answered, and `ErrorKind::NotFound` if the port for the reply
+	/// no longer exists.
+	pub fn reply(&mut self, reply: Message) -> Result<()> {
+		if (self.header.flags & MESSAGE_FLAG_REPLY_REQUIRED) == 0 {
+			return Err(HaikuError::new(
+				ErrorKind::NotAllowed,
+				"the sender did not ask for a reply",
+			));
+		}
+		if (self.header.flags & MESSAGE_FLAG_REPLY_DONE) != 0 {
+			return Err(HaikuError::from_raw_os_error(B_DUPLICATE_REPLY));
+		}
+		let mut messenger = self.get_return_address().ok_or_else(|| {
+			HaikuError::new(
+				ErrorKind::NotFound,
+				"the port for the reply no longer exists",
+			)
+		})?;
+		if self.header.reply_target >= 0 {
+			messenger.set_token(self.header.reply_target);
+		}
+		messenger.send_reply(reply)?;
+		self.header.flags |= MESSAGE_FLAG_REPLY_DONE;
+		Ok(())
+	}
+
+	/// Get the token of the Handler that this message was sent to
+	///
+	/// This returns `None` when the message was not aimed at a specific
+	/// Handler, for example when it was sent to the preferred Handler of a
+	/// Looper.
+	pub fn target_token(&self) -> Option<i32> {
+		if self.header.target < 0 {
+			None
+		} else {
+			Some(self.header.target)
+		}
+	}
+
+	/// Get the port that replies to this message should be sent to
+	///
+	/// This returns `None` if the message was not delivered through a
+	/// messenger, or if the sender did not set a reply port.
+	pub fn reply_port(&self) -> Option<port_id> {
+		if (self.header.flags & MESSAGE_FLAG_WAS_DELIVERED) == 0 || self.header.reply_port < 0 {
+			None
+		} else {
+			Some(self.header.reply_port)
+		}
+	}
+
+	/// Get the team that sent this message
+	///
+	/// This returns `None` if the message was not delivered through a
+	/// messenger. Note that the team may no longer be running.
+	pub fn reply_team(&self) -> Option<Team> {
+		if (self.header.flags & MESSAGE_FLAG_WAS_DELIVERED) == 0 {
+			None
+		} else {
+			Team::from(self.header.reply_team)
+		}
+	}
+
+	// Check if the message was delivered by another team
+	fn is_from_other_team(&self) -> bool {
+		if (self.header.flags & MESSAGE_FLAG_WAS_DELIVERED) == 0 {
+			return false;
+		}
+		let (team, _) = get_current_team_and_thread();
+		self.header.reply_team != team
+	}
+
+	fn hash_name(&self, name: &[u8]) -> u32 {
 		let mut result: u32 = 0;
-		for byte in name.bytes() {
+		for &byte in name {
 			result = (result << 7) ^ (result >> 24);
 			result ^= byte as u32;
 		}
@@ -508,6 +1021,58 @@ impl Message {
 		result
 	}
 
+	// Swap the byte order of the data of a message from a system with the
+	// other byte order. The size of every item of a variable sized field is
+	// swapped, and the values are swapped with swap_data(). The data of
+	// types that swap_data() does not know, is left as it is.
+	fn swap_field_data(&mut self) -> Result<()> {
+		let corrupt = || HaikuError::new(ErrorKind::InvalidData, "the message data is corrupt");
+		for field in self.fields.iter() {
+			let start = field.offset as usize + field.name_length as usize;
+			let end = start + field.data_size as usize;
+			let data = self.data.get_mut(start..end).ok_or_else(corrupt)?;
+			if (field.flags & FIELD_FLAG_FIXED_SIZE) != 0 {
+				let _ = swap_data(field.field_type, data);
+				continue;
+			}
+			let mut offset = 0;
+			for _ in 0..field.count {
+				let size_data = data
+					.get_mut(offset..offset + size_of::<u32>())
+					.ok_or_else(corrupt)?;
+				size_data.reverse();
+				let size = u32::unflatten(size_data)? as usize;
+				offset += size_of::<u32>();
+				let item = data.get_mut(offset..offset + size).ok_or_else(corrupt)?;
+				let _ = swap_data(field.field_type, item);
+				offset += size;
+			}
+		}
+		Ok(())
+	}
+
+	fn rebuild_index(&mut self) {
+		// The fields of a received message may be corrupt, so do not assume
+		// that the names are valid
+		let hashes: Vec<u32> = self
+			.fields
+			.iter()
+			.map(|field| {
+				let start = field.offset as usize;
+				let end = start + (field.name_length as usize).saturating_sub(1);
+				self.hash_name(self.data.get(start..end).unwrap_or_default())
+			})
+			.collect();
+		self.index.rebuild(hashes.into_iter());
+	}
+
+	// Get the name of a field, without the trailing \0
+	fn field_name(&self, field: &field_header) -> &[u8] {
+		let start = field.offset as usize;
+		let end = start + field.name_length as usize - 1;
+		&self.data[start..end]
+	}
+
 	/// Find the field, returns ErrorKind::NotFound if the field does not
 	/// exist, or ErrorKind::InvalidInput in the case that the field exists
 	/// but has a different type.
@@ -520,21 +1085,16 @@ impl Message {
 			return Err(HaikuError::from(ErrorKind::NotFound));
 		}
 
-		let hash = self.hash_name(name) % self.header.hash_table_size;
-		let mut next_index = self.header.hash_table[hash as usize];
-		while next_index >= 0 {
-			let field = &self.fields[next_index as usize];
-			let start = field.offset as usize;
-			let end = (field.offset + field.name_length as u32 - 1) as usize; // do not add trailing \0 to range
-			if *name.as_bytes() == self.data[start..end] {
+		let hash = self.hash_name(name.as_bytes());
+		for index in self.index.candidates(hash) {
+			let field = &self.fields[index];
+			if *name.as_bytes() == *self.field_name(field) {
 				if field.field_type == type_code || type_code == B_ANY_TYPE {
-					return Ok(next_index as usize);
+					return Ok(index);
 				} else {
 					return Err(HaikuError::from(ErrorKind::InvalidInput));
 				}
 			}
-
-			next_index = field.next_field;
 		}
 		Err(HaikuError::from(ErrorKind::NotFound))
 	}
@@ -548,7 +1108,9 @@ impl Message {
 			flags |= FIELD_FLAG_FIXED_SIZE;
 		}
 
-		let hash: u32 = self.hash_name(name) % self.header.hash_table_size;
+		let hash = self.hash_name(name.as_bytes());
+		self.index.push(hash);
+		let hash: u32 = hash % self.header.hash_table_size;
 		let mut current_index: i32 = self.header.hash_table[hash as usize];
 		if current_index >= 0 {
 			{
@@ -617,57 +1179,25 @@ impl Flattenable<Message> for Message {
 	}
 
 	fn flatten(&self) -> Vec<u8> {
-		let mut vec: Vec<u8> = vec![0; self.flattened_size()];
-		// Copy message header
-		{
-			let (message_header_slice, _) =
-				vec.as_mut_slice().split_at_mut(size_of::<message_header>());
-			let message_header_bytes: &[u8] = unsafe {
-				from_raw_parts(
-					(&self.header as *const message_header) as *const u8,
-					size_of::<message_header>(),
-				)
-			};
-			message_header_slice.clone_from_slice(message_header_bytes);
-		}
-		// Copy field headers and data
-		if self.fields.len() > 0 {
-			{
-				let (_, field_header_slice) =
-					vec.as_mut_slice().split_at_mut(size_of::<message_header>());
-				let field_header_bytes: &[u8] = unsafe {
-					from_raw_parts(
-						(self.fields.as_slice() as *const [field_header]) as *const u8,
-						size_of::<field_header>(),
-					)
-				};
-				unsafe {
-					ptr::copy_nonoverlapping(
-						field_header_bytes.as_ptr(),
-						field_header_slice.as_mut_ptr(),
-						size_of::<field_header>() * self.fields.len(),
-					);
-				}
-			}
-			{
-				// Copy data
-				let (_, data_slice) = vec.as_mut_slice().split_at_mut(
-					size_of::<message_header>() + size_of::<field_header>() * self.fields.len(),
-				);
-				unsafe {
-					ptr::copy_nonoverlapping(
-						self.data.as_ptr(),
-						data_slice.as_mut_ptr(),
-						self.data.len(),
-					);
-				}
-			}
-		}
-
+		let mut vec: Vec<u8> = Vec::new();
+		self.flatten_into(&mut vec);
 		vec
 	}
 
 	fn unflatten(buffer: &[u8]) -> Result<Message> {
+		Message::unflatten_message(buffer, false)
+	}
+}
+
+impl Message {
+	// Unflatten a message that is read from a port. Unlike `unflatten()`,
+	// this accepts messages that are passed by area, and takes ownership of
+	// the area that was transferred to this team.
+	pub(crate) fn unflatten_received(buffer: &[u8]) -> Result<Message> {
+		Message::unflatten_message(buffer, true)
+	}
+
+	fn unflatten_message(buffer: &[u8], from_port: bool) -> Result<Message> {
 		// minimum size is at least the header
 		if buffer.len() < size_of::<message_header>() {
 			return Err(HaikuError::new(
@@ -675,93 +1205,276 @@ impl Flattenable<Message> for Message {
 				"buffer size is shorter than a message",
 			));
 		}
-		// check the first 4 bytes and compare the message constant
-		if buffer[0] != 'H' as u8
-			|| buffer[1] != 'M' as u8
-			|| buffer[2] != 'F' as u8
-			|| buffer[3] != '1' as u8
-		{
-			return Err(HaikuError::new(
-				ErrorKind::InvalidData,
-				"buffer does not contain a valid haiku message",
-			));
-		}
+		// check the first 4 bytes and compare the message constant. A
+		// message from a system with the other byte order has the constant
+		// reversed.
+		let swapped = match &buffer[0..4] {
+			b"HMF1" => false,
+			b"1FMH" => true,
+			_ => {
+				return Err(HaikuError::new(
+					ErrorKind::InvalidData,
+					"buffer does not contain a valid haiku message",
+				))
+			}
+		};
 
-		let data_ptr: *const u8 = buffer.as_ptr();
-		let header_ptr: *const message_header = data_ptr as *const _;
-		let header_ref: &message_header = unsafe { &*header_ptr };
+		// The buffer may not be aligned, for example when the message is
+		// stored inside another message, so the headers are read unaligned
+		let header_ptr: *const message_header = buffer.as_ptr() as *const _;
+		let mut header = unsafe { ptr::read_unaligned(header_ptr) };
+		if swapped {
+			swap_message_header(&mut header);
+		}
 
 		let mut msg = Message {
-			header: header_ref.clone(),
+			header,
 			fields: Vec::new(),
 			data: Vec::new(),
+			index: FieldIndex::new(),
 		};
 
-		let total_size = size_of::<message_header>()
-			+ size_of::<field_header>() * msg.header.field_count as usize
-			+ msg.header.data_size as usize;
+		// A large message only has the header in the buffer, and the fields
+		// and the data in an area that was transferred to this team. The
+		// area is deleted when it has been read. Only messages that come
+		// from a port can refer to an area, the area id in flattened data
+		// from other sources cannot be trusted.
+		let area = if (msg.header.flags & MESSAGE_FLAG_PASS_BY_AREA) != 0 {
+			if !from_port {
+				return Err(HaikuError::new(
+					ErrorKind::InvalidData,
+					"a message that is passed by area can only be read from a port",
+				));
+			}
+			if buffer.len() != size_of::<message_header>() {
+				return Err(HaikuError::new(
+					ErrorKind::InvalidData,
+					"a message that is passed by area should only contain the header",
+				));
+			}
+			let area = Area::adopt_transferred(msg.header.message_area, MESSAGE_AREA_NAME)?;
+			msg.header.flags &= !MESSAGE_FLAG_PASS_BY_AREA;
+			msg.header.message_area = -1;
+			Some(area)
+		} else {
+			None
+		};
 
-		if total_size != buffer.len() {
-			return Err(HaikuError::new(
+		let body_size = size_of::<field_header>() * msg.header.field_count as usize
+			+ msg.header.data_size as usize;
+		let body = match area {
+			Some(ref area) => area.as_slice()?.get(..body_size),
+			None => buffer
+				.get(size_of::<message_header>()..)
+				.filter(|body| body.len() == body_size),
+		}
+		.ok_or_else(|| {
+			HaikuError::new(
 				ErrorKind::InvalidData,
 				"buffer is smaller than the advertised message size",
-			));
-		}
+			)
+		})?;
 
-		let mut offset = size_of::<message_header>();
+		let mut offset = 0;
 		for _ in 0..msg.header.field_count {
-			let (_, field_header_slice) = buffer.split_at(offset);
+			let (_, field_header_slice) = body.split_at(offset);
 			let field_header_ptr: *const field_header = field_header_slice.as_ptr() as *const _;
-			let field_header_ref: &field_header = unsafe { &*field_header_ptr };
-			msg.fields.push(field_header_ref.clone());
+			let mut field = unsafe { ptr::read_unaligned(field_header_ptr) };
+			if swapped {
+				swap_field_header(&mut field);
+			}
+			msg.fields.push(field);
 			offset += size_of::<field_header>();
 		}
 
-		let (_, data_part_slice) = buffer.split_at(offset);
+		let (_, data_part_slice) = body.split_at(offset);
 		msg.data.extend_from_slice(data_part_slice);
+		if swapped {
+			msg.swap_field_data()?;
+		}
+		msg.rebuild_index();
 
 		Ok(msg)
 	}
 }
 
-impl fmt::Debug for Message {
-	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		// TODO: make this mirror BMessage::PrintToStream()
-		let chars = unsafe { transmute_copy::<u32, [u8; 4]>(&self.what()) };
-		let mut print_chars = true;
-		for ch in chars.iter() {
-			if !(*ch as char).is_ascii_graphic() {
-				print_chars = false;
-				break;
-			}
-		}
+// Swap the byte order of all the values in a message header
+fn swap_message_header(header: &mut message_header) {
+	header.message_format = header.message_format.swap_bytes();
+	header.what = header.what.swap_bytes();
+	header.flags = header.flags.swap_bytes();
+	header.target = header.target.swap_bytes();
+	header.current_specifier = header.current_specifier.swap_bytes();
+	header.message_area = header.message_area.swap_bytes();
+	header.reply_port = header.reply_port.swap_bytes();
+	header.reply_target = header.reply_target.swap_bytes();
+	header.reply_team = header.reply_team.swap_bytes();
+	header.data_size = header.data_size.swap_bytes();
+	header.field_count = header.field_count.swap_bytes();
+	header.hash_table_size = header.hash_table_size.swap_bytes();
+	for bucket in header.hash_table.iter_mut() {
+		*bucket = bucket.swap_bytes();
+	}
+}
 
-		let result = if print_chars {
-			write!(
-				f,
-				"BMessage: ({:?})",
-				(
-					chars[3] as char,
-					chars[2] as char,
-					chars[1] as char,
-					chars[0] as char
-				)
-			)
-		} else {
-			write!(f, "BMessage: ({})", self.what())
-		};
+// Swap the byte order of all the values in a field header
+fn swap_field_header(field: &mut field_header) {
+	field.flags = field.flags.swap_bytes();
+	field.name_length = field.name_length.swap_bytes();
+	field.field_type = field.field_type.swap_bytes();
+	field.count = field.count.swap_bytes();
+	field.data_size = field.data_size.swap_bytes();
+	field.offset = field.offset.swap_bytes();
+	field.next_field = field.next_field.swap_bytes();
+}
+
+// Find an item of a field with a variable size
+//
+// Every item of such a field is stored as its size (a u32), followed by
+// the data. The walk starts at the `start` of the field's data, and reads
+// the size of every item up to and including the one at `index`. The
+// offset of that item's data, past its size, is returned with the size.
+fn variable_size_item(data: &[u8], start: usize, index: usize) -> (usize, usize) {
+	let mut offset = start;
+	let mut item_size: usize = 0;
+	for _ in 0..=index {
+		offset += item_size;
+		item_size = u32::unflatten(&data[offset..offset + size_of::<u32>()]).unwrap() as usize;
+		offset += size_of::<u32>();
+	}
+	(offset, item_size)
+}
+
+/// A value that can be added to a message with the `message!` macro
+///
+/// This is implemented for all `Flattenable` types, which are added with
+/// `Message::add_data()`, and for string slices, which are added with
+/// `Message::add_string()`. It is an implementation detail of the macro.
+#[doc(hidden)]
+pub trait MessageValue {
+	fn add_to(&self, message: &mut Message, name: &str) -> Result<()>;
+}
+
+impl<T: Flattenable<T>> MessageValue for T {
+	fn add_to(&self, message: &mut Message, name: &str) -> Result<()> {
+		message.add_data(name, self)
+	}
+}
+
+impl MessageValue for &str {
+	fn add_to(&self, message: &mut Message, name: &str) -> Result<()> {
+		message.add_string(name, self)
+	}
+}
+
+impl TryFrom<&[u8]> for Message {
+	type Error = HaikuError;
+
+	/// Read a Message from a flattened buffer
+	///
+	/// This is the same as `Message::unflatten()`.
+	fn try_from(buffer: &[u8]) -> Result<Message> {
+		Message::unflatten(buffer)
+	}
+}
+
+impl From<&Message> for Vec<u8> {
+	/// Flatten a Message into a new buffer
+	///
+	/// This is the same as `Message::flatten()`.
+	fn from(message: &Message) -> Vec<u8> {
+		message.flatten()
+	}
+}
+
+// Format a fourcc like 'abcd', or as a number if it is not printable
+fn format_type_code(type_code: u32) -> String {
+	let chars = type_code.to_be_bytes();
+	if chars.iter().all(|c| (*c as char).is_ascii_graphic()) {
+		format!("'{}'", chars.iter().map(|c| *c as char).collect::<String>())
+	} else {
+		format!("0x{:x}", type_code)
+	}
+}
 
-		if self.fields.len() > 0 {
-			write!(f, "\n{{\n").ok();
-			for field in self.fields.iter() {
-				let name_slice = &self.data
-					[(field.offset as usize)..(field.offset + field.name_length as u32) as usize];
-				write!(f, "\t{}\n", str::from_utf8(name_slice).unwrap()).ok();
+// Format an item of a message, for the types that can be shown as a value
+fn format_item(type_code: u32, data: &[u8]) -> Option<String> {
+	match type_code {
+		B_BOOL_TYPE => bool::unflatten(data).ok().map(|v| format!("bool({})", v)),
+		B_INT8_TYPE => i8::unflatten(data)
+			.ok()
+			.map(|v| format!("int8(0x{:x} or {})", v, v)),
+		B_INT16_TYPE => i16::unflatten(data)
+			.ok()
+			.map(|v| format!("int16(0x{:x} or {})", v, v)),
+		B_INT32_TYPE => i32::unflatten(data)
+			.ok()
+			.map(|v| format!("int32(0x{:x} or {})", v, v)),
+		B_INT64_TYPE => i64::unflatten(data)
+			.ok()
+			.map(|v| format!("int64(0x{:x} or {})", v, v)),
+		B_UINT8_TYPE => u8::unflatten(data)
+			.ok()
+			.map(|v| format!("uint8(0x{:x} or {})", v, v)),
+		B_UINT16_TYPE => u16::unflatten(data)
+			.ok()
+			.map(|v| format!("uint16(0x{:x} or {})", v, v)),
+		B_UINT32_TYPE => u32::unflatten(data)
+			.ok()
+			.map(|v| format!("uint32(0x{:x} or {})", v, v)),
+		B_UINT64_TYPE => u64::unflatten(data)
+			.ok()
+			.map(|v| format!("uint64(0x{:x} or {})", v, v)),
+		B_FLOAT_TYPE => f32::unflatten(data)
+			.ok()
+			.map(|v| format!("float({:.4})", v)),
+		B_DOUBLE_TYPE => f64::unflatten(data)
+			.ok()
+			.map(|v| format!("double({:.8})", v)),
+		B_STRING_TYPE => String::unflatten(data)
+			.ok()
+			.map(|v| format!("string({:?}, {} bytes)", v, data.len())),
+		_ => None,
+	}
+}
+
+// The output mirrors BMessage::PrintToStream(): every item is printed with
+// its type and value, and items of other types are printed as a hex dump.
+impl fmt::Debug for Message {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		writeln!(f, "BMessage({}) {{", format_type_code(self.what()))?;
+		for field in self.fields.iter() {
+			let name = String::from_utf8_lossy(self.field_name(field));
+			for index in 0..field.count as usize {
+				if field.count > 1 {
+					write!(f, "\t{}[{}] = ", name, index)?;
+				} else {
+					write!(f, "\t{} = ", name)?;
+				}
+				let data = match self.find_item(&name, field.field_type, index) {
+					Ok(data) => data,
+					Err(e) => {
+						writeln!(f, "<{}>", e)?;
+						continue;
+					}
+				};
+				match format_item(field.field_type, data) {
+					Some(value) => writeln!(f, "{}", value)?,
+					None => {
+						writeln!(
+							f,
+							"(type = {})(size = {})",
+							format_type_code(field.field_type),
+							data.len()
+						)?;
+						for line in dump_raw_data(data).lines() {
+							writeln!(f, "\t{}", line)?;
+						}
+					}
+				}
 			}
-			write!(f, "}}")
-		} else {
-			result
 		}
+		write!(f, "}}")
 	}
 }
 
@@ -902,6 +1615,31 @@ fn test_message_replace() {
 	assert_eq!(flattened_message, comparison);
 }
 
+#[test]
+fn test_message_variable_size_items() {
+	let mut message = Message::new(0);
+	for item in ["a", "bb", "ccc"] {
+		message.add_data("items", &String::from(item)).unwrap();
+	}
+	// Every item is found past the sizes and the data of the ones before it
+	assert_eq!(message.find_data::<String>("items", 0).unwrap(), "a");
+	assert_eq!(message.find_data::<String>("items", 1).unwrap(), "bb");
+	assert_eq!(message.find_data::<String>("items", 2).unwrap(), "ccc");
+	assert_eq!(message.find_data_bytes("items", 2).unwrap(), b"ccc\0");
+
+	message
+		.replace_data("items", 1, &String::from("longer"))
+		.unwrap();
+	assert_eq!(message.find_data::<String>("items", 0).unwrap(), "a");
+	assert_eq!(message.find_data::<String>("items", 1).unwrap(), "longer");
+	assert_eq!(message.find_data::<String>("items", 2).unwrap(), "ccc");
+
+	message.remove_data("items", 1).unwrap();
+	assert_eq!(message.find_data::<String>("items", 0).unwrap(), "a");
+	assert_eq!(message.find_data::<String>("items", 1).unwrap(), "ccc");
+	assert!(message.find_data::<String>("items", 2).is_err());
+}
+
 #[test]
 fn test_message_flattening() {
 	use crate::haiku_constant;
@@ -972,3 +1710,864 @@ fn test_system_message() {
 	let other_message = Message::new(other_constant);
 	assert!(!other_message.is_system());
 }
+
+#[test]
+fn test_message_typed_accessors() {
+	use crate::haiku_constant;
+
+	let mut message = Message::new(haiku_constant!('t', 'y', 'p', 'e'));
+	message.add_string("string", "Haiku").unwrap();
+	message.add_string("string", "").unwrap();
+	message.add_int32("int32", -42).unwrap();
+	message.add_bool("bool", true).unwrap();
+	message.add_float("float", 1.5).unwrap();
+	assert!(message.add_string("string", "nul\0").is_err());
+	assert!(message.add_int32("string", 1).is_err());
+
+	assert_eq!(message.find_string("string", 0).unwrap(), "Haiku");
+	assert_eq!(message.find_string("string", 1).unwrap(), "");
+	assert_eq!(message.find_int32("int32", 0).unwrap(), -42);
+	assert!(message.find_bool("bool", 0).unwrap());
+	assert_eq!(message.find_float("float", 0).unwrap(), 1.5);
+	assert!(message.find_string("int32", 0).is_err());
+
+	// The result is the same as adding a String
+	let mut other = Message::new(haiku_constant!('t', 'y', 'p', 'e'));
+	other.add_data("string", &String::from("Haiku")).unwrap();
+	assert_eq!(
+		message.find_data_bytes("string", 0).unwrap(),
+		other.find_data_bytes("string", 0).unwrap()
+	);
+}
+
+#[test]
+fn test_message_names() {
+	use libc::{B_BOOL_TYPE, B_INT32_TYPE};
+
+	use crate::haiku_constant;
+
+	let mut message = Message::new(haiku_constant!('n', 'a', 'm', 'e'));
+	assert_eq!(message.names().count(), 0);
+	message.add_int32("first", 1).unwrap();
+	message.add_string("second", "a").unwrap();
+	message.add_string("second", "b").unwrap();
+	message.add_bool("third", false).unwrap();
+	message.add_int32("first", 2).unwrap();
+	message.add_int32("first", 3).unwrap();
+
+	let names: Vec<(&str, u32, usize)> = message.names().collect();
+	assert_eq!(
+		names,
+		vec![
+			("first", B_INT32_TYPE, 3),
+			("second", B_STRING_TYPE, 2),
+			("third", B_BOOL_TYPE, 1)
+		]
+	);
+}
+
+#[test]
+fn test_message_debug() {
+	use crate::haiku_constant;
+
+	let mut message = Message::new(haiku_constant!('d', 'b', 'u', 'g'));
+	assert_eq!(format!("{:?}", message), "BMessage('dbug') {\n}");
+
+	message.add_int32("int", 42).unwrap();
+	message.add_string("tags", "os").unwrap();
+	message.add_string("tags", "beos").unwrap();
+	message.add_bool("flag", true).unwrap();
+	message.add_data("blob", &vec![0x41u8, 0x00, 0xff]).unwrap();
+	assert_eq!(
+		format!("{:?}", message),
+		"BMessage('dbug') {\n\
+		 \tint = int32(0x2a or 42)\n\
+		 \ttags[0] = string(\"os\", 3 bytes)\n\
+		 \ttags[1] = string(\"beos\", 5 bytes)\n\
+		 \tflag = bool(true)\n\
+		 \tblob = (type = 'RAWT')(size = 3)\n\
+		 \t\t   0: 41  0 ff                                         A..\n\
+		 }"
+	);
+	assert!(format!("{:?}", Message::new(1)).starts_with("BMessage(0x1) {"));
+}
+
+#[test]
+fn test_message_nested() {
+	use crate::haiku_constant;
+
+	let mut first = Message::new(haiku_constant!('c', 'h', 'l', '1'));
+	first.add_string("name", "first").unwrap();
+	first.add_int32("value", 1).unwrap();
+	let mut second = Message::new(haiku_constant!('c', 'h', 'l', '2'));
+	second.add_string("name", "the second child").unwrap();
+	second.add_message("grandchild", &first).unwrap();
+
+	let mut parent = Message::new(haiku_constant!('p', 'r', 'n', 't'));
+	parent.add_int32("before", 0).unwrap();
+	parent.add_message("children", &first).unwrap();
+	parent.add_message("children", &second).unwrap();
+	parent.add_int32("after", 2).unwrap();
+	assert_eq!(
+		parent.get_info("children"),
+		Some((B_MESSAGE_TYPE, 2, false))
+	);
+
+	let parent = Message::unflatten(&parent.flatten()).unwrap();
+	let found_first = parent.find_message("children", 0).unwrap();
+	let found_second = parent.find_message("children", 1).unwrap();
+	assert_eq!(found_first.flatten(), first.flatten());
+	assert_eq!(found_second.flatten(), second.flatten());
+	assert_eq!(found_first.what(), first.what());
+	assert_eq!(found_first.find_string("name", 0).unwrap(), "first");
+	assert_eq!(found_first.find_int32("value", 0).unwrap(), 1);
+	let grandchild = found_second.find_message("grandchild", 0).unwrap();
+	assert_eq!(grandchild.find_string("name", 0).unwrap(), "first");
+	assert_eq!(parent.find_int32("after", 0).unwrap(), 2);
+	assert!(parent.find_message("children", 2).is_err());
+	assert!(parent.find_message("before", 0).is_err());
+}
+
+#[test]
+fn test_message_make_empty() {
+	use crate::haiku_constant;
+
+	let constant = haiku_constant!('e', 'm', 'p', 't');
+	let mut message = Message::new(constant);
+	message.add_int32("first", 1).unwrap();
+	message.add_string("second", "some text").unwrap();
+	message.add_string("second", "more text").unwrap();
+	message.make_empty();
+	assert!(message.is_empty());
+	assert_eq!(message.what(), constant);
+	assert_eq!(message.flatten(), Message::new(constant).flatten());
+	assert!(message.find_int32("first", 0).is_err());
+
+	message.add_string("second", "other").unwrap();
+	let mut fresh = Message::new(constant);
+	fresh.add_string("second", "other").unwrap();
+	assert_eq!(message.flatten(), fresh.flatten());
+	assert_eq!(message.find_string("second", 0).unwrap(), "other");
+}
+
+#[test]
+fn test_message_many_fields() {
+	use crate::haiku_constant;
+
+	let mut message = Message::new(haiku_constant!('m', 'a', 'n', 'y'));
+	for i in 0..1000 {
+		message.add_int32(&format!("field {}", i), i).unwrap();
+	}
+	assert_eq!(message.header.hash_table_size, 5);
+	for i in 0..1000 {
+		let name = format!("field {}", i);
+		assert_eq!(message.find_field(&name, B_INT32_TYPE).unwrap(), i as usize);
+		assert_eq!(message.find_int32(&name, 0).unwrap(), i);
+	}
+	assert!(message.find_field("field 1000", B_ANY_TYPE).is_err());
+
+	// The index is rebuilt when the fields change
+	message.remove_field("field 0").unwrap();
+	assert_eq!(message.find_field("field 999", B_ANY_TYPE).unwrap(), 998);
+	assert!(message.find_field("field 0", B_ANY_TYPE).is_err());
+	let message = Message::unflatten(&message.flatten()).unwrap();
+	assert_eq!(message.find_int32("field 500", 0).unwrap(), 500);
+
+	// The chains in the header are still valid for the receivers
+	let mut count = 0;
+	for bucket in message.header.hash_table {
+		let mut next = bucket;
+		while next >= 0 {
+			count += 1;
+			next = message.fields[next as usize].next_field;
+		}
+	}
+	assert_eq!(count, 999);
+}
+
+#[test]
+fn test_message_find_data_raw() {
+	use crate::haiku_constant;
+
+	let blob: Vec<u8> = (0..=255).cycle().take(4096).collect();
+	let mut message = Message::new(haiku_constant!('r', 'a', 'w', 'd'));
+	message.add_data("blob", &vec![1u8, 2, 3]).unwrap();
+	message.adopt_raw("blob", blob.clone()).unwrap();
+	message.add_string("string", "not raw").unwrap();
+
+	assert_eq!(message.find_data_raw("blob", 0).unwrap(), &[1, 2, 3]);
+	let found = message.find_data_raw("blob", 1).unwrap();
+	assert_eq!(found, blob.as_slice());
+	assert_eq!(
+		found.as_ptr(),
+		message.find_data_bytes("blob", 1).unwrap().as_ptr()
+	);
+	assert!(message.find_data_raw("blob", 2).is_err());
+	assert!(message.find_data_raw("string", 0).is_err());
+	assert!(message.find_data_raw("missing", 0).is_err());
+}
+
+#[test]
+fn test_message_unflatten_swapped() {
+	use libc::B_POINT_TYPE;
+
+	use crate::haiku_constant;
+	use crate::interface::Point;
+
+	let mut message = Message::new(haiku_constant!('s', 'w', 'a', 'p'));
+	message.add_data("int16", &(-2 as i16)).unwrap();
+	message.add_int32("int32", 0x01020304).unwrap();
+	message.add_int32("int32", -1).unwrap();
+	message.add_data("int64", &(1i64 << 40)).unwrap();
+	message.add_data("double", &0.25f64).unwrap();
+	message.add_data("point", &Point::new(1.0, -2.0)).unwrap();
+	message.add_string("string", "first").unwrap();
+	message.add_string("string", "second").unwrap();
+	message.add_bool("bool", true).unwrap();
+	let flattened = message.flatten();
+
+	// Build the message as a big endian system would have flattened it
+	let mut swapped = flattened.clone();
+	let header_size = size_of::<message_header>();
+	for word in swapped[0..header_size].chunks_mut(4) {
+		word.reverse();
+	}
+	for index in 0..message.fields.len() {
+		let start = header_size + index * size_of::<field_header>();
+		swapped[start..start + 2].reverse();
+		swapped[start + 2..start + 4].reverse();
+		for word in swapped[start + 4..start + size_of::<field_header>()].chunks_mut(4) {
+			word.reverse();
+		}
+	}
+	let data_start = header_size + message.fields.len() * size_of::<field_header>();
+	let data_offset =
+		|item: &[u8]| data_start + (item.as_ptr() as usize - message.data.as_ptr() as usize);
+	for (name, type_code, count) in message.names() {
+		let value_size = match type_code {
+			B_INT16_TYPE => 2,
+			B_INT32_TYPE | B_POINT_TYPE => 4,
+			B_INT64_TYPE | B_DOUBLE_TYPE => 8,
+			_ => 1,
+		};
+		let (_, _, is_fixed_size) = message.get_info(name).unwrap();
+		for index in 0..count {
+			let item = message.find_data_bytes(name, index).unwrap();
+			let start = data_offset(item);
+			if !is_fixed_size {
+				swapped[start - 4..start].reverse();
+			}
+			for value in swapped[start..start + item.len()].chunks_mut(value_size) {
+				value.reverse();
+			}
+		}
+	}
+	assert_eq!(&swapped[0..4], b"1FMH");
+	assert_ne!(swapped, flattened);
+
+	let unflattened = Message::unflatten(&swapped).unwrap();
+	assert_eq!(unflattened.flatten(), flattened);
+	assert_eq!(unflattened.find_int32("int32", 0).unwrap(), 0x01020304);
+	assert_eq!(unflattened.find_data::<i16>("int16", 0).unwrap(), -2);
+	assert_eq!(
+		unflattened.find_data::<Point>("point", 0).unwrap(),
+		Point::new(1.0, -2.0)
+	);
+	assert_eq!(unflattened.find_string("string", 1).unwrap(), "second");
+
+	// A corrupt swapped message is rejected
+	let mut corrupt = swapped.clone();
+	let size_start = data_offset(message.find_data_bytes("string", 0).unwrap()) - 4;
+	corrupt[size_start..size_start + 4].copy_from_slice(&[0, 0, 0xff, 0xff]);
+	assert!(Message::unflatten(&corrupt).is_err());
+}
+
+#[test]
+fn test_message_raw_data() {
+	use crate::haiku_constant;
+
+	let constant: u32 = haiku_constant!('r', 'a', 'w', 'd');
+	let blob: Vec<u8> = vec![0, 1, 2, 0, 255, 42];
+	let mut message = Message::new(constant);
+	message.add_data("blob", &blob).unwrap();
+	let (type_code, _, _) = message.get_info("blob").unwrap();
+	assert_eq!(type_code, B_RAW_TYPE);
+
+	let flattened = message.flatten();
+	let unflattened = Message::unflatten(&flattened).unwrap();
+	assert_eq!(unflattened.find_data::<Vec<u8>>("blob", 0).unwrap(), blob);
+}
+
+#[test]
+fn test_message_adopt_raw() {
+	use crate::haiku_constant;
+
+	// A minimal raw data type, to compare against adding data the regular way
+	struct Raw(Vec<u8>);
+
+	impl Flattenable<Raw> for Raw {
+		fn type_code() -> u32 {
+			B_RAW_TYPE
+		}
+
+		fn is_fixed_size() -> bool {
+			false
+		}
+
+		fn flattened_size(&self) -> usize {
+			self.0.len()
+		}
+
+		fn flatten(&self) -> Vec<u8> {
+			self.0.clone()
+		}
+
+		fn unflatten(buffer: &[u8]) -> Result<Raw> {
+			Ok(Raw(buffer.to_vec()))
+		}
+	}
+
+	let constant: u32 = haiku_constant!('r', 'a', 'w', 'd');
+	let frame: Vec<u8> = (0..=255).collect();
+	let mut adopted_message = Message::new(constant);
+	adopted_message.add_data("before", &(1 as i32)).unwrap();
+	adopted_message.adopt_raw("frame", frame.clone()).unwrap();
+	adopted_message.add_data("after", &(2 as i32)).unwrap();
+	adopted_message.adopt_raw("frame", vec![1, 2, 3]).unwrap();
+
+	let mut message = Message::new(constant);
+	message.add_data("before", &(1 as i32)).unwrap();
+	message.add_data("frame", &Raw(frame.clone())).unwrap();
+	message.add_data("after", &(2 as i32)).unwrap();
+	message.add_data("frame", &Raw(vec![1, 2, 3])).unwrap();
+
+	assert_eq!(adopted_message.flatten(), message.flatten());
+	assert_eq!(
+		adopted_message.find_data::<Raw>("frame", 0).unwrap().0,
+		frame
+	);
+	assert!(adopted_message.adopt_raw("before", vec![0]).is_err());
+}
+
+#[test]
+fn test_message_wire_size() {
+	use crate::haiku_constant;
+
+	let mut message = Message::new(haiku_constant!('w', 'i', 'r', 'e'));
+	assert_eq!(message.wire_size(), message.flatten().len());
+	message.add_data("int", &(-1 as i32)).unwrap();
+	message
+		.add_data("string", &String::from("wire size"))
+		.unwrap();
+	message.adopt_raw("raw", vec![1, 2, 3]).unwrap();
+	assert_eq!(message.wire_size(), message.flatten().len());
+}
+
+#[test]
+fn test_message_find_data_bytes() {
+	use crate::haiku_constant;
+
+	let mut message = Message::new(haiku_constant!('b', 'y', 't', 'e'));
+	message.add_data("int", &7i32).unwrap();
+	message.add_data("int", &-7i32).unwrap();
+	message.add_data("string", &String::from("first")).unwrap();
+	message.add_data("string", &String::from("second")).unwrap();
+	message
+		.adopt_raw("raw", vec![0xde, 0xad, 0xbe, 0xef])
+		.unwrap();
+
+	// Fixed size items
+	let bytes = message.find_data_bytes("int", 1).unwrap();
+	assert_eq!(i32::unflatten(bytes).unwrap(), -7);
+
+	// Variable size items do not include the size prefix
+	for index in 0..2 {
+		let bytes = message.find_data_bytes("string", index).unwrap();
+		let owned = message.find_data::<String>("string", index).unwrap();
+		assert_eq!(bytes, owned.flatten().as_slice());
+	}
+	assert_eq!(
+		message.find_data_bytes("raw", 0).unwrap(),
+		&[0xde, 0xad, 0xbe, 0xef]
+	);
+
+	assert!(message.find_data_bytes("string", 2).is_err());
+	assert!(message.find_data_bytes("missing", 0).is_err());
+}
+
+#[test]
+fn test_message_pointer_guard() {
+	use crate::haiku_constant;
+
+	let pointer: usize = 0xdeadbeef;
+	let mut message = Message::new(haiku_constant!('p', 't', 'r', 's'));
+	message
+		.add_flattened_data("pointer", B_POINTER_TYPE, true, &pointer.to_ne_bytes())
+		.unwrap();
+	assert!(message.find_data_bytes("pointer", 0).is_ok());
+
+	// A message from the same team may be read
+	let (team, _) = get_current_team_and_thread();
+	message.header.flags |= MESSAGE_FLAG_WAS_DELIVERED;
+	message.header.reply_team = team;
+	assert!(message.find_data_bytes("pointer", 0).is_ok());
+
+	// A message from another team may not
+	message.header.reply_team = team + 1;
+	let error = message.find_data_bytes("pointer", 0).err().unwrap();
+	assert!(matches!(error.kind(), ErrorKind::NotAllowed));
+	assert!(error.to_string().contains("not transferable"));
+}
+
+#[test]
+fn test_message_flatten_into() {
+	use crate::haiku_constant;
+
+	let mut message = Message::new(haiku_constant!('f', 'l', 'a', 't'));
+	message.add_data("int", &42i32).unwrap();
+	message
+		.add_data("string", &String::from("flatten"))
+		.unwrap();
+
+	// The buffer is overwritten, and reused
+	let mut buffer: Vec<u8> = vec![1; 1024];
+	let capacity = buffer.capacity();
+	message.flatten_into(&mut buffer);
+	assert_eq!(buffer, message.flatten());
+	assert_eq!(buffer.capacity(), capacity);
+	assert_eq!(
+		Message::unflatten(&buffer)
+			.unwrap()
+			.find_data::<String>("string", 0)
+			.unwrap(),
+		"flatten"
+	);
+}
+
+#[test]
+fn test_message_refs_received() {
+	extern crate tempfile;
+
+	let directory = tempfile::tempdir().unwrap();
+	let first = directory.path().join("first");
+	let second = directory.path().join("second");
+	let message = Message::refs_received(&[&first, &second]).unwrap();
+	assert_eq!(message.what(), B_REFS_RECEIVED);
+	assert_eq!(message.get_info("refs").unwrap().1, 2);
+
+	let message = Message::unflatten(&message.flatten()).unwrap();
+	for (index, path) in [&first, &second].iter().enumerate() {
+		let expected = entry_ref::from_path(path).unwrap();
+		let entry: entry_ref = message.find_data("refs", index).unwrap();
+		assert_eq!(entry.device, expected.device);
+		assert_eq!(entry.directory, expected.directory);
+		assert_eq!(entry.name, expected.name);
+	}
+
+	assert!(Message::refs_received(&[Path::new("/doesnotexist/file")]).is_err());
+}
+
+#[test]
+fn test_message_path() {
+	extern crate tempfile;
+	use crate::haiku_constant;
+	use std::fs;
+
+	let directory = tempfile::tempdir().unwrap();
+	let path = directory.path().join("shared file");
+	fs::write(&path, "shared contents").unwrap();
+
+	let mut message = Message::new(haiku_constant!('p', 'a', 't', 'h'));
+	message.add_path("file", &path).unwrap();
+	let message = Message::unflatten(&message.flatten()).unwrap();
+
+	// The receiver reopens the file through the path
+	let received = message.find_path("file", 0).unwrap();
+	assert_eq!(received, path.canonicalize().unwrap());
+	assert_eq!(fs::read_to_string(received).unwrap(), "shared contents");
+	assert!(message.find_path("file", 1).is_err());
+	assert!(message.find_path("missing", 0).is_err());
+}
+
+#[test]
+fn test_message_path_across_teams() {
+	extern crate tempfile;
+	use std::env;
+	use std::fs;
+	use std::process::Command;
+	use std::time::Duration;
+
+	use crate::kernel::ports::Port;
+
+	let directory = tempfile::tempdir().unwrap();
+	let path = directory.path().join("file from another team");
+	fs::write(&path, "shared contents").unwrap();
+
+	// Run the helper below in a child team, which sends the path back
+	let port = Port::create("path across teams test", 1).unwrap();
+	let mut child = Command::new(env::current_exe().unwrap())
+		.args(["--exact", "app::message::test_message_path_child"])
+		.env("HAIKU_RS_TEST_PATH_PORT", port.get_port_id().to_string())
+		.env("HAIKU_RS_TEST_PATH", &path)
+		.spawn()
+		.unwrap();
+	let (_, buffer) = port.try_read(Duration::from_secs(10)).unwrap();
+	assert!(child.wait().unwrap().success());
+
+	let message = Message::unflatten(&buffer).unwrap();
+	assert_eq!(message.header.reply_team, child.id() as i32);
+	let received = message.find_path("file", 0).unwrap();
+	assert_eq!(received, path.canonicalize().unwrap());
+	assert_eq!(fs::read_to_string(received).unwrap(), "shared contents");
+}
+
+// The child team of `test_message_path_across_teams()`. It does nothing when
+// it is run as a normal test.
+#[test]
+fn test_message_path_child() {
+	use std::env;
+
+	use crate::haiku_constant;
+	use crate::kernel::ports::Port;
+
+	let (port, path) = match (
+		env::var("HAIKU_RS_TEST_PATH_PORT"),
+		env::var_os("HAIKU_RS_TEST_PATH"),
+	) {
+		(Ok(port), Some(path)) => (port.parse().unwrap(), path),
+		_ => return,
+	};
+	let mut message = Message::new(haiku_constant!('p', 'a', 't', 'h'));
+	message.add_path("file", Path::new(&path)).unwrap();
+	let messenger = Messenger::from_port_id(port).unwrap();
+	let reply_port = Port::create("path child", 1).unwrap();
+	let sender = Messenger::from_port(&reply_port).unwrap();
+	messenger.send(message, &sender).unwrap();
+}
+
+#[test]
+fn test_message_conversions() {
+	use crate::haiku_constant;
+
+	let constant: u32 = haiku_constant!('a', 'b', 'c', 'd');
+	let basic_message = Message::new(constant);
+	let comparison: Vec<u8> = vec![
+		72, 77, 70, 49, 100, 99, 98, 97, 1, 0, 0, 0, 255, 255, 255, 255, 255, 255, 255, 255, 255,
+		255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 0, 0, 0, 0, 0,
+		0, 0, 0, 5, 0, 0, 0, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255,
+		255, 255, 255, 255, 255, 255,
+	];
+	assert_eq!(Vec::from(&basic_message), comparison);
+	let converted = Message::try_from(comparison.as_slice()).unwrap();
+	assert_eq!(converted.what(), constant);
+	assert!(converted.is_empty());
+
+	let constant: u32 = haiku_constant!('e', 'f', 'g', 'h');
+	let mut message_with_data = Message::new(constant);
+	message_with_data.add_data("UInt8", &('a' as u8)).unwrap();
+	message_with_data
+		.add_data("UInt16", &(1234 as u16))
+		.unwrap();
+	let buffer: Vec<u8> = (&message_with_data).into();
+	assert_eq!(buffer, message_with_data.flatten());
+	let converted = Message::try_from(buffer.as_slice()).unwrap();
+	assert_eq!(converted.what(), constant);
+	assert_eq!(converted.find_data::<u8>("UInt8", 0).unwrap(), 'a' as u8);
+	assert_eq!(converted.find_data::<u16>("UInt16", 0).unwrap(), 1234);
+
+	assert!(Message::try_from(&buffer[0..buffer.len() - 1]).is_err());
+	assert!(Message::try_from(&[0u8; 4][..]).is_err());
+}
+
+#[test]
+fn test_message_merge_from() {
+	use libc::B_STRING_TYPE;
+
+	use crate::haiku_constant;
+
+	const SETTINGS: u32 = haiku_constant!('s', 't', 'n', 'g');
+
+	let mut defaults = Message::new(SETTINGS);
+	defaults.add_data("name", &String::from("default")).unwrap();
+	defaults.add_data("count", &1i32).unwrap();
+	defaults.add_data("tags", &String::from("a")).unwrap();
+	let mut overrides = Message::new(SETTINGS);
+	overrides.add_data("name", &String::from("custom")).unwrap();
+	overrides.add_data("tags", &String::from("b")).unwrap();
+	overrides.add_data("tags", &String::from("c")).unwrap();
+	overrides.add_data("enabled", &true).unwrap();
+
+	// Appending keeps the existing items
+	let mut appended = Message::unflatten(&defaults.flatten()).unwrap();
+	appended.merge_from(&overrides, false).unwrap();
+	assert_eq!(appended.get_info("name"), Some((B_STRING_TYPE, 2, false)));
+	assert_eq!(appended.find_data::<String>("name", 0).unwrap(), "default");
+	assert_eq!(appended.find_data::<String>("name", 1).unwrap(), "custom");
+	assert_eq!(appended.get_info("tags"), Some((B_STRING_TYPE, 3, false)));
+	assert_eq!(appended.find_data::<String>("tags", 2).unwrap(), "c");
+	assert_eq!(appended.find_data::<i32>("count", 0).unwrap(), 1);
+	assert!(appended.find_data::<bool>("enabled", 0).unwrap());
+
+	// Overwriting replaces the fields with the same name
+	let mut overwritten = Message::unflatten(&defaults.flatten()).unwrap();
+	overwritten.merge_from(&overrides, true).unwrap();
+	assert_eq!(
+		overwritten.get_info("name"),
+		Some((B_STRING_TYPE, 1, false))
+	);
+	assert_eq!(
+		overwritten.find_data::<String>("name", 0).unwrap(),
+		"custom"
+	);
+	assert_eq!(
+		overwritten.get_info("tags"),
+		Some((B_STRING_TYPE, 2, false))
+	);
+	assert_eq!(overwritten.find_data::<String>("tags", 0).unwrap(), "b");
+	assert_eq!(overwritten.find_data::<i32>("count", 0).unwrap(), 1);
+	assert!(overwritten.find_data::<bool>("enabled", 0).unwrap());
+
+	// The result survives a round trip, so the headers are consistent
+	let unflattened = Message::unflatten(&overwritten.flatten()).unwrap();
+	assert_eq!(unflattened.flatten(), overwritten.flatten());
+	assert_eq!(unflattened.find_data::<String>("tags", 1).unwrap(), "c");
+
+	// Fields with the same name and a different type can only be replaced
+	let mut conflict = Message::new(SETTINGS);
+	conflict.add_data("count", &String::from("one")).unwrap();
+	let mut merged = Message::unflatten(&defaults.flatten()).unwrap();
+	let error = merged.merge_from(&conflict, false).unwrap_err();
+	assert!(matches!(error.kind(), ErrorKind::InvalidInput));
+	assert_eq!(merged.flatten(), defaults.flatten());
+	merged.merge_from(&conflict, true).unwrap();
+	assert_eq!(merged.find_data::<String>("count", 0).unwrap(), "one");
+}
+
+#[test]
+fn test_message_delivery_headers() {
+	use crate::haiku_constant;
+	use crate::kernel::ports::Port;
+
+	const ROUTED: u32 = haiku_constant!('r', 'o', 'u', 't');
+
+	let message = Message::new(ROUTED);
+	assert_eq!(message.target_token(), None);
+	assert_eq!(message.reply_port(), None);
+	assert!(message.reply_team().is_none());
+
+	let target_port = Port::create("delivery headers target", 1).unwrap();
+	let sender_port = Port::create("delivery headers sender", 1).unwrap();
+	let mut target = Messenger::from_port(&target_port).unwrap();
+	target.set_token(42);
+	let sender = Messenger::from_port(&sender_port).unwrap();
+	target.send(Message::new(ROUTED), &sender).unwrap();
+
+	let (_, buffer) = target_port.read().unwrap();
+	let received = Message::unflatten(&buffer).unwrap();
+	let (team, _) = get_current_team_and_thread();
+	assert_eq!(received.target_token(), Some(42));
+	assert_eq!(received.reply_port(), Some(sender_port.get_port_id()));
+	assert_eq!(received.reply_team().unwrap().get_team_id(), team);
+
+	// Messages to the preferred handler do not have a specific target
+	let preferred = Messenger::from_port(&target_port).unwrap();
+	preferred.send(Message::new(ROUTED), &sender).unwrap();
+	let (_, buffer) = target_port.read().unwrap();
+	let received = Message::unflatten(&buffer).unwrap();
+	assert_eq!(received.target_token(), None);
+}
+
+#[test]
+fn test_message_make_reply() {
+	use std::thread;
+	use std::time::Duration;
+
+	use crate::haiku_constant;
+	use crate::kernel::ports::Port;
+
+	const REQUEST: u32 = haiku_constant!('r', 'q', 's', 't');
+	const REPLY: u32 = haiku_constant!('r', 'p', 'l', 'y');
+
+	let port = Port::create("make reply test", 1).unwrap();
+	let messenger = Messenger::from_port(&port).unwrap();
+	let responder = thread::spawn(move || {
+		let (_, buffer) = port.read().unwrap();
+		let request = Message::unflatten(&buffer).unwrap();
+		let value: i32 = request.find_data("value", 0).unwrap();
+		let (mut reply, return_address) = request.make_reply(REPLY).unwrap();
+		assert!(reply.is_reply());
+		reply.add_data("value", &(value * 2)).unwrap();
+		return_address.send(reply, &return_address).unwrap();
+
+		// A message that does not ask for a reply cannot be replied to
+		let (_, buffer) = port.read().unwrap();
+		let message = Message::unflatten(&buffer).unwrap();
+		assert!(message.make_reply(REPLY).is_none());
+	});
+
+	let mut request = Message::new(REQUEST);
+	request.add_data("value", &21).unwrap();
+	let reply = messenger
+		.send_and_wait_for_reply(request, Some(Duration::from_secs(5)))
+		.unwrap();
+	assert_eq!(reply.what(), REPLY);
+	assert!(reply.is_reply());
+	assert_eq!(reply.find_data::<i32>("value", 0).unwrap(), 42);
+
+	messenger.send(Message::new(REQUEST), &messenger).unwrap();
+	responder.join().unwrap();
+	assert!(Message::new(REQUEST).make_reply(REPLY).is_none());
+}
+
+#[test]
+fn test_message_fields_of_type() {
+	use libc::{B_INT32_TYPE, B_STRING_TYPE};
+
+	let mut message = Message::new(0);
+	message.add_data("name", &String::from("Haiku")).unwrap();
+	message.add_data("count", &(3 as i32)).unwrap();
+	message.add_data("tags", &String::from("os")).unwrap();
+	message.add_data("tags", &String::from("beos")).unwrap();
+	message.add_data("enabled", &true).unwrap();
+
+	let strings: Vec<(&str, usize)> = message.fields_of_type(B_STRING_TYPE).collect();
+	assert_eq!(strings, vec![("name", 1), ("tags", 2)]);
+	let integers: Vec<(&str, usize)> = message.fields_of_type(B_INT32_TYPE).collect();
+	assert_eq!(integers, vec![("count", 1)]);
+	let all: Vec<&str> = message
+		.fields_of_type(B_ANY_TYPE)
+		.map(|(name, _)| name)
+		.collect();
+	assert_eq!(all, vec!["name", "count", "tags", "enabled"]);
+	assert_eq!(message.fields_of_type(B_RAW_TYPE).count(), 0);
+}
+
+#[test]
+fn test_message_pass_by_area() {
+	use crate::app::Messenger;
+	use crate::haiku_constant;
+	use crate::kernel::ports::Port;
+
+	// A message with more data than a port buffer is sent with its data in
+	// an area
+	let data: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+	let mut message = Message::new(haiku_constant!('a', 'r', 'e', 'a'));
+	message.add_data("blob", &data).unwrap();
+	message.add_data("count", &(3 as i32)).unwrap();
+	assert!(message.should_pass_by_area());
+
+	let port = Port::create("message_area_test", 1).unwrap();
+	let messenger = Messenger::from_port(&port).unwrap();
+	let what = message.what();
+	messenger.send(message, &messenger).unwrap();
+	let (_, buffer) = port.read().unwrap();
+	assert_eq!(buffer.len(), size_of::<message_header>());
+
+	// The area of a message is only picked up when it is read from a port
+	assert!(Message::try_from(buffer.as_slice()).is_err());
+	let received = Message::unflatten_received(buffer.as_slice()).unwrap();
+	assert_eq!(received.what(), what);
+	assert_eq!(received.find_data::<Vec<u8>>("blob", 0).unwrap(), data);
+	assert_eq!(received.find_data::<i32>("count", 0).unwrap(), 3);
+	assert_eq!(received.header.flags & MESSAGE_FLAG_PASS_BY_AREA, 0);
+	assert_eq!(received.header.message_area, -1);
+
+	// The area can only be read once
+	assert!(Message::unflatten_received(buffer.as_slice()).is_err());
+}
+
+#[test]
+fn test_message_native_area_is_adopted() {
+	use libc::{B_READ_AREA, B_WRITE_AREA};
+
+	use crate::haiku_constant;
+
+	// A native sender puts the fields and the data in an area that is named
+	// like the ones that BMessage creates
+	let mut message = Message::new(haiku_constant!('n', 'a', 't', 'v'));
+	message.add_data("count", &(3 as i32)).unwrap();
+	message.add_data("name", &String::from("native")).unwrap();
+	let field_header_bytes: &[u8] = unsafe {
+		from_raw_parts(
+			message.fields.as_ptr() as *const u8,
+			size_of::<field_header>() * message.fields.len(),
+		)
+	};
+	let body_size = field_header_bytes.len() + message.data.len();
+	let mut area = Area::create("BMessage data", body_size, B_READ_AREA | B_WRITE_AREA).unwrap();
+	let (area_fields, area_data) =
+		area.as_mut_slice().unwrap()[..body_size].split_at_mut(field_header_bytes.len());
+	area_fields.copy_from_slice(field_header_bytes);
+	area_data.copy_from_slice(&message.data);
+
+	let mut header = message.header;
+	header.flags |= MESSAGE_FLAG_PASS_BY_AREA;
+	header.message_area = area.get_area_id();
+	let buffer: &[u8] = unsafe {
+		from_raw_parts(
+			(&header as *const message_header) as *const u8,
+			size_of::<message_header>(),
+		)
+	};
+
+	// The received message takes over the area, and deletes it
+	std::mem::forget(area);
+	let received = Message::unflatten_received(buffer).unwrap();
+	assert_eq!(received.what(), message.what());
+	assert_eq!(received.find_data::<i32>("count", 0).unwrap(), 3);
+	assert_eq!(received.find_string("name", 0).unwrap(), "native");
+	assert!(Message::unflatten_received(buffer).is_err());
+}
+
+#[test]
+fn test_message_forged_area_is_rejected() {
+	use libc::{B_READ_AREA, B_WRITE_AREA};
+
+	// A header that claims that the body is in an area of this team, which
+	// was not transferred with the message
+	let area = Area::create("forged message data", 4096, B_READ_AREA | B_WRITE_AREA).unwrap();
+	let mut header = Message::new(0).header;
+	header.flags |= MESSAGE_FLAG_PASS_BY_AREA;
+	header.message_area = area.get_area_id();
+	let buffer: &[u8] = unsafe {
+		from_raw_parts(
+			(&header as *const message_header) as *const u8,
+			size_of::<message_header>(),
+		)
+	};
+
+	assert!(Message::try_from(buffer).is_err());
+	let error = Message::unflatten_received(buffer).unwrap_err();
+	assert!(matches!(error.kind(), ErrorKind::NotAllowed));
+	// The area is left alone
+	assert!(area.get_info().is_ok());
+}
+
+#[test]
+fn test_message_macro() {
+	use crate::haiku_constant;
+	use crate::message;
+
+	const WHAT: u32 = haiku_constant!('m', 'a', 'c', 'r');
+
+	let mut expected = Message::new(WHAT);
+	expected.add_data("count", &3i32).unwrap();
+	expected.add_data("name", &String::from("foo")).unwrap();
+	expected.add_data("name", &String::from("bar")).unwrap();
+	expected.add_data("enabled", &true).unwrap();
+	let message =
+		message!(WHAT, "count" => 3i32, "name" => "foo", "name" => "bar", "enabled" => true)
+			.unwrap();
+	assert_eq!(message.flatten(), expected.flatten());
+
+	// A trailing comma, and no fields at all
+	let message = message!(WHAT, "count" => 3i32,).unwrap();
+	assert_eq!(message.find_data::<i32>("count", 0).unwrap(), 3);
+	assert_eq!(
+		message!(WHAT).unwrap().flatten(),
+		Message::new(WHAT).flatten()
+	);
+
+	// Errors of adding the data are returned
+	assert!(message!(WHAT, "count" => 3i32, "count" => "three").is_err());
+}
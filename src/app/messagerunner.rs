@@ -0,0 +1,97 @@
+//
+// Copyright 2024, Niels Sascha Reedijk <niels.reedijk@gmail.com>
+// All rights reserved. Distributed under the terms of the MIT License.
+//
+
+use std::time::Duration;
+
+use crate::app::timer::Timer;
+use crate::app::{Message, Messenger};
+
+/// Send a message repeatedly, with a fixed interval
+///
+/// This is the equivalent of Haiku's `BMessageRunner`. The message is sent
+/// to the `target` for the first time when the first `interval` has passed,
+/// and then after every next interval. The message is sent `count` times, or
+/// for as long as the runner exists if there is no count. The runner stops
+/// when the target no longer exists.
+///
+/// The runner is cancelled when it is dropped, so keep it around for as long
+/// as you want the messages to be sent.
+pub struct MessageRunner {
+	_timer: Timer,
+}
+
+impl MessageRunner {
+	/// Start sending `message` to `target` every `interval`
+	pub fn new(
+		target: Messenger,
+		message: Message,
+		interval: Duration,
+		count: Option<u32>,
+	) -> MessageRunner {
+		MessageRunner {
+			_timer: Timer::start(target, &message, interval, interval, count),
+		}
+	}
+}
+
+#[test]
+fn test_message_runner() {
+	use std::thread;
+	use std::time::Instant;
+
+	use crate::haiku_constant;
+	use crate::kernel::ports::Port;
+
+	const TICK: u32 = haiku_constant!('t', 'i', 'c', 'k');
+	let port = Port::create("message_runner_test", 10).unwrap();
+	let target = Messenger::from_port(&port).unwrap();
+	let interval = Duration::from_millis(20);
+
+	// The messages arrive with the interval
+	let start = Instant::now();
+	let runner = MessageRunner::new(target.clone(), Message::new(TICK), interval, None);
+	for i in 1..=3 {
+		let (_, buffer) = port.read().unwrap();
+		assert!(start.elapsed() >= interval * i);
+		assert_eq!(Message::try_from(buffer.as_slice()).unwrap().what(), TICK);
+	}
+
+	// No more messages arrive when the runner is dropped
+	drop(runner);
+	let pending = port.get_count().unwrap();
+	thread::sleep(interval * 3);
+	assert_eq!(port.get_count().unwrap(), pending);
+	while port.get_count().unwrap() > 0 {
+		port.read().unwrap();
+	}
+
+	// With a count, the runner stops by itself
+	let _runner = MessageRunner::new(target, Message::new(TICK), interval, Some(2));
+	thread::sleep(interval * 5);
+	assert_eq!(port.get_count().unwrap(), 2);
+}
+
+#[test]
+fn test_message_runner_drop_with_full_port() {
+	use std::thread;
+	use std::time::Instant;
+
+	use crate::haiku_constant;
+	use crate::kernel::ports::Port;
+
+	// The runner is blocked on a full port, like when the looper that it
+	// sends to drops it from one of its handlers
+	const TICK: u32 = haiku_constant!('t', 'i', 'c', 'k');
+	let port = Port::create("message_runner_full_test", 1).unwrap();
+	let target = Messenger::from_port(&port).unwrap();
+	let interval = Duration::from_millis(5);
+	let runner = MessageRunner::new(target, Message::new(TICK), interval, None);
+	thread::sleep(interval * 10);
+	assert_eq!(port.get_count().unwrap(), 1);
+
+	let start = Instant::now();
+	drop(runner);
+	assert!(start.elapsed() < Duration::from_secs(1));
+}
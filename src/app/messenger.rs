@@ -3,23 +3,44 @@
 // All rights reserved. Distributed under the terms of the MIT License.
 //
 
+use std::cell::RefCell;
+use std::mem::size_of;
 use std::time::Duration;
 
-use libc::{port_id, B_MESSAGE_TYPE, B_OK};
+use libc::{port_id, B_MESSAGE_TYPE, B_MESSENGER_TYPE, B_OK};
 
+use crate::app::application::get_current_team_and_thread;
 use crate::app::message::Message;
-use crate::app::roster::{LAUNCH_ROSTER, ROSTER};
+use crate::app::roster::{LaunchRoster, Roster};
 use crate::app::sys::*;
+use crate::kernel::helpers::retry_on_interrupt_until;
 use crate::kernel::ports::Port;
+use crate::kernel::system_time;
 use crate::kernel::teams::Team;
 use crate::support::{ErrorKind, Flattenable, HaikuError, Result};
 
+// Every thread reuses a buffer to flatten messages, so that sending messages
+// does not allocate memory once the buffer is large enough
+//
+// The initializer is already const, but clippy still flags it when
+// building for Haiku.
+thread_local! {
+	#[allow(clippy::missing_const_for_thread_local)]
+	static SEND_BUFFER: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+// The largest capacity that the send buffer keeps after a message is sent.
+// A buffer that grew beyond it for an unusually large message is shrunk, so
+// that threads do not hold on to that memory.
+const SEND_BUFFER_MAX_CAPACITY: usize = 64 * 1024;
+
 /// A messenger is a helper that sends Messages through ports
 ///
 /// The best way to understand a messenger is that it is a communication pipe
 /// to a specific Looper/Handler pair. This pipe can work within the
 /// application, but it may also point to an external application, or a
 /// system service.
+#[derive(Clone)]
 pub struct Messenger {
 	port: Port,
 	token: i32,
@@ -68,49 +89,28 @@ impl Messenger {
 		// TODO: Haiku's C++ version of Messenger also stores the team, we may want that in ours too
 		let mut app_argv_only: bool = false;
 		let mut port: port_id = -1;
-		if team.is_some() {
-			match ROSTER.get_running_app_info(team.unwrap()) {
-				Some(info) => {
-					if info.signature != signature {
-						return Err(HaikuError::new(
-							ErrorKind::InvalidInput,
-							"signature did not match the signature of the team",
-						));
-					}
-					port = info.port;
-					app_argv_only = info.is_argv_only();
-				}
-				None => {
-					return Err(HaikuError::new(
-						ErrorKind::NotFound,
-						"cannot find application info for this team",
-					))
-				}
+		if let Some(team) = team {
+			let info = Roster::try_global()?.get_running_app_info(team)?;
+			if info.signature != signature {
+				return Err(HaikuError::new(
+					ErrorKind::InvalidInput,
+					"signature did not match the signature of the team",
+				));
 			}
+			port = info.port;
+			app_argv_only = info.is_argv_only();
 		} else {
 			// no team is given, first see if the launch roster has data
-			match LAUNCH_ROSTER.get_data(signature) {
-				Ok(data) => {
-					if data.what() == B_OK as u32 {
-						port = data.find_data("port", 0).unwrap_or(-1);
-					}
+			if let Ok(data) = LaunchRoster::try_global()?.get_data(signature) {
+				if data.what() == B_OK as u32 {
+					port = data.find_data("port", 0).unwrap_or(-1);
 				}
-				Err(_) => (),
 			}
 
 			if port < 0 {
-				match ROSTER.get_app_info(signature) {
-					Some(info) => {
-						port = info.port;
-						app_argv_only = info.is_argv_only();
-					}
-					None => {
-						return Err(HaikuError::new(
-							ErrorKind::NotFound,
-							"Cannot find a running app with this signature",
-						))
-					}
-				}
+				let info = Roster::try_global()?.get_app_info(signature)?;
+				port = info.port;
+				app_argv_only = info.is_argv_only();
 			}
 		}
 
@@ -127,6 +127,15 @@ impl Messenger {
 		})
 	}
 
+	/// Check whether the target of the messenger still exists
+	///
+	/// This checks whether the port that the messenger sends to is still
+	/// alive. It will be gone when the target application quits. Note that
+	/// the target may still go away right after this check.
+	pub fn is_valid(&self) -> bool {
+		self.port.get_info().is_ok()
+	}
+
 	/// Synchronously send a Message and wait for a reply
 	///
 	/// Optionally you can add a timeout, with a maximum wait time. If you do
@@ -150,16 +159,35 @@ impl Messenger {
 		message.header.flags |= MESSAGE_FLAG_REPLY_REQUIRED;
 		message.header.flags &= !MESSAGE_FLAG_REPLY_DONE;
 
-		let flattened_message = message.flatten();
-		self.port
-			.write(B_MESSAGE_TYPE as i32, &flattened_message)
-			.ok();
+		self.write_message(&message, None)?;
 
+		// The read is retried when it is interrupted, so that signals that
+		// are delivered while waiting do not make the request fail. Port::read()
+		// does this by itself.
 		let result = match timeout {
-			Some(timeout) => p.try_read(timeout)?,
+			Some(timeout) => {
+				let deadline = system_time() + timeout;
+				retry_on_interrupt_until(deadline, |timeout| p.try_read(timeout))?
+			}
 			None => p.read()?,
 		};
-		Message::unflatten(&result.1.as_slice())
+		Message::unflatten_received(result.1.as_slice())
+	}
+
+	/// Synchronously send a Message and measure the time it took to reply
+	///
+	/// This method works like `send_and_wait_for_reply()`, but in addition
+	/// to the reply it returns the time between sending the message and
+	/// receiving the reply. This can be used to diagnose slow receivers.
+	pub fn send_and_time(
+		&self,
+		message: Message,
+		timeout: Option<Duration>,
+	) -> Result<(Message, Duration)> {
+		let start = system_time();
+		let reply = self.send_and_wait_for_reply(message, timeout)?;
+		let round_trip = system_time().saturating_sub(start);
+		Ok((reply, round_trip))
 	}
 
 	/// Aynchronously send a Message and ask for a reply
@@ -179,10 +207,7 @@ impl Messenger {
 		message.header.flags |= MESSAGE_FLAG_REPLY_REQUIRED;
 		message.header.flags &= !MESSAGE_FLAG_REPLY_DONE;
 
-		let flattened_message = message.flatten();
-		self.port
-			.write(B_MESSAGE_TYPE as i32, &flattened_message)
-			.ok();
+		self.write_message(&message, None).ok();
 		Ok(())
 	}
 
@@ -192,25 +217,155 @@ impl Messenger {
 	/// that does ask for a reply. The `sender` argument is used to identify
 	/// the sender.
 	pub fn send(&self, mut message: Message, sender: &Messenger) -> Result<()> {
+		self.prepare_send(&mut message, sender)?;
+		self.write_message(&message, None).ok();
+		Ok(())
+	}
+
+	// Send a message without asking a reply, like `send()`, but give up when
+	// the port stays full for longer than `timeout`. Unlike `send()`, this
+	// returns the error if the message could not be written to the port.
+	pub(crate) fn send_with_timeout(
+		&self,
+		mut message: Message,
+		sender: &Messenger,
+		timeout: Duration,
+	) -> Result<()> {
+		self.prepare_send(&mut message, sender)?;
+		self.write_message(&message, Some(timeout))
+	}
+
+	// Fill out the header of a message that is sent by `sender`
+	fn prepare_send(&self, message: &mut Message, sender: &Messenger) -> Result<()> {
 		let info = sender.port.get_info()?;
-		// Fill out header info
 		message.header.target = self.token;
 		message.header.reply_port = sender.port.get_port_id();
 		message.header.reply_target = sender.token;
 		message.header.reply_team = info.team.get_team_id();
 		message.header.flags |= MESSAGE_FLAG_WAS_DELIVERED;
 		message.header.flags &= !MESSAGE_FLAG_REPLY_DONE;
-
-		let flattened_message = message.flatten();
-		self.port
-			.write(B_MESSAGE_TYPE as i32, &flattened_message)
-			.ok();
 		Ok(())
 	}
 
+	/// Send a reply to a message
+	///
+	/// The `reply` is marked as a reply, and sent to the target of this
+	/// messenger. It does not ask for a reply in turn. This messenger is
+	/// usually the return address of the message that is answered, see
+	/// `Message::get_return_address()`. The `Message::reply()` method finds
+	/// the return address and sends the reply in one go.
+	///
+	/// Unlike the other send methods, this returns the error if the reply
+	/// could not be written to the port.
+	pub fn send_reply(&self, mut reply: Message) -> Result<()> {
+		let (team, _) = get_current_team_and_thread();
+		reply.header.target = self.token;
+		reply.header.reply_port = -1;
+		reply.header.reply_target = B_NULL_TOKEN;
+		reply.header.reply_team = team;
+		reply.header.flags |= MESSAGE_FLAG_WAS_DELIVERED | MESSAGE_FLAG_IS_REPLY;
+		reply.header.flags &= !(MESSAGE_FLAG_REPLY_REQUIRED | MESSAGE_FLAG_REPLY_DONE);
+
+		self.write_message(&reply, None)
+	}
+
 	pub(crate) fn set_token(&mut self, token: i32) {
 		self.token = token;
 	}
+
+	// Flatten the message into the buffer of the current thread, and write
+	// it to the port. Large messages pass their data in an area, that is
+	// given to the team that owns the port. Without a `timeout`, this blocks
+	// until there is room in the port.
+	fn write_message(&self, message: &Message, timeout: Option<Duration>) -> Result<()> {
+		SEND_BUFFER.with(|buffer| {
+			let mut buffer = buffer.borrow_mut();
+			let result = self.flatten_and_write(message, &mut buffer, timeout);
+			if buffer.capacity() > SEND_BUFFER_MAX_CAPACITY {
+				buffer.clear();
+				buffer.shrink_to(SEND_BUFFER_MAX_CAPACITY);
+			}
+			result
+		})
+	}
+
+	fn flatten_and_write(
+		&self,
+		message: &Message,
+		buffer: &mut Vec<u8>,
+		timeout: Option<Duration>,
+	) -> Result<()> {
+		if message.should_pass_by_area() {
+			let target = self.port.get_info()?.team;
+			message.flatten_to_area(buffer, &target)?;
+		} else {
+			message.flatten_into(buffer);
+		}
+		match timeout {
+			Some(timeout) => self.port.try_write(B_MESSAGE_TYPE as i32, buffer, timeout),
+			None => self.port.write(B_MESSAGE_TYPE as i32, buffer),
+		}
+	}
+}
+
+impl PartialEq for Messenger {
+	/// Messengers are equal when they send to the same port and handler
+	fn eq(&self, other: &Messenger) -> bool {
+		self.port.get_port_id() == other.port.get_port_id() && self.token == other.token
+	}
+}
+
+impl Eq for Messenger {}
+
+impl Flattenable<Messenger> for Messenger {
+	const FLATTENED_SIZE: Option<usize> = Some(3 * size_of::<i32>());
+
+	fn type_code() -> u32 {
+		B_MESSENGER_TYPE
+	}
+
+	fn is_fixed_size() -> bool {
+		true
+	}
+
+	fn flattened_size(&self) -> usize {
+		3 * size_of::<i32>()
+	}
+
+	// The layout is the same as the BMessenger class: the port, the handler
+	// token and the team
+	fn flatten(&self) -> Vec<u8> {
+		let team = match self.port.get_info() {
+			Ok(info) => info.team.get_team_id(),
+			Err(_) => -1,
+		};
+		let mut vec: Vec<u8> = Vec::with_capacity(self.flattened_size());
+		vec.extend(self.port.get_port_id().flatten().iter());
+		vec.extend(self.token.flatten().iter());
+		vec.extend(team.flatten().iter());
+		vec
+	}
+
+	fn unflatten(buffer: &[u8]) -> Result<Messenger> {
+		if buffer.len() != 3 * size_of::<i32>() {
+			return Err(HaikuError::new(
+				ErrorKind::InvalidData,
+				"the buffer does not contain a flattened messenger",
+			));
+		}
+		let port = port_id::unflatten(&buffer[0..4])?;
+		let token = i32::unflatten(&buffer[4..8])?;
+		match Messenger::from_port_id(port) {
+			Some(mut messenger) => {
+				messenger.set_token(token);
+				Ok(messenger)
+			}
+			None => Err(HaikuError::new(
+				ErrorKind::NotFound,
+				"the port of the messenger does not exist",
+			)),
+		}
+	}
 }
 
 #[test]
@@ -224,12 +379,17 @@ fn test_messenger_creation() {
 	assert!(Messenger::from_signature("application/doesnotexist", None).is_err());
 
 	// Find team by signature and team id
-	let tracker_info = ROSTER.get_app_info("application/x-vnd.Be-TRAK").unwrap();
+	let tracker_info = Roster::try_global()
+		.unwrap()
+		.get_app_info("application/x-vnd.Be-TRAK")
+		.unwrap();
 	assert!(Messenger::from_signature(
 		"application/x-vnd.Be-TRAK",
-		Some(&Team::from(tracker_info.team).unwrap())
+		Some(&tracker_info.team().unwrap())
 	)
 	.is_ok());
+	assert!(tracker_info.thread().get_info().is_ok());
+	assert!(tracker_info.messenger().is_ok());
 }
 
 #[test]
@@ -255,3 +415,132 @@ fn test_synchronous_message_sending() {
 	let port = response_message.find_data::<i32>("port", 0).unwrap();
 	println!("registrar port: {}", port);
 }
+
+#[test]
+fn test_send_and_time() {
+	use crate::haiku_constant;
+	use std::thread;
+
+	const TIMED_REQUEST: u32 = haiku_constant!('t', 'r', 'e', 'q');
+	const TIMED_REPLY: u32 = haiku_constant!('t', 'r', 'p', 'l');
+	let delay = Duration::from_millis(10);
+
+	let port = Port::create("send_and_time_test", 1).unwrap();
+	let messenger = Messenger::from_port(&port).unwrap();
+	let responder = thread::spawn(move || {
+		let (_, buffer) = port.read().unwrap();
+		let request = Message::unflatten(&buffer).unwrap();
+		assert_eq!(request.what(), TIMED_REQUEST);
+		thread::sleep(delay);
+		let reply_port = Port::from_id(request.header.reply_port).unwrap();
+		let reply = Message::new(TIMED_REPLY);
+		reply_port
+			.write(B_MESSAGE_TYPE as i32, &reply.flatten())
+			.unwrap();
+	});
+
+	let (reply, round_trip) = messenger
+		.send_and_time(Message::new(TIMED_REQUEST), Some(Duration::from_secs(5)))
+		.unwrap();
+	responder.join().unwrap();
+	assert_eq!(reply.what(), TIMED_REPLY);
+	assert!(round_trip >= delay);
+	assert!(round_trip < Duration::from_secs(5));
+}
+
+#[test]
+fn test_send_many_messages() {
+	use crate::haiku_constant;
+	use std::thread;
+
+	const EVENT: u32 = haiku_constant!('e', 'v', 'n', 't');
+	const COUNT: i32 = 10_000;
+
+	let port = Port::create("send_many_messages_test", 100).unwrap();
+	let messenger = Messenger::from_port(&port).unwrap();
+	let receiver = thread::spawn(move || {
+		for expected in 0..COUNT {
+			let (_, buffer) = port.read().unwrap();
+			let message = Message::unflatten(&buffer).unwrap();
+			assert_eq!(message.find_data::<i32>("index", 0).unwrap(), expected);
+		}
+	});
+
+	// After the first message, the send buffer is large enough for all the
+	// others, so only the messages themselves are allocated
+	let send_buffer = || SEND_BUFFER.with(|buffer| buffer.borrow().as_ptr());
+	let mut first_buffer = None;
+	for index in 0..COUNT {
+		let mut message = Message::new(EVENT);
+		message.add_data("index", &index).unwrap();
+		messenger.send(message, &messenger).unwrap();
+		let buffer = send_buffer();
+		assert_eq!(*first_buffer.get_or_insert(buffer), buffer);
+	}
+	receiver.join().unwrap();
+}
+
+#[test]
+fn test_send_buffer_capacity() {
+	use crate::haiku_constant;
+
+	const EVENT: u32 = haiku_constant!('e', 'v', 'n', 't');
+
+	// A message with many fields is sent through the port, but its flattened
+	// size is over the capacity that the send buffer keeps
+	let port = Port::create("send_buffer_capacity_test", 1).unwrap();
+	let messenger = Messenger::from_port(&port).unwrap();
+	let mut message = Message::new(EVENT);
+	for index in 0..4000 {
+		message.add_data(&index.to_string(), &index).unwrap();
+	}
+	assert!(!message.should_pass_by_area());
+	assert!(message.flattened_size() > SEND_BUFFER_MAX_CAPACITY);
+	messenger.send(message, &messenger).unwrap();
+	let capacity = SEND_BUFFER.with(|buffer| buffer.borrow().capacity());
+	assert!(capacity <= SEND_BUFFER_MAX_CAPACITY);
+	let (_, buffer) = port.read().unwrap();
+	let message = Message::unflatten(&buffer).unwrap();
+	assert_eq!(message.find_data::<i32>("3999", 0).unwrap(), 3999);
+}
+
+#[test]
+fn test_messenger_flatten() {
+	let port = Port::create("messenger_flatten_test", 1).unwrap();
+	let mut messenger = Messenger::from_port(&port).unwrap();
+	messenger.set_token(42);
+	let buffer = messenger.flatten();
+	assert_eq!(buffer.len(), messenger.flattened_size());
+
+	let unflattened = Messenger::unflatten(&buffer).unwrap();
+	assert_eq!(unflattened.port.get_port_id(), port.get_port_id());
+	assert_eq!(unflattened.token, 42);
+	assert!(Messenger::unflatten(&buffer[0..8]).is_err());
+}
+
+#[test]
+fn test_messenger_is_valid() {
+	let port = Port::create("messenger_is_valid_test", 1).unwrap();
+	let messenger = Messenger::from_port(&port).unwrap();
+	assert!(messenger.is_valid());
+
+	// Dropping the owned port deletes it
+	drop(port);
+	assert!(!messenger.is_valid());
+}
+
+#[test]
+fn test_messenger_equality() {
+	let port = Port::create("messenger_equality_test", 1).unwrap();
+	let other_port = Port::create("messenger_equality_other", 1).unwrap();
+	let messenger = Messenger::from_port(&port).unwrap();
+	let clone = messenger.clone();
+	assert!(clone == messenger);
+	assert_eq!(clone.port.get_port_id(), port.get_port_id());
+
+	// Another port or another handler is a different target
+	assert!(Messenger::from_port(&other_port).unwrap() != messenger);
+	let mut handler = messenger.clone();
+	handler.set_token(42);
+	assert!(handler != messenger);
+}
@@ -7,17 +7,30 @@
 //! communicate with other applications and services
 
 mod application;
+pub mod constants;
 mod looper;
 mod message;
+mod messagerunner;
 mod messenger;
 mod notification;
+mod replyhandler;
 mod roster;
+pub mod scripting;
 pub(crate) mod serverlink;
 pub(crate) mod sys;
+mod timer;
 
 pub use self::application::{Application, ApplicationDelegate, ApplicationHooks, Context};
-pub use self::looper::{Handler, Looper, LooperDelegate};
+pub use self::looper::{FilterResult, FnHandler, Handler, Looper, LooperDelegate, MessageFilter};
 pub use self::message::Message;
+#[doc(hidden)]
+pub use self::message::MessageValue;
+pub use self::messagerunner::MessageRunner;
 pub use self::messenger::Messenger;
 pub use self::notification::{Notification, NotificationType};
-pub use self::roster::{AppInfo, Roster, ROSTER};
+pub use self::replyhandler::ReplyHandler;
+pub use self::roster::{
+	AppInfo, ApplicationRegistrationResult, ApplicationRegistrationStatus, Roster, RosterEvents,
+	ROSTER,
+};
+pub use self::serverlink::DesktopConnectionOptions;
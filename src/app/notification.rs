@@ -3,18 +3,28 @@
 // All rights reserved. Distributed under the terms of the MIT License.
 //
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 use crate::app::application::get_current_team_and_thread;
-use crate::app::{Message, Messenger, ROSTER};
-use crate::haiku_constant;
+use crate::app::constants::NOTIFICATION_MESSAGE;
+use crate::app::{Message, Messenger, Roster};
+use crate::interface::Rect;
 use crate::kernel::teams::Team;
-use crate::support::Result;
+use crate::support::{ErrorKind, HaikuError, Result};
 
-const NOTIFICATION_MESSAGE: u32 = haiku_constant!('n', 's', 's', 'm');
 const NOTIFICATION_SERVER_SIGNATURE: &str = "application/x-vnd.Haiku-notification_server";
 
+// The display time in microseconds of a notification that replaces one that
+// is withdrawn
+const WITHDRAW_TIMEOUT: i64 = 1;
+
+// The default width and height of an icon (B_LARGE_ICON)
+const DEFAULT_ICON_SIZE: u32 = 32;
+
+// The color space of the icon bitmap (B_RGBA32)
+const ICON_COLOR_SPACE: i32 = 0x2008;
+
 #[derive(PartialEq)]
 /// The type of notification
 ///
@@ -46,7 +56,9 @@ pub enum NotificationType {
 /// server, you create an object from this class and set the parameters that
 /// you want to tweak.
 ///
-/// By default all parameters are optional and have a default value.
+/// By default all parameters are optional and have a default value. The
+/// default values are for the current application. Programs that do not
+/// create an `Application` can use `Notification::builder()` instead.
 ///
 /// # Example
 ///
@@ -99,27 +111,79 @@ pub struct Notification {
 	/// 1.0 being set.
 	pub progress: f32,
 
-	// TODO: onclick_app: Option<String>,
-	// TODO: onclick_file: entry_ref,
-	// TODO: onclick_refs: Vec<entry_ref>,
-	// TODO: onclick_args: Vec<String>,
-	// TODO: icon
+	/// The signature of the application that is launched on a click
+	///
+	/// When the user clicks the notification, the notification server
+	/// launches this application with the `onclick_refs` and the
+	/// `onclick_args`.
+	pub onclick_app: Option<String>,
+
+	/// The file that is opened on a click
+	///
+	/// When the user clicks the notification, the notification server opens
+	/// this file with its preferred application.
+	pub onclick_file: Option<PathBuf>,
+
+	/// The files that are passed to the `onclick_app` on a click
+	pub onclick_refs: Vec<PathBuf>,
+
+	/// The arguments that are passed to the `onclick_app` on a click
+	pub onclick_args: Vec<String>,
+
+	/// The icon that is shown with the notification
+	///
+	/// The icon is a square bitmap in the `B_RGBA32` color space, with four
+	/// bytes per pixel, one row after another. Its width and height in pixels
+	/// is set by `icon_size`. If there is no icon, the notification server
+	/// shows the icon of the application.
+	pub icon: Option<Vec<u8>>,
+
+	/// The width and height of the `icon` in pixels
+	///
+	/// This is 32 by default, which is the size of the large icons in Haiku.
+	pub icon_size: u32,
+
 	source_signature: String,
 	source_name: String,
 }
 
 impl Default for Notification {
+	/// Create a notification with the default values
+	///
+	/// # Panics
+	///
+	/// This panics if the current program is not registered at the
+	/// registrar. Use `Notification::try_default()` in programs that may not
+	/// be registered, like command line tools.
 	fn default() -> Self {
-		// get app info
+		Notification::try_default()
+			.expect("the program is not registered as an application at the registrar")
+	}
+}
+
+impl Notification {
+	/// Create a notification with the default values
+	///
+	/// The notification is sent on behalf of the current application. If the
+	/// current program is not registered as an application at the
+	/// registrar, for example because it does not create an `Application`,
+	/// an error with `ErrorKind::NotFound` is returned.
+	pub fn try_default() -> Result<Notification> {
 		let (team, _) = get_current_team_and_thread();
-		let info = ROSTER
-			.get_running_app_info(&Team::from(team).unwrap())
-			.unwrap();
-		let filename = match Path::new(&info.path).file_name() {
-			Some(file) => String::from(file.to_str().unwrap()),
-			None => String::new(),
-		};
+		let team = Team::try_from(team)?;
+		Notification::default_for_team(Roster::try_global()?, &team)
+	}
 
+	/// Create a notification with the default values for an application
+	///
+	/// Unlike `Notification::default()`, this does not ask the registrar for
+	/// the details of the current application, so it can be used by programs
+	/// that do not create an `Application`, like command line tools. The
+	/// notification is shown on behalf of the application with `signature`,
+	/// and with `app_name` as its name.
+	///
+	/// The other values can be set on the returned notification.
+	pub fn builder(signature: &str, app_name: &str) -> Notification {
 		Notification {
 			notification_type: NotificationType::Information,
 			group: None,
@@ -127,18 +191,29 @@ impl Default for Notification {
 			content: None,
 			id: None,
 			progress: 0.0,
-			// onclick_app: String::new(),
-			// onclick_file,
-			// onclick_refs: Vec::new(),
-			// onclick_args: Vec::new(),
-			// icon,
-			source_signature: info.signature,
-			source_name: filename,
+			onclick_app: None,
+			onclick_file: None,
+			onclick_refs: Vec::new(),
+			onclick_args: Vec::new(),
+			icon: None,
+			icon_size: DEFAULT_ICON_SIZE,
+			source_signature: String::from(signature),
+			source_name: String::from(app_name),
 		}
 	}
-}
 
-impl Notification {
+	// Create a notification with the default values for the application in
+	// `team`, as it is known by the `roster`
+	fn default_for_team(roster: &Roster, team: &Team) -> Result<Notification> {
+		let info = roster.get_running_app_info(team)?;
+		let filename = match Path::new(&info.path).file_name() {
+			Some(file) => String::from(file.to_str().unwrap()),
+			None => String::new(),
+		};
+
+		Ok(Notification::builder(&info.signature, &filename))
+	}
+
 	fn to_message(&self) -> Result<Message> {
 		let mut message = Message::new(NOTIFICATION_MESSAGE);
 		message.add_data("_appname", &self.source_name).unwrap();
@@ -164,10 +239,6 @@ impl Notification {
 		self.id
 			.as_ref()
 			.map(|id| message.add_data("_messageID", id).unwrap());
-		if self.notification_type == NotificationType::Progress {
-			message.add_data("_progress", &self.progress).unwrap();
-		}
-
 		if self.notification_type == NotificationType::Progress {
 			let progress = if self.progress < 0.0 {
 				0.0
@@ -178,14 +249,45 @@ impl Notification {
 			};
 			message.add_data("_progress", &progress).unwrap();
 		}
-		// TODO: message.add_data("_onClickApp"
-		// TODO: message.add_data("_onClickFile"
-		// TODO: message.add_data("_onClickRef"
-		// TODO: message.add_data("_onClickArgv"
-		// TODO: message.add_data("_icon"
+		if let Some(app) = &self.onclick_app {
+			message.add_string("_onClickApp", app)?;
+		}
+		if let Some(file) = &self.onclick_file {
+			message.add_path("_onClickFile", file)?;
+		}
+		for path in &self.onclick_refs {
+			message.add_path("_onClickRef", path)?;
+		}
+		for arg in &self.onclick_args {
+			message.add_string("_onClickArgv", arg)?;
+		}
+		if let Some(icon) = &self.icon {
+			message.add_message("_icon", &self.icon_to_archive(icon)?)?;
+		}
 		Ok(message)
 	}
 
+	// The notification server restores the icon as a BBitmap, so the data is
+	// stored in the layout of an archived bitmap
+	fn icon_to_archive(&self, icon: &[u8]) -> Result<Message> {
+		let bytes_per_row = self.icon_size as usize * 4;
+		if self.icon_size == 0 || icon.len() != bytes_per_row * self.icon_size as usize {
+			return Err(HaikuError::new(
+				ErrorKind::InvalidInput,
+				"the icon data does not match the icon size",
+			));
+		}
+		let bottom_right = (self.icon_size - 1) as f32;
+		let mut archive = Message::new(0);
+		archive.add_string("class", "BBitmap")?;
+		archive.add_data("_frame", &Rect::new(0.0, 0.0, bottom_right, bottom_right))?;
+		archive.add_int32("_cspace", ICON_COLOR_SPACE)?;
+		archive.add_int32("_bmflags", 0)?;
+		archive.add_int32("_rowbytes", bytes_per_row as i32)?;
+		archive.adopt_raw("_data", icon.to_vec())?;
+		Ok(archive)
+	}
+
 	/// Send the notification to the system to display it
 	///
 	/// It is possible to add a duration. This will override the default
@@ -207,6 +309,39 @@ impl Notification {
 		messenger.send(message, replyto)?;
 		Ok(())
 	}
+
+	/// Withdraw the notification with the identifier `id`
+	///
+	/// Use this to dismiss a notification that is no longer relevant, like
+	/// a progress notification of a task that has completed. The
+	/// notification must have been sent with the same `id`.
+	///
+	/// The notification server has no request to remove a notification.
+	/// Instead, the notification is replaced by one that expires
+	/// immediately. If there is no notification with the `id`, this will
+	/// briefly show an empty notification.
+	pub fn withdraw(id: &str, replyto: &Messenger) -> Result<()> {
+		if id.is_empty() {
+			return Err(HaikuError::new(
+				ErrorKind::InvalidInput,
+				"a notification can only be withdrawn by a non-empty id",
+			));
+		}
+		let notification = Notification {
+			id: Some(String::from(id)),
+			..Notification::try_default()?
+		};
+		let message = notification.to_withdrawal_message()?;
+		let messenger = Messenger::from_signature(NOTIFICATION_SERVER_SIGNATURE, None)?;
+		messenger.send(message, replyto)?;
+		Ok(())
+	}
+
+	fn to_withdrawal_message(&self) -> Result<Message> {
+		let mut message = self.to_message()?;
+		message.add_data("timeout", &WITHDRAW_TIMEOUT)?;
+		Ok(message)
+	}
 }
 
 #[cfg(test)]
@@ -231,7 +366,146 @@ mod tests {
 
 	#[test]
 	fn test_notification() {
-		let application = Application::new(MOCK_SIGNATURE, MockApplicationState {});
+		let application = Application::new(MOCK_SIGNATURE, MockApplicationState {}).unwrap();
 		application.run().unwrap();
 	}
+
+	#[test]
+	fn test_notification_withdrawal_message() {
+		let notification = Notification {
+			id: Some(String::from("progress-42")),
+			..Notification::builder(MOCK_SIGNATURE, "notification_test")
+		};
+		let message = notification.to_withdrawal_message().unwrap();
+		assert_eq!(message.what(), NOTIFICATION_MESSAGE);
+		assert_eq!(
+			message.find_data::<String>("_messageID", 0).unwrap(),
+			"progress-42"
+		);
+		assert_eq!(
+			message.find_data::<String>("_signature", 0).unwrap(),
+			MOCK_SIGNATURE
+		);
+		assert_eq!(message.find_data::<i32>("_type", 0).unwrap(), 0);
+		assert_eq!(
+			message.find_data::<i64>("timeout", 0).unwrap(),
+			WITHDRAW_TIMEOUT
+		);
+
+		// A notification can only be withdrawn by its id
+		let port = crate::kernel::ports::Port::create("withdrawal test", 1).unwrap();
+		let messenger = Messenger::from_port(&port).unwrap();
+		let error = Notification::withdraw("", &messenger).unwrap_err();
+		assert!(matches!(error.kind(), ErrorKind::InvalidInput));
+	}
+
+	#[test]
+	fn test_notification_progress_is_clamped() {
+		let notification = Notification {
+			notification_type: NotificationType::Progress,
+			progress: 2.0,
+			..Notification::builder(MOCK_SIGNATURE, "notification_test")
+		};
+		let message = notification.to_message().unwrap();
+		assert_eq!(message.get_info("_progress").unwrap().1, 1);
+		assert_eq!(message.find_data::<f32>("_progress", 0).unwrap(), 1.0);
+	}
+
+	#[test]
+	fn test_notification_icon() {
+		let mut notification = Notification {
+			icon: Some(vec![0xff; 16 * 16 * 4]),
+			icon_size: 16,
+			..Notification::builder(MOCK_SIGNATURE, "notification_test")
+		};
+		let message = notification.to_message().unwrap();
+		let icon = message.find_message("_icon", 0).unwrap();
+		assert_eq!(
+			icon.find_data::<Rect>("_frame", 0).unwrap(),
+			Rect::new(0.0, 0.0, 15.0, 15.0)
+		);
+		assert_eq!(icon.find_int32("_rowbytes", 0).unwrap(), 64);
+		assert_eq!(icon.find_data_raw("_data", 0).unwrap().len(), 16 * 16 * 4);
+
+		// The data has to match the size of the icon
+		notification.icon_size = 32;
+		let error = notification.to_message().unwrap_err();
+		assert!(matches!(error.kind(), ErrorKind::InvalidInput));
+
+		// Without an icon there is no icon field
+		notification.icon = None;
+		let message = notification.to_message().unwrap();
+		assert!(message.get_info("_icon").is_none());
+	}
+
+	#[test]
+	fn test_notification_onclick() {
+		let notification = Notification {
+			onclick_app: Some(String::from("application/x-vnd.Haiku-StyledEdit")),
+			onclick_args: vec![String::from("--first"), String::from("--second")],
+			..Notification::builder(MOCK_SIGNATURE, "notification_test")
+		};
+		let message = notification.to_message().unwrap();
+		assert_eq!(
+			message.find_string("_onClickApp", 0).unwrap(),
+			"application/x-vnd.Haiku-StyledEdit"
+		);
+		assert_eq!(message.get_info("_onClickArgv").unwrap().1, 2);
+		assert_eq!(message.find_string("_onClickArgv", 1).unwrap(), "--second");
+		assert!(message.get_info("_onClickFile").is_none());
+		assert!(message.get_info("_onClickRef").is_none());
+	}
+
+	#[test]
+	fn test_notification_builder() {
+		use crate::kernel::ports::Port;
+
+		let mut notification = Notification::builder(MOCK_SIGNATURE, "notification_test");
+		notification.title = Some(String::from("Builder"));
+		notification.content = Some(String::from("This notification has no Application"));
+		let message = notification.to_message().unwrap();
+		assert_eq!(
+			message.find_string("_signature", 0).unwrap(),
+			MOCK_SIGNATURE
+		);
+		assert_eq!(
+			message.find_string("_appname", 0).unwrap(),
+			"notification_test"
+		);
+
+		let port = Port::create("builder test", 1).unwrap();
+		let messenger = Messenger::from_port(&port).unwrap();
+		notification.send(&messenger, None).unwrap();
+	}
+
+	#[test]
+	fn test_notification_without_registration() {
+		use libc::B_MESSAGE_TYPE;
+		use std::thread;
+
+		use crate::kernel::ports::Port;
+		use crate::support::Flattenable;
+
+		// The mock registrar does not know the application
+		let registrar_port = Port::create("mock registrar", 10).unwrap();
+		let roster = Roster::with_messenger(Messenger::from_port(&registrar_port).unwrap());
+		let registrar = thread::spawn(move || {
+			let (_, buffer) = registrar_port.read().unwrap();
+			let request = Message::unflatten(&buffer).unwrap();
+			let reply = Message::new(0);
+			let reply_port = Port::from_id(request.header.reply_port).unwrap();
+			reply_port
+				.write(B_MESSAGE_TYPE as i32, &reply.flatten())
+				.unwrap();
+		});
+
+		let (team, _) = get_current_team_and_thread();
+		let team = Team::try_from(team).unwrap();
+		let error = match Notification::default_for_team(&roster, &team) {
+			Ok(_) => panic!("the application should not be registered"),
+			Err(e) => e,
+		};
+		registrar.join().unwrap();
+		assert!(matches!(error.kind(), ErrorKind::NotFound));
+	}
 }
@@ -0,0 +1,227 @@
+//
+// Copyright 2024, Niels Sascha Reedijk <niels.reedijk@gmail.com>
+// All rights reserved. Distributed under the terms of the MIT License.
+//
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::app::{Context, Handler, Message, Messenger};
+use crate::support::{ErrorKind, HaikuError, Result};
+
+// The field that carries the correlation token in requests and replies
+const REPLY_TOKEN_FIELD: &str = "_reply_token";
+
+type ReplyCallback<A> = Box<dyn FnOnce(&Context<A>, &Message) + Send>;
+
+struct PendingReplies<A>
+where
+	A: Send + 'static,
+{
+	next_token: u64,
+	callbacks: HashMap<u64, ReplyCallback<A>>,
+}
+
+/// A helper to match asynchronous replies with their requests
+///
+/// Replies to messages that are sent with `Messenger::send_and_ask_reply()`
+/// arrive as ordinary messages at the looper of the `reply_to` messenger.
+/// The `ReplyHandler` adds a correlation token to every request that is sent
+/// with `send_request()`, and when a reply with the same token comes in, the
+/// callback for that request is invoked.
+///
+/// The `ReplyHandler` is a shared handle, so it can be cloned. Add a clone to
+/// the looper that receives the replies, either as a Handler, or by calling
+/// `dispatch_reply()` from the `message_received()` method of your own
+/// Handler. Keep another clone to send requests.
+///
+/// The receiver of a request has to copy the token into the reply, using
+/// `ReplyHandler::prepare_reply()`.
+pub struct ReplyHandler<A>
+where
+	A: Send + 'static,
+{
+	pending: Arc<Mutex<PendingReplies<A>>>,
+}
+
+impl<A> ReplyHandler<A>
+where
+	A: Send + 'static,
+{
+	/// Create a new ReplyHandler without any outstanding requests
+	pub fn new() -> ReplyHandler<A> {
+		ReplyHandler {
+			pending: Arc::new(Mutex::new(PendingReplies {
+				next_token: 1,
+				callbacks: HashMap::new(),
+			})),
+		}
+	}
+
+	/// Send a request to the `target` and register a callback for the reply
+	///
+	/// The reply will be sent to `reply_to`, which should point to a looper
+	/// that hosts this ReplyHandler. The `callback` is called once, from that
+	/// looper's thread, when the reply arrives.
+	pub fn send_request<F>(
+		&self,
+		target: &Messenger,
+		mut message: Message,
+		reply_to: &Messenger,
+		callback: F,
+	) -> Result<()>
+	where
+		F: FnOnce(&Context<A>, &Message) + Send + 'static,
+	{
+		if message.get_info(REPLY_TOKEN_FIELD).is_some() {
+			return Err(HaikuError::new(
+				ErrorKind::InvalidInput,
+				"the message already contains a reply token",
+			));
+		}
+		let token = {
+			let mut pending = self.pending.lock().unwrap();
+			let token = pending.next_token;
+			pending.next_token += 1;
+			pending.callbacks.insert(token, Box::new(callback));
+			token
+		};
+		message.add_data(REPLY_TOKEN_FIELD, &token)?;
+		let result = target.send_and_ask_reply(message, reply_to);
+		if result.is_err() {
+			self.pending.lock().unwrap().callbacks.remove(&token);
+		}
+		result
+	}
+
+	/// Invoke the callback if the message is a reply to one of the requests
+	///
+	/// This method returns `true` if the message is a reply that belongs to an
+	/// outstanding request, and `false` if it is any other message.
+	pub fn dispatch_reply(&self, context: &Context<A>, message: &Message) -> bool {
+		let token: u64 = match message.find_data(REPLY_TOKEN_FIELD, 0) {
+			Ok(token) => token,
+			Err(_) => return false,
+		};
+		// Release the lock before calling back, so that the callback may send
+		// new requests
+		let callback = self.pending.lock().unwrap().callbacks.remove(&token);
+		match callback {
+			Some(callback) => {
+				callback(context, message);
+				true
+			}
+			None => false,
+		}
+	}
+
+	/// Get the number of requests that are waiting for a reply
+	pub fn pending_count(&self) -> usize {
+		self.pending.lock().unwrap().callbacks.len()
+	}
+
+	/// Copy the correlation token of a `request` into its `reply`
+	///
+	/// The receiver of a request that was sent by a `ReplyHandler` should call
+	/// this before sending the reply. It does nothing if the request does not
+	/// have a token.
+	pub fn prepare_reply(request: &Message, reply: &mut Message) -> Result<()> {
+		match request.find_data::<u64>(REPLY_TOKEN_FIELD, 0) {
+			Ok(token) => reply.add_data(REPLY_TOKEN_FIELD, &token),
+			Err(_) => Ok(()),
+		}
+	}
+}
+
+impl<A> Default for ReplyHandler<A>
+where
+	A: Send + 'static,
+{
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<A> Clone for ReplyHandler<A>
+where
+	A: Send + 'static,
+{
+	fn clone(&self) -> Self {
+		ReplyHandler {
+			pending: self.pending.clone(),
+		}
+	}
+}
+
+impl<A> Handler<A> for ReplyHandler<A>
+where
+	A: Send + 'static,
+{
+	fn message_received(&mut self, context: &Context<A>, message: &Message) {
+		self.dispatch_reply(context, message);
+	}
+}
+
+#[test]
+fn test_reply_handler() {
+	use std::sync::mpsc::channel;
+	use std::thread;
+	use std::time::Duration;
+
+	use crate::app::looper::create_test_looper;
+	use crate::app::sys::QUIT;
+	use crate::haiku_constant;
+	use crate::kernel::ports::Port;
+	use crate::support::Flattenable;
+
+	const REQUEST: u32 = haiku_constant!('r', 'e', 'q', 'u');
+	const REPLY: u32 = haiku_constant!('r', 'p', 'l', 'y');
+
+	// The responder reads three requests, and replies in reverse order
+	let responder_port = Port::create("reply handler responder", 10).unwrap();
+	let target = Messenger::from_port(&responder_port).unwrap();
+	let responder = thread::spawn(move || {
+		let sender = Messenger::from_port(&responder_port).unwrap();
+		let mut requests: Vec<Message> = Vec::new();
+		for _ in 0..3 {
+			let (_, buffer) = responder_port.read().unwrap();
+			requests.push(Message::unflatten(&buffer).unwrap());
+		}
+		for request in requests.iter().rev() {
+			let value: i32 = request.find_data("value", 0).unwrap();
+			let mut reply = Message::new(REPLY);
+			reply.add_data("value", &(value * 10)).unwrap();
+			ReplyHandler::<()>::prepare_reply(request, &mut reply).unwrap();
+			let return_address = request.get_return_address().unwrap();
+			return_address.send(reply, &sender).unwrap();
+		}
+	});
+
+	let reply_handler: ReplyHandler<()> = ReplyHandler::new();
+	let looper = create_test_looper("reply handler test", Box::new(reply_handler.clone()));
+	let reply_to = looper.get_messenger();
+	looper.run().unwrap();
+
+	let (sender, receiver) = channel();
+	for value in 1..=3 {
+		let mut request = Message::new(REQUEST);
+		request.add_data("value", &value).unwrap();
+		let sender = sender.clone();
+		reply_handler
+			.send_request(&target, request, &reply_to, move |_context, reply| {
+				let reply_value: i32 = reply.find_data("value", 0).unwrap();
+				sender.send((value, reply_value)).unwrap();
+			})
+			.unwrap();
+	}
+	responder.join().unwrap();
+
+	let mut results: Vec<(i32, i32)> = (0..3)
+		.map(|_| receiver.recv_timeout(Duration::from_secs(5)).unwrap())
+		.collect();
+	assert_eq!(results[0].0, 3);
+	results.sort();
+	assert_eq!(results, vec![(1, 10), (2, 20), (3, 30)]);
+	assert_eq!(reply_handler.pending_count(), 0);
+	reply_to.send(Message::new(QUIT), &reply_to).unwrap();
+}
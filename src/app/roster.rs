@@ -3,36 +3,97 @@
 // All rights reserved. Distributed under the terms of the MIT License.
 //
 
+use std::ffi::CString;
+use std::ops::BitOr;
+use std::path::{Path, PathBuf};
 use std::result;
 use std::str::{from_utf8, Utf8Error};
+use std::sync::OnceLock;
+use std::time::Duration;
 use std::{mem, ptr};
 
 use libc::{
-	c_char, dev_t, getuid, ino_t, port_id, status_t, team_id, thread_id, B_ERROR,
-	B_FILE_NAME_LENGTH, B_MIME_TYPE_LENGTH, B_OK,
+	c_char, dev_t, getuid, ino_t, load_image, port_id, status_t, team_id, thread_id, B_ERROR,
+	B_FILE_NAME_LENGTH, B_LAUNCH_FAILED_APP_NOT_FOUND, B_MIME_TYPE_LENGTH, B_OK,
 };
 
+use crate::app::application::{argv_message, get_app_flags};
+use crate::app::constants::{
+	B_GET_LAUNCH_DATA, B_READY_TO_RUN, B_REG_GET_APP_INFO, B_REG_GET_APP_LIST,
+	B_REG_GET_MIME_MESSENGER, B_REG_SUCCESS,
+};
 use crate::app::message::Message;
 use crate::app::messenger::Messenger;
 use crate::haiku_constant;
 use crate::kernel::helpers;
 use crate::kernel::ports::Port;
 use crate::kernel::teams::Team;
+use crate::kernel::threads::Thread;
 use crate::storage::sys::entry_ref;
-use crate::support::{ErrorKind, Flattenable, HaikuError, Result};
+use crate::storage::MimeType;
+use crate::support::{retry, ErrorKind, Flattenable, HaikuError, Result};
+
+// The number of times a query to a system server is attempted, when it
+// fails with a transient error
+const QUERY_ATTEMPTS: u32 = 5;
+// The time to wait before the first retry of a query. This is doubled for
+// every next retry.
+const QUERY_BACKOFF: Duration = Duration::from_millis(50);
+// The time to wait for the reply to a single attempt of a query
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+// The time to wait for the reply to a request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Send a request to a system server and wait for the reply
+//
+// The request is sent once. Use this for requests that change the state of
+// the server, like registering an application, as a request that seemed to
+// fail may still have been carried out. A server that does not reply in
+// time results in an error with `ErrorKind::TimedOut`.
+fn send_request(messenger: &Messenger, request: Message) -> Result<Message> {
+	messenger.send_and_wait_for_reply(request, Some(REQUEST_TIMEOUT))
+}
+
+// Send a query to a system server and wait for the reply
+//
+// A query does not change the state of the server, so it is retried when
+// the server is busy or does not respond in time, which may happen when
+// the system is starting.
+fn send_query(messenger: &Messenger, query: Message) -> Result<Message> {
+	let data = Vec::<u8>::from(&query);
+	retry(QUERY_ATTEMPTS, QUERY_BACKOFF, || {
+		let query = Message::try_from(data.as_slice())?;
+		messenger.send_and_wait_for_reply(query, Some(QUERY_TIMEOUT))
+	})
+}
 
 pub(crate) struct LaunchRoster {
 	messenger: Messenger,
 }
 
 impl LaunchRoster {
-	fn init() -> LaunchRoster {
-		let port = Port::find("system:launch_daemon").expect("Cannot find the launch daemon");
-		let roster_messenger =
-			Messenger::from_port(&port).expect("Cannot connect to the launch daemon");
-		LaunchRoster {
-			messenger: roster_messenger,
+	fn connect() -> Result<LaunchRoster> {
+		let port = match Port::find("system:launch_daemon") {
+			Some(port) => port,
+			None => {
+				return Err(HaikuError::new(
+					ErrorKind::NotFound,
+					"cannot find the launch_daemon",
+				))
+			}
+		};
+		let messenger = Messenger::from_port(&port).unwrap();
+		Ok(LaunchRoster { messenger })
+	}
+
+	/// Get the shared connection to the launch_daemon, without panicking
+	pub(crate) fn try_global() -> Result<&'static LaunchRoster> {
+		static GLOBAL_LAUNCH_ROSTER: OnceLock<LaunchRoster> = OnceLock::new();
+		if let Some(launch_roster) = GLOBAL_LAUNCH_ROSTER.get() {
+			return Ok(launch_roster);
 		}
+		let launch_roster = LaunchRoster::connect()?;
+		Ok(GLOBAL_LAUNCH_ROSTER.get_or_init(|| launch_roster))
 	}
 
 	/// Method to get the data that the launch_daemon has on an application
@@ -42,63 +103,152 @@ impl LaunchRoster {
 	/// returned. Note that inside this message there still may be an error,
 	/// but this is stored in the message.what.
 	pub(crate) fn get_data(&self, signature: &str) -> Result<Message> {
-		let constant: u32 = haiku_constant!('l', 'n', 'd', 'a');
-		let mut message = Message::new(constant);
-		// TODO: add support for &str as Flattenable
-		message.add_data("name", &String::from(signature)).unwrap();
+		let mut message = Message::new(B_GET_LAUNCH_DATA);
+		message.add_string("name", signature).unwrap();
 		let uid = unsafe { getuid() };
 		message.add_data("user", &(uid as i32)).unwrap();
 
 		// Send message
-		let response = self.messenger.send_and_wait_for_reply(message, None)?;
+		let response = send_query(&self.messenger, message)?;
 		Ok(response)
 	}
 }
 
-pub(crate) enum ApplicationRegistrationStatus {
+/// The registration status of an application at the registrar
+pub enum ApplicationRegistrationStatus {
+	/// The application is fully registered
 	Registered(AppInfo),
+	/// The application is pre-registered
+	///
+	/// This is the case when the application is launched by the roster. The
+	/// launcher registers the application before it is started, and the
+	/// application has to complete the registration.
 	PreRegistered(AppInfo),
+	/// The application is not registered
 	NotRegistered,
 }
 
-pub(crate) enum ApplicationRegistrationResult {
+/// The result of adding an application to the registrar
+pub enum ApplicationRegistrationResult {
+	/// The application is fully registered
 	Registered,
+	/// The application is pre-registered, with the token that identifies it
+	/// until the registration is completed
 	PreRegistered(i32),
+	/// Another instance of a single or exclusive launch application is
+	/// already running in the team, with the pre-registration token
 	OtherInstance(team_id, i32),
 }
 
+// The outcome of registering an application
+pub(crate) enum Registration {
+	// The application is registered, and uses this port for its main looper
+	Registered(Port),
+	// Another instance of the single or exclusive launch application is
+	// already running in this team
+	OtherInstance(team_id),
+}
+
 /// This struct provides information about applications on the Haiku system
 ///
-/// This struct should be accessed through the static `ROSTER` reference. It
-/// is automatically initialized to retrieve information from Haiku's
-/// registrar.
+/// This struct should be accessed through the static `ROSTER` reference, or
+/// through `Roster::try_global()`. It is automatically initialized to
+/// retrieve information from Haiku's registrar.
 pub struct Roster {
 	messenger: Messenger,
 }
 
 impl Roster {
+	fn connect() -> Result<Roster> {
+		let roster_data =
+			LaunchRoster::try_global()?.get_data("application/x-vnd.haiku-registrar")?;
+		if roster_data.what() != (B_OK as u32) {
+			return Err(HaikuError::new(
+				ErrorKind::NotFound,
+				"the launch_daemon does not know the registrar",
+			));
+		}
+		let port: port_id = roster_data.find_data("port", 0)?;
+		match Messenger::from_port_id(port) {
+			Some(messenger) => Ok(Roster { messenger }),
+			None => Err(HaikuError::new(
+				ErrorKind::NotFound,
+				"the port of the registrar is invalid",
+			)),
+		}
+	}
+
+	// Create a Roster that talks to a mock registrar
+	#[cfg(test)]
+	pub(crate) fn with_messenger(messenger: Messenger) -> Roster {
+		Roster { messenger }
+	}
+
+	/// Get the global `Roster`, or an error if the registrar is unreachable
+	///
+	/// This is the non-panicking alternative to the `ROSTER` static. Use it
+	/// in programs that should keep running when the launch_daemon or the
+	/// registrar are not available, like in a headless environment. If the
+	/// connection fails, a next call will try again.
+	pub fn try_global() -> Result<&'static Roster> {
+		static GLOBAL_ROSTER: OnceLock<Roster> = OnceLock::new();
+		if let Some(roster) = GLOBAL_ROSTER.get() {
+			return Ok(roster);
+		}
+		let roster = Roster::connect()?;
+		Ok(GLOBAL_ROSTER.get_or_init(|| roster))
+	}
+
 	/// Get a list of teams that are currently running
 	///
 	/// If there is a problem connecting to the registrar, this method
 	/// will return None.
 	pub fn get_app_list(&self) -> Option<Vec<Team>> {
-		let request = Message::new(haiku_constant!('r', 'g', 'a', 'l'));
-		let response = self.messenger.send_and_wait_for_reply(request, None);
+		let request = Message::new(B_REG_GET_APP_LIST);
+		self.request_app_list(request)
+	}
+
+	/// Get the teams of the running instances of an application
+	///
+	/// An application that allows multiple launches may run in several teams.
+	/// If there are no running instances, or if there is a problem connecting
+	/// to the registrar, the list is empty.
+	pub fn get_app_teams(&self, signature: &str) -> Vec<Team> {
+		let mut request = Message::new(B_REG_GET_APP_LIST);
+		if request.add_string("signature", signature).is_err() {
+			return Vec::new();
+		}
+		self.request_app_list(request).unwrap_or_default()
+	}
+
+	/// Check if an application with `signature` is running
+	pub fn is_running(&self, signature: &str) -> bool {
+		self.get_app_info(signature)
+			.is_ok_and(|info| info.team().is_some())
+	}
+
+	// Send a B_REG_GET_APP_LIST `request` and get the teams in the reply
+	fn request_app_list(&self, request: Message) -> Option<Vec<Team>> {
+		let response = send_query(&self.messenger, request);
 
 		if response.is_err() {
 			return None;
 		}
 
 		let response = response.unwrap();
-		if response.what() == haiku_constant!('r', 'g', 's', 'u') {
+		if response.what() == B_REG_SUCCESS {
+			// The registrar leaves out the field if there are no teams
 			let count = match response.get_info("teams") {
 				Some(info) => info.1,
-				None => return None,
+				None => 0,
 			};
 			let mut result: Vec<Team> = Vec::with_capacity(count);
 			for index in 0..count {
 				let team = response.find_data::<i32>("teams", index).unwrap();
-				result.push(Team::from(team).unwrap());
+				// Skip any teams that have quit in the mean time
+				if let Ok(team) = Team::try_from(team) {
+					result.push(team);
+				}
 			}
 			return Some(result);
 		}
@@ -107,51 +257,212 @@ impl Roster {
 
 	/// Get the information of a running application
 	///
-	/// If there is a problem connecting to the registrar, this method
-	/// will return None.
-	pub fn get_running_app_info(&self, team: &Team) -> Option<AppInfo> {
-		let mut request = Message::new(haiku_constant!('r', 'g', 'a', 'i'));
-		request.add_data("team", &team.get_team_id()).unwrap();
-		let response = self.messenger.send_and_wait_for_reply(request, None);
+	/// This returns an error with `ErrorKind::NotFound` if the team is not a
+	/// registered application, and `ErrorKind::InvalidData` if the reply of
+	/// the registrar cannot be read. Errors while talking to the registrar
+	/// are passed on.
+	pub fn get_running_app_info(&self, team: &Team) -> Result<AppInfo> {
+		let mut request = Message::new(B_REG_GET_APP_INFO);
+		request.add_data("team", &team.get_team_id())?;
+		let response = send_query(&self.messenger, request)?;
+		parse_app_info_reply(&response)
+	}
 
-		if response.is_err() {
-			println!("Response.is err");
-			return None;
-		}
+	/// Get the information of an application with a certain signature
+	///
+	/// The errors are the same as those of `get_running_app_info()`.
+	pub fn get_app_info(&self, signature: &str) -> Result<AppInfo> {
+		let mut request = Message::new(B_REG_GET_APP_INFO);
+		request.add_string("signature", signature)?;
+		let response = send_query(&self.messenger, request)?;
+		parse_app_info_reply(&response)
+	}
 
-		let response = response.unwrap();
-		if response.what() == haiku_constant!('r', 'g', 's', 'u') {
-			let flat_app_info = response.find_data::<FlatAppInfo>("app_info", 0).unwrap();
-			return Some(flat_app_info.to_app_info());
+	/// Get the messenger to the MIME database of the registrar
+	pub(crate) fn get_mime_messenger(&self) -> Result<Messenger> {
+		let request = Message::new(B_REG_GET_MIME_MESSENGER);
+		let response = send_query(&self.messenger, request)?;
+		if response.what() == B_REG_SUCCESS {
+			response.find_data("messenger", 0)
+		} else {
+			let error: status_t = response.find_data("error", 0).unwrap_or(B_ERROR);
+			Err(HaikuError::from_raw_os_error(error))
 		}
-		return None;
 	}
 
-	/// Get the information of an application with a certain signature
+	/// Launch the application with `signature`
 	///
-	/// If there is a problem connecting tot the registrar, this method
-	/// will return None.
-	/// Get the information of a running application
+	/// The executable of the application is found through the MIME
+	/// database, where applications store their location when they are
+	/// installed or run. The new instance receives a `B_READY_TO_RUN`
+	/// message, like applications that are started from Tracker.
 	///
-	/// If there is a problem connecting to the registrar, this method
-	/// will return None.
-	pub fn get_app_info(&self, signature: &str) -> Option<AppInfo> {
-		let mut request = Message::new(haiku_constant!('r', 'g', 'a', 'i'));
-		request
-			.add_data("signature", &String::from(signature))
-			.unwrap();
-		let response = self.messenger.send_and_wait_for_reply(request, None);
+	/// If the application is single or exclusive launch and an instance is
+	/// already running, no new instance is started, and the team of the
+	/// running instance is returned.
+	///
+	/// If the application cannot be found, an error with the raw os error
+	/// `B_LAUNCH_FAILED_APP_NOT_FOUND` and `ErrorKind::NotFound` is returned.
+	pub fn launch(&self, signature: &str) -> Result<Team> {
+		self.launch_app(signature, None, &[])
+	}
 
-		if response.is_err() {
-			return None;
+	/// Launch the application with `signature` with arguments and files
+	///
+	/// The `args` are passed as the command line arguments of the new
+	/// instance, and in a `B_ARGV_RECEIVED` message, which is also sent when
+	/// there are no `args`. The `refs` are sent in a `B_REFS_RECEIVED`
+	/// message, so that the application opens them. If a single or exclusive
+	/// launch application is already running, these messages are sent to the
+	/// running instance instead.
+	///
+	/// All the `refs` must exist. If one does not, an error with
+	/// `ErrorKind::NotFound` is returned before the application is launched.
+	pub fn launch_with(&self, signature: &str, args: &[String], refs: &[PathBuf]) -> Result<Team> {
+		self.launch_app(signature, Some(args), refs)
+	}
+
+	// Start the application with `signature` with the command line `args`,
+	// and send it the `args` and the `refs` before B_READY_TO_RUN. This
+	// follows the steps of BRoster::_LaunchApp(): the application is
+	// pre-registered, its executable is loaded, and then the registrar
+	// creates the port for its main looper, which is where the messages are
+	// delivered.
+	fn launch_app(
+		&self,
+		signature: &str,
+		args: Option<&[String]>,
+		refs: &[PathBuf],
+	) -> Result<Team> {
+		if let Some(missing) = refs.iter().find(|path| !path.exists()) {
+			return Err(HaikuError::new(
+				ErrorKind::NotFound,
+				format!("the file {} does not exist", missing.display()),
+			));
 		}
+		let mime_type = MimeType::new(signature).ok_or_else(|| {
+			HaikuError::new(
+				ErrorKind::InvalidInput,
+				"the signature is not a valid MIME type",
+			)
+		})?;
+		let path = match mime_type.get_app_hint() {
+			Some(path) if path.exists() => path,
+			_ => return Err(HaikuError::from_raw_os_error(B_LAUNCH_FAILED_APP_NOT_FOUND)),
+		};
+		let flags = get_app_flags(&path);
+		let entry = entry_ref::from_path(&path)?;
 
-		let response = response.unwrap();
-		if response.what() == haiku_constant!('r', 'g', 's', 'u') {
-			let flat_app_info = response.find_data::<FlatAppInfo>("app_info", 0).unwrap();
-			return Some(flat_app_info.to_app_info());
+		let mut argv = vec![path.to_string_lossy().into_owned()];
+		argv.extend_from_slice(args.unwrap_or_default());
+		let mut messages = Vec::new();
+		if args.is_some() {
+			messages.push(argv_message(&argv, &argv[0])?);
+		}
+		if !refs.is_empty() {
+			let refs: Vec<&Path> = refs.iter().map(PathBuf::as_path).collect();
+			messages.push(Message::refs_received(&refs)?);
+		}
+
+		// The registrar refuses the pre-registration if a single or exclusive
+		// launch application is already running
+		let token = match self.add_application(
+			&String::from(signature),
+			&entry,
+			flags,
+			-1,
+			-1,
+			-1,
+			false,
+		)? {
+			ApplicationRegistrationResult::PreRegistered(token) => token,
+			ApplicationRegistrationResult::OtherInstance(other_team, _) => {
+				let team = Team::try_from(other_team)?;
+				if !messages.is_empty() {
+					let info = self.get_running_app_info(&team)?;
+					let messenger = info.messenger()?;
+					for message in messages {
+						messenger.send(message, &messenger)?;
+					}
+				}
+				return Ok(team);
+			}
+			ApplicationRegistrationResult::Registered => {
+				return Err(HaikuError::new(
+					ErrorKind::InvalidData,
+					"the registrar did not pre-register the application",
+				))
+			}
+		};
+
+		let thread = match load_executable(&argv) {
+			Ok(thread) => thread,
+			Err(error) => {
+				let _ = self.remove_pre_registered_application(token);
+				return Err(error);
+			}
+		};
+		let started = thread.get_info().and_then(|info| {
+			let port = self.set_thread_and_team(token, &info.team, &thread)?;
+			thread.resume()?;
+			Ok((info.team, port))
+		});
+		let (team, port) = match started {
+			Ok(started) => started,
+			Err(error) => {
+				let _ = thread.kill();
+				let _ = self.remove_pre_registered_application(token);
+				return Err(error);
+			}
+		};
+
+		let messenger = Messenger::from_port_id(port).ok_or_else(|| {
+			HaikuError::new(
+				ErrorKind::NotFound,
+				"the port of the launched application does not exist",
+			)
+		})?;
+		if flags & B_ARGV_ONLY == 0 {
+			for message in messages {
+				messenger.send(message, &messenger)?;
+			}
+		}
+		messenger.send(Message::new(B_READY_TO_RUN), &messenger)?;
+		Ok(team)
+	}
+
+	/// Ask the registrar to notify `target` of changes to running applications
+	///
+	/// The `events` select the notifications. The registrar sends a
+	/// `B_SOME_APP_LAUNCHED`, `B_SOME_APP_QUIT` or `B_SOME_APP_ACTIVATED`
+	/// message to the `target`, with the `be:signature`, `be:team`,
+	/// `be:thread`, `be:flags` and `be:ref` of the application.
+	///
+	/// Calling this again for the same `target` replaces the `events`.
+	pub fn start_watching(&self, target: &Messenger, events: RosterEvents) -> Result<()> {
+		let mut request = Message::new(B_REG_START_WATCHING);
+		request.add_data("target", target)?;
+		request.add_data("events", &(events.bits() as i32))?;
+		self.send_watching_request(request)
+	}
+
+	/// Stop the notifications to `target` that were set up with
+	/// `start_watching()`
+	pub fn stop_watching(&self, target: &Messenger) -> Result<()> {
+		let mut request = Message::new(B_REG_STOP_WATCHING);
+		request.add_data("target", target)?;
+		self.send_watching_request(request)
+	}
+
+	// Send a request to start or stop watching, and return the result
+	fn send_watching_request(&self, request: Message) -> Result<()> {
+		let response = send_request(&self.messenger, request)?;
+		if response.what() == B_REG_SUCCESS {
+			Ok(())
+		} else {
+			let error: status_t = response.find_data("error", 0).unwrap_or(B_ERROR);
+			Err(HaikuError::from_raw_os_error(error))
 		}
-		return None;
 	}
 
 	/// Register or preregister an app in the Registrar
@@ -165,8 +476,7 @@ impl Roster {
 		port: port_id,
 		full_registration: bool,
 	) -> Result<ApplicationRegistrationResult> {
-		// B_REG_ADD_APP
-		let mut request = Message::new(haiku_constant!('r', 'g', 'a', 'a'));
+		let mut request = Message::new(B_REG_ADD_APP);
 		request.add_data("signature", signature).unwrap();
 		request.add_data("ref", entry).unwrap();
 		request.add_data("flags", &flags).unwrap();
@@ -176,7 +486,7 @@ impl Roster {
 		request
 			.add_data("full_registration", &full_registration)
 			.unwrap();
-		let response = self.messenger.send_and_wait_for_reply(request, None)?;
+		let response = send_request(&self.messenger, request)?;
 		if response.what() == B_REG_SUCCESS {
 			if !full_registration && team < 0 {
 				let token: i32 = match response.find_data("token", 0) {
@@ -192,20 +502,15 @@ impl Roster {
 			} else {
 				Ok(ApplicationRegistrationResult::Registered)
 			}
+		} else if let Ok(other_team) = response.find_data::<team_id>("other_team", 0) {
+			// The token is only set when the other instance is pre-registered
+			let token: i32 = response.find_data("token", 0).unwrap_or(0);
+			Ok(ApplicationRegistrationResult::OtherInstance(
+				other_team, token,
+			))
 		} else {
-			let token: Result<i32> = response.find_data("token", 0);
-			let team: Result<team_id> = response.find_data("team", 0);
-			if token.is_ok() && team.is_ok() {
-				Ok(ApplicationRegistrationResult::OtherInstance(
-					team.unwrap(),
-					token.unwrap(),
-				))
-			} else {
-				Err(HaikuError::new(
-					ErrorKind::InvalidData,
-					"Invalid registration response by Registrar",
-				))
-			}
+			let error: status_t = response.find_data("error", 0).unwrap_or(B_ERROR);
+			Err(HaikuError::from_raw_os_error(error))
 		}
 	}
 
@@ -216,13 +521,12 @@ impl Roster {
 		team: team_id,
 		token: u32,
 	) -> Result<ApplicationRegistrationStatus> {
-		// B_REG_IS_APP_REGISTERED
-		let mut request = Message::new(haiku_constant!('r', 'g', 'i', 'p'));
+		let mut request = Message::new(B_REG_IS_APP_REGISTERED);
 		request.add_data("ref", entry).unwrap();
 		request.add_data("team", &team).unwrap();
 		request.add_data("token", &(token as i32)).unwrap();
 
-		let response = self.messenger.send_and_wait_for_reply(request, None)?;
+		let response = send_query(&self.messenger, request)?;
 		if response.what() == B_REG_SUCCESS {
 			let registered: bool = response.find_data("registered", 0).unwrap_or(false);
 			let pre_registered: bool = response.find_data("pre-registered", 0).unwrap_or(false);
@@ -253,13 +557,131 @@ impl Roster {
 		}
 	}
 
+	/// Register the application at the registrar
+	///
+	/// An application that is launched by the roster is pre-registered. In
+	/// that case the roster may also have created the port for the main
+	/// looper of the application, and put the first messages in it. That port
+	/// replaces the `port` that was created by the application. The port that
+	/// the application should use is returned.
+	///
+	/// When the `flags` do not allow multiple instances, and the application
+	/// is already running, the team of the running instance is returned.
+	pub(crate) fn register_application(
+		&self,
+		signature: &str,
+		entry: &entry_ref,
+		flags: u32,
+		team: team_id,
+		thread: thread_id,
+		port: Port,
+	) -> Result<Registration> {
+		match self.is_application_registered(entry, team, 0)? {
+			ApplicationRegistrationStatus::PreRegistered(app_info) => {
+				let port = if app_info.port >= 0 && app_info.port != port.get_port_id() {
+					match Port::adopt(app_info.port) {
+						Some(roster_port) => roster_port,
+						None => return Err(HaikuError::new(
+							ErrorKind::NotFound,
+							"the port that the roster created for the application does not exist",
+						)),
+					}
+				} else {
+					port
+				};
+				self.complete_registration(team, thread, port.get_port_id())?;
+				Ok(Registration::Registered(port))
+			}
+			// Already registered applications are ignored by the C++
+			// implementation as well
+			ApplicationRegistrationStatus::Registered(_)
+			| ApplicationRegistrationStatus::NotRegistered => {
+				let result = self.add_application(
+					&String::from(signature),
+					entry,
+					flags,
+					team,
+					thread,
+					port.get_port_id(),
+					true,
+				)?;
+				match result {
+					ApplicationRegistrationResult::OtherInstance(other_team, _) => {
+						Ok(Registration::OtherInstance(other_team))
+					}
+					_ => Ok(Registration::Registered(port)),
+				}
+			}
+		}
+	}
+
+	/// Complete the registration of a pre-registered application
+	pub(crate) fn complete_registration(
+		&self,
+		team: team_id,
+		thread: thread_id,
+		port: port_id,
+	) -> Result<()> {
+		let mut request = Message::new(B_REG_COMPLETE_REGISTRATION);
+		request.add_data("team", &team)?;
+		request.add_data("thread", &thread)?;
+		request.add_data("port", &port)?;
+
+		let response = send_request(&self.messenger, request)?;
+		if response.what() == B_REG_SUCCESS {
+			Ok(())
+		} else {
+			let error: status_t = response.find_data("error", 0).unwrap_or(B_ERROR);
+			Err(HaikuError::from_raw_os_error(error))
+		}
+	}
+
+	/// Set the team and the main thread of a pre-registered application
+	///
+	/// The registrar creates the port for the main looper of the
+	/// application, and transfers it to the `team`. The id of the port is
+	/// returned.
+	pub(crate) fn set_thread_and_team(
+		&self,
+		token: i32,
+		team: &Team,
+		thread: &Thread,
+	) -> Result<port_id> {
+		let mut request = Message::new(B_REG_SET_THREAD_AND_TEAM);
+		request.add_data("token", &token)?;
+		request.add_data("team", &team.get_team_id())?;
+		request.add_data("thread", &thread.get_thread_id())?;
+
+		let response = send_request(&self.messenger, request)?;
+		if response.what() == B_REG_SUCCESS {
+			response.find_data("port", 0)
+		} else {
+			let error: status_t = response.find_data("error", 0).unwrap_or(B_ERROR);
+			Err(HaikuError::from_raw_os_error(error))
+		}
+	}
+
+	/// Remove an application that was pre-registered with `token`, but did
+	/// not start
+	pub(crate) fn remove_pre_registered_application(&self, token: i32) -> Result<()> {
+		let mut request = Message::new(B_REG_REMOVE_PRE_REGISTERED_APP);
+		request.add_data("token", &token)?;
+
+		let response = send_request(&self.messenger, request)?;
+		if response.what() == B_REG_SUCCESS {
+			Ok(())
+		} else {
+			let error: status_t = response.find_data("error", 0).unwrap_or(B_ERROR);
+			Err(HaikuError::from_raw_os_error(error))
+		}
+	}
+
 	/// Unregister a previously registered application
 	pub(crate) fn remove_application(&self, team: team_id) -> Result<()> {
-		// B_REG_REMOVE_APP
-		let mut request = Message::new(haiku_constant!('r', 'g', 'r', 'a'));
+		let mut request = Message::new(B_REG_REMOVE_APP);
 		request.add_data("team", &team).unwrap();
 
-		let response = self.messenger.send_and_wait_for_reply(request, None)?;
+		let response = send_request(&self.messenger, request)?;
 		if response.what() == B_REG_SUCCESS {
 			Ok(())
 		} else {
@@ -269,8 +691,72 @@ impl Roster {
 	}
 }
 
+// Get the app info from the reply to a B_REG_GET_APP_INFO request
+//
+// If the application is not running, the registrar replies with an error,
+// which results in ErrorKind::NotFound. A successful reply without the
+// app_info is returned as ErrorKind::InvalidData.
+fn parse_app_info_reply(response: &Message) -> Result<AppInfo> {
+	if response.what() != B_REG_SUCCESS {
+		let error: status_t = response.find_data("error", 0).unwrap_or(B_ERROR);
+		return Err(HaikuError::new(
+			ErrorKind::NotFound,
+			format!("the application is not running (error {})", error),
+		));
+	}
+	match response.find_data::<FlatAppInfo>("app_info", 0) {
+		Ok(flat_app_info) => Ok(flat_app_info.to_app_info()),
+		Err(_) => Err(HaikuError::new(
+			ErrorKind::InvalidData,
+			"the reply of the registrar does not contain the app info",
+		)),
+	}
+}
+
+// Load the executable in a new team, with `argv` as its command line. The
+// first argument is the path of the executable. The main thread of the team
+// is suspended.
+fn load_executable(argv: &[String]) -> Result<Thread> {
+	extern "C" {
+		static environ: *const *const c_char;
+	}
+
+	let invalid_argument = |_| {
+		HaikuError::new(
+			ErrorKind::InvalidInput,
+			"the arguments cannot contain a nul character",
+		)
+	};
+	let argv = argv
+		.iter()
+		.map(|arg| CString::new(arg.as_bytes()).map_err(invalid_argument))
+		.collect::<Result<Vec<CString>>>()?;
+	let mut argv_pointers: Vec<*const c_char> = argv.iter().map(|arg| arg.as_ptr()).collect();
+	argv_pointers.push(ptr::null());
+
+	let thread = unsafe {
+		load_image(
+			argv.len() as i32,
+			argv_pointers.as_mut_ptr(),
+			environ as *mut *const c_char,
+		)
+	};
+	if thread < 0 {
+		return Err(HaikuError::from_raw_os_error(thread));
+	}
+	Ok(Thread::from(thread).unwrap())
+}
+
+// Private registrar protocol, see private/app/RegistrarDefs.h
+const B_REG_ADD_APP: u32 = haiku_constant!('r', 'g', 'a', 'a');
+const B_REG_COMPLETE_REGISTRATION: u32 = haiku_constant!('r', 'g', 'c', 'r');
+const B_REG_IS_APP_REGISTERED: u32 = haiku_constant!('r', 'g', 'i', 'p');
+const B_REG_REMOVE_PRE_REGISTERED_APP: u32 = haiku_constant!('r', 'g', 'r', 'p');
+const B_REG_REMOVE_APP: u32 = haiku_constant!('r', 'g', 'r', 'a');
+const B_REG_SET_THREAD_AND_TEAM: u32 = haiku_constant!('r', 'g', 't', 't');
+const B_REG_START_WATCHING: u32 = haiku_constant!('r', 'g', 'w', 'a');
+const B_REG_STOP_WATCHING: u32 = haiku_constant!('r', 'g', 's', 'w');
 const B_REG_APP_INFO_TYPE: u32 = haiku_constant!('r', 'g', 'a', 'i');
-const B_REG_SUCCESS: u32 = haiku_constant!('r', 'g', 's', 'u');
 
 // It is not possible to safely get references from packed structs. Therefore
 // we have a private FlatAppInfo to read data from messages, and a public
@@ -324,6 +810,8 @@ impl FlatAppInfo {
 }
 
 impl Flattenable<FlatAppInfo> for FlatAppInfo {
+	const FLATTENED_SIZE: Option<usize> = Some(mem::size_of::<FlatAppInfo>());
+
 	fn type_code() -> u32 {
 		B_REG_APP_INFO_TYPE
 	}
@@ -337,7 +825,10 @@ impl Flattenable<FlatAppInfo> for FlatAppInfo {
 	}
 
 	fn flatten(&self) -> Vec<u8> {
-		unimplemented!();
+		let data = unsafe {
+			std::slice::from_raw_parts(self as *const _ as *const u8, self.flattened_size())
+		};
+		data.to_vec()
 	}
 
 	fn unflatten(buffer: &[u8]) -> Result<FlatAppInfo> {
@@ -354,13 +845,48 @@ impl Flattenable<FlatAppInfo> for FlatAppInfo {
 
 // Supporting constants for AppInfo
 //const B_SINGLE_LAUNCH: u32 = 0x0;
-const B_MULTIPLE_LAUNCH: u32 = 0x1;
+pub(crate) const B_MULTIPLE_LAUNCH: u32 = 0x1;
 const B_EXCLUSIVE_LAUNCH: u32 = 0x2;
 // B_LAUNCH_MASK 0x3
 const B_BACKGROUND_APP: u32 = 0x4;
 const B_ARGV_ONLY: u32 = 0x8;
 // B_APP_INFO_RESERVED1_ 0x10000000
 
+/// The changes to running applications that a roster watcher is notified of
+///
+/// The events can be combined, for example
+/// `RosterEvents::LAUNCHED | RosterEvents::TERMINATED`. See
+/// `Roster::start_watching()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RosterEvents(u32);
+
+impl RosterEvents {
+	/// An application is launched (`B_SOME_APP_LAUNCHED`)
+	pub const LAUNCHED: RosterEvents = RosterEvents(0x1);
+	/// An application quits (`B_SOME_APP_QUIT`)
+	pub const TERMINATED: RosterEvents = RosterEvents(0x2);
+	/// An application is activated (`B_SOME_APP_ACTIVATED`)
+	pub const ACTIVATED: RosterEvents = RosterEvents(0x4);
+
+	/// Get the raw event mask of the registrar protocol
+	pub fn bits(&self) -> u32 {
+		self.0
+	}
+
+	/// Check if all the events in `other` are in this set
+	pub fn contains(&self, other: RosterEvents) -> bool {
+		self.0 & other.0 == other.0
+	}
+}
+
+impl BitOr for RosterEvents {
+	type Output = RosterEvents;
+
+	fn bitor(self, other: RosterEvents) -> RosterEvents {
+		RosterEvents(self.0 | other.0)
+	}
+}
+
 /// Contains the information about a running application
 ///
 /// The information is provided by Haiku's registrar, and can be queried using
@@ -418,25 +944,81 @@ impl AppInfo {
 	pub fn is_argv_only(&self) -> bool {
 		self.flags & B_ARGV_ONLY != 0
 	}
+
+	/// Get the team of the running application
+	///
+	/// This returns `None` if the application is not running.
+	pub fn team(&self) -> Option<Team> {
+		Team::from(self.team)
+	}
+
+	/// Get the main thread of the running application
+	///
+	/// If the application is not running, or its main thread has quit, the
+	/// operations on the returned thread, like `get_info()`, return an
+	/// error.
+	pub fn thread(&self) -> Thread {
+		Thread::from_raw(self.thread)
+	}
+
+	/// Get a Messenger that targets the application's main looper
+	///
+	/// This returns an error with `ErrorKind::NotFound` when the application's
+	/// port no longer exists, and `ErrorKind::NotAllowed` if the application
+	/// only accepts command line arguments.
+	pub fn messenger(&self) -> Result<Messenger> {
+		if self.is_argv_only() {
+			return Err(HaikuError::new(
+				ErrorKind::NotAllowed,
+				"This application only accepts command line arguments",
+			));
+		}
+		match Messenger::from_port_id(self.port) {
+			Some(messenger) => Ok(messenger),
+			None => Err(HaikuError::new(
+				ErrorKind::NotFound,
+				"the port of the application does not exist",
+			)),
+		}
+	}
 }
 
 lazy_static! {
-	pub(crate) static ref LAUNCH_ROSTER: LaunchRoster = LaunchRoster::init();
+	pub(crate) static ref LAUNCH_ROSTER: &'static LaunchRoster =
+		LaunchRoster::try_global().expect("Cannot connect to the launch daemon");
 }
 
 lazy_static! {
 	/// The `ROSTER` gives access to a global `Roster` object that can be used
 	/// to communicate with Haiku's registrar that tracks all the running Haiku
 	/// applications.
-	pub static ref ROSTER: Roster = {
-		// Get a connection with the registrar
-		let roster_data = LAUNCH_ROSTER.get_data("application/x-vnd.haiku-registrar").expect("Cannot connect to the launch_daemon to get info about the registrar!");
-		if roster_data.what() != (B_OK as u32) {
-			panic!("Cannot connect to the registrar");
-		}
-		let port: port_id = roster_data.find_data("port", 0).expect("Cannot find port info for registrar");
-		Roster{ messenger: Messenger::from_port_id(port).unwrap() }
-	};
+	///
+	/// Accessing the `ROSTER` panics if the registrar cannot be reached. Use
+	/// `Roster::try_global()` to handle that situation gracefully. Both share
+	/// the same connection.
+	pub static ref ROSTER: &'static Roster =
+		Roster::try_global().expect("Cannot connect to the registrar");
+}
+
+#[test]
+fn test_parse_app_info_reply() {
+	use libc::B_BAD_TEAM_ID;
+
+	let success_without_info = Message::new(B_REG_SUCCESS);
+	let error = parse_app_info_reply(&success_without_info).err().unwrap();
+	assert!(matches!(error.kind(), ErrorKind::InvalidData));
+
+	let mut not_running = Message::new(haiku_constant!('r', 'g', 'e', 'r'));
+	not_running.add_data("error", &B_BAD_TEAM_ID).unwrap();
+	let error = parse_app_info_reply(&not_running).err().unwrap();
+	assert!(matches!(error.kind(), ErrorKind::NotFound));
+}
+
+#[test]
+fn test_roster_try_global() {
+	let roster = Roster::try_global().unwrap();
+	assert!(ptr::eq(roster, Roster::try_global().unwrap()));
+	assert!(roster.messenger == ROSTER.messenger);
 }
 
 #[test]
@@ -444,3 +1026,210 @@ fn test_roster_get_app_list() {
 	let app_list = ROSTER.get_app_list().unwrap();
 	assert!(app_list.len() != 0);
 }
+
+#[test]
+fn test_roster_get_app_teams() {
+	// Tracker always runs
+	assert!(ROSTER.is_running("application/x-vnd.Be-TRAK"));
+	let teams = ROSTER.get_app_teams("application/x-vnd.Be-TRAK");
+	assert!(!teams.is_empty());
+	let tracker = ROSTER.get_app_info("application/x-vnd.Be-TRAK").unwrap();
+	assert!(teams.iter().any(|team| team.get_team_id() == tracker.team));
+
+	let signature = "application/x-vnd.haiku-rs-not-running";
+	assert!(!ROSTER.is_running(signature));
+	assert!(ROSTER.get_app_teams(signature).is_empty());
+}
+
+#[test]
+fn test_roster_launch() {
+	// Tracker is a single launch application that always runs
+	let tracker = ROSTER.get_app_info("application/x-vnd.Be-TRAK").unwrap();
+	let team = ROSTER.launch("application/x-vnd.Be-TRAK").unwrap();
+	assert_eq!(team.get_team_id(), tracker.team);
+
+	let team = ROSTER.launch("application/x-vnd.Haiku-StyledEdit").unwrap();
+	assert!(team.get_info().is_ok());
+	team.kill().unwrap();
+
+	let error = ROSTER
+		.launch("application/x-vnd.haiku-rs-not-installed")
+		.unwrap_err();
+	assert_eq!(error.raw_os_error(), Some(B_LAUNCH_FAILED_APP_NOT_FOUND));
+	assert!(matches!(error.kind(), ErrorKind::NotFound));
+}
+
+#[test]
+fn test_roster_launch_with() {
+	use std::io::Write;
+
+	let mut file = tempfile::NamedTempFile::new().unwrap();
+	writeln!(file, "Opened by haiku-rs").unwrap();
+	let path = file.path().to_path_buf();
+
+	let team = ROSTER
+		.launch_with("application/x-vnd.Haiku-StyledEdit", &[], &[path.clone()])
+		.unwrap();
+	assert!(team.get_info().is_ok());
+	team.kill().unwrap();
+
+	// Files that do not exist are refused before launching
+	let missing = path.with_extension("missing");
+	let error = ROSTER
+		.launch_with("application/x-vnd.Haiku-StyledEdit", &[], &[missing])
+		.unwrap_err();
+	assert!(matches!(error.kind(), ErrorKind::NotFound));
+}
+
+#[test]
+fn test_roster_watching() {
+	use crate::app::constants::B_SOME_APP_LAUNCHED;
+	use std::time::Instant;
+
+	let events = RosterEvents::LAUNCHED | RosterEvents::TERMINATED;
+	assert!(events.contains(RosterEvents::LAUNCHED));
+	assert!(!events.contains(RosterEvents::ACTIVATED));
+	assert_eq!(events.bits(), 0x3);
+
+	let port = Port::create("roster watcher", 100).unwrap();
+	let watcher = Messenger::from_port(&port).unwrap();
+	ROSTER.start_watching(&watcher, events).unwrap();
+	let team = ROSTER.launch("application/x-vnd.Haiku-StyledEdit").unwrap();
+
+	// Other applications may start in the mean time
+	let deadline = Instant::now() + Duration::from_secs(10);
+	let mut launched = false;
+	while !launched {
+		let (_, buffer) = port.try_read_until(deadline).unwrap();
+		let message = Message::unflatten(&buffer).unwrap();
+		launched = message.what() == B_SOME_APP_LAUNCHED
+			&& message.find_data::<team_id>("be:team", 0).unwrap() == team.get_team_id();
+	}
+	team.kill().unwrap();
+	ROSTER.stop_watching(&watcher).unwrap();
+}
+
+#[test]
+fn test_register_pre_registered_application() {
+	use libc::B_MESSAGE_TYPE;
+	use std::thread;
+
+	let registrar_port = Port::create("mock registrar", 10).unwrap();
+	let roster = Roster::with_messenger(Messenger::from_port(&registrar_port).unwrap());
+	// The port that the launching roster created for the application
+	let roster_port = Port::create("roster application port", 10).unwrap();
+	let roster_port_id = roster_port.get_port_id();
+	let application_port = Port::create("application", 10).unwrap();
+	let application_port_id = application_port.get_port_id();
+
+	let registrar = thread::spawn(move || {
+		let mut requests = Vec::new();
+		for _ in 0..2 {
+			let (_, buffer) = registrar_port.read().unwrap();
+			let request = Message::unflatten(&buffer).unwrap();
+			let mut reply = Message::new(B_REG_SUCCESS);
+			if request.what() == B_REG_IS_APP_REGISTERED {
+				let app_info = FlatAppInfo {
+					thread: -1,
+					team: 42,
+					port: roster_port_id,
+					flags: 0,
+					ref_device: 0,
+					ref_directory: 0,
+					signature: [0; B_MIME_TYPE_LENGTH],
+					ref_name: [0; B_FILE_NAME_LENGTH + 1],
+				};
+				reply.add_data("registered", &false).unwrap();
+				reply.add_data("pre-registered", &true).unwrap();
+				reply.add_data("app_info", &app_info).unwrap();
+			}
+			let reply_port = Port::from_id(request.header.reply_port).unwrap();
+			reply_port
+				.write(B_MESSAGE_TYPE as i32, &reply.flatten())
+				.unwrap();
+			requests.push(request);
+		}
+		requests
+	});
+
+	let entry = entry_ref {
+		device: 0,
+		directory: 0,
+		name: std::ffi::CString::new("app").unwrap(),
+	};
+	let port = match roster
+		.register_application(
+			"application/x-vnd.test",
+			&entry,
+			0,
+			42,
+			43,
+			application_port,
+		)
+		.unwrap()
+	{
+		Registration::Registered(port) => port,
+		Registration::OtherInstance(_) => panic!("the application should be registered"),
+	};
+	// The application adopts the port that the roster created
+	assert_eq!(port.get_port_id(), roster_port_id);
+	assert!(Port::from_id(application_port_id).is_none());
+	// Prevent the port from being deleted twice
+	std::mem::forget(roster_port);
+
+	let requests = registrar.join().unwrap();
+	assert_eq!(requests[0].what(), B_REG_IS_APP_REGISTERED);
+	assert_eq!(requests[1].what(), B_REG_COMPLETE_REGISTRATION);
+	assert_eq!(requests[1].find_data::<team_id>("team", 0).unwrap(), 42);
+	assert_eq!(requests[1].find_data::<thread_id>("thread", 0).unwrap(), 43);
+	assert_eq!(
+		requests[1].find_data::<port_id>("port", 0).unwrap(),
+		roster_port_id
+	);
+}
+
+#[test]
+fn test_register_other_instance() {
+	use libc::{B_ALREADY_RUNNING, B_MESSAGE_TYPE};
+	use std::thread;
+
+	let registrar_port = Port::create("mock registrar", 10).unwrap();
+	let roster = Roster::with_messenger(Messenger::from_port(&registrar_port).unwrap());
+	let registrar = thread::spawn(move || {
+		for _ in 0..2 {
+			let (_, buffer) = registrar_port.read().unwrap();
+			let request = Message::unflatten(&buffer).unwrap();
+			let reply = if request.what() == B_REG_IS_APP_REGISTERED {
+				let mut reply = Message::new(B_REG_SUCCESS);
+				reply.add_data("registered", &false).unwrap();
+				reply
+			} else {
+				// A single launch application is already running in team 77
+				assert_eq!(request.what(), B_REG_ADD_APP);
+				let mut reply = Message::new(haiku_constant!('r', 'g', 'e', 'r'));
+				reply.add_data("error", &B_ALREADY_RUNNING).unwrap();
+				reply.add_data("other_team", &(77 as team_id)).unwrap();
+				reply
+			};
+			let reply_port = Port::from_id(request.header.reply_port).unwrap();
+			reply_port
+				.write(B_MESSAGE_TYPE as i32, &reply.flatten())
+				.unwrap();
+		}
+	});
+
+	let entry = entry_ref {
+		device: 0,
+		directory: 0,
+		name: std::ffi::CString::new("app").unwrap(),
+	};
+	let port = Port::create("application", 10).unwrap();
+	let registration = roster
+		.register_application("application/x-vnd.test", &entry, 0, 42, 43, port)
+		.unwrap();
+	registrar.join().unwrap();
+	match registration {
+		Registration::OtherInstance(team) => assert_eq!(team, 77),
+		Registration::Registered(_) => panic!("another instance should be running"),
+	}
+}
@@ -0,0 +1,304 @@
+//
+// Copyright 2024, Niels Sascha Reedijk <niels.reedijk@gmail.com>
+// All rights reserved. Distributed under the terms of the MIT License.
+//
+
+//! Support for Haiku's scripting protocol
+//!
+//! Scriptable applications describe the properties they support with a
+//! `BPropertyInfo` object. When another application (for example the `hey`
+//! command line tool) sends a `B_GET_SUPPORTED_SUITES` request, the reply
+//! contains a `"suites"` string field with the suite names, and a
+//! `"messages"` field with the flattened property info. This module provides
+//! the Rust equivalent of the property info, so that it can be added to such
+//! a reply.
+
+use std::ffi::CStr;
+
+use libc::B_PROPERTY_INFO_TYPE;
+
+use crate::haiku_constant;
+use crate::support::{ErrorKind, Flattenable, HaikuError, Result};
+
+/// The what code of a request for the supported suites of a handler
+pub const B_GET_SUPPORTED_SUITES: u32 = haiku_constant!('S', 'U', 'I', 'T');
+
+// The maximum number of items in the fixed-size arrays of property_info, see
+// os/app/PropertyInfo.h
+const MAX_COMMANDS: usize = 10;
+const MAX_SPECIFIERS: usize = 10;
+const MAX_TYPES: usize = 10;
+const MAX_COMPOUND_TYPES: usize = 3;
+const MAX_COMPOUND_PAIRS: usize = 5;
+
+// Flags that are part of the flattened header
+const FLAG_HAS_PROPERTY_INFO: u32 = 0x1;
+
+/// The description of a single scriptable property
+///
+/// This is the equivalent of Haiku's `property_info` struct. Haiku limits the
+/// number of commands, specifiers and types to 10 each, and the number of
+/// compound types to 3, with up to 5 fields each. Any additional items are
+/// ignored when flattening.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Property {
+	/// The name of the property
+	pub name: String,
+	/// The commands (like `B_GET_PROPERTY`) that the property supports
+	pub commands: Vec<u32>,
+	/// The specifiers (like `B_DIRECT_SPECIFIER`) that the property supports
+	pub specifiers: Vec<u32>,
+	/// A human readable description of the property
+	pub usage: Option<String>,
+	/// Application specific data
+	pub extra_data: u32,
+	/// The type codes of the data that the property returns or accepts
+	pub types: Vec<u32>,
+	/// Compound types, each consisting of a list of field names and types
+	pub compound_types: Vec<Vec<(String, u32)>>,
+}
+
+/// A list of scriptable properties
+///
+/// The flattened layout is identical to the output of
+/// `BPropertyInfo::Flatten()`:
+///
+/// ```text
+/// u8   endianness (0 for little endian)
+/// i32  number of properties
+/// i32  flags (0x1: property info present, 0x2: value info present)
+/// for every property:
+///     name as a \0-terminated string
+///     usage as a \0-terminated string (a single \0 if there is none)
+///     u32  extra_data
+///     u32  commands, terminated by a 0
+///     u32  specifiers, terminated by a 0
+///     u32  types, terminated by a 0
+///     for every compound type:
+///         (name as a \0-terminated string, u32 type) pairs, terminated by a
+///         u32 0
+///     u32  0 to terminate the compound types
+/// ```
+///
+/// Value info (the list of `value_info` structs in the C++ API) is not
+/// supported. It is never written, and it is ignored when reading.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PropertyInfo {
+	/// The properties that are described
+	pub properties: Vec<Property>,
+}
+
+impl PropertyInfo {
+	/// Create a new property info with a list of properties
+	pub fn new(properties: Vec<Property>) -> PropertyInfo {
+		PropertyInfo { properties }
+	}
+}
+
+impl Flattenable<PropertyInfo> for PropertyInfo {
+	fn type_code() -> u32 {
+		B_PROPERTY_INFO_TYPE
+	}
+
+	fn is_fixed_size() -> bool {
+		false
+	}
+
+	fn flattened_size(&self) -> usize {
+		self.flatten().len()
+	}
+
+	fn flatten(&self) -> Vec<u8> {
+		let mut vec: Vec<u8> = Vec::new();
+		vec.push(0); // little endian
+		vec.extend((self.properties.len() as i32).flatten().iter());
+		vec.extend(FLAG_HAS_PROPERTY_INFO.flatten().iter());
+		for property in self.properties.iter() {
+			vec.extend(property.name.as_bytes());
+			vec.push(0);
+			if let Some(ref usage) = property.usage {
+				vec.extend(usage.as_bytes());
+			}
+			vec.push(0);
+			vec.extend(property.extra_data.flatten().iter());
+			write_list(&mut vec, &property.commands, MAX_COMMANDS);
+			write_list(&mut vec, &property.specifiers, MAX_SPECIFIERS);
+			write_list(&mut vec, &property.types, MAX_TYPES);
+			for compound_type in property.compound_types.iter().take(MAX_COMPOUND_TYPES) {
+				if compound_type.is_empty() {
+					// An empty compound type ends the list in the C++ API
+					break;
+				}
+				for (name, type_code) in compound_type.iter().take(MAX_COMPOUND_PAIRS) {
+					vec.extend(name.as_bytes());
+					vec.push(0);
+					vec.extend(type_code.flatten().iter());
+				}
+				vec.extend((0 as u32).flatten().iter());
+			}
+			vec.extend((0 as u32).flatten().iter());
+		}
+		vec
+	}
+
+	fn unflatten(buffer: &[u8]) -> Result<PropertyInfo> {
+		let mut pos: usize = 0;
+		if read_bytes(buffer, &mut pos, 1)?[0] != 0 {
+			return Err(HaikuError::new(
+				ErrorKind::InvalidData,
+				"big endian property info is not supported",
+			));
+		}
+		let count = i32::unflatten(read_bytes(buffer, &mut pos, 4)?)?;
+		let flags = u32::unflatten(read_bytes(buffer, &mut pos, 4)?)?;
+		if count < 0 {
+			return Err(HaikuError::new(
+				ErrorKind::InvalidData,
+				"invalid number of properties",
+			));
+		}
+
+		// The count comes from the sender, so do not reserve more than the
+		// remaining data can hold
+		let capacity = (count as usize).min(buffer.len() - pos);
+		let mut properties: Vec<Property> = Vec::with_capacity(capacity);
+		if flags & FLAG_HAS_PROPERTY_INFO == 0 {
+			return Ok(PropertyInfo { properties });
+		}
+		for _ in 0..count {
+			let name = read_string(buffer, &mut pos)?;
+			let usage = read_string(buffer, &mut pos)?;
+			let extra_data = u32::unflatten(read_bytes(buffer, &mut pos, 4)?)?;
+			let commands = read_list(buffer, &mut pos)?;
+			let specifiers = read_list(buffer, &mut pos)?;
+			let types = read_list(buffer, &mut pos)?;
+			let mut compound_types: Vec<Vec<(String, u32)>> = Vec::new();
+			loop {
+				// Every list of pairs, and the list of compound types itself,
+				// ends with a zero u32. Peek if we are at the end of the list.
+				if u32::unflatten(peek_bytes(buffer, pos, 4)?)? == 0 {
+					pos += 4;
+					break;
+				}
+				let mut pairs: Vec<(String, u32)> = Vec::new();
+				while u32::unflatten(peek_bytes(buffer, pos, 4)?)? != 0 {
+					let name = read_string(buffer, &mut pos)?;
+					let type_code = u32::unflatten(read_bytes(buffer, &mut pos, 4)?)?;
+					pairs.push((name, type_code));
+				}
+				pos += 4;
+				compound_types.push(pairs);
+			}
+			properties.push(Property {
+				name,
+				commands,
+				specifiers,
+				usage: if usage.is_empty() { None } else { Some(usage) },
+				extra_data,
+				types,
+				compound_types,
+			});
+		}
+		Ok(PropertyInfo { properties })
+	}
+}
+
+fn write_list(vec: &mut Vec<u8>, list: &[u32], max: usize) {
+	for item in list.iter().take(max) {
+		if *item == 0 {
+			break;
+		}
+		vec.extend(item.flatten().iter());
+	}
+	vec.extend((0 as u32).flatten().iter());
+}
+
+fn peek_bytes(buffer: &[u8], pos: usize, size: usize) -> Result<&[u8]> {
+	if pos + size > buffer.len() {
+		return Err(HaikuError::new(
+			ErrorKind::InvalidData,
+			"the property info is truncated",
+		));
+	}
+	Ok(&buffer[pos..pos + size])
+}
+
+fn read_bytes<'a>(buffer: &'a [u8], pos: &mut usize, size: usize) -> Result<&'a [u8]> {
+	let bytes = peek_bytes(buffer, *pos, size)?;
+	*pos += size;
+	Ok(bytes)
+}
+
+fn read_string(buffer: &[u8], pos: &mut usize) -> Result<String> {
+	let end = match buffer[*pos..].iter().position(|&b| b == 0) {
+		Some(index) => *pos + index + 1,
+		None => {
+			return Err(HaikuError::new(
+				ErrorKind::InvalidData,
+				"the property info contains an unterminated string",
+			))
+		}
+	};
+	let s = CStr::from_bytes_with_nul(&buffer[*pos..end]).unwrap();
+	*pos = end;
+	match s.to_str() {
+		Ok(s) => Ok(String::from(s)),
+		Err(_) => Err(HaikuError::new(
+			ErrorKind::InvalidData,
+			"Invalid UTF8 characters",
+		)),
+	}
+}
+
+fn read_list(buffer: &[u8], pos: &mut usize) -> Result<Vec<u32>> {
+	let mut list: Vec<u32> = Vec::new();
+	loop {
+		let item = u32::unflatten(read_bytes(buffer, pos, 4)?)?;
+		if item == 0 {
+			return Ok(list);
+		}
+		list.push(item);
+	}
+}
+
+#[test]
+fn test_property_info_round_trip() {
+	use libc::{B_INT32_TYPE, B_STRING_TYPE};
+
+	let get_property = haiku_constant!('P', 'G', 'E', 'T');
+	let set_property = haiku_constant!('P', 'S', 'E', 'T');
+	let direct_specifier: u32 = 1;
+	let property_info = PropertyInfo::new(vec![
+		Property {
+			name: String::from("Name"),
+			commands: vec![get_property, set_property],
+			specifiers: vec![direct_specifier],
+			usage: Some(String::from("Get or set the name")),
+			types: vec![B_STRING_TYPE],
+			..Default::default()
+		},
+		Property {
+			name: String::from("Size"),
+			commands: vec![get_property],
+			specifiers: vec![direct_specifier],
+			extra_data: 7,
+			compound_types: vec![vec![
+				(String::from("width"), B_INT32_TYPE),
+				(String::from("height"), B_INT32_TYPE),
+			]],
+			..Default::default()
+		},
+	]);
+
+	let flattened = property_info.flatten();
+	assert_eq!(flattened.len(), property_info.flattened_size());
+	assert_eq!(&flattened[0..9], &[0, 2, 0, 0, 0, 1, 0, 0, 0]);
+	let unflattened = PropertyInfo::unflatten(&flattened).unwrap();
+	assert_eq!(property_info, unflattened);
+	assert!(PropertyInfo::unflatten(&flattened[0..20]).is_err());
+
+	// A huge count in a short buffer is an error, not a huge allocation
+	let mut huge_count = flattened.clone();
+	huge_count[1..5].copy_from_slice(&i32::MAX.to_le_bytes());
+	assert!(PropertyInfo::unflatten(&huge_count).is_err());
+}
@@ -13,13 +13,14 @@ use std::str;
 use std::time::Duration;
 
 use libc::{
-	c_void, port_buffer_size_etc, port_id, read_port_etc, ssize_t, B_INTERRUPTED,
-	B_RELATIVE_TIMEOUT,
+	area_id, c_void, port_buffer_size_etc, port_id, read_port_etc, ssize_t, B_INTERRUPTED,
+	B_READ_AREA, B_RELATIVE_TIMEOUT, B_WRITE_AREA,
 };
 
 use crate::app::message::Message;
 use crate::app::messenger::Messenger;
 use crate::haiku_constant;
+use crate::kernel::areas::Area;
 use crate::kernel::ports::Port;
 use crate::support::{ErrorKind, Flattenable, HaikuError, Result};
 
@@ -27,6 +28,8 @@ const LINK_CODE: i32 = haiku_constant!('_', 'P', 'T', 'L') as i32;
 const INITIAL_BUFFER_SIZE: usize = 2048;
 const BUFFER_WATERMARK: u64 = INITIAL_BUFFER_SIZE as u64 - 24;
 const MAX_BUFFER_SIZE: usize = 65536;
+// The name of the area that carries data that is too large for the buffer
+const LINK_AREA_NAME: &str = "LinkSenderArea";
 const MAX_STRING_SIZE: usize = 4096;
 const NEEDS_REPLY: u32 = 0x01;
 const HEADER_SIZE: usize = 12;
@@ -69,8 +72,9 @@ pub(crate) mod server_protocol {
 ///
 /// Memory Management
 /// There are two intermediate memory buffers: on is through a heap-allocated
-/// data store with the MAX_BUFFER_SIZE (currently at 64kb). The other uses
-/// the area system (not yet implemented).
+/// data store with the MAX_BUFFER_SIZE (currently at 64kb). Data that is
+/// larger than that is copied into an area, which is transferred to the team
+/// of the receiver. Only the id of the area is written in the message.
 pub(crate) struct LinkSender {
 	port: Port,
 	cursor: Cursor<Vec<u8>>,
@@ -83,11 +87,11 @@ impl LinkSender {
 	pub(crate) fn start_message(&mut self, code: i32, mut size_hint: usize) -> Result<()> {
 		self.end_message(false)?;
 
-		// Switch memory allocation method when size is larger than the buffersize
-		size_hint += HEADER_SIZE;
-		if size_hint > MAX_BUFFER_SIZE {
-			unimplemented!()
+		// Large data is attached in an area, so only its id is in the buffer
+		if size_hint >= MAX_BUFFER_SIZE {
+			size_hint = mem::size_of::<area_id>();
 		}
+		size_hint += HEADER_SIZE;
 
 		// Flush the message queue if we are going to hit the watermark
 		if self.cursor.position() + size_hint as u64 > BUFFER_WATERMARK {
@@ -135,8 +139,14 @@ impl LinkSender {
 		}
 
 		// Check if the data size will overrun the buffer, if so switch to area
-		if data.flattened_size() > MAX_BUFFER_SIZE {
-			unimplemented!();
+		let size = data.flattened_size();
+		if size >= MAX_BUFFER_SIZE {
+			let mut area = Area::create(LINK_AREA_NAME, size, B_READ_AREA | B_WRITE_AREA)?;
+			area.as_mut_slice()?[..size].copy_from_slice(&data.flatten());
+			let target = self.port.get_info()?.team;
+			let area = area.transfer(&target)?;
+			self.cursor.write_all(&area.flatten()).unwrap();
+			return Ok(());
 		}
 
 		// Write data to the buffer
@@ -273,6 +283,20 @@ impl LinkReceiver {
 		if T::is_fixed_size() {
 			size = mem::size_of::<T>();
 		}
+
+		// Large data is passed in an area, the buffer only has its id
+		if size >= MAX_BUFFER_SIZE {
+			let area_id = self.read::<area_id>(0)?;
+			let area = Area::adopt_transferred(area_id, LINK_AREA_NAME)?;
+			return match area.as_slice()?.get(..size) {
+				Some(data) => T::unflatten(data),
+				None => Err(HaikuError::new(
+					ErrorKind::InvalidData,
+					"size of the data is larger than the area",
+				)),
+			};
+		}
+
 		if size > (end - pos) {
 			return Err(HaikuError::new(
 				ErrorKind::InvalidData,
@@ -450,29 +474,92 @@ pub(crate) struct ServerLink {
 }
 
 const APPSERVER_PORT_NAME: &str = "a<app_server";
+const APPSERVER_SIGNATURE: &str = "application/x-vnd.Haiku-app_server";
 const DEFAULT_PORT_CAPACITY: i32 = 100;
+/// The default time to wait for the app_server to reply to the desktop
+/// connection request
+const DEFAULT_CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
 
-impl ServerLink {
-	pub(crate) fn create_desktop_connection() -> Result<ServerLink> {
-		let receiver_port = Port::create(APPSERVER_PORT_NAME, DEFAULT_PORT_CAPACITY)?;
+/// The parameters of the request for a desktop connection
+///
+/// The default options are those of the current process: the user id, the
+/// protocol version of this crate, and the screen in the `TARGET_SCREEN`
+/// environment variable, if it is set. By default, the app_server gets ten
+/// seconds to reply to the request. Use them with
+/// `Application::new_with_desktop_options()` to connect to another screen,
+/// without changing the environment of the process.
+///
+/// ```norun
+/// use haiku::app::DesktopConnectionOptions;
+///
+/// let options = DesktopConnectionOptions {
+///     target_screen: Some(String::from("second")),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Clone, Debug)]
+pub struct DesktopConnectionOptions {
+	/// The user that owns the desktop
+	pub user: i32,
+	/// The version of the app_server protocol
+	pub version: i32,
+	/// The name of the screen to connect to, or `None` for the default
+	pub target_screen: Option<String>,
+	/// The time to wait for the app_server to reply to the request
+	pub timeout: Duration,
+}
 
-		let mut request = Message::new(server_protocol::AS_GET_DESKTOP as u32);
+impl Default for DesktopConnectionOptions {
+	fn default() -> Self {
 		let uid = unsafe { libc::getuid() };
+		DesktopConnectionOptions {
+			user: uid as i32,
+			version: server_protocol::AS_PROTOCOL_VERSION,
+			target_screen: env::var_os("TARGET_SCREEN")
+				.map(|target| target.to_string_lossy().into_owned()),
+			timeout: DEFAULT_CONNECTION_TIMEOUT,
+		}
+	}
+}
 
-		println!("uid: {}", uid);
-		request.add_data("user", &(uid as i32)).unwrap();
-		request
-			.add_data("version", &server_protocol::AS_PROTOCOL_VERSION)
-			.unwrap();
-		match env::var_os("TARGET_SCREEN") {
-			Some(target) => request
-				.add_data("target", &String::from(target.to_str().unwrap()))
-				.unwrap(),
-			None => (),
+impl DesktopConnectionOptions {
+	fn to_message(&self) -> Result<Message> {
+		let mut request = Message::new(server_protocol::AS_GET_DESKTOP as u32);
+		request.add_data("user", &self.user)?;
+		request.add_data("version", &self.version)?;
+		if let Some(target) = &self.target_screen {
+			request.add_data("target", target)?;
 		}
+		Ok(request)
+	}
+}
 
-		let server = Messenger::from_signature("application/x-vnd.Haiku-app_server", None)?;
-		let reply = server.send_and_wait_for_reply(request, None)?;
+impl ServerLink {
+	/// Connect to the app_server with explicit connection `options`,
+	/// waiting at most the timeout of the options for its reply
+	///
+	/// If the app_server does not respond in time, an error with
+	/// `ErrorKind::TimedOut` is returned.
+	pub(crate) fn create_desktop_connection_with_options(
+		options: &DesktopConnectionOptions,
+	) -> Result<ServerLink> {
+		let server = Messenger::from_signature(APPSERVER_SIGNATURE, None)?;
+		ServerLink::connect(&server, options)
+	}
+
+	fn connect(server: &Messenger, options: &DesktopConnectionOptions) -> Result<ServerLink> {
+		let receiver_port = Port::create(APPSERVER_PORT_NAME, DEFAULT_PORT_CAPACITY)?;
+		let request = options.to_message()?;
+
+		let reply = server
+			.send_and_wait_for_reply(request, Some(options.timeout))
+			.map_err(|e| match e.kind() {
+				ErrorKind::TimedOut => HaikuError::new(
+					ErrorKind::TimedOut,
+					"the app_server did not reply to the desktop connection request in time",
+				),
+				_ => e,
+			})?;
 		println!("{:?}", reply);
 
 		let server_port: port_id = reply.find_data("port", 0)?;
@@ -494,7 +581,9 @@ impl ServerLink {
 
 #[test]
 fn test_server_link() {
-	let mut link = ServerLink::create_desktop_connection().unwrap();
+	let mut link =
+		ServerLink::create_desktop_connection_with_options(&DesktopConnectionOptions::default())
+			.unwrap();
 	// Create a mock looper port
 	let looper_port = Port::create("mock_looper", 100).unwrap();
 	// Simulate attaching a program
@@ -505,6 +594,74 @@ fn test_server_link() {
 	link.sender.attach(&looper_port.get_port_id()).unwrap();
 }
 
+#[test]
+fn test_server_link_timeout() {
+	// Use a port that nobody reads from to simulate an unresponsive app_server
+	let mock_server_port = Port::create("mock_app_server", DEFAULT_PORT_CAPACITY).unwrap();
+	let server = Messenger::from_port(&mock_server_port).unwrap();
+	let options = DesktopConnectionOptions {
+		timeout: Duration::from_millis(100),
+		..Default::default()
+	};
+	let result = ServerLink::connect(&server, &options);
+	match result {
+		Err(e) => match e.kind() {
+			ErrorKind::TimedOut => (),
+			_ => panic!("expected a timeout error, got {:?}", e),
+		},
+		Ok(_) => panic!("the connection to the mock app_server should time out"),
+	}
+}
+
+#[test]
+fn test_server_link_options() {
+	use std::thread;
+
+	use libc::B_MESSAGE_TYPE;
+
+	// The mock app_server checks the request, and replies with the port of
+	// the desktop
+	let mock_server_port = Port::create("mock_app_server", DEFAULT_PORT_CAPACITY).unwrap();
+	let desktop_port = Port::create("mock_desktop", DEFAULT_PORT_CAPACITY).unwrap();
+	let desktop_port_id = desktop_port.get_port_id();
+	let server = Messenger::from_port(&mock_server_port).unwrap();
+	let responder = thread::spawn(move || {
+		let (_, buffer) = mock_server_port.read().unwrap();
+		let request = Message::unflatten(&buffer).unwrap();
+		assert_eq!(request.what(), server_protocol::AS_GET_DESKTOP as u32);
+		assert_eq!(request.find_data::<i32>("user", 0).unwrap(), 1234);
+		assert_eq!(request.find_data::<i32>("version", 0).unwrap(), 7);
+		assert_eq!(
+			request.find_data::<String>("target", 0).unwrap(),
+			"second screen"
+		);
+		let mut reply = Message::new(B_MESSAGE_TYPE);
+		reply.add_data("port", &desktop_port_id).unwrap();
+		let reply_port = Port::from_id(request.header.reply_port).unwrap();
+		reply_port
+			.write(B_MESSAGE_TYPE as i32, &reply.flatten())
+			.unwrap();
+	});
+
+	let options = DesktopConnectionOptions {
+		user: 1234,
+		version: 7,
+		target_screen: Some(String::from("second screen")),
+		timeout: Duration::from_secs(5),
+	};
+	let link = ServerLink::connect(&server, &options).unwrap();
+	responder.join().unwrap();
+	assert_eq!(link.sender.get_port_id(), desktop_port_id);
+
+	// Without a target screen, the request does not have the field
+	let options = DesktopConnectionOptions {
+		target_screen: None,
+		..options
+	};
+	let request = options.to_message().unwrap();
+	assert!(request.find_data::<String>("target", 0).is_err());
+}
+
 #[test]
 fn test_link_sender_receiver_behaviour() {
 	let receiver_port = Port::create("mock_receiver", DEFAULT_PORT_CAPACITY).unwrap();
@@ -595,3 +752,71 @@ fn test_link_sender_receiver_behaviour() {
 	}
 	assert_eq!(count, 103);
 }
+
+#[test]
+fn test_link_sender_receiver_area() {
+	let receiver_port = Port::create("mock_receiver", DEFAULT_PORT_CAPACITY).unwrap();
+	let sender_port = Port::from_id(receiver_port.get_port_id()).unwrap();
+	let mut sender = LinkSender {
+		port: sender_port,
+		cursor: Cursor::new(Vec::with_capacity(INITIAL_BUFFER_SIZE)),
+		current_message_start: 0,
+	};
+	let mut receiver = LinkReceiver {
+		port: receiver_port,
+		buffer: Vec::with_capacity(INITIAL_BUFFER_SIZE),
+		position: Position::Empty,
+	};
+
+	// Data larger than the buffer is passed in an area, the message only
+	// holds the id of the area
+	let data: Vec<u8> = (0..MAX_BUFFER_SIZE + 100)
+		.map(|i| (i % 251) as u8)
+		.collect();
+	sender.start_message(103, data.len()).unwrap();
+	sender.attach(&data).unwrap();
+	sender.attach(&(7 as i32)).unwrap();
+	sender.flush(false).unwrap();
+
+	let (code, size, _) = receiver.get_next_message(Duration::new(0, 0)).unwrap();
+	assert_eq!(code, 103);
+	assert_eq!(size, HEADER_SIZE + 2 * mem::size_of::<area_id>());
+	assert_eq!(receiver.read::<Vec<u8>>(data.len()).unwrap(), data);
+	assert_eq!(receiver.read::<i32>(0).unwrap(), 7);
+}
+
+#[test]
+fn test_link_receiver_forged_area_is_rejected() {
+	use libc::{B_READ_AREA, B_WRITE_AREA};
+
+	let receiver_port = Port::create("mock_receiver", DEFAULT_PORT_CAPACITY).unwrap();
+	let sender_port = Port::from_id(receiver_port.get_port_id()).unwrap();
+	let mut sender = LinkSender {
+		port: sender_port,
+		cursor: Cursor::new(Vec::with_capacity(INITIAL_BUFFER_SIZE)),
+		current_message_start: 0,
+	};
+	let mut receiver = LinkReceiver {
+		port: receiver_port,
+		buffer: Vec::with_capacity(INITIAL_BUFFER_SIZE),
+		position: Position::Empty,
+	};
+
+	// The id of a live area of this team that was not transferred as link
+	// data
+	let area = Area::create(
+		"forged link data",
+		MAX_BUFFER_SIZE,
+		B_READ_AREA | B_WRITE_AREA,
+	)
+	.unwrap();
+	sender.start_message(104, 0).unwrap();
+	sender.attach(&area.get_area_id()).unwrap();
+	sender.flush(false).unwrap();
+
+	receiver.get_next_message(Duration::new(0, 0)).unwrap();
+	let error = receiver.read::<Vec<u8>>(MAX_BUFFER_SIZE).unwrap_err();
+	assert!(matches!(error.kind(), ErrorKind::NotAllowed));
+	// The area is left alone
+	assert!(area.get_info().is_ok());
+}
@@ -18,9 +18,9 @@ use crate::haiku_constant;
 use crate::support::{ErrorKind, HaikuError, Result};
 
 // os/app/AppDefs.h
-pub const B_ARGV_RECEIVED: u32 = haiku_constant!('_', 'A', 'R', 'G');
-pub const B_READY_TO_RUN: u32 = haiku_constant!('_', 'R', 'T', 'R');
-pub const B_QUIT_REQUESTED: u32 = haiku_constant!('_', 'Q', 'R', 'Q');
+pub use crate::app::constants::{
+	B_ARGV_RECEIVED, B_QUIT_REQUESTED, B_READY_TO_RUN, B_REFS_RECEIVED, B_SILENT_RELAUNCH,
+};
 pub const QUIT: u32 = haiku_constant!('_', 'Q', 'I', 'T');
 
 // private/app/MessagePrivate.h
@@ -38,6 +38,7 @@ pub const FIELD_FLAG_VALID: u16 = 0x0001;
 pub const FIELD_FLAG_FIXED_SIZE: u16 = 0x0002;
 
 pub const MESSAGE_FORMAT_HAIKU: u32 = haiku_constant!('1', 'F', 'M', 'H');
+pub const MESSAGE_BODY_HASH_TABLE_SIZE: usize = 5;
 
 // private/app/TokenSpace.h
 pub const B_PREFERRED_TOKEN: i32 = -2;
@@ -76,7 +77,7 @@ pub struct message_header {
 	pub data_size: u32,
 	pub field_count: u32,
 	pub hash_table_size: u32,
-	pub hash_table: [i32; 5],
+	pub hash_table: [i32; MESSAGE_BODY_HASH_TABLE_SIZE],
 }
 
 // Helper functions
@@ -0,0 +1,109 @@
+//
+// Copyright 2024, Niels Sascha Reedijk <niels.reedijk@gmail.com>
+// All rights reserved. Distributed under the terms of the MIT License.
+//
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use libc::B_TIMED_OUT;
+
+use crate::app::{Message, Messenger};
+
+// The time that a timer waits for room in the port of its target, before it
+// checks whether it has been cancelled
+const SEND_TIMEOUT: Duration = Duration::from_millis(100);
+
+// Deliver a message at a later time
+//
+// A timer thread waits until the message is due, and then sends it to the
+// target. The delivery is repeated every `interval`, until `count` messages
+// are sent, or forever if there is no count. The deliveries are scheduled
+// relative to the start, so that a slow send does not make the next ones
+// drift. The timer stops when the target no longer exists.
+//
+// The timer is cancelled when it is dropped, unless it is detached. Dropping
+// the timer waits for the thread, which takes at most `SEND_TIMEOUT` when the
+// port of the target is full, for example when the timer is dropped by the
+// looper that it sends to.
+pub(crate) struct Timer {
+	cancelled: Arc<(Mutex<bool>, Condvar)>,
+	thread: Option<JoinHandle<()>>,
+}
+
+impl Timer {
+	pub(crate) fn start(
+		target: Messenger,
+		message: &Message,
+		delay: Duration,
+		interval: Duration,
+		count: Option<u32>,
+	) -> Timer {
+		let cancelled = Arc::new((Mutex::new(false), Condvar::new()));
+		let thread_cancelled = cancelled.clone();
+		let data = Vec::<u8>::from(message);
+		let thread = thread::spawn(move || {
+			let (lock, condition) = &*thread_cancelled;
+			let mut due = Instant::now() + delay;
+			let mut sent = 0;
+			while count != Some(sent) {
+				// Wait until the message is due, or until the timer is
+				// cancelled
+				let mut cancelled = lock.lock().unwrap();
+				loop {
+					if *cancelled {
+						return;
+					}
+					let now = Instant::now();
+					if now >= due {
+						break;
+					}
+					cancelled = condition.wait_timeout(cancelled, due - now).unwrap().0;
+				}
+				drop(cancelled);
+
+				// Wait for room in a full port in short steps, so that a
+				// cancelled timer does not stay blocked in the send
+				loop {
+					let message = match Message::try_from(data.as_slice()) {
+						Ok(message) => message,
+						Err(_) => return,
+					};
+					match target.send_with_timeout(message, &target, SEND_TIMEOUT) {
+						Ok(()) => break,
+						Err(ref e) if e.raw_os_error() == Some(B_TIMED_OUT) => {
+							if *lock.lock().unwrap() {
+								return;
+							}
+						}
+						Err(_) => return,
+					}
+				}
+				sent += 1;
+				due += interval;
+			}
+		});
+		Timer {
+			cancelled,
+			thread: Some(thread),
+		}
+	}
+
+	// Let the timer deliver its messages, without keeping a handle to it
+	pub(crate) fn detach(mut self) {
+		self.thread.take();
+	}
+}
+
+impl Drop for Timer {
+	fn drop(&mut self) {
+		if let Some(thread) = self.thread.take() {
+			let (lock, condition) = &*self.cancelled;
+			*lock.lock().unwrap() = true;
+			condition.notify_all();
+			let _ = thread.join();
+		}
+	}
+}
@@ -0,0 +1,77 @@
+//
+// Copyright 2024, Niels Sascha Reedijk <niels.reedijk@gmail.com>
+// All rights reserved. Distributed under the terms of the MIT License.
+//
+
+use libc::B_RGB_COLOR_TYPE;
+
+use crate::support::{ErrorKind, Flattenable, HaikuError, Result};
+
+/// A 32 bit color
+///
+/// This is the equivalent of Haiku's `rgb_color`. An alpha of 255 is fully
+/// opaque, and an alpha of 0 is fully transparent.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct RgbColor {
+	/// The red component
+	pub red: u8,
+	/// The green component
+	pub green: u8,
+	/// The blue component
+	pub blue: u8,
+	/// The opacity
+	pub alpha: u8,
+}
+
+impl RgbColor {
+	/// Create a color from its components
+	pub fn new(red: u8, green: u8, blue: u8, alpha: u8) -> RgbColor {
+		RgbColor {
+			red,
+			green,
+			blue,
+			alpha,
+		}
+	}
+}
+
+impl Flattenable<RgbColor> for RgbColor {
+	const FLATTENED_SIZE: Option<usize> = Some(4);
+
+	fn type_code() -> u32 {
+		B_RGB_COLOR_TYPE
+	}
+
+	fn flattened_size(&self) -> usize {
+		4
+	}
+
+	fn is_fixed_size() -> bool {
+		true
+	}
+
+	fn flatten(&self) -> Vec<u8> {
+		vec![self.red, self.green, self.blue, self.alpha]
+	}
+
+	fn unflatten(buffer: &[u8]) -> Result<RgbColor> {
+		if buffer.len() != 4 {
+			return Err(HaikuError::new(
+				ErrorKind::InvalidData,
+				"the buffer does not contain a flattened RgbColor",
+			));
+		}
+		Ok(RgbColor::new(buffer[0], buffer[1], buffer[2], buffer[3]))
+	}
+}
+
+#[test]
+fn test_rgb_color() {
+	let red = RgbColor::new(255, 0, 0, 255);
+	assert_eq!(red.flatten(), vec![255, 0, 0, 255]);
+	assert_eq!(RgbColor::unflatten(&red.flatten()).unwrap(), red);
+	let color = RgbColor::new(1, 2, 3, 4);
+	assert_eq!(RgbColor::unflatten(&color.flatten()).unwrap(), color);
+	assert!(RgbColor::unflatten(&[255, 0, 0]).is_err());
+	assert!(RgbColor::unflatten(&[255, 0, 0, 255, 0]).is_err());
+}
@@ -0,0 +1,16 @@
+//
+// Copyright 2024, Niels Sascha Reedijk <niels.reedijk@gmail.com>
+// All rights reserved. Distributed under the terms of the MIT License.
+//
+
+//! The interface kit contains the types that describe what is on the screen
+
+mod color;
+mod point;
+mod rect;
+mod windowinfo;
+
+pub use self::color::RgbColor;
+pub use self::point::Point;
+pub use self::rect::Rect;
+pub use self::windowinfo::WindowInfo;
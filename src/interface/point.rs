@@ -0,0 +1,78 @@
+//
+// Copyright 2024, Niels Sascha Reedijk <niels.reedijk@gmail.com>
+// All rights reserved. Distributed under the terms of the MIT License.
+//
+
+use std::mem;
+
+use libc::B_POINT_TYPE;
+
+use crate::support::{ErrorKind, Flattenable, HaikuError, Result};
+
+/// A point on the screen
+///
+/// This is the equivalent of Haiku's `BPoint`. The x coordinate increases to
+/// the right, and the y coordinate increases downwards.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Point {
+	/// The horizontal coordinate
+	pub x: f32,
+	/// The vertical coordinate
+	pub y: f32,
+}
+
+impl Point {
+	/// Create a point from its coordinates
+	pub fn new(x: f32, y: f32) -> Point {
+		Point { x, y }
+	}
+}
+
+impl Flattenable<Point> for Point {
+	const FLATTENED_SIZE: Option<usize> = Some(2 * mem::size_of::<f32>());
+
+	fn type_code() -> u32 {
+		B_POINT_TYPE
+	}
+
+	fn flattened_size(&self) -> usize {
+		2 * mem::size_of::<f32>()
+	}
+
+	fn is_fixed_size() -> bool {
+		true
+	}
+
+	fn flatten(&self) -> Vec<u8> {
+		let mut vec: Vec<u8> = Vec::with_capacity(self.flattened_size());
+		vec.extend(self.x.flatten().iter());
+		vec.extend(self.y.flatten().iter());
+		vec
+	}
+
+	fn unflatten(buffer: &[u8]) -> Result<Point> {
+		if buffer.len() != 2 * mem::size_of::<f32>() {
+			return Err(HaikuError::new(
+				ErrorKind::InvalidData,
+				"the buffer does not contain a flattened Point",
+			));
+		}
+		Ok(Point {
+			x: f32::unflatten(&buffer[0..4])?,
+			y: f32::unflatten(&buffer[4..8])?,
+		})
+	}
+}
+
+#[test]
+fn test_point() {
+	let point = Point::new(1.0, -2.5);
+	let flattened = point.flatten();
+	assert_eq!(
+		flattened,
+		vec![0x00, 0x00, 0x80, 0x3f, 0x00, 0x00, 0x20, 0xc0]
+	);
+	assert_eq!(flattened.len(), point.flattened_size());
+	assert_eq!(Point::unflatten(&flattened).unwrap(), point);
+	assert!(Point::unflatten(&flattened[0..4]).is_err());
+}
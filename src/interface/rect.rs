@@ -0,0 +1,115 @@
+//
+// Copyright 2024, Niels Sascha Reedijk <niels.reedijk@gmail.com>
+// All rights reserved. Distributed under the terms of the MIT License.
+//
+
+use std::mem;
+
+use libc::B_RECT_TYPE;
+
+use crate::support::{ErrorKind, Flattenable, HaikuError, Result};
+
+/// A rectangle on the screen
+///
+/// Like Haiku's `BRect`, the coordinates are inclusive. This means that a
+/// rectangle with the same left and right coordinate is one pixel wide, but
+/// its `width()` is 0.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rect {
+	/// The left edge of the rectangle
+	pub left: f32,
+	/// The top edge of the rectangle
+	pub top: f32,
+	/// The right edge of the rectangle
+	pub right: f32,
+	/// The bottom edge of the rectangle
+	pub bottom: f32,
+}
+
+impl Rect {
+	/// Create a rectangle from its edges
+	pub fn new(left: f32, top: f32, right: f32, bottom: f32) -> Rect {
+		Rect {
+			left,
+			top,
+			right,
+			bottom,
+		}
+	}
+
+	/// Get the width of the rectangle
+	pub fn width(&self) -> f32 {
+		self.right - self.left
+	}
+
+	/// Get the height of the rectangle
+	pub fn height(&self) -> f32 {
+		self.bottom - self.top
+	}
+
+	/// Check if the rectangle is valid
+	///
+	/// A rectangle is valid when the right edge is not to the left of the
+	/// left edge, and the bottom edge is not above the top edge.
+	pub fn is_valid(&self) -> bool {
+		self.left <= self.right && self.top <= self.bottom
+	}
+}
+
+impl Flattenable<Rect> for Rect {
+	const FLATTENED_SIZE: Option<usize> = Some(4 * mem::size_of::<f32>());
+
+	fn type_code() -> u32 {
+		B_RECT_TYPE
+	}
+
+	fn flattened_size(&self) -> usize {
+		4 * mem::size_of::<f32>()
+	}
+
+	fn is_fixed_size() -> bool {
+		true
+	}
+
+	fn flatten(&self) -> Vec<u8> {
+		let mut vec: Vec<u8> = Vec::with_capacity(self.flattened_size());
+		vec.extend(self.left.flatten().iter());
+		vec.extend(self.top.flatten().iter());
+		vec.extend(self.right.flatten().iter());
+		vec.extend(self.bottom.flatten().iter());
+		vec
+	}
+
+	fn unflatten(buffer: &[u8]) -> Result<Rect> {
+		if buffer.len() != 4 * mem::size_of::<f32>() {
+			return Err(HaikuError::new(
+				ErrorKind::InvalidData,
+				"the buffer does not contain a flattened Rect",
+			));
+		}
+		Ok(Rect {
+			left: f32::unflatten(&buffer[0..4])?,
+			top: f32::unflatten(&buffer[4..8])?,
+			right: f32::unflatten(&buffer[8..12])?,
+			bottom: f32::unflatten(&buffer[12..16])?,
+		})
+	}
+}
+
+#[test]
+fn test_rect() {
+	let rect = Rect::new(10.0, 20.0, 109.0, 69.0);
+	assert_eq!(rect.width(), 99.0);
+	assert_eq!(rect.height(), 49.0);
+	assert!(rect.is_valid());
+	assert!(!Rect::new(10.0, 0.0, 0.0, 0.0).is_valid());
+	assert_eq!(Rect::unflatten(&rect.flatten()).unwrap(), rect);
+	assert_eq!(
+		Rect::new(0.0, 1.0, 2.0, -1.0).flatten(),
+		vec![
+			0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x3f, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00,
+			0x80, 0xbf
+		]
+	);
+	assert!(Rect::unflatten(&rect.flatten()[0..12]).is_err());
+}
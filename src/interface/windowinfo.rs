@@ -0,0 +1,162 @@
+//
+// Copyright 2024, Niels Sascha Reedijk <niels.reedijk@gmail.com>
+// All rights reserved. Distributed under the terms of the MIT License.
+//
+
+use libc::{port_id, team_id, thread_id};
+
+use crate::interface::Rect;
+use crate::support::{ErrorKind, Flattenable, HaikuError, Result};
+
+// The app_server replies to AS_GET_WINDOW_INFO with a packed
+// client_window_info struct (see private/interface/WindowInfo.h):
+//
+//   offset  type     field
+//        0  team_id  team
+//        4  int32    server_token
+//        8  thread_id thread
+//       12  int32    client_token
+//       16  port_id  client_port
+//       20  uint32   workspaces
+//       24  int32    layer
+//       28  uint32   feel
+//       32  uint32   flags
+//       36  int32    window_left
+//       40  int32    window_top
+//       44  int32    window_right
+//       48  int32    window_bottom
+//       52  int32    show_hide_level
+//       56  bool     is_mini
+//       57  float    tab_height
+//       61  float    border_size
+//       65  char[]   name, terminated by a nul character
+const WINDOW_INFO_SIZE: usize = 65;
+
+/// Information about a window, as it is known by the app_server
+///
+/// This is the information that window managers like the Deskbar use to
+/// show and manipulate the windows of other applications.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WindowInfo {
+	/// The team that owns the window
+	pub team: team_id,
+	/// The identifier of the window in the app_server
+	pub server_token: i32,
+	/// The thread of the looper of the window
+	pub thread: thread_id,
+	/// The handler token of the window in its application
+	pub client_token: i32,
+	/// The port of the looper of the window
+	pub client_port: port_id,
+	/// A bitmap of the workspaces that the window is on
+	pub workspaces: u32,
+	/// The layer of the window, which determines its stacking order
+	pub layer: i32,
+	/// The feel of the window
+	pub feel: u32,
+	/// The flags of the window
+	pub flags: u32,
+	/// The frame of the window on the screen, in screen coordinates
+	pub frame: Rect,
+	/// Whether the window is hidden
+	pub is_hidden: bool,
+	/// Whether the window is minimized
+	pub is_minimized: bool,
+	/// The height of the tab of the window decorator
+	pub tab_height: f32,
+	/// The size of the border of the window decorator
+	pub border_size: f32,
+	/// The title of the window
+	pub name: String,
+}
+
+impl WindowInfo {
+	/// Decode the window info from the data that the app_server sends
+	///
+	/// The `buffer` should contain the packed `client_window_info` struct,
+	/// including the name of the window. An error with the kind
+	/// `ErrorKind::InvalidData` is returned when the buffer is too small.
+	pub fn from_bytes(buffer: &[u8]) -> Result<WindowInfo> {
+		if buffer.len() < WINDOW_INFO_SIZE {
+			return Err(HaikuError::new(
+				ErrorKind::InvalidData,
+				"the buffer is too small to contain a window info",
+			));
+		}
+		let read_i32 = |offset: usize| i32::unflatten(&buffer[offset..offset + 4]);
+		let read_u32 = |offset: usize| u32::unflatten(&buffer[offset..offset + 4]);
+		let read_f32 = |offset: usize| f32::unflatten(&buffer[offset..offset + 4]);
+
+		let name = &buffer[WINDOW_INFO_SIZE..];
+		let name = match name.iter().position(|&c| c == 0) {
+			Some(end) => &name[..end],
+			None => name,
+		};
+
+		Ok(WindowInfo {
+			team: read_i32(0)?,
+			server_token: read_i32(4)?,
+			thread: read_i32(8)?,
+			client_token: read_i32(12)?,
+			client_port: read_i32(16)?,
+			workspaces: read_u32(20)?,
+			layer: read_i32(24)?,
+			feel: read_u32(28)?,
+			flags: read_u32(32)?,
+			frame: Rect::new(
+				read_i32(36)? as f32,
+				read_i32(40)? as f32,
+				read_i32(44)? as f32,
+				read_i32(48)? as f32,
+			),
+			is_hidden: read_i32(52)? > 0,
+			is_minimized: buffer[56] != 0,
+			tab_height: read_f32(57)?,
+			border_size: read_f32(61)?,
+			name: String::from_utf8_lossy(name).into_owned(),
+		})
+	}
+}
+
+#[test]
+fn test_window_info_from_bytes() {
+	// The info of a Terminal window on the first workspace
+	let buffer: Vec<u8> = vec![
+		0xa3, 0x02, 0x00, 0x00, // team: 675
+		0x2a, 0x00, 0x00, 0x00, // server_token: 42
+		0xa7, 0x02, 0x00, 0x00, // thread: 679
+		0x05, 0x00, 0x00, 0x00, // client_token: 5
+		0x10, 0x27, 0x00, 0x00, // client_port: 10000
+		0x01, 0x00, 0x00, 0x00, // workspaces: 1
+		0x00, 0x00, 0x00, 0x00, // layer: 0
+		0x00, 0x00, 0x00, 0x00, // feel: B_NORMAL_WINDOW_FEEL
+		0x40, 0x00, 0x00, 0x00, // flags: 0x40
+		0x32, 0x00, 0x00, 0x00, // window_left: 50
+		0x46, 0x00, 0x00, 0x00, // window_top: 70
+		0x1f, 0x03, 0x00, 0x00, // window_right: 799
+		0x57, 0x02, 0x00, 0x00, // window_bottom: 599
+		0x00, 0x00, 0x00, 0x00, // show_hide_level: 0
+		0x01, // is_mini: true
+		0x00, 0x00, 0xb8, 0x41, // tab_height: 23.0
+		0x00, 0x00, 0xa0, 0x40, // border_size: 5.0
+		b'T', b'e', b'r', b'm', b'i', b'n', b'a', b'l', 0x00,
+	];
+	let info = WindowInfo::from_bytes(&buffer).unwrap();
+	assert_eq!(info.team, 675);
+	assert_eq!(info.server_token, 42);
+	assert_eq!(info.thread, 679);
+	assert_eq!(info.client_token, 5);
+	assert_eq!(info.client_port, 10000);
+	assert_eq!(info.workspaces, 1);
+	assert_eq!(info.layer, 0);
+	assert_eq!(info.feel, 0);
+	assert_eq!(info.flags, 0x40);
+	assert_eq!(info.frame, Rect::new(50.0, 70.0, 799.0, 599.0));
+	assert!(!info.is_hidden);
+	assert!(info.is_minimized);
+	assert_eq!(info.tab_height, 23.0);
+	assert_eq!(info.border_size, 5.0);
+	assert_eq!(info.name, "Terminal");
+
+	assert!(WindowInfo::from_bytes(&buffer[0..60]).is_err());
+}
@@ -14,15 +14,19 @@
 /// Ports are the lower level transportation mechanism for Messages.
 pub mod ports {
 	use std::ffi::{CStr, CString};
-	use std::mem;
-	use std::time::Duration;
+	use std::time::{Duration, Instant};
+	use std::{mem, ptr};
 
 	use libc::{
-		c_char, c_void, close_port, create_port, delete_port, find_port, get_port_info,
-		port_buffer_size, port_buffer_size_etc, port_count, port_id, port_info, read_port,
-		read_port_etc, write_port, write_port_etc, B_OS_NAME_LENGTH, B_RELATIVE_TIMEOUT,
+		c_char, c_void, close_port, create_port, delete_port, find_port, get_next_port_info,
+		get_next_team_info, get_port_info, port_buffer_size, port_buffer_size_etc, port_count,
+		port_id, port_info, read_port, read_port_etc, ssize_t, team_id, team_info, write_port,
+		write_port_etc, B_ABSOLUTE_TIMEOUT, B_MESSAGE_TYPE, B_OK, B_OS_NAME_LENGTH,
+		B_RELATIVE_TIMEOUT, B_TIMED_OUT, B_WOULD_BLOCK,
 	};
 
+	use crate::app::Message;
+	use crate::kernel::helpers::retry_on_interrupt;
 	use crate::kernel::teams::Team;
 	use crate::support::{ErrorKind, HaikuError, Result};
 
@@ -49,8 +53,14 @@ pub mod ports {
 	pub struct Port {
 		port: port_id,
 		owned: bool,
+		max_read_size: usize,
 	}
 
+	/// The default maximum size of a message that is read from a port
+	///
+	/// This is the largest message that the kernel accepts in a port.
+	pub const DEFAULT_MAX_READ_SIZE: usize = 256 * 1024;
+
 	/// Properties of the port
 	pub struct PortInfo {
 		/// Representation of the team that the port is part of
@@ -87,6 +97,7 @@ pub mod ports {
 				Ok(Port {
 					port: port,
 					owned: true,
+					max_read_size: DEFAULT_MAX_READ_SIZE,
 				})
 			}
 		}
@@ -110,10 +121,33 @@ pub mod ports {
 				Some(Port {
 					port: port,
 					owned: false,
+					max_read_size: DEFAULT_MAX_READ_SIZE,
 				})
 			}
 		}
 
+		/// Iterate over the ports of all the teams in the system
+		///
+		/// The iterator returns the info of every port at the moment that
+		/// it gets to it. Ports that are created or deleted while iterating
+		/// may or may not be included.
+		pub fn iter() -> PortInfoIterator {
+			PortInfoIterator {
+				team: None,
+				team_cookie: Some(0),
+				port_cookie: 0,
+			}
+		}
+
+		/// Iterate over the ports of a `team`
+		pub fn iter_team(team: &Team) -> PortInfoIterator {
+			PortInfoIterator {
+				team: Some(team.get_team_id()),
+				team_cookie: None,
+				port_cookie: 0,
+			}
+		}
+
 		/// Construct a borrowed port from id
 		///
 		/// If the port exists, this function will return a borrowed `Port`
@@ -130,12 +164,24 @@ pub mod ports {
 				Some(Port {
 					port: id,
 					owned: false,
+					max_read_size: DEFAULT_MAX_READ_SIZE,
 				})
 			} else {
 				None
 			}
 		}
 
+		// Take ownership of an existing port
+		//
+		// This is for ports that are created by another team, and then
+		// transferred to the current team, like the port of the main looper
+		// of an application that is launched by the roster.
+		pub(crate) fn adopt(id: port_id) -> Option<Port> {
+			let mut port = Port::from_id(id)?;
+			port.owned = true;
+			Some(port)
+		}
+
 		/// Write data to the port
 		///
 		/// The data is identified by a `type_code` and is sent as an array of
@@ -166,15 +212,43 @@ pub mod ports {
 		/// timeout is reached. Set the timeout to 0 if you want to return
 		/// immediately if the port is at capacity.
 		pub fn try_write(&self, type_code: i32, data: &[u8], timeout: Duration) -> Result<()> {
-			let timeout_ms = timeout.as_secs() as i64 * 1_000_000 + timeout.subsec_micros() as i64;
+			self.write_etc(
+				type_code,
+				data,
+				B_RELATIVE_TIMEOUT,
+				relative_timeout(timeout),
+			)
+		}
+
+		/// Attempt to write data to the port before a deadline
+		///
+		/// This method behaves like `try_write()`, but it gives up at the
+		/// `deadline`, instead of after a timeout. Use this in loops that
+		/// retry the operation, so that the deadline does not drift.
+		pub fn try_write_until(
+			&self,
+			type_code: i32,
+			data: &[u8],
+			deadline: Instant,
+		) -> Result<()> {
+			self.write_etc(
+				type_code,
+				data,
+				B_ABSOLUTE_TIMEOUT,
+				absolute_timeout(deadline),
+			)
+		}
+
+		// Write to the port with the timeout `flags`
+		fn write_etc(&self, type_code: i32, data: &[u8], flags: u32, timeout: i64) -> Result<()> {
 			let status = unsafe {
 				write_port_etc(
 					self.port,
 					type_code,
 					data.as_ptr() as *const c_void,
 					data.len() as usize,
-					B_RELATIVE_TIMEOUT,
-					timeout_ms,
+					flags,
+					timeout,
 				)
 			};
 
@@ -191,33 +265,37 @@ pub mod ports {
 		/// This method reads the next message from the port. The data is
 		/// returned as a tuple of a type code and a buffer. The method waits
 		/// until there is a next message.
+		///
+		/// When the wait is interrupted by a signal, this method keeps
+		/// waiting. Use `read_interruptible()` if you want to handle the
+		/// interruption yourself.
+		///
+		/// This method panics when you do not own the port. See
+		/// `read_unchecked()` if you really need to read from a port that you
+		/// do not own.
 		pub fn read(&self) -> Result<(i32, Vec<u8>)> {
 			if !self.owned {
 				panic!(
 					"You are trying to read from a port that you do not own. This is not allowed"
 				);
 			}
-			let size = unsafe { port_buffer_size(self.port) };
-			if size < 0 {
-				return Err(HaikuError::from_raw_os_error(size as i32));
-			}
-			let mut dst: Vec<u8> = Vec::with_capacity(size as usize);
-			let pdst = dst.as_mut_ptr() as *mut c_void;
-			let mut type_code: i32 = 0;
-			let dst_len = unsafe { read_port(self.port, &mut type_code, pdst, size as usize) };
-
-			if dst_len > 0 && dst_len != size {
-				panic!("read_port does not return data with the predicted size");
-			}
+			unsafe { self.read_unchecked() }
+		}
 
-			if dst_len < 0 {
-				Err(HaikuError::from_raw_os_error(dst_len as i32))
-			} else {
-				unsafe {
-					dst.set_len(dst_len as usize);
-				};
-				Ok((type_code, dst))
+		/// Read data from a port, unless the wait is interrupted
+		///
+		/// This method behaves like `read()`, but when the wait is
+		/// interrupted by a signal, it returns an error with
+		/// `ErrorKind::Interrupted`.
+		///
+		/// This method panics when you do not own the port.
+		pub fn read_interruptible(&self) -> Result<(i32, Vec<u8>)> {
+			if !self.owned {
+				panic!(
+					"You are trying to read from a port that you do not own. This is not allowed"
+				);
 			}
+			unsafe { self.read_once() }
 		}
 
 		/// Attempt to read data from a port
@@ -227,49 +305,183 @@ pub mod ports {
 		/// until there is a next message, or until when a timeout if reached.
 		/// If you don't want to wait for a message to come in, you can set the
 		/// timeout to 0
+		///
+		/// Unlike `read()`, this method returns an error with
+		/// `ErrorKind::Interrupted` when the wait is interrupted by a signal.
+		///
+		/// This method panics when you do not own the port. See
+		/// `read_unchecked_etc()` if you really need to read from a port that
+		/// you do not own.
 		pub fn try_read(&self, timeout: Duration) -> Result<(i32, Vec<u8>)> {
 			if !self.owned {
 				panic!(
 					"You are trying to read from a port that you do not own. This is not allowed"
 				);
 			}
-			let timeout_ms = timeout.as_secs() as i64 * 1_000_000 + timeout.subsec_micros() as i64;
-			let size = unsafe { port_buffer_size_etc(self.port, B_RELATIVE_TIMEOUT, timeout_ms) };
-			if size < 0 {
-				return Err(HaikuError::from_raw_os_error(size as i32));
+			unsafe { self.read_unchecked_etc(timeout) }
+		}
+
+		/// Attempt to read data from a port before a deadline
+		///
+		/// This method behaves like `try_read()`, but it gives up at the
+		/// `deadline`, instead of after a timeout. Use this in loops that
+		/// retry the operation, so that the deadline does not drift.
+		pub fn try_read_until(&self, deadline: Instant) -> Result<(i32, Vec<u8>)> {
+			if !self.owned {
+				panic!(
+					"You are trying to read from a port that you do not own. This is not allowed"
+				);
 			}
-			let mut dst: Vec<u8> = Vec::with_capacity(size as usize);
+			unsafe { self.read_etc(B_ABSOLUTE_TIMEOUT, absolute_timeout(deadline)) }
+		}
+
+		/// Read data from a port, even if you do not own it
+		///
+		/// This method behaves like `read()`, but it does not check whether
+		/// you own the port.
+		///
+		/// # Safety
+		///
+		/// The owner of the port expects to be the only reader. Any message
+		/// that you read is taken away from the owner, which may leave it
+		/// waiting for a message that never arrives. Only use this for
+		/// debugging or monitoring, when you know what the owner does.
+		pub unsafe fn read_unchecked(&self) -> Result<(i32, Vec<u8>)> {
+			// The size of the next message is queried again after an
+			// interruption, as the message may have been read by another
+			// reader in the mean time.
+			retry_on_interrupt(|| self.read_once())
+		}
+
+		// Read the next message, waiting until it arrives
+		unsafe fn read_once(&self) -> Result<(i32, Vec<u8>)> {
+			let size = self.prepare_read(port_buffer_size(self.port))?;
+			let mut dst: Vec<u8> = Vec::with_capacity(size);
 			let pdst = dst.as_mut_ptr() as *mut c_void;
 			let mut type_code: i32 = 0;
-			let dst_len = unsafe {
-				// Technically if there is only one consumer of the port, we
-				// could use read_port without a timeout, because we already
-				// checked if there is a message waiting with a timeout above.
-				// However, there might be bad actors out there that are also
-				// listening to this port, so using the timeout again will
-				// prevent a lock when that's the case.
-				read_port_etc(
-					self.port,
-					&mut type_code,
-					pdst,
-					size as usize,
-					B_RELATIVE_TIMEOUT,
-					timeout_ms,
-				)
-			};
+			let dst_len = read_port(self.port, &mut type_code, pdst, size);
+			finish_read(dst, size, type_code, dst_len)
+		}
 
-			if dst_len > 0 && dst_len != size {
-				panic!("read_port does not return data with the predicted size");
-			}
+		/// Attempt to read data from a port, even if you do not own it
+		///
+		/// This method behaves like `try_read()`, but it does not check
+		/// whether you own the port.
+		///
+		/// # Safety
+		///
+		/// See `read_unchecked()`.
+		pub unsafe fn read_unchecked_etc(&self, timeout: Duration) -> Result<(i32, Vec<u8>)> {
+			self.read_etc(B_RELATIVE_TIMEOUT, relative_timeout(timeout))
+		}
 
-			if dst_len < 0 {
-				Err(HaikuError::from_raw_os_error(dst_len as i32))
-			} else {
+		// Read from the port with the timeout `flags`
+		unsafe fn read_etc(&self, flags: u32, timeout: i64) -> Result<(i32, Vec<u8>)> {
+			let size = self.prepare_read(port_buffer_size_etc(self.port, flags, timeout))?;
+			let mut dst: Vec<u8> = Vec::with_capacity(size);
+			let pdst = dst.as_mut_ptr() as *mut c_void;
+			let mut type_code: i32 = 0;
+			// Technically if there is only one consumer of the port, we could
+			// use read_port without a timeout, because we already checked if
+			// there is a message waiting with a timeout above. However, there
+			// might be bad actors out there that are also listening to this
+			// port, so using the timeout again will prevent a lock when that's
+			// the case.
+			let dst_len = read_port_etc(self.port, &mut type_code, pdst, size, flags, timeout);
+			finish_read(dst, size, type_code, dst_len)
+		}
+
+		/// Get the maximum size of a message that can be read from the port
+		pub fn max_read_size(&self) -> usize {
+			self.max_read_size
+		}
+
+		/// Set the maximum size of a message that can be read from the port
+		///
+		/// Any team can write to a port. To protect against a writer that
+		/// sends messages that are bigger than expected, `read()` and
+		/// `try_read()` do not allocate a buffer for a message that is larger
+		/// than `size`. Instead, they discard the message and return an error
+		/// with `ErrorKind::InvalidData`. The default is
+		/// `DEFAULT_MAX_READ_SIZE`.
+		pub fn set_max_read_size(&mut self, size: usize) {
+			self.max_read_size = size;
+		}
+
+		// Check the size of the next message, and discard it if it is too big
+		fn prepare_read(&self, size: ssize_t) -> Result<usize> {
+			let result = checked_read_size(size, self.max_read_size);
+			if size >= 0 && result.is_err() {
+				let mut type_code: i32 = 0;
 				unsafe {
-					dst.set_len(dst_len as usize);
+					read_port_etc(
+						self.port,
+						&mut type_code,
+						ptr::null_mut(),
+						0,
+						B_RELATIVE_TIMEOUT,
+						0,
+					)
+				};
+			}
+			result
+		}
+
+		/// Read all the messages that are waiting in the port
+		///
+		/// This method drains the queue of the port without waiting for new
+		/// data to come in. Every buffer that contains a flattened `Message`
+		/// is unflattened. Buffers with another type code, and buffers that
+		/// cannot be unflattened, result in an error in the returned list, so
+		/// that the list has an entry for every item that was in the queue.
+		///
+		/// An error with `ErrorKind::NotAllowed` is returned when you do not
+		/// own the port. Use `read_all_messages_unchecked()` to inspect the
+		/// queue of a port of another looper.
+		pub fn read_all_messages(&self) -> Result<Vec<Result<Message>>> {
+			if !self.owned {
+				return Err(HaikuError::new(
+					ErrorKind::NotAllowed,
+					"the port is owned by someone else",
+				));
+			}
+			Ok(unsafe { self.read_all_messages_unchecked() })
+		}
+
+		/// Read all the messages that are waiting in the port, even if you
+		/// do not own it
+		///
+		/// This method behaves like `read_all_messages()`, but it does not
+		/// check whether you own the port. This is useful for inspecting the
+		/// message queue of a looper that seems to be stuck.
+		///
+		/// # Safety
+		///
+		/// See `read_unchecked()`. All the messages are taken away from the
+		/// owner of the port.
+		pub unsafe fn read_all_messages_unchecked(&self) -> Vec<Result<Message>> {
+			let mut messages = Vec::new();
+			loop {
+				let (type_code, buffer) = match self.read_unchecked_etc(Duration::ZERO) {
+					Ok(data) => data,
+					Err(e) => {
+						match e.raw_os_error() {
+							Some(B_WOULD_BLOCK) | Some(B_TIMED_OUT) => (),
+							_ => messages.push(Err(e)),
+						}
+						break;
+					}
 				};
-				Ok((type_code, dst))
+				if type_code as u32 == B_MESSAGE_TYPE {
+					messages.push(Message::unflatten_received(&buffer));
+				} else {
+					messages.push(Err(HaikuError::new(
+						ErrorKind::InvalidData,
+						"the data on the port does not contain a Message",
+					)));
+				}
 			}
+			messages
 		}
 
 		/// Close a port
@@ -293,6 +505,8 @@ pub mod ports {
 		/// Get the port count
 		///
 		/// This returns the number of items that are waiting to be processed.
+		/// Unlike `get_info()`, this only asks the kernel for the count, so it
+		/// is cheap enough to call for every message in a message loop.
 		pub fn get_count(&self) -> Result<usize> {
 			let status = unsafe { port_count(self.port) };
 			if status < 0 {
@@ -304,19 +518,42 @@ pub mod ports {
 
 		/// Get the port info
 		pub fn get_info(&self) -> Result<PortInfo> {
+			port_info_from_raw(&self.get_raw_info()?)
+		}
+
+		/// Update an existing PortInfo with the current port info
+		///
+		/// This is a cheaper alternative to `get_info()` when the info is
+		/// retrieved repeatedly. The storage of the name is reused, and the
+		/// team is only looked up if it is different from the one in `info`.
+		pub fn get_info_into(&self, info: &mut PortInfo) -> Result<()> {
+			let raw_info = self.get_raw_info()?;
+			if info.team.get_team_id() != raw_info.team {
+				info.team = Team::try_from(raw_info.team)?;
+			}
+			// Borrow the name, so that valid names are not copied twice
+			let c_name = unsafe { CStr::from_ptr((&raw_info.name) as *const c_char) };
+			info.name.clear();
+			info.name.push_str(&c_name.to_string_lossy());
+			info.capacity = raw_info.capacity;
+			info.queue_count = raw_info.queue_count;
+			info.total_count = raw_info.total_count;
+			Ok(())
+		}
+
+		/// Get the name of the port
+		pub fn name(&self) -> Result<String> {
+			let info = self.get_raw_info()?;
+			Ok(port_name(&info))
+		}
+
+		fn get_raw_info(&self) -> Result<port_info> {
 			let mut info: port_info = unsafe { mem::zeroed() };
 			let status = unsafe { get_port_info(self.port, &mut info) };
 			if status != 0 {
 				Err(HaikuError::from_raw_os_error(status))
 			} else {
-				let c_name = unsafe { CStr::from_ptr((&info.name) as *const c_char) };
-				Ok(PortInfo {
-					team: Team::from(info.team).unwrap(),
-					name: String::from(c_name.to_str().unwrap()),
-					capacity: info.capacity,
-					queue_count: info.queue_count,
-					total_count: info.total_count,
-				})
+				Ok(info)
 			}
 		}
 
@@ -326,6 +563,124 @@ pub mod ports {
 		}
 	}
 
+	// Convert the size that the kernel reports for the next message in a port
+	pub(super) fn checked_read_size(size: ssize_t, max_read_size: usize) -> Result<usize> {
+		if size < 0 {
+			return Err(HaikuError::from_raw_os_error(size as i32));
+		}
+		match usize::try_from(size) {
+			Ok(size) if size <= max_read_size => Ok(size),
+			_ => Err(HaikuError::new(
+				ErrorKind::InvalidData,
+				format!(
+					"the message in the port is larger than the maximum of {} bytes",
+					max_read_size
+				),
+			)),
+		}
+	}
+
+	// Convert a timeout into microseconds, for use with B_RELATIVE_TIMEOUT
+	fn relative_timeout(timeout: Duration) -> i64 {
+		timeout.as_secs() as i64 * 1_000_000 + timeout.subsec_micros() as i64
+	}
+
+	// Convert a deadline into a system time in microseconds, for use with
+	// B_ABSOLUTE_TIMEOUT
+	fn absolute_timeout(deadline: Instant) -> i64 {
+		let remaining = deadline.saturating_duration_since(Instant::now());
+		let now = unsafe { libc::system_time() };
+		now.saturating_add(relative_timeout(remaining))
+	}
+
+	fn changed_size_error() -> HaikuError {
+		HaikuError::new(
+			ErrorKind::InvalidData,
+			"the message in the port changed size while it was read",
+		)
+	}
+
+	// Check the result of reading a message into `dst`, that was allocated for a
+	// message of `size` bytes
+	unsafe fn finish_read(
+		mut dst: Vec<u8>,
+		size: usize,
+		type_code: i32,
+		dst_len: ssize_t,
+	) -> Result<(i32, Vec<u8>)> {
+		if dst_len > 0 && dst_len as usize != size {
+			return Err(changed_size_error());
+		}
+
+		if dst_len < 0 {
+			Err(HaikuError::from_raw_os_error(dst_len as i32))
+		} else {
+			dst.set_len(dst_len as usize);
+			Ok((type_code, dst))
+		}
+	}
+
+	/// An iterator over the info of ports
+	///
+	/// This iterator is returned by `Port::iter()` and `Port::iter_team()`.
+	pub struct PortInfoIterator {
+		// The team whose ports are currently listed
+		team: Option<team_id>,
+		// The cookie to get the next team, when all teams are listed
+		team_cookie: Option<i32>,
+		port_cookie: i32,
+	}
+
+	impl Iterator for PortInfoIterator {
+		type Item = PortInfo;
+
+		fn next(&mut self) -> Option<PortInfo> {
+			loop {
+				let team = match (self.team, self.team_cookie.as_mut()) {
+					(Some(team), _) => team,
+					(None, Some(team_cookie)) => {
+						let mut info: team_info = unsafe { mem::zeroed() };
+						if unsafe { get_next_team_info(team_cookie, &mut info) } != B_OK {
+							self.team_cookie = None;
+							return None;
+						}
+						self.team = Some(info.team);
+						self.port_cookie = 0;
+						info.team
+					}
+					(None, None) => return None,
+				};
+
+				let mut info: port_info = unsafe { mem::zeroed() };
+				if unsafe { get_next_port_info(team, &mut self.port_cookie, &mut info) } == B_OK {
+					// Skip ports of teams that quit in the mean time
+					if let Ok(port_info) = port_info_from_raw(&info) {
+						return Some(port_info);
+					}
+				} else {
+					// Continue with the next team, if there is one
+					self.team = None;
+				}
+			}
+		}
+	}
+
+	fn port_info_from_raw(info: &port_info) -> Result<PortInfo> {
+		Ok(PortInfo {
+			team: Team::try_from(info.team)?,
+			name: port_name(info),
+			capacity: info.capacity,
+			queue_count: info.queue_count,
+			total_count: info.total_count,
+		})
+	}
+
+	// Names that are not valid UTF-8 are converted lossily
+	fn port_name(info: &port_info) -> String {
+		let c_name = unsafe { CStr::from_ptr((&info.name) as *const c_char) };
+		c_name.to_string_lossy().into_owned()
+	}
+
 	impl Clone for Port {
 		/// Create a borrowed clone of the Port
 		///
@@ -337,6 +692,7 @@ pub mod ports {
 			Port {
 				port: self.port,
 				owned: false,
+				max_read_size: self.max_read_size,
 			}
 		}
 	}
@@ -354,13 +710,92 @@ pub mod ports {
 
 /// A team is a unique process that is running on Haiku
 pub mod teams {
-	use libc::team_id;
+	use std::ffi::CStr;
+	use std::fmt;
+	use std::path::PathBuf;
+	use std::{mem, ptr};
+
+	use libc::{
+		c_char, c_void, dev_t, find_thread, get_team_info, get_thread_info, gid_t, ino_t,
+		kill_team, size_t, status_t, team_id, team_info, thread_info, uid_t, wait_for_thread,
+		B_BAD_TEAM_ID, B_BUFFER_OVERFLOW, B_NOT_ALLOWED, B_OK, B_PERMISSION_DENIED,
+	};
+
+	use crate::haiku_constant;
+	use crate::kernel::helpers;
+	use crate::support::{ErrorKind, Flattenable, HaikuError, Result};
+
+	// private/system/extended_system_info_defs.h
+	const B_TEAM_INFO_BASIC: u32 = 0x01;
+
+	// private/kernel/util/KMessage.h
+	const KMESSAGE_HEADER_MAGIC: u32 = haiku_constant!('k', 'M', 's', 'G');
+	const KMESSAGE_HEADER_SIZE: usize = 28;
+	const KMESSAGE_FIELD_NAME_OFFSET: usize = 18;
 
 	/// This struct is a representation of a team
+	#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 	pub struct Team {
 		id: team_id,
 	}
 
+	impl fmt::Debug for Team {
+		fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+			f.debug_tuple("Team").field(&self.id).finish()
+		}
+	}
+
+	/// Build a team object from a raw team id, and check that it exists
+	///
+	/// Unlike `Team::from()`, this checks with the kernel if the team is
+	/// actually running. It returns an error with `ErrorKind::InvalidInput`
+	/// if the id is negative, and an error with `ErrorKind::NotFound` if
+	/// there is no team with this id. Other errors of the kernel are passed
+	/// on.
+	impl TryFrom<team_id> for Team {
+		type Error = HaikuError;
+
+		fn try_from(id: team_id) -> Result<Team> {
+			if id < 0 {
+				return Err(HaikuError::new(
+					ErrorKind::InvalidInput,
+					format!("invalid team id: {}", id),
+				));
+			}
+			let mut info: team_info = unsafe { mem::zeroed() };
+			match unsafe { get_team_info(id, &mut info) } {
+				B_OK => Ok(Team { id }),
+				B_BAD_TEAM_ID => Err(HaikuError::new(
+					ErrorKind::NotFound,
+					format!("there is no team with id {}", id),
+				)),
+				status => Err(HaikuError::from_raw_os_error(status)),
+			}
+		}
+	}
+
+	/// Properties of a team
+	pub struct TeamInfo {
+		/// The number of threads in the team
+		pub thread_count: i32,
+		/// The number of images (the executable and its libraries) that are
+		/// loaded in the team
+		pub image_count: i32,
+		/// The number of areas that the team owns
+		pub area_count: i32,
+		/// The number of arguments that the team was started with
+		pub argc: i32,
+		/// The command line of the team
+		///
+		/// The kernel only stores the first 64 bytes, so long command lines
+		/// are truncated.
+		pub args: String,
+		/// The user that owns the team
+		pub uid: uid_t,
+		/// The group that owns the team
+		pub gid: gid_t,
+	}
+
 	impl Team {
 		/// Build a team object from a raw team id
 		pub fn from(id: team_id) -> Option<Team> {
@@ -371,99 +806,1627 @@ pub mod teams {
 			}
 		}
 
+		/// Get the team of the calling thread
+		pub fn current() -> Team {
+			let mut info = mem::MaybeUninit::<thread_info>::uninit();
+			let status = unsafe { get_thread_info(find_thread(ptr::null()), info.as_mut_ptr()) };
+			// The kernel always knows the calling thread
+			assert_eq!(status, B_OK);
+			let info = unsafe { info.assume_init() };
+			Team { id: info.team }
+		}
+
 		/// Get the raw team identifier
 		pub fn get_team_id(&self) -> team_id {
 			self.id
 		}
-	}
-}
-
-use std::time::Duration;
-/// An infinite timeout
-pub const INFINITE_TIMEOUT: Duration = Duration::from_micros(i64::max_value() as u64);
-
-// Helpers for this crate only
-pub(crate) mod helpers {
-	use std::ffi::CStr;
-	use std::str;
 
-	use libc::{c_char, dev_t, ino_t, size_t, status_t, B_PATH_NAME_LENGTH};
+		/// Get the information of the team
+		///
+		/// The function returns an error if the team no longer exists.
+		pub fn get_info(&self) -> Result<TeamInfo> {
+			let mut info: team_info = unsafe { mem::zeroed() };
+			let status = unsafe { get_team_info(self.id, &mut info) };
+			if status != B_OK {
+				return Err(HaikuError::from_raw_os_error(status));
+			}
+			let c_args = unsafe { CStr::from_ptr((&info.args) as *const c_char) };
+			Ok(TeamInfo {
+				thread_count: info.thread_count,
+				image_count: info.image_count,
+				area_count: info.area_count,
+				argc: info.argc,
+				args: c_args.to_string_lossy().into_owned(),
+				uid: info.uid,
+				gid: info.gid,
+			})
+		}
 
-	use crate::support::{HaikuError, Result};
+		/// Kill the team
+		///
+		/// All the threads of the team are killed immediately. This is
+		/// abrupt: the team does not get a chance to clean up, so destructors
+		/// and exit handlers in that team do not run.
+		pub fn kill(&self) -> Result<()> {
+			let status = unsafe { kill_team(self.id) };
+			if status != B_OK {
+				return Err(HaikuError::from_raw_os_error(status));
+			}
+			Ok(())
+		}
 
-	pub(crate) fn get_path_for_entry_ref(
-		device: dev_t,
-		dir: ino_t,
-		leaf: *const c_char,
-	) -> Result<String> {
-		extern "C" {
-			pub fn _kern_entry_ref_to_path(
-				device: dev_t,
-				inode: ino_t,
-				leaf: *const c_char,
-				buf: *mut c_char,
-				bufferSize: size_t,
-			) -> status_t;
+		/// Wait for the team to exit, and return its exit status
+		///
+		/// This function blocks until the team exits. The exit status is the
+		/// value that the main thread of the team returned, or the value
+		/// that was passed to `exit()`.
+		pub fn wait(&self) -> Result<status_t> {
+			// The main thread of a team has the same id as the team, and the
+			// team exits when the main thread exits.
+			let mut exit_status: status_t = B_OK;
+			let status = unsafe { wait_for_thread(self.id, &mut exit_status) };
+			if status != B_OK {
+				return Err(HaikuError::from_raw_os_error(status));
+			}
+			Ok(exit_status)
 		}
 
-		let mut buf = [0 as c_char; B_PATH_NAME_LENGTH];
-		let p = buf.as_mut_ptr();
-		let path = unsafe {
-			let result = _kern_entry_ref_to_path(device, dir, leaf, p, buf.len());
-			if result != 0 {
-				return Err(HaikuError::from_raw_os_error(result));
+		/// Get the current working directory of the team
+		///
+		/// Regular users can only inspect the teams that they own. For other
+		/// teams, the function returns an error with `ErrorKind::NotAllowed`.
+		/// The root user can inspect all teams.
+		pub fn cwd(&self) -> Result<PathBuf> {
+			extern "C" {
+				fn _kern_get_extended_team_info(
+					team: team_id,
+					flags: u32,
+					buffer: *mut c_void,
+					size: size_t,
+					size_needed: *mut size_t,
+				) -> status_t;
 			}
 
-			let p = p as *const _;
-			str::from_utf8(CStr::from_ptr(p).to_bytes())
-				.unwrap()
-				.to_owned()
-		};
-		Ok(path)
+			// The kernel returns the information as a flattened KMessage.
+			// Start with a reasonable buffer size, and grow it if needed.
+			let mut buffer: Vec<u8> = vec![0; 1024];
+			loop {
+				let mut size_needed: size_t = 0;
+				let status = unsafe {
+					_kern_get_extended_team_info(
+						self.id,
+						B_TEAM_INFO_BASIC,
+						buffer.as_mut_ptr() as *mut c_void,
+						buffer.len(),
+						&mut size_needed,
+					)
+				};
+				match status {
+					B_OK => break,
+					B_BUFFER_OVERFLOW if size_needed > buffer.len() => {
+						buffer.resize(size_needed, 0)
+					}
+					B_NOT_ALLOWED | B_PERMISSION_DENIED => {
+						return Err(HaikuError::from_raw_os_error(B_NOT_ALLOWED))
+					}
+					_ => return Err(HaikuError::from_raw_os_error(status)),
+				}
+			}
+
+			let device = find_kmessage_field(&buffer, "cwd device")
+				.and_then(|data| dev_t::unflatten(data).ok());
+			let directory = find_kmessage_field(&buffer, "cwd directory")
+				.and_then(|data| ino_t::unflatten(data).ok());
+			match (device, directory) {
+				(Some(device), Some(directory)) => {
+					let path = helpers::get_path_for_entry_ref(device, directory, ptr::null())?;
+					Ok(PathBuf::from(path))
+				}
+				_ => Err(HaikuError::new(
+					ErrorKind::InvalidData,
+					"the kernel did not return the working directory of the team",
+				)),
+			}
+		}
 	}
-}
 
-/// Pause execution of the application and open the Debugger
-///
-/// You can show the `message` to the user when the debugger opens.
-pub fn debugger(message: &str) {
-	use libc::c_char;
-	use std::ffi::CString;
-	extern "C" {
-		fn debugger(message: *const c_char);
+	// Find the data of the first element of a fixed size field in a flattened
+	// KMessage. The layout of the message is a header, followed by a list of
+	// fields. Each field has a header with the type, the element size, the
+	// element count, the size of the data, the size of the header and the
+	// name of the field. The data follows the header, and the next field
+	// starts at the next 4 byte boundary.
+	fn find_kmessage_field<'a>(buffer: &'a [u8], name: &str) -> Option<&'a [u8]> {
+		let read_i32 =
+			|pos: usize| -> Option<i32> { i32::unflatten(buffer.get(pos..pos + 4)?).ok() };
+		if read_i32(0)? as u32 != KMESSAGE_HEADER_MAGIC {
+			return None;
+		}
+		let size = (read_i32(4)? as usize).min(buffer.len());
+		let mut pos = KMESSAGE_HEADER_SIZE;
+		while pos + KMESSAGE_FIELD_NAME_OFFSET < size {
+			let element_size = read_i32(pos + 4)?;
+			let element_count = read_i32(pos + 8)?;
+			let field_size = read_i32(pos + 12)? as usize;
+			let header_size = i16::unflatten(buffer.get(pos + 16..pos + 18)?).ok()? as usize;
+			let field_name = CStr::from_bytes_until_nul(
+				buffer.get(pos + KMESSAGE_FIELD_NAME_OFFSET..pos + header_size)?,
+			)
+			.ok()?;
+			let data = pos + header_size;
+			if field_name.to_bytes() == name.as_bytes() {
+				if element_size < 0 || element_count < 1 {
+					return None;
+				}
+				return buffer.get(data..data + element_size as usize);
+			}
+			pos = (data + field_size + 3) & !3;
+		}
+		None
 	}
-	let msg = CString::new(message).unwrap();
-	unsafe { debugger(msg.as_ptr()) };
-}
 
-#[test]
-fn test_basic_port() {
-	use crate::kernel::ports::Port;
+	#[test]
+	fn test_find_kmessage_field() {
+		// Build a message with an int32 field and an int64 field
+		fn add_field(buffer: &mut Vec<u8>, name: &str, data: &[u8]) {
+			let header_size = (20 + name.len() + 3) & !3;
+			buffer.extend(0i32.flatten()); // type, unused
+			buffer.extend((data.len() as i32).flatten());
+			buffer.extend(1i32.flatten());
+			buffer.extend((data.len() as i32).flatten());
+			buffer.extend((header_size as i16).flatten());
+			buffer.extend(name.as_bytes());
+			buffer.resize(buffer.len() + header_size - 18 - name.len(), 0);
+			buffer.extend(data);
+			buffer.resize((buffer.len() + 3) & !3, 0);
+		}
+		let mut buffer: Vec<u8> = KMESSAGE_HEADER_MAGIC.flatten();
+		buffer.resize(KMESSAGE_HEADER_SIZE, 0);
+		add_field(&mut buffer, "cwd device", &3i32.flatten());
+		add_field(&mut buffer, "cwd directory", &42i64.flatten());
+		let size = (buffer.len() as i32).flatten();
+		buffer[4..8].copy_from_slice(&size);
 
-	let port = Port::create("test_basic_port", 16).unwrap();
-	let port_data = b"testdata for port\n";
-	let port_code: i32 = 47483658;
-	port.write(port_code, port_data).unwrap();
-	assert_eq!(port.get_count().unwrap(), 1);
-	let (read_code, read_data) = port.read().unwrap();
-	assert_eq!(port_code, read_code);
-	assert_eq!(port_data.len(), read_data.len());
-	port.close().unwrap();
-	assert!(port.write(port_code, port_data).is_err());
+		let device = find_kmessage_field(&buffer, "cwd device").unwrap();
+		assert_eq!(i32::unflatten(device).unwrap(), 3);
+		let directory = find_kmessage_field(&buffer, "cwd directory").unwrap();
+		assert_eq!(i64::unflatten(directory).unwrap(), 42);
+		assert!(find_kmessage_field(&buffer, "cwd").is_none());
+		assert!(find_kmessage_field(&buffer[4..], "cwd device").is_none());
+	}
 }
 
-#[test]
-fn test_port_with_timeout() {
-	use crate::kernel::ports::Port;
+/// A thread is a unit of execution within a team
+pub mod threads {
+	use std::marker::PhantomData;
+	use std::ptr;
 	use std::time::Duration;
 
-	let port = Port::create("timeout_port", 1).unwrap();
-	assert!(port.try_read(Duration::new(5, 0)).is_err());
-}
+	use std::ffi::{CStr, CString};
+	use std::mem;
+	use std::panic::{catch_unwind, AssertUnwindSafe};
 
-#[test]
-fn test_find_port() {
-	use crate::kernel::ports::Port;
-	assert!(Port::find("x-vnd.haiku-debug_server").is_some());
-	assert!(Port::find("random port").is_none());
+	use libc::{
+		c_char, c_void, find_thread, get_thread_info, has_data, kill_thread, resume_thread,
+		send_data, spawn_thread, status_t, suspend_thread, thread_id, thread_info, wait_for_thread,
+		B_CAN_INTERRUPT, B_ERROR, B_INTERRUPTED, B_OK, B_OS_NAME_LENGTH, B_RELATIVE_TIMEOUT,
+	};
+
+	use crate::kernel::teams::Team;
+	use crate::kernel::INFINITE_TIMEOUT;
+	use crate::support::{ErrorKind, HaikuError, Result};
+
+	// private/system/tls.h
+	const TLS_USER_THREAD_SLOT: i32 = 4;
+
+	// private/system/user_thread_defs.h
+	#[repr(C)]
+	struct user_thread {
+		pthread: *mut c_void,
+		flags: i32,
+		wait_status: status_t,
+	}
+
+	fn get_user_thread() -> *mut user_thread {
+		extern "C" {
+			fn tls_get(index: i32) -> *mut c_void;
+		}
+		unsafe { tls_get(TLS_USER_THREAD_SLOT) as *mut user_thread }
+	}
+
+	/// A token that allows the current thread to block until it is woken up
+	///
+	/// These are the low-level primitives that Haiku uses to build its own
+	/// synchronization objects. Most users should use the primitives of the
+	/// standard library instead.
+	///
+	/// Blocking is a two-step process. First, the thread that wants to wait
+	/// creates a `BlockToken` with `prepare()`. Then it publishes its
+	/// `thread_id()` to the threads that may wake it up, usually while it
+	/// holds a lock that protects the shared state. Finally it calls
+	/// `block()`. Another thread wakes it up with `unblock()`.
+	///
+	/// The token must be prepared before the thread id is published. An
+	/// `unblock()` that happens between `prepare()` and `block()` is not lost:
+	/// `block()` will return immediately with the status that was passed to
+	/// `unblock()`. If the token is prepared after the id is published, a
+	/// wake up may be missed, and the thread would block until the timeout.
+	///
+	/// The token belongs to the thread that prepared it, so it cannot be sent
+	/// to other threads.
+	pub struct BlockToken {
+		thread: thread_id,
+		_not_send: PhantomData<*const ()>,
+	}
+
+	impl BlockToken {
+		/// Prepare the current thread to block
+		pub fn prepare() -> BlockToken {
+			unsafe { (*get_user_thread()).wait_status = 1 };
+			BlockToken {
+				thread: unsafe { find_thread(ptr::null()) },
+				_not_send: PhantomData,
+			}
+		}
+
+		/// Get the id of the thread that will block
+		///
+		/// Pass this id to `unblock()` to wake up the thread.
+		pub fn thread_id(&self) -> thread_id {
+			self.thread
+		}
+
+		/// Block the current thread until it is unblocked or the timeout expires
+		///
+		/// The `timeout` is relative to the current time. Use
+		/// `INFINITE_TIMEOUT` to wait without a time limit. If the thread is
+		/// unblocked with `B_OK`, this returns `Ok`, otherwise it returns the
+		/// error that was passed to `unblock()`. If the timeout expires, the
+		/// error has the kind `ErrorKind::TimedOut`, and when the wait is
+		/// interrupted by a signal the kind is `ErrorKind::Interrupted`. The
+		/// token is consumed in all cases; prepare a new one to wait again.
+		pub fn block(self, timeout: Duration) -> Result<()> {
+			extern "C" {
+				fn _kern_block_thread(flags: u32, timeout: i64) -> status_t;
+			}
+			let status = if timeout == INFINITE_TIMEOUT {
+				unsafe { _kern_block_thread(B_CAN_INTERRUPT, 0) }
+			} else {
+				unsafe {
+					_kern_block_thread(
+						B_CAN_INTERRUPT | B_RELATIVE_TIMEOUT,
+						timeout.as_micros() as i64,
+					)
+				}
+			};
+			if status != B_OK {
+				return Err(HaikuError::from_raw_os_error(status));
+			}
+			Ok(())
+		}
+	}
+
+	/// Wake up a thread that is blocked, or is about to block, on a BlockToken
+	///
+	/// The `status` is what the blocked thread receives: `B_OK`, or an error
+	/// code. Positive values are not allowed, as the kernel uses them to mark
+	/// a thread that is still waiting. Unblocking a thread that has not
+	/// prepared a token has no effect.
+	pub fn unblock(thread: thread_id, status: status_t) -> Result<()> {
+		if status > 0 {
+			return Err(HaikuError::new(
+				ErrorKind::InvalidInput,
+				"the status to unblock a thread with cannot be positive",
+			));
+		}
+		extern "C" {
+			fn _kern_unblock_thread(thread: thread_id, status: status_t) -> status_t;
+		}
+		let result = unsafe { _kern_unblock_thread(thread, status) };
+		if result != B_OK {
+			return Err(HaikuError::from_raw_os_error(result));
+		}
+		Ok(())
+	}
+
+	/// This struct is a representation of a thread
+	pub struct Thread {
+		id: thread_id,
+	}
+
+	/// Properties of a thread
+	pub struct ThreadInfo {
+		/// The team that the thread is part of
+		pub team: Team,
+		/// The name of the thread
+		pub name: String,
+		/// The priority of the thread
+		pub priority: i32,
+	}
+
+	// The function that a spawned thread runs
+	type ThreadEntry = Box<dyn FnOnce() -> status_t + Send + 'static>;
+
+	extern "C" fn thread_entry(data: *mut c_void) -> status_t {
+		let entry = unsafe { Box::from_raw(data as *mut ThreadEntry) };
+		// Panics cannot unwind into the kernel
+		catch_unwind(AssertUnwindSafe(entry)).unwrap_or(B_ERROR)
+	}
+
+	impl Thread {
+		/// Build a thread object from a raw thread id
+		pub fn from(id: thread_id) -> Option<Thread> {
+			if id < 0 {
+				None
+			} else {
+				Some(Thread { id })
+			}
+		}
+
+		// Build a thread object from a raw thread id without checking it.
+		// Operations on a thread that does not exist return an error.
+		pub(crate) fn from_raw(id: thread_id) -> Thread {
+			Thread { id }
+		}
+
+		/// Spawn a new native thread
+		///
+		/// The thread runs `entry`, and its return value is the exit status
+		/// of the thread. The `priority` is a value like `B_NORMAL_PRIORITY`.
+		/// Like on Haiku, the thread is created in a suspended state. Call
+		/// `resume()` to start it.
+		///
+		/// If the thread is killed before it runs `entry`, the closure is
+		/// leaked.
+		pub fn spawn<F>(name: &str, priority: i32, entry: F) -> Result<Thread>
+		where
+			F: FnOnce() -> status_t + Send + 'static,
+		{
+			if name.len() > B_OS_NAME_LENGTH {
+				return Err(HaikuError::new(
+					ErrorKind::InvalidInput,
+					"The name is too long",
+				));
+			}
+			let c_name = CString::new(name).unwrap();
+			let entry: ThreadEntry = Box::new(entry);
+			let data = Box::into_raw(Box::new(entry));
+			let id = unsafe {
+				spawn_thread(thread_entry, c_name.as_ptr(), priority, data as *mut c_void)
+			};
+			if id < 0 {
+				drop(unsafe { Box::from_raw(data) });
+				Err(HaikuError::from_raw_os_error(id))
+			} else {
+				Ok(Thread { id })
+			}
+		}
+
+		/// Get the thread that is currently running
+		pub fn current() -> Thread {
+			Thread {
+				id: unsafe { find_thread(ptr::null()) },
+			}
+		}
+
+		/// Get the raw thread identifier
+		pub fn get_thread_id(&self) -> thread_id {
+			self.id
+		}
+
+		/// Send a code and a buffer to the thread
+		///
+		/// The thread receives the data with `receive_data()`. A thread can
+		/// hold only one item of data, so if the thread has not yet received
+		/// the previous item, this method blocks until it does.
+		pub fn send_data(&self, code: i32, data: &[u8]) -> Result<()> {
+			status_to_result(unsafe {
+				send_data(self.id, code, data.as_ptr() as *const c_void, data.len())
+			})
+		}
+
+		/// Check whether the thread has data waiting to be received
+		pub fn has_data(&self) -> bool {
+			unsafe { has_data(self.id) }
+		}
+
+		/// Start a thread that was spawned, or continue a suspended thread
+		pub fn resume(&self) -> Result<()> {
+			status_to_result(unsafe { resume_thread(self.id) })
+		}
+
+		/// Suspend the thread until it is resumed
+		pub fn suspend(&self) -> Result<()> {
+			status_to_result(unsafe { suspend_thread(self.id) })
+		}
+
+		/// Kill the thread
+		pub fn kill(&self) -> Result<()> {
+			status_to_result(unsafe { kill_thread(self.id) })
+		}
+
+		/// Wait for the thread to finish, and get its exit status
+		///
+		/// A thread that was spawned and not yet resumed, is resumed first.
+		pub fn wait(&self) -> Result<status_t> {
+			let mut exit_status: status_t = B_OK;
+			status_to_result(unsafe { wait_for_thread(self.id, &mut exit_status) })?;
+			Ok(exit_status)
+		}
+
+		/// Get the information of the thread
+		pub fn get_info(&self) -> Result<ThreadInfo> {
+			let mut info = mem::MaybeUninit::<thread_info>::uninit();
+			status_to_result(unsafe { get_thread_info(self.id, info.as_mut_ptr()) })?;
+			let info = unsafe { info.assume_init() };
+			let c_name = unsafe { CStr::from_ptr((&info.name) as *const c_char) };
+			Ok(ThreadInfo {
+				team: Team::try_from(info.team)?,
+				name: c_name.to_string_lossy().into_owned(),
+				priority: info.priority,
+			})
+		}
+	}
+
+	/// Receive the data that is sent to the current thread
+	///
+	/// This function blocks until another thread sends data to the current
+	/// thread with `Thread::send_data()`. Use `Thread::current().has_data()`
+	/// to check if there is data without blocking. The code and the data are
+	/// returned.
+	///
+	/// Haiku does not tell the size of the data that was sent. The buffer is
+	/// `size` bytes long, data that does not fit is discarded, and if the
+	/// data is shorter, the remainder of the buffer is filled with zeros.
+	/// When the wait is interrupted, an error with `ErrorKind::Interrupted`
+	/// is returned, which means that `B_INTERRUPTED` cannot be used as a
+	/// code.
+	pub fn receive_data(size: usize) -> Result<(i32, Vec<u8>)> {
+		let mut buffer = vec![0u8; size];
+		let mut sender: thread_id = 0;
+		let code = unsafe {
+			libc::receive_data(
+				&mut sender,
+				buffer.as_mut_ptr() as *mut c_void,
+				buffer.len(),
+			)
+		};
+		if code == B_INTERRUPTED {
+			Err(HaikuError::from_raw_os_error(code))
+		} else {
+			Ok((code, buffer))
+		}
+	}
+
+	fn status_to_result(status: status_t) -> Result<()> {
+		if status == B_OK {
+			Ok(())
+		} else {
+			Err(HaikuError::from_raw_os_error(status))
+		}
+	}
+
+	#[test]
+	fn test_thread_spawn() {
+		use libc::B_NORMAL_PRIORITY;
+
+		let thread = Thread::spawn("spawn test", B_NORMAL_PRIORITY, || 42).unwrap();
+		let info = thread.get_info().unwrap();
+		assert_eq!(info.name, "spawn test");
+		assert_eq!(info.priority, B_NORMAL_PRIORITY);
+		thread.resume().unwrap();
+		assert_eq!(thread.wait().unwrap(), 42);
+	}
+
+	#[test]
+	fn test_thread_data() {
+		use std::sync::mpsc::channel;
+		use std::thread;
+
+		let (sender, receiver) = channel();
+		let receiving = thread::spawn(move || {
+			sender.send(unsafe { find_thread(ptr::null()) }).unwrap();
+			receive_data(8)
+		});
+		let thread = Thread::from(receiver.recv().unwrap()).unwrap();
+		thread.send_data(1234, b"hello").unwrap();
+		let (code, data) = receiving.join().unwrap().unwrap();
+		assert_eq!(code, 1234);
+		assert_eq!(data, b"hello\0\0\0");
+		assert!(!Thread::current().has_data());
+	}
+
+	#[test]
+	fn test_block_token() {
+		use libc::B_NOT_ALLOWED;
+		use std::sync::mpsc::channel;
+		use std::thread;
+
+		// The token is prepared before the id is sent, so the wake up cannot
+		// get lost if it happens before the thread blocks.
+		let (sender, receiver) = channel();
+		let blocked = thread::spawn(move || {
+			let token = BlockToken::prepare();
+			sender.send(token.thread_id()).unwrap();
+			token.block(INFINITE_TIMEOUT)
+		});
+		let thread = receiver.recv().unwrap();
+		unblock(thread, B_OK).unwrap();
+		assert!(blocked.join().unwrap().is_ok());
+
+		// An error passed to unblock is returned by block
+		let (sender, receiver) = channel();
+		let blocked = thread::spawn(move || {
+			let token = BlockToken::prepare();
+			sender.send(token.thread_id()).unwrap();
+			token.block(INFINITE_TIMEOUT)
+		});
+		let thread = receiver.recv().unwrap();
+		unblock(thread, B_NOT_ALLOWED).unwrap();
+		let error = blocked.join().unwrap().err().unwrap();
+		assert!(matches!(error.kind(), ErrorKind::NotAllowed));
+		assert!(unblock(thread, 1).is_err());
+
+		// Without an unblock, the timeout expires
+		let token = BlockToken::prepare();
+		let error = token.block(Duration::from_millis(10)).err().unwrap();
+		assert!(matches!(error.kind(), ErrorKind::TimedOut));
+	}
+}
+
+/// An area is a piece of memory that can be shared between teams
+///
+/// Areas are used to pass large amounts of data between teams, without
+/// copying it through a port.
+pub mod areas {
+	use std::ffi::{CStr, CString};
+	use std::{mem, ptr, slice};
+
+	use libc::{
+		area_id, area_info, c_char, c_void, clone_area, create_area, delete_area, find_area,
+		get_area_info, resize_area, team_id, B_ANY_ADDRESS, B_NO_LOCK, B_OS_NAME_LENGTH,
+		B_PAGE_SIZE, B_READ_AREA, B_WRITE_AREA,
+	};
+
+	use crate::kernel::teams::Team;
+	use crate::support::{ErrorKind, HaikuError, Result};
+
+	/// The area object represents an area that is mapped into this team
+	///
+	/// An area is created with `Area::create()`, or an existing area of
+	/// another (or this) team is mapped with `Area::clone_area()`. Either
+	/// way, the area is deleted from this team when the object is dropped.
+	/// The memory of a cloned area stays available to the other teams that
+	/// use it.
+	///
+	/// The memory of the area is available as a slice of bytes, as far as
+	/// the protection of the area in this team allows. Note that other teams
+	/// that map the same area may change the data at any time.
+	pub struct Area {
+		area: area_id,
+		address: *mut u8,
+		size: usize,
+		protection: u32,
+	}
+
+	/// Properties of the area
+	pub struct AreaInfo {
+		/// Representation of the team that the area is part of
+		pub team: Team,
+		/// The name of the area
+		pub name: String,
+		/// The size of the area in bytes
+		pub size: usize,
+		/// The protection flags, like `B_READ_AREA` and `B_WRITE_AREA`
+		pub protection: u32,
+	}
+
+	impl Area {
+		/// Create a new area
+		///
+		/// The `name` parameter should be no more than 32 characters. The
+		/// `size` is rounded up to a multiple of the page size. The
+		/// `protection` is a combination of the flags `B_READ_AREA` and
+		/// `B_WRITE_AREA`, and optionally `B_CLONEABLE_AREA` if other teams
+		/// are allowed to clone the area. The area is placed at any address.
+		pub fn create(name: &str, size: usize, protection: u32) -> Result<Area> {
+			if name.len() > B_OS_NAME_LENGTH {
+				return Err(HaikuError::new(
+					ErrorKind::InvalidInput,
+					"The name is too long",
+				));
+			}
+			if size == 0 {
+				return Err(HaikuError::new(
+					ErrorKind::InvalidInput,
+					"the size of an area cannot be zero",
+				));
+			}
+			let size = round_to_page_size(size);
+			let c_name = CString::new(name).unwrap();
+			let mut address: *mut c_void = ptr::null_mut();
+			let area = unsafe {
+				create_area(
+					c_name.as_ptr(),
+					&mut address,
+					B_ANY_ADDRESS,
+					size,
+					B_NO_LOCK,
+					protection,
+				)
+			};
+			if area < 0 {
+				Err(HaikuError::from_raw_os_error(area))
+			} else {
+				Ok(Area {
+					area,
+					address: address as *mut u8,
+					size,
+					protection,
+				})
+			}
+		}
+
+		/// Map an existing area into this team
+		///
+		/// The clone is called `name`, and uses the same memory as the
+		/// `source` area. The `protection` flags of the clone can differ from
+		/// the original.
+		pub fn clone_area(name: &str, source: area_id, protection: u32) -> Result<Area> {
+			if name.len() > B_OS_NAME_LENGTH {
+				return Err(HaikuError::new(
+					ErrorKind::InvalidInput,
+					"The name is too long",
+				));
+			}
+			let c_name = CString::new(name).unwrap();
+			let mut address: *mut c_void = ptr::null_mut();
+			let area = unsafe {
+				clone_area(
+					c_name.as_ptr(),
+					&mut address,
+					B_ANY_ADDRESS,
+					protection,
+					source,
+				)
+			};
+			if area < 0 {
+				return Err(HaikuError::from_raw_os_error(area));
+			}
+			let info = match raw_area_info(area) {
+				Ok(info) => info,
+				Err(e) => {
+					unsafe { delete_area(area) };
+					return Err(e);
+				}
+			};
+			Ok(Area {
+				area,
+				address: address as *mut u8,
+				size: info.size,
+				protection: info.protection,
+			})
+		}
+
+		/// Take ownership of an area of this team
+		///
+		/// This is used for areas that are created by other code, or that
+		/// are transferred to this team by another team with
+		/// `Area::transfer()`. The area is deleted when the object is
+		/// dropped. An error is returned when the area does not exist, or
+		/// when it belongs to another team.
+		///
+		/// # Safety
+		///
+		/// The area is unmapped when the object is dropped. The caller must
+		/// make sure that nothing else in this team uses the memory of the
+		/// area. Taking ownership of areas like the heap or the stack of a
+		/// thread leads to undefined behavior.
+		pub unsafe fn from_id(area: area_id) -> Result<Area> {
+			let info = raw_area_info(area)?;
+			if info.team != Team::current().get_team_id() {
+				return Err(HaikuError::new(
+					ErrorKind::NotAllowed,
+					"the area belongs to another team",
+				));
+			}
+			Ok(Area {
+				area,
+				address: info.address as *mut u8,
+				size: info.size,
+				protection: info.protection,
+			})
+		}
+
+		// Take ownership of an area that another team transferred to this
+		// team to pass data along with a message. The area id comes from
+		// the message, so it is only accepted when the area has the `name`
+		// that the sender gives these areas. This prevents a forged id from
+		// taking over another area of this team, like the heap.
+		pub(crate) fn adopt_transferred(area: area_id, name: &str) -> Result<Area> {
+			let info = raw_area_info(area)?;
+			let area_name = unsafe { CStr::from_ptr((&info.name) as *const c_char) };
+			if area_name.to_bytes() != name.as_bytes() {
+				return Err(HaikuError::new(
+					ErrorKind::NotAllowed,
+					"the area was not transferred with a message",
+				));
+			}
+			// Safety: the area is a transfer area, which is not used by
+			// anything else in this team
+			unsafe { Area::from_id(area) }
+		}
+
+		/// Give the area to another team
+		///
+		/// The area is removed from this team, and mapped into the `target`
+		/// team, which becomes responsible for deleting it. The id of the
+		/// area in the target team is returned. If the transfer fails, the
+		/// area is deleted.
+		pub fn transfer(self, target: &Team) -> Result<area_id> {
+			extern "C" {
+				fn _kern_transfer_area(
+					area: area_id,
+					address: *mut *mut c_void,
+					address_spec: u32,
+					target: team_id,
+				) -> area_id;
+			}
+
+			let mut address: *mut c_void = ptr::null_mut();
+			let area = unsafe {
+				_kern_transfer_area(self.area, &mut address, B_ANY_ADDRESS, target.get_team_id())
+			};
+			if area < 0 {
+				return Err(HaikuError::from_raw_os_error(area));
+			}
+			// The area is no longer ours to delete
+			mem::forget(self);
+			Ok(area)
+		}
+
+		/// Find the id of an area by name
+		pub fn find(name: &str) -> Option<area_id> {
+			if name.len() > B_OS_NAME_LENGTH {
+				return None;
+			}
+			let c_name = CString::new(name).unwrap();
+			let area = unsafe { find_area(c_name.as_ptr()) };
+			if area < 0 {
+				None
+			} else {
+				Some(area)
+			}
+		}
+
+		/// Get the area id
+		pub fn get_area_id(&self) -> area_id {
+			self.area
+		}
+
+		/// Get the size of the area in bytes
+		pub fn size(&self) -> usize {
+			self.size
+		}
+
+		/// Get the memory of the area
+		///
+		/// This returns an error with `ErrorKind::NotAllowed` when the area
+		/// is not mapped with `B_READ_AREA` in this team.
+		pub fn as_slice(&self) -> Result<&[u8]> {
+			if self.protection & B_READ_AREA == 0 {
+				return Err(HaikuError::new(
+					ErrorKind::NotAllowed,
+					"the area cannot be read",
+				));
+			}
+			Ok(unsafe { slice::from_raw_parts(self.address, self.size) })
+		}
+
+		/// Get the memory of the area for writing
+		///
+		/// This returns an error with `ErrorKind::NotAllowed` when the area
+		/// is not mapped with `B_WRITE_AREA` in this team. Note that areas
+		/// are usually mapped with `B_READ_AREA` as well, to read back what
+		/// is written.
+		pub fn as_mut_slice(&mut self) -> Result<&mut [u8]> {
+			if self.protection & B_WRITE_AREA == 0 {
+				return Err(HaikuError::new(
+					ErrorKind::NotAllowed,
+					"the area cannot be written",
+				));
+			}
+			Ok(unsafe { slice::from_raw_parts_mut(self.address, self.size) })
+		}
+
+		/// Change the size of the area
+		///
+		/// The `size` is rounded up to a multiple of the page size. The area
+		/// stays at the same address, so growing fails when the memory after
+		/// the area is in use.
+		pub fn resize(&mut self, size: usize) -> Result<()> {
+			if size == 0 {
+				return Err(HaikuError::new(
+					ErrorKind::InvalidInput,
+					"the size of an area cannot be zero",
+				));
+			}
+			let size = round_to_page_size(size);
+			let status = unsafe { resize_area(self.area, size) };
+			if status == 0 {
+				self.size = size;
+				Ok(())
+			} else {
+				Err(HaikuError::from_raw_os_error(status))
+			}
+		}
+
+		/// Get the information of the area
+		pub fn get_info(&self) -> Result<AreaInfo> {
+			let info = raw_area_info(self.area)?;
+			let c_name = unsafe { CStr::from_ptr((&info.name) as *const c_char) };
+			Ok(AreaInfo {
+				team: Team::try_from(info.team)?,
+				name: c_name.to_string_lossy().into_owned(),
+				size: info.size,
+				protection: info.protection,
+			})
+		}
+	}
+
+	fn raw_area_info(area: area_id) -> Result<area_info> {
+		let mut info: area_info = unsafe { mem::zeroed() };
+		let status = unsafe { get_area_info(area, &mut info) };
+		if status == 0 {
+			Ok(info)
+		} else {
+			Err(HaikuError::from_raw_os_error(status))
+		}
+	}
+
+	fn round_to_page_size(size: usize) -> usize {
+		size.div_ceil(B_PAGE_SIZE) * B_PAGE_SIZE
+	}
+
+	impl Drop for Area {
+		fn drop(&mut self) {
+			unsafe {
+				delete_area(self.area);
+			};
+		}
+	}
+}
+
+/// A semaphore is a counter that threads can acquire and release, to
+/// synchronize the access to shared resources between threads and teams
+pub mod sems {
+	use std::ffi::{CStr, CString};
+	use std::mem;
+	use std::time::Duration;
+
+	use libc::{
+		acquire_sem, acquire_sem_etc, c_char, create_sem, delete_sem, get_sem_info,
+		release_sem_etc, sem_id, sem_info, B_OS_NAME_LENGTH, B_RELATIVE_TIMEOUT,
+	};
+
+	use crate::kernel::teams::Team;
+	use crate::kernel::INFINITE_TIMEOUT;
+	use crate::support::{ErrorKind, HaikuError, Result};
+
+	/// The semaphore object represents a Haiku semaphore
+	///
+	/// Like ports, there are owned and borrowed semaphores. An owned
+	/// semaphore is created with `Semaphore::create()`, and is deleted when
+	/// the object is dropped. A borrowed semaphore is retrieved with
+	/// `Semaphore::from_id()`, and outlives the `Semaphore` object.
+	pub struct Semaphore {
+		sem: sem_id,
+		owned: bool,
+	}
+
+	/// Properties of the semaphore
+	pub struct SemaphoreInfo {
+		/// Representation of the team that the semaphore is part of
+		pub team: Team,
+		/// The name of the semaphore
+		pub name: String,
+		/// The count of the semaphore at the time of getting the info
+		pub count: i32,
+	}
+
+	impl Semaphore {
+		/// Create a new semaphore and take ownership of it
+		///
+		/// The `name` parameter should be no more than 32 characters. The
+		/// `count` is the number of times the semaphore can be acquired
+		/// before a thread has to wait, and should be zero or higher.
+		pub fn create(name: &str, count: i32) -> Result<Semaphore> {
+			if name.len() > B_OS_NAME_LENGTH {
+				return Err(HaikuError::new(
+					ErrorKind::InvalidInput,
+					"The name is too long",
+				));
+			}
+			let c_name = CString::new(name).unwrap();
+			let sem = unsafe { create_sem(count, c_name.as_ptr()) };
+			if sem < 0 {
+				Err(HaikuError::from_raw_os_error(sem))
+			} else {
+				Ok(Semaphore { sem, owned: true })
+			}
+		}
+
+		/// Construct a borrowed semaphore from id
+		///
+		/// If the semaphore exists, this function will return a borrowed
+		/// `Semaphore` object. This means that the semaphore will not be
+		/// deleted when the object goes out of scope.
+		pub fn from_id(id: sem_id) -> Option<Semaphore> {
+			if id < 0 {
+				return None;
+			}
+			let mut info: sem_info = unsafe { mem::zeroed() };
+			let status = unsafe { get_sem_info(id, &mut info) };
+			if status == 0 {
+				Some(Semaphore {
+					sem: id,
+					owned: false,
+				})
+			} else {
+				None
+			}
+		}
+
+		/// Acquire the semaphore
+		///
+		/// This decreases the count of the semaphore by one. If the count
+		/// drops below zero, this method waits until the semaphore is
+		/// released by another thread.
+		pub fn acquire(&self) -> Result<()> {
+			let status = unsafe { acquire_sem(self.sem) };
+			if status == 0 {
+				Ok(())
+			} else {
+				Err(HaikuError::from_raw_os_error(status))
+			}
+		}
+
+		/// Attempt to acquire the semaphore `count` times
+		///
+		/// This method waits until the semaphore can be acquired, or until
+		/// the timeout is reached. Use `INFINITE_TIMEOUT` to wait without a
+		/// time limit. If you don't want to wait, you can set the timeout to
+		/// 0, and an error with `B_WOULD_BLOCK` is returned when the
+		/// semaphore cannot be acquired right away.
+		pub fn acquire_etc(&self, count: i32, timeout: Duration) -> Result<()> {
+			let status = if timeout == INFINITE_TIMEOUT {
+				unsafe { acquire_sem_etc(self.sem, count, 0, 0) }
+			} else {
+				let timeout_ms =
+					timeout.as_secs() as i64 * 1_000_000 + timeout.subsec_micros() as i64;
+				unsafe { acquire_sem_etc(self.sem, count, B_RELATIVE_TIMEOUT, timeout_ms) }
+			};
+			if status == 0 {
+				Ok(())
+			} else {
+				Err(HaikuError::from_raw_os_error(status))
+			}
+		}
+
+		/// Release the semaphore `count` times
+		///
+		/// This increases the count of the semaphore, and wakes up the
+		/// threads that are waiting for it.
+		pub fn release(&self, count: i32) -> Result<()> {
+			let status = unsafe { release_sem_etc(self.sem, count, 0) };
+			if status == 0 {
+				Ok(())
+			} else {
+				Err(HaikuError::from_raw_os_error(status))
+			}
+		}
+
+		/// Get the semaphore id
+		pub fn get_sem_id(&self) -> sem_id {
+			self.sem
+		}
+
+		/// Get the information of the semaphore
+		pub fn get_info(&self) -> Result<SemaphoreInfo> {
+			let mut info: sem_info = unsafe { mem::zeroed() };
+			let status = unsafe { get_sem_info(self.sem, &mut info) };
+			if status != 0 {
+				return Err(HaikuError::from_raw_os_error(status));
+			}
+			let c_name = unsafe { CStr::from_ptr((&info.name) as *const c_char) };
+			Ok(SemaphoreInfo {
+				team: Team::try_from(info.team)?,
+				name: c_name.to_string_lossy().into_owned(),
+				count: info.count,
+			})
+		}
+	}
+
+	impl Drop for Semaphore {
+		fn drop(&mut self) {
+			if self.owned {
+				unsafe {
+					delete_sem(self.sem);
+				};
+			}
+		}
+	}
+}
+
+use std::time::Duration;
+/// An infinite timeout
+pub const INFINITE_TIMEOUT: Duration = Duration::from_micros(i64::max_value() as u64);
+
+// Helpers for this crate only
+pub(crate) mod helpers {
+	use std::ffi::CStr;
+	use std::str;
+
+	use std::time::Duration;
+
+	use libc::{
+		c_char, dev_t, ino_t, size_t, status_t, B_PATH_NAME_LENGTH, B_TIMED_OUT, B_WOULD_BLOCK,
+	};
+
+	use crate::kernel::system_time;
+	use crate::support::{ErrorKind, HaikuError, Result};
+
+	/// Retry an operation for as long as it is interrupted
+	///
+	/// Blocking kernel calls return `B_INTERRUPTED` when a signal arrives
+	/// while they are waiting. In most cases the operation can simply be
+	/// retried.
+	pub(crate) fn retry_on_interrupt<T, F>(mut operation: F) -> Result<T>
+	where
+		F: FnMut() -> Result<T>,
+	{
+		loop {
+			match operation() {
+				Err(ref e) if matches!(e.kind(), ErrorKind::Interrupted) => continue,
+				result => return result,
+			}
+		}
+	}
+
+	/// Retry an operation with a timeout for as long as it is interrupted
+	///
+	/// The operation is called with the time that is left until the
+	/// `deadline`. When an interrupted attempt used up the remaining time,
+	/// the next attempt gets a timeout of zero and reports `B_WOULD_BLOCK`.
+	/// That is returned as `B_TIMED_OUT`, like an attempt that ran out of
+	/// time while it was waiting.
+	pub(crate) fn retry_on_interrupt_until<T, F>(deadline: Duration, mut operation: F) -> Result<T>
+	where
+		F: FnMut(Duration) -> Result<T>,
+	{
+		let mut interrupted = false;
+		loop {
+			let timeout = deadline.saturating_sub(system_time());
+			match operation(timeout) {
+				Err(ref e) if matches!(e.kind(), ErrorKind::Interrupted) => interrupted = true,
+				Err(ref e)
+					if interrupted
+						&& timeout.is_zero()
+						&& e.raw_os_error() == Some(B_WOULD_BLOCK) =>
+				{
+					return Err(HaikuError::from_raw_os_error(B_TIMED_OUT))
+				}
+				result => return result,
+			}
+		}
+	}
+
+	pub(crate) fn get_path_for_entry_ref(
+		device: dev_t,
+		dir: ino_t,
+		leaf: *const c_char,
+	) -> Result<String> {
+		extern "C" {
+			pub fn _kern_entry_ref_to_path(
+				device: dev_t,
+				inode: ino_t,
+				leaf: *const c_char,
+				buf: *mut c_char,
+				bufferSize: size_t,
+			) -> status_t;
+		}
+
+		let mut buf = [0 as c_char; B_PATH_NAME_LENGTH];
+		let p = buf.as_mut_ptr();
+		let path = unsafe {
+			let result = _kern_entry_ref_to_path(device, dir, leaf, p, buf.len());
+			if result != 0 {
+				return Err(HaikuError::from_raw_os_error(result));
+			}
+
+			let p = p as *const _;
+			str::from_utf8(CStr::from_ptr(p).to_bytes())
+				.unwrap()
+				.to_owned()
+		};
+		Ok(path)
+	}
+}
+
+/// Get the time that has passed since the system was booted
+///
+/// The system time is monotonic, which makes it suitable for measuring the
+/// time between two events.
+pub fn system_time() -> Duration {
+	let time = unsafe { libc::system_time() };
+	Duration::from_micros(time as u64)
+}
+
+/// Pause execution of the application and open the Debugger
+///
+/// You can show the `message` to the user when the debugger opens.
+pub fn debugger(message: &str) {
+	use libc::c_char;
+	use std::ffi::CString;
+	extern "C" {
+		fn debugger(message: *const c_char);
+	}
+	let msg = CString::new(message).unwrap();
+	unsafe { debugger(msg.as_ptr()) };
+}
+
+#[test]
+fn test_basic_port() {
+	use crate::kernel::ports::Port;
+
+	let port = Port::create("test_basic_port", 16).unwrap();
+	let port_data = b"testdata for port\n";
+	let port_code: i32 = 47483658;
+	port.write(port_code, port_data).unwrap();
+	assert_eq!(port.get_count().unwrap(), 1);
+	let (read_code, read_data) = port.read().unwrap();
+	assert_eq!(port_code, read_code);
+	assert_eq!(port_data.len(), read_data.len());
+	port.close().unwrap();
+	assert!(port.write(port_code, port_data).is_err());
+}
+
+#[test]
+fn test_port_with_timeout() {
+	use crate::kernel::ports::Port;
+	use std::time::Duration;
+
+	let port = Port::create("timeout_port", 1).unwrap();
+	assert!(port.try_read(Duration::new(5, 0)).is_err());
+}
+
+#[test]
+fn test_port_read_all_messages() {
+	use crate::app::Message;
+	use crate::haiku_constant;
+	use crate::kernel::ports::Port;
+	use crate::support::{ErrorKind, Flattenable};
+	use libc::B_MESSAGE_TYPE;
+
+	let port = Port::create("read_all_messages", 16).unwrap();
+	assert!(port.read_all_messages().unwrap().is_empty());
+	for index in 0..3 {
+		let mut message = Message::new(haiku_constant!('t', 'e', 's', 't'));
+		message.add_data("index", &index).unwrap();
+		port.write(B_MESSAGE_TYPE as i32, &message.flatten())
+			.unwrap();
+	}
+	port.write(47483658, b"not a message").unwrap();
+
+	let messages = port.read_all_messages().unwrap();
+	assert_eq!(messages.len(), 4);
+	for (index, message) in messages.iter().take(3).enumerate() {
+		let message = message.as_ref().unwrap();
+		assert_eq!(message.what(), haiku_constant!('t', 'e', 's', 't'));
+		assert_eq!(message.find_data::<i32>("index", 0).unwrap(), index as i32);
+	}
+	assert!(messages[3].is_err());
+	assert_eq!(port.get_count().unwrap(), 0);
+
+	// The queue of a port that is not owned is only read when asked for
+	let message = Message::new(haiku_constant!('t', 'e', 's', 't'));
+	port.write(B_MESSAGE_TYPE as i32, &message.flatten())
+		.unwrap();
+	let borrowed = Port::from_id(port.get_port_id()).unwrap();
+	let error = borrowed.read_all_messages().unwrap_err();
+	assert!(matches!(error.kind(), ErrorKind::NotAllowed));
+	let messages = unsafe { borrowed.read_all_messages_unchecked() };
+	assert_eq!(messages.len(), 1);
+	assert_eq!(port.get_count().unwrap(), 0);
+}
+
+#[test]
+fn test_checked_read_size() {
+	use libc::{ssize_t, B_BAD_PORT_ID};
+
+	use crate::kernel::ports::{checked_read_size, DEFAULT_MAX_READ_SIZE};
+	use crate::support::ErrorKind;
+
+	assert_eq!(checked_read_size(0, 16).unwrap(), 0);
+	assert_eq!(checked_read_size(16, 16).unwrap(), 16);
+	let error = checked_read_size(17, 16).unwrap_err();
+	assert!(matches!(error.kind(), ErrorKind::InvalidData));
+	// A huge size that is reported for a port is not allocated
+	let error = checked_read_size(ssize_t::MAX, DEFAULT_MAX_READ_SIZE).unwrap_err();
+	assert!(matches!(error.kind(), ErrorKind::InvalidData));
+	let error = checked_read_size(B_BAD_PORT_ID as ssize_t, 16).unwrap_err();
+	assert_eq!(error.raw_os_error(), Some(B_BAD_PORT_ID));
+}
+
+#[test]
+fn test_port_max_read_size() {
+	use std::time::Duration;
+
+	use crate::kernel::ports::{Port, DEFAULT_MAX_READ_SIZE};
+	use crate::support::ErrorKind;
+
+	let mut port = Port::create("port_max_read_size_test", 16).unwrap();
+	assert_eq!(port.max_read_size(), DEFAULT_MAX_READ_SIZE);
+	port.set_max_read_size(8);
+	port.write(47483658, &[0u8; 16]).unwrap();
+	port.write(47483658, b"small").unwrap();
+	port.write(47483658, &[0u8; 9]).unwrap();
+	port.write(47483658, b"tiny").unwrap();
+
+	// The messages that are too big are rejected and discarded
+	let error = port.read().unwrap_err();
+	assert!(matches!(error.kind(), ErrorKind::InvalidData));
+	assert_eq!(port.read().unwrap().1, b"small");
+	let error = port.try_read(Duration::ZERO).unwrap_err();
+	assert!(matches!(error.kind(), ErrorKind::InvalidData));
+	assert_eq!(port.try_read(Duration::ZERO).unwrap().1, b"tiny");
+	assert_eq!(port.get_count().unwrap(), 0);
+}
+
+#[test]
+fn test_port_read_unchecked() {
+	use std::time::Duration;
+
+	use crate::kernel::ports::Port;
+
+	let port = Port::create("port_read_unchecked_test", 16).unwrap();
+	port.write(47483658, b"first").unwrap();
+	port.write(47483658, b"second").unwrap();
+
+	// A port that is found by id is not owned, but can be read explicitly
+	let other = Port::from_id(port.get_port_id()).unwrap();
+	let (type_code, data) = unsafe { other.read_unchecked() }.unwrap();
+	assert_eq!(type_code, 47483658);
+	assert_eq!(data, b"first");
+	let (_, data) = unsafe { other.read_unchecked_etc(Duration::ZERO) }.unwrap();
+	assert_eq!(data, b"second");
+	assert!(unsafe { other.read_unchecked_etc(Duration::ZERO) }.is_err());
+	assert_eq!(port.get_count().unwrap(), 0);
+}
+
+#[test]
+fn test_port_deadline() {
+	use std::time::{Duration, Instant};
+
+	use libc::B_TIMED_OUT;
+
+	use crate::kernel::ports::Port;
+
+	let port = Port::create("port_deadline_test", 1).unwrap();
+	let deadline = Instant::now() + Duration::from_secs(1);
+	port.try_write_until(47483658, b"data", deadline).unwrap();
+	assert_eq!(port.try_read_until(deadline).unwrap().1, b"data");
+
+	// A deadline that has passed times out immediately
+	let start = Instant::now();
+	let past = start - Duration::from_millis(100);
+	let error = port.try_read_until(past).unwrap_err();
+	assert_eq!(error.raw_os_error(), Some(B_TIMED_OUT));
+	port.write(47483658, b"full").unwrap();
+	let error = port.try_write_until(47483658, b"data", past).unwrap_err();
+	assert_eq!(error.raw_os_error(), Some(B_TIMED_OUT));
+	assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn test_port_read_interruptible() {
+	use crate::kernel::ports::Port;
+
+	let port = Port::create("port_read_interruptible_test", 16).unwrap();
+	port.write(47483658, b"first").unwrap();
+	port.write(47483658, b"second").unwrap();
+	assert_eq!(port.read_interruptible().unwrap().1, b"first");
+	assert_eq!(port.read().unwrap().1, b"second");
+}
+
+#[test]
+fn test_area() {
+	use libc::{B_CLONEABLE_AREA, B_PAGE_SIZE, B_READ_AREA, B_WRITE_AREA};
+
+	use crate::kernel::areas::Area;
+	use crate::support::ErrorKind;
+
+	let mut area = Area::create(
+		"area_test",
+		100,
+		B_READ_AREA | B_WRITE_AREA | B_CLONEABLE_AREA,
+	)
+	.unwrap();
+	assert_eq!(area.size(), B_PAGE_SIZE);
+	for (i, byte) in area.as_mut_slice().unwrap().iter_mut().enumerate() {
+		*byte = (i % 251) as u8;
+	}
+
+	// A clone shares the memory of the original area
+	let source = Area::find("area_test").unwrap();
+	assert_eq!(source, area.get_area_id());
+	let mut clone = Area::clone_area("area_test_clone", source, B_READ_AREA).unwrap();
+	assert_eq!(clone.size(), area.size());
+	assert!(clone
+		.as_slice()
+		.unwrap()
+		.iter()
+		.enumerate()
+		.all(|(i, byte)| *byte == (i % 251) as u8));
+	area.as_mut_slice().unwrap()[0] = 255;
+	assert_eq!(clone.as_slice().unwrap()[0], 255);
+	assert_eq!(clone.get_info().unwrap().name, "area_test_clone");
+
+	// The read only clone cannot be written
+	let error = clone.as_mut_slice().unwrap_err();
+	assert!(matches!(error.kind(), ErrorKind::NotAllowed));
+
+	// The area is deleted when it is dropped
+	drop(clone);
+	drop(area);
+	assert!(Area::find("area_test").is_none());
+}
+
+#[test]
+fn test_semaphore() {
+	use std::time::Duration;
+
+	use libc::B_WOULD_BLOCK;
+
+	use crate::kernel::sems::Semaphore;
+
+	let sem = Semaphore::create("semaphore_test", 1).unwrap();
+	sem.acquire().unwrap();
+	let error = sem.acquire_etc(1, Duration::ZERO).unwrap_err();
+	assert_eq!(error.raw_os_error(), Some(B_WOULD_BLOCK));
+	sem.release(1).unwrap();
+	sem.acquire_etc(1, Duration::ZERO).unwrap();
+
+	// A borrowed semaphore shares the count, and does not delete it
+	let borrowed = Semaphore::from_id(sem.get_sem_id()).unwrap();
+	borrowed.release(2).unwrap();
+	assert_eq!(sem.get_info().unwrap().count, 2);
+	drop(borrowed);
+	assert_eq!(sem.get_info().unwrap().name, "semaphore_test");
+
+	// The owned semaphore is deleted when it is dropped
+	let id = sem.get_sem_id();
+	drop(sem);
+	assert!(Semaphore::from_id(id).is_none());
+}
+
+#[test]
+fn test_port_get_count() {
+	use crate::kernel::ports::Port;
+
+	let port = Port::create("port_get_count_test", 16).unwrap();
+	assert_eq!(port.get_count().unwrap(), 0);
+	for _ in 0..3 {
+		port.write(47483658, b"item").unwrap();
+	}
+	assert_eq!(port.get_count().unwrap(), 3);
+	port.read().unwrap();
+	assert_eq!(port.get_count().unwrap(), 2);
+}
+
+#[test]
+fn test_port_name_and_count() {
+	use crate::kernel::ports::Port;
+
+	let port = Port::create("port_name_test", 16).unwrap();
+	port.write(47483658, b"first").unwrap();
+	port.write(47483658, b"second").unwrap();
+
+	let mut info = port.get_info().unwrap();
+	assert_eq!(port.name().unwrap(), info.name);
+	assert_eq!(port.name().unwrap(), "port_name_test");
+	assert_eq!(port.get_count().unwrap(), info.queue_count as usize);
+	assert_eq!(port.get_count().unwrap(), 2);
+
+	port.read().unwrap();
+	port.get_info_into(&mut info).unwrap();
+	let fresh_info = port.get_info().unwrap();
+	assert_eq!(info.name, fresh_info.name);
+	assert_eq!(info.team.get_team_id(), fresh_info.team.get_team_id());
+	assert_eq!(info.capacity, fresh_info.capacity);
+	assert_eq!(info.queue_count, 1);
+	assert_eq!(info.total_count, fresh_info.total_count);
+
+	// A name that is not valid UTF-8 is converted
+	let id = unsafe { libc::create_port(1, b"port_\xff_test\0".as_ptr() as *const libc::c_char) };
+	assert!(id >= 0);
+	let port = Port::from_id(id).unwrap();
+	assert_eq!(port.name().unwrap(), "port_\u{fffd}_test");
+	port.get_info_into(&mut info).unwrap();
+	assert_eq!(info.name, "port_\u{fffd}_test");
+	unsafe { libc::delete_port(id) };
+}
+
+#[test]
+fn test_port_iter() {
+	use crate::kernel::ports::Port;
+
+	assert!(Port::iter().any(|info| info.name == "x-vnd.haiku-debug_server"));
+}
+
+#[test]
+fn test_port_iter_team() {
+	use crate::kernel::ports::{Port, PortInfo};
+
+	let port = Port::create("port_iter_team_test", 4).unwrap();
+	let team = port.get_info().unwrap().team;
+	let infos: Vec<PortInfo> = Port::iter_team(&team).collect();
+	let info = infos
+		.iter()
+		.find(|info| info.name == "port_iter_team_test")
+		.unwrap();
+	assert_eq!(info.capacity, 4);
+	assert!(infos
+		.iter()
+		.all(|info| info.team.get_team_id() == team.get_team_id()));
+}
+
+#[test]
+fn test_find_port() {
+	use crate::kernel::ports::Port;
+	assert!(Port::find("x-vnd.haiku-debug_server").is_some());
+	assert!(Port::find("random port").is_none());
+}
+
+#[test]
+fn test_retry_on_interrupt() {
+	use crate::kernel::helpers::retry_on_interrupt;
+	use crate::support::{ErrorKind, HaikuError};
+	use libc::{B_INTERRUPTED, B_TIMED_OUT};
+
+	// Simulate an operation that is interrupted twice before it succeeds
+	let mut attempts = 0;
+	let result = retry_on_interrupt(|| {
+		attempts += 1;
+		if attempts < 3 {
+			Err(HaikuError::from_raw_os_error(B_INTERRUPTED))
+		} else {
+			Ok(attempts)
+		}
+	});
+	assert_eq!(result.unwrap(), 3);
+
+	// Other errors are returned immediately
+	let mut attempts = 0;
+	let result: crate::support::Result<()> = retry_on_interrupt(|| {
+		attempts += 1;
+		Err(HaikuError::from_raw_os_error(B_TIMED_OUT))
+	});
+	assert!(matches!(result.err().unwrap().kind(), ErrorKind::TimedOut));
+	assert_eq!(attempts, 1);
+}
+
+#[test]
+fn test_retry_on_interrupt_until() {
+	use crate::kernel::helpers::retry_on_interrupt_until;
+	use crate::support::{ErrorKind, HaikuError};
+	use libc::{B_INTERRUPTED, B_WOULD_BLOCK};
+
+	// An interrupted attempt is retried with the time that is left
+	let deadline = system_time() + Duration::from_secs(60);
+	let mut timeouts = Vec::new();
+	let result = retry_on_interrupt_until(deadline, |timeout| {
+		timeouts.push(timeout);
+		if timeouts.len() < 2 {
+			Err(HaikuError::from_raw_os_error(B_INTERRUPTED))
+		} else {
+			Ok(timeouts.len())
+		}
+	});
+	assert_eq!(result.unwrap(), 2);
+	assert!(timeouts[1] <= timeouts[0]);
+	assert!(timeouts[1] > Duration::from_secs(50));
+
+	// When the interruption comes after the deadline, the caller gets a
+	// time out instead of the B_WOULD_BLOCK of the last attempt
+	let deadline = system_time() + Duration::from_millis(10);
+	let mut timeouts = Vec::new();
+	let result: crate::support::Result<()> = retry_on_interrupt_until(deadline, |timeout| {
+		timeouts.push(timeout);
+		if timeouts.len() < 2 {
+			std::thread::sleep(timeout);
+			Err(HaikuError::from_raw_os_error(B_INTERRUPTED))
+		} else {
+			Err(HaikuError::from_raw_os_error(B_WOULD_BLOCK))
+		}
+	});
+	assert!(matches!(result.unwrap_err().kind(), ErrorKind::TimedOut));
+	assert_eq!(timeouts[1], Duration::ZERO);
+
+	// A zero timeout that is not interrupted still reports B_WOULD_BLOCK
+	let result: crate::support::Result<()> = retry_on_interrupt_until(system_time(), |_| {
+		Err(HaikuError::from_raw_os_error(B_WOULD_BLOCK))
+	});
+	assert_eq!(result.unwrap_err().raw_os_error(), Some(B_WOULD_BLOCK));
+}
+
+#[test]
+fn test_team_try_from() {
+	use crate::kernel::teams::Team;
+	use crate::support::ErrorKind;
+
+	let team_id = unsafe { libc::getpid() };
+	assert_eq!(Team::try_from(team_id).unwrap().get_team_id(), team_id);
+	assert!(matches!(
+		Team::try_from(-1).err().unwrap().kind(),
+		ErrorKind::InvalidInput
+	));
+	assert!(matches!(
+		Team::try_from(i32::MAX).err().unwrap().kind(),
+		ErrorKind::NotFound
+	));
+}
+
+#[test]
+fn test_team_traits() {
+	use std::collections::HashSet;
+
+	use crate::kernel::teams::Team;
+
+	let team = Team::from(42).unwrap();
+	let copy = team;
+	assert_eq!(team, copy);
+	assert_ne!(team, Team::from(43).unwrap());
+	let teams: HashSet<Team> = [team, copy, Team::from(43).unwrap()].into_iter().collect();
+	assert_eq!(teams.len(), 2);
+	assert_eq!(format!("{:?}", team), "Team(42)");
+}
+
+#[test]
+fn test_team_info() {
+	use crate::kernel::teams::Team;
+
+	let team = Team::current();
+	assert_eq!(team.get_team_id(), unsafe { libc::getpid() });
+	let info = team.get_info().unwrap();
+	assert!(info.thread_count >= 1);
+	assert!(info.argc >= 1);
+	assert!(Team::from(i32::MAX).unwrap().get_info().is_err());
+}
+
+#[test]
+fn test_team_kill_and_wait() {
+	use std::process::Command;
+
+	use crate::kernel::teams::Team;
+
+	// A team that exits by itself
+	let child = Command::new("true").spawn().unwrap();
+	let team = Team::from(child.id() as i32).unwrap();
+	assert_eq!(team.wait().unwrap(), 0);
+
+	// A team that has to be killed
+	let child = Command::new("sleep").arg("60").spawn().unwrap();
+	let team = Team::from(child.id() as i32).unwrap();
+	team.kill().unwrap();
+	assert!(team.wait().is_ok());
+	assert!(team.get_info().is_err());
+}
+
+#[test]
+fn test_team_cwd() {
+	use crate::kernel::teams::Team;
+
+	let team = Team::from(unsafe { libc::getpid() }).unwrap();
+	assert_eq!(team.cwd().unwrap(), std::env::current_dir().unwrap());
 }
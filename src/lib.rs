@@ -18,8 +18,11 @@
 #[macro_use]
 extern crate lazy_static;
 extern crate libc;
+// The derive macros refer to the types in this crate as ::haiku
+extern crate self as haiku;
 
 pub mod app;
+pub mod interface;
 pub mod kernel;
 pub mod storage;
 pub mod support;
@@ -30,3 +33,26 @@ macro_rules! haiku_constant {
 		(($a as u32) << 24) + (($b as u32) << 16) + (($c as u32) << 8) + ($d as u32)
 	};
 }
+
+/// Create a `Message` with a what code and a list of fields
+///
+/// Each field is a name and a value, separated by `=>`. The values are added
+/// with `Message::add_data()`, so any `Flattenable` type can be used. String
+/// slices are added as strings with `Message::add_string()`. Using a name
+/// more than once adds multiple values to the field.
+///
+/// The macro returns a `Result<Message>` with the error of the first value
+/// that could not be added.
+///
+/// ```ignore
+/// let message = message!(MY_WHAT, "count" => 3i32, "name" => "foo")?;
+/// ```
+#[macro_export]
+macro_rules! message {
+	($what:expr $(, $name:expr => $value:expr)* $(,)?) => {
+		$crate::app::Message::with_fields(
+			$what,
+			&[$(($name, &$value as &dyn $crate::app::MessageValue)),*],
+		)
+	};
+}
@@ -3,20 +3,102 @@
 // All rights reserved. Distributed under the terms of the MIT License.
 //
 
+use std::collections::HashMap;
 use std::ffi::{CStr, CString};
+use std::fmt::{Display, Write};
 use std::fs::File;
 use std::io;
 use std::mem;
 use std::os::unix::io::AsRawFd;
 use std::path::Path;
+use std::sync::{Arc, Mutex};
 
 use libc::{
 	c_int, c_void, fs_close_attr_dir, fs_fopen_attr_dir, fs_read_attr, fs_read_attr_dir,
-	fs_remove_attr, fs_stat_attr, fs_write_attr, off_t, size_t, type_code, DIR,
+	fs_remove_attr, fs_stat_attr, fs_write_attr, off_t, size_t, type_code, B_BOOL_TYPE,
+	B_DOUBLE_TYPE, B_FLOAT_TYPE, B_INT16_TYPE, B_INT32_TYPE, B_INT64_TYPE, B_INT8_TYPE,
+	B_MIME_STRING_TYPE, B_OBJECT_TYPE, B_POINTER_TYPE, B_STRING_TYPE, B_UINT16_TYPE, B_UINT32_TYPE,
+	B_UINT64_TYPE, B_UINT8_TYPE, DIR,
 };
 
 use crate::support::Flattenable;
 
+/// A function that converts the raw data of an attribute into a string
+pub type AttributeDecoder = dyn Fn(&[u8]) -> io::Result<String> + Send + Sync;
+
+lazy_static! {
+	static ref ATTRIBUTE_DECODERS: Mutex<HashMap<type_code, Arc<AttributeDecoder>>> = {
+		let mut decoders: HashMap<type_code, Arc<AttributeDecoder>> = HashMap::new();
+		decoders.insert(B_BOOL_TYPE, Arc::new(decode_display::<bool>));
+		decoders.insert(B_INT8_TYPE, Arc::new(decode_display::<i8>));
+		decoders.insert(B_UINT8_TYPE, Arc::new(decode_display::<u8>));
+		decoders.insert(B_INT16_TYPE, Arc::new(decode_display::<i16>));
+		decoders.insert(B_UINT16_TYPE, Arc::new(decode_display::<u16>));
+		decoders.insert(B_INT32_TYPE, Arc::new(decode_display::<i32>));
+		decoders.insert(B_UINT32_TYPE, Arc::new(decode_display::<u32>));
+		decoders.insert(B_INT64_TYPE, Arc::new(decode_display::<i64>));
+		decoders.insert(B_UINT64_TYPE, Arc::new(decode_display::<u64>));
+		decoders.insert(B_FLOAT_TYPE, Arc::new(decode_display::<f32>));
+		decoders.insert(B_DOUBLE_TYPE, Arc::new(decode_display::<f64>));
+		decoders.insert(B_STRING_TYPE, Arc::new(decode_display::<String>));
+		decoders.insert(B_MIME_STRING_TYPE, Arc::new(decode_display::<String>));
+		Mutex::new(decoders)
+	};
+}
+
+/// Register a decoder that converts attributes of a type into a string
+///
+/// The decoder is used by `AttributeExt::read_attribute_display()` for all
+/// attributes with the `raw_type`. A decoder that is registered for a type
+/// that already has one, replaces the existing decoder. This includes the
+/// built-in decoders for the numeric types, booleans and strings.
+pub fn register_attribute_decoder<F>(raw_type: type_code, decoder: F)
+where
+	F: Fn(&[u8]) -> io::Result<String> + Send + Sync + 'static,
+{
+	ATTRIBUTE_DECODERS
+		.lock()
+		.unwrap()
+		.insert(raw_type, Arc::new(decoder));
+}
+
+fn find_attribute_decoder(raw_type: type_code) -> Option<Arc<AttributeDecoder>> {
+	ATTRIBUTE_DECODERS.lock().unwrap().get(&raw_type).cloned()
+}
+
+fn decode_display<T: Flattenable<T> + Display>(data: &[u8]) -> io::Result<String> {
+	match T::unflatten(data) {
+		Ok(value) => Ok(value.to_string()),
+		Err(_) => Err(io::Error::new(
+			io::ErrorKind::InvalidData,
+			"error unflattening data",
+		)),
+	}
+}
+
+// Dump the data as lines of 16 bytes, with the hexadecimal value and the
+// printable characters
+pub(crate) fn dump_raw_data(data: &[u8]) -> String {
+	const CHUNK_SIZE: usize = 16;
+	let mut output = String::new();
+	for (index, chunk) in data.chunks(CHUNK_SIZE).enumerate() {
+		write!(&mut output, "\t{:>4}: ", index * CHUNK_SIZE).unwrap();
+		for i in 0..CHUNK_SIZE {
+			match chunk.get(i) {
+				Some(byte) => write!(&mut output, "{:2x} ", byte).unwrap(),
+				None => output.push_str("   "),
+			}
+		}
+		output.push(' ');
+		for byte in chunk {
+			let c = *byte as char;
+			output.push(if c.is_ascii_graphic() { c } else { '.' });
+		}
+		output.push('\n');
+	}
+	output
+}
+
 /// A descriptor with the metadata of an attribute.
 pub struct AttributeDescriptor {
 	/// The name of the attribute
@@ -27,6 +109,24 @@ pub struct AttributeDescriptor {
 	pub raw_attribute_type: type_code,
 }
 
+impl AttributeDescriptor {
+	/// Get the raw attribute type as four characters
+	///
+	/// Most type codes are four-character codes, like `'MIMS'` for a MIME
+	/// string. This is useful to show types that are otherwise unknown. Bytes
+	/// that are not printable ASCII characters are returned as `'.'`.
+	pub fn raw_attribute_type_chars(&self) -> [char; 4] {
+		self.raw_attribute_type.to_be_bytes().map(|b| {
+			let c = b as char;
+			if c.is_ascii_graphic() || c == ' ' {
+				c
+			} else {
+				'.'
+			}
+		})
+	}
+}
+
 enum FileDescriptor {
 	Owned(File),
 	Borrowed(c_int),
@@ -141,7 +241,19 @@ pub trait AttributeExt {
 	/// This method reads the attribute and returns it in the type `T`. Please
 	/// note that you should make sure that the type `T` matches the type in the
 	/// `AttributeDescriptor`. The type T should implement the Flattenable trait.
+	///
+	/// Attributes with the `B_POINTER_TYPE` or `B_OBJECT_TYPE` types cannot
+	/// be read, as pointers are only valid in the process that created them.
 	fn read_attribute<T: Flattenable<T>>(&self, attribute: &AttributeDescriptor) -> io::Result<T> {
+		if attribute.raw_attribute_type == B_POINTER_TYPE
+			|| attribute.raw_attribute_type == B_OBJECT_TYPE
+		{
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidData,
+				"pointer types are not transferable",
+			));
+		}
+
 		let value = self.read_attribute_raw(&attribute.name, attribute.raw_attribute_type, 0, 0);
 		if value.is_err() {
 			return Err(value.unwrap_err());
@@ -162,6 +274,32 @@ pub trait AttributeExt {
 		}
 	}
 
+	/// Read an attribute and convert it into a human readable string
+	///
+	/// If there is a decoder registered for the type of the attribute (see
+	/// `register_attribute_decoder()`), it is used to convert the data.
+	/// Otherwise, the result is a hexadecimal dump of (at most) the first 256
+	/// bytes of the attribute.
+	fn read_attribute_display(&self, attribute: &AttributeDescriptor) -> io::Result<String> {
+		const DUMP_LIMIT: i64 = 256;
+		match find_attribute_decoder(attribute.raw_attribute_type) {
+			Some(decoder) => {
+				let data =
+					self.read_attribute_raw(&attribute.name, attribute.raw_attribute_type, 0, 0)?;
+				decoder(&data)
+			}
+			None => {
+				let data = self.read_attribute_raw(
+					&attribute.name,
+					attribute.raw_attribute_type,
+					0,
+					DUMP_LIMIT.min(attribute.size),
+				)?;
+				Ok(dump_raw_data(&data))
+			}
+		}
+	}
+
 	/// Write an object as a file system attribute
 	///
 	/// This method writes a copy of any object that implements the Flattenable
@@ -225,31 +363,34 @@ impl AttributeExt for File {
 
 		// Read the data
 		let attr_name = CString::new(descriptor.name).unwrap();
-		let len = if size > 0 {
+		let read = |offset: off_t, buffer: &mut [u8]| {
+			let read_size = unsafe {
+				fs_read_attr(
+					fd,
+					attr_name.as_ptr(),
+					descriptor.raw_attribute_type,
+					pos + offset,
+					buffer.as_mut_ptr() as *mut c_void,
+					buffer.len() as size_t,
+				)
+			};
+			if read_size < 0 {
+				Err(io::Error::last_os_error())
+			} else {
+				Ok(read_size as usize)
+			}
+		};
+		if size > 0 {
 			// Use the user-supplied size
-			size
+			let mut dst = vec![0u8; size as usize];
+			let read_size = read(0, &mut dst)?;
+			dst.truncate(read_size);
+			Ok(dst)
 		} else {
-			// Calculate the size
-			descriptor.size - pos
-		};
-		let mut dst: Vec<u8> = Vec::with_capacity(descriptor.size as usize);
-		let read_size = unsafe {
-			fs_read_attr(
-				fd,
-				attr_name.as_ptr(),
-				descriptor.raw_attribute_type,
-				pos,
-				dst.as_mut_ptr() as *mut c_void,
-				len as size_t,
-			)
-		};
-
-		if read_size == -1 {
-			return Err(io::Error::last_os_error());
+			// The attribute may grow after it was stat'ed, so read until the
+			// end, instead of trusting the size of the descriptor
+			read_to_end((descriptor.size - pos) as usize, read)
 		}
-
-		unsafe { dst.set_len(read_size as usize) };
-		Ok(dst)
 	}
 
 	fn write_attribute_raw(
@@ -344,6 +485,26 @@ impl AttributeExt for Path {
 	}
 }
 
+// Read data with `read` until it returns less than what was asked for. The
+// buffer starts with `initial_size` bytes, and grows when it is filled.
+fn read_to_end<F>(initial_size: usize, mut read: F) -> io::Result<Vec<u8>>
+where
+	F: FnMut(off_t, &mut [u8]) -> io::Result<usize>,
+{
+	let mut dst = vec![0u8; initial_size.max(1)];
+	let mut total = 0;
+	loop {
+		let read_size = read(total as off_t, &mut dst[total..])?;
+		total += read_size;
+		if total < dst.len() {
+			break;
+		}
+		dst.resize(dst.len() * 2, 0);
+	}
+	dst.truncate(total);
+	Ok(dst)
+}
+
 #[cfg(test)]
 mod test {
 	extern crate tempfile;
@@ -404,4 +565,140 @@ mod test {
 		path.remove_attribute("test_u8").unwrap();
 		assert!(path.find_attribute("test_u8").is_err());
 	}
+
+	#[test]
+	fn test_read_grown_attribute() {
+		use super::read_to_end;
+
+		// The attribute had 4 bytes when it was stat'ed, but grew to 10
+		// bytes before it was read
+		let attribute: Vec<u8> = (0..10).collect();
+		let mut calls = 0;
+		let data = read_to_end(4, |offset, buffer| {
+			calls += 1;
+			let start = (offset as usize).min(attribute.len());
+			let len = buffer.len().min(attribute.len() - start);
+			buffer[..len].copy_from_slice(&attribute[start..start + len]);
+			Ok(len)
+		})
+		.unwrap();
+		assert_eq!(data, attribute);
+		assert_eq!(calls, 3);
+
+		// An empty attribute
+		let data = read_to_end(0, |_, _| Ok(0)).unwrap();
+		assert!(data.is_empty());
+	}
+
+	#[test]
+	fn test_raw_attribute_type_chars() {
+		use libc::{B_MIME_STRING_TYPE, B_STRING_TYPE};
+
+		use crate::storage::AttributeDescriptor;
+
+		let mut attribute = AttributeDescriptor {
+			name: String::from("META:type"),
+			size: 0,
+			raw_attribute_type: B_MIME_STRING_TYPE,
+		};
+		assert_eq!(attribute.raw_attribute_type_chars(), ['M', 'I', 'M', 'S']);
+		attribute.raw_attribute_type = B_STRING_TYPE;
+		assert_eq!(attribute.raw_attribute_type_chars(), ['C', 'S', 'T', 'R']);
+		attribute.raw_attribute_type = 0x41420001;
+		assert_eq!(attribute.raw_attribute_type_chars(), ['A', 'B', '.', '.']);
+	}
+
+	#[test]
+	fn test_read_attribute_display() {
+		use std::io;
+
+		use libc::{off_t, type_code, B_INT32_TYPE};
+
+		use crate::haiku_constant;
+		use crate::storage::attributes::{register_attribute_decoder, AttributeIterator};
+		use crate::storage::AttributeDescriptor;
+		use crate::support::Flattenable;
+
+		// An object with a single attribute in memory
+		struct MemoryAttribute(type_code, Vec<u8>);
+
+		impl AttributeExt for MemoryAttribute {
+			fn iter_attributes(&self) -> io::Result<AttributeIterator> {
+				unimplemented!()
+			}
+
+			fn find_attribute(&self, _name: &str) -> io::Result<AttributeDescriptor> {
+				unimplemented!()
+			}
+
+			fn read_attribute_raw(
+				&self,
+				_name: &str,
+				_raw_type: type_code,
+				pos: off_t,
+				size: i64,
+			) -> io::Result<Vec<u8>> {
+				let start = pos as usize;
+				let end = if size > 0 {
+					start + size as usize
+				} else {
+					self.1.len()
+				};
+				Ok(self.1[start..end].to_vec())
+			}
+
+			fn write_attribute_raw(
+				&self,
+				_name: &str,
+				_raw_type: type_code,
+				_pos: off_t,
+				_buffer: &[u8],
+			) -> io::Result<()> {
+				unimplemented!()
+			}
+
+			fn remove_attribute(&self, _name: &str) -> io::Result<()> {
+				unimplemented!()
+			}
+		}
+
+		fn descriptor(attribute: &MemoryAttribute) -> AttributeDescriptor {
+			AttributeDescriptor {
+				name: String::from("test"),
+				size: attribute.1.len() as i64,
+				raw_attribute_type: attribute.0,
+			}
+		}
+
+		// Built-in decoder
+		let int_attribute = MemoryAttribute(B_INT32_TYPE, (-42 as i32).flatten());
+		let display = int_attribute
+			.read_attribute_display(&descriptor(&int_attribute))
+			.unwrap();
+		assert_eq!(display, "-42");
+
+		// Unknown types are dumped
+		let test_type = haiku_constant!('T', 'E', 'S', 'T');
+		let test_attribute = MemoryAttribute(test_type, vec![0x41, 0x42, 0x00, 0xff]);
+		let display = test_attribute
+			.read_attribute_display(&descriptor(&test_attribute))
+			.unwrap();
+		assert_eq!(
+			display.trim_end(),
+			"\t   0: 41 42  0 ff                                      AB.."
+		);
+
+		// Custom decoder
+		register_attribute_decoder(test_type, |data| {
+			Ok(data
+				.iter()
+				.map(|byte| format!("{:02x}", byte))
+				.collect::<Vec<String>>()
+				.join(":"))
+		});
+		let display = test_attribute
+			.read_attribute_display(&descriptor(&test_attribute))
+			.unwrap();
+		assert_eq!(display, "41:42:00:ff");
+	}
 }
@@ -3,7 +3,38 @@
 // All rights reserved. Distributed under the terms of the MIT License.
 //
 
-use crate::storage::B_MIME_TYPE_LENGTH;
+use std::ffi::CStr;
+use std::path::PathBuf;
+
+use libc::{
+	c_char, directory_which, find_directory, status_t, B_FILE_EXISTS, B_OK, B_PATH_NAME_LENGTH,
+};
+
+use crate::app::constants::{
+	B_REG_MIME_DELETE, B_REG_MIME_INSTALL, B_REG_MIME_SET_PARAM, B_REG_RESULT,
+};
+use crate::app::{Message, Roster};
+use crate::haiku_constant;
+use crate::storage::{AttributeExt, B_MIME_TYPE_LENGTH};
+use crate::support::{ErrorKind, HaikuError, Result};
+
+// private/app/RegistrarDefs.h
+const B_REG_MIME_DESCRIPTION: i32 = haiku_constant!('r', 'm', 'd', 's') as i32;
+
+// The attribute of a type in the MIME database that stores the path of the
+// application with that signature, see the database_support in the storage kit
+const APP_HINT_ATTRIBUTE: &str = "META:PPATH";
+const APP_HINT_TYPE: u32 = haiku_constant!('M', 'P', 'T', 'H');
+
+// The directories that contain a MIME database, see the DatabaseLocation
+// class in Haiku's storage kit
+const MIME_DATABASE_DIRECTORIES: [directory_which; 5] = [
+	directory_which::B_USER_SETTINGS_DIRECTORY,
+	directory_which::B_USER_NONPACKAGED_DATA_DIRECTORY,
+	directory_which::B_USER_DATA_DIRECTORY,
+	directory_which::B_SYSTEM_NONPACKAGED_DATA_DIRECTORY,
+	directory_which::B_SYSTEM_DATA_DIRECTORY,
+];
 
 /// Represents a mime type as defined by RFC 6838
 #[derive(PartialEq)]
@@ -54,6 +85,102 @@ impl MimeType {
 		!self.type_string.contains('/')
 	}
 
+	/// Check if the type is installed in the MIME database
+	///
+	/// An error is returned if a MIME database cannot be read, for example
+	/// because of missing permissions.
+	pub fn is_installed(&self) -> Result<bool> {
+		let type_path = self.type_string.to_lowercase();
+		for which in MIME_DATABASE_DIRECTORIES.iter() {
+			if let Some(directory) = get_directory(*which) {
+				let type_file = directory.join("mime_db").join(&type_path);
+				match type_file.try_exists() {
+					Ok(true) => return Ok(true),
+					Ok(false) => {}
+					Err(e) => {
+						return Err(match e.raw_os_error() {
+							Some(code) => HaikuError::from_raw_os_error(code),
+							None => HaikuError::new(ErrorKind::Other, e),
+						})
+					}
+				}
+			}
+		}
+		Ok(false)
+	}
+
+	/// Get the path of the application with this signature
+	///
+	/// Applications store their location in the MIME database, when they are
+	/// installed or launched. This returns `None` if the type is not in the
+	/// database, or if it does not have a path.
+	pub fn get_app_hint(&self) -> Option<PathBuf> {
+		let type_path = self.type_string.to_lowercase();
+		for which in MIME_DATABASE_DIRECTORIES.iter() {
+			let type_file = match get_directory(*which) {
+				Some(directory) => directory.join("mime_db").join(&type_path),
+				None => continue,
+			};
+			if let Ok(data) = type_file.read_attribute_raw(APP_HINT_ATTRIBUTE, APP_HINT_TYPE, 0, 0)
+			{
+				let path = CStr::from_bytes_until_nul(&data).ok()?;
+				return Some(PathBuf::from(path.to_str().ok()?));
+			}
+		}
+		None
+	}
+
+	/// Install the type in the MIME database
+	///
+	/// Installing a type that is already installed is not an error, so this
+	/// can safely be called every time an application starts.
+	pub fn install(&self) -> Result<()> {
+		let mut request = Message::new(B_REG_MIME_INSTALL);
+		request.add_data("type", &self.type_string)?;
+		match self.send_database_request(request) {
+			Err(ref e) if e.raw_os_error() == Some(B_FILE_EXISTS) => Ok(()),
+			result => result,
+		}
+	}
+
+	/// Remove the type from the MIME database
+	pub fn delete(&self) -> Result<()> {
+		let mut request = Message::new(B_REG_MIME_DELETE);
+		request.add_data("type", &self.type_string)?;
+		self.send_database_request(request)
+	}
+
+	/// Set the short description of the type in the MIME database
+	///
+	/// This is the name that is shown to users, for example in Tracker. The
+	/// type is installed if it was not yet in the database.
+	pub fn set_short_description(&self, description: &str) -> Result<()> {
+		let mut request = Message::new(B_REG_MIME_SET_PARAM);
+		request.add_data("type", &self.type_string)?;
+		request.add_data("which", &B_REG_MIME_DESCRIPTION)?;
+		request.add_data("long", &false)?;
+		request.add_string("description", description)?;
+		self.send_database_request(request)
+	}
+
+	// Send a request to the MIME database of the registrar and return the
+	// result
+	fn send_database_request(&self, request: Message) -> Result<()> {
+		let messenger = Roster::try_global()?.get_mime_messenger()?;
+		let response = messenger.send_and_wait_for_reply(request, None)?;
+		if response.what() != B_REG_RESULT {
+			return Err(HaikuError::new(
+				ErrorKind::InvalidData,
+				"the registrar returned an invalid response",
+			));
+		}
+		let result: status_t = response.find_data("result", 0)?;
+		if result != B_OK {
+			return Err(HaikuError::from_raw_os_error(result));
+		}
+		Ok(())
+	}
+
 	/// Get the super type of this mimetype
 	///
 	/// For example, `text/plain` will return `text`.
@@ -71,6 +198,16 @@ impl MimeType {
 	}
 }
 
+fn get_directory(which: directory_which) -> Option<PathBuf> {
+	let mut buf = [0 as c_char; B_PATH_NAME_LENGTH];
+	let status = unsafe { find_directory(which, -1, false, buf.as_mut_ptr(), buf.len() as i32) };
+	if status != B_OK {
+		return None;
+	}
+	let path = unsafe { CStr::from_ptr(buf.as_ptr()) };
+	Some(PathBuf::from(path.to_str().ok()?))
+}
+
 #[test]
 fn test_mimetype_check() {
 	assert!(MimeType::new("application/x-Vnd-Haiku").is_some());
@@ -88,3 +225,23 @@ fn test_mimetype_methods() {
 	assert!(!childtype.is_supertype_only());
 	assert!(supertype == childtype.get_supertype());
 }
+
+#[test]
+fn test_mimetype_install() {
+	let mime_type = MimeType::new("application/x-vnd.haiku-rs-install-test").unwrap();
+	mime_type.install().unwrap();
+	assert!(mime_type.is_installed().unwrap());
+	// Installing it again is not an error
+	mime_type.install().unwrap();
+	mime_type
+		.set_short_description("haiku-rs install test")
+		.unwrap();
+	assert!(mime_type.is_installed().unwrap());
+	assert!(!MimeType::new("application/x-vnd.haiku-rs-not-installed")
+		.unwrap()
+		.is_installed()
+		.unwrap());
+	// Clean up the database
+	mime_type.delete().unwrap();
+	assert!(!mime_type.is_installed().unwrap());
+}
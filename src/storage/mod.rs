@@ -11,9 +11,15 @@ use libc::{FILENAME_MAX, PATH_MAX};
 mod attributes;
 mod mimetype;
 pub(crate) mod sys;
+mod volume;
 
-pub use self::attributes::{AttributeDescriptor, AttributeExt, AttributeIterator};
+pub(crate) use self::attributes::dump_raw_data;
+pub use self::attributes::{
+	register_attribute_decoder, AttributeDecoder, AttributeDescriptor, AttributeExt,
+	AttributeIterator,
+};
 pub use self::mimetype::MimeType;
+pub use self::volume::{mount, Volume};
 
 // Kit constants
 /// Maximum length for the name of a device
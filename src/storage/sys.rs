@@ -3,7 +3,7 @@
 // All rights reserved. Distributed under the terms of the MIT License.
 //
 
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
 use std::mem;
 use std::os::unix::ffi::OsStrExt;
 use std::path::Path;
@@ -78,12 +78,33 @@ impl Flattenable<entry_ref> for entry_ref {
 		vec.extend(self.device.flatten().iter());
 		vec.extend(self.directory.flatten().iter());
 		vec.extend(self.name.as_bytes_with_nul().iter());
-		println!("vec {:?}", vec);
 		vec
 	}
 
-	fn unflatten(_buffer: &[u8]) -> Result<entry_ref> {
-		unimplemented!()
+	fn unflatten(buffer: &[u8]) -> Result<entry_ref> {
+		let name_offset = mem::size_of::<dev_t>() + mem::size_of::<ino_t>();
+		if buffer.len() <= name_offset {
+			return Err(HaikuError::new(
+				ErrorKind::InvalidData,
+				"the buffer is smaller than a flattened entry_ref",
+			));
+		}
+		let device = dev_t::unflatten(&buffer[0..mem::size_of::<dev_t>()])?;
+		let directory = ino_t::unflatten(&buffer[mem::size_of::<dev_t>()..name_offset])?;
+		let name = match CStr::from_bytes_until_nul(&buffer[name_offset..]) {
+			Ok(name) => CString::from(name),
+			Err(_) => {
+				return Err(HaikuError::new(
+					ErrorKind::InvalidData,
+					"the name of the entry_ref is not terminated",
+				))
+			}
+		};
+		Ok(entry_ref {
+			device,
+			directory,
+			name,
+		})
 	}
 }
 
@@ -94,3 +115,22 @@ fn test_entry_ref_from_path() {
 	let path = Path::new("/boot/bogus/doesnotexist");
 	assert!(entry_ref::from_path(&path).is_err());
 }
+
+#[test]
+fn test_entry_ref_flatten() {
+	let reference = entry_ref {
+		device: 3,
+		directory: 42,
+		name: CString::new("StyledEdit").unwrap(),
+	};
+	let flattened = reference.flatten();
+	assert_eq!(flattened.len(), reference.flattened_size());
+	let unflattened = entry_ref::unflatten(&flattened).unwrap();
+	assert_eq!(unflattened.device, reference.device);
+	assert_eq!(unflattened.directory, reference.directory);
+	assert_eq!(unflattened.name, reference.name);
+
+	// The buffer needs to contain at least the terminator of the name
+	assert!(entry_ref::unflatten(&flattened[..12]).is_err());
+	assert!(entry_ref::unflatten(&flattened[..flattened.len() - 1]).is_err());
+}
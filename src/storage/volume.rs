@@ -0,0 +1,191 @@
+//
+// Copyright 2024, Niels Sascha Reedijk <niels.reedijk@gmail.com>
+// All rights reserved. Distributed under the terms of the MIT License.
+//
+
+use std::ffi::{CStr, CString};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::{mem, ptr};
+
+use libc::{
+	c_char, dev_t, fs_info, fs_mount_volume, fs_stat_dev, fs_unmount_volume, status_t, B_BUSY,
+	B_FS_IS_READONLY, B_NOT_ALLOWED, B_OK, B_PERMISSION_DENIED, B_READ_ONLY_DEVICE,
+};
+
+use crate::kernel::helpers;
+
+/// A mounted file system
+///
+/// Haiku identifies every mounted volume by a device id. This is the same id
+/// that is in the `dev()` field of the metadata of any file on the volume.
+pub struct Volume {
+	device: dev_t,
+}
+
+impl Volume {
+	/// Get the volume with a device id
+	///
+	/// This returns an error if there is no volume mounted with this id.
+	pub fn from_device(device: dev_t) -> io::Result<Volume> {
+		let volume = Volume { device };
+		volume.stat()?;
+		Ok(volume)
+	}
+
+	/// Get the device id of the volume
+	pub fn device(&self) -> dev_t {
+		self.device
+	}
+
+	/// Get the name of the volume
+	pub fn name(&self) -> io::Result<String> {
+		let info = self.stat()?;
+		let name = unsafe { CStr::from_ptr(info.volume_name.as_ptr() as *const c_char) };
+		Ok(name.to_string_lossy().into_owned())
+	}
+
+	/// Check if the volume is mounted read-only
+	pub fn is_read_only(&self) -> io::Result<bool> {
+		Ok(self.stat()?.flags & B_FS_IS_READONLY != 0)
+	}
+
+	/// Get the directory where the volume is mounted
+	pub fn mount_point(&self) -> io::Result<PathBuf> {
+		let info = self.stat()?;
+		match helpers::get_path_for_entry_ref(self.device, info.root, ptr::null()) {
+			Ok(path) => Ok(PathBuf::from(path)),
+			Err(e) => Err(io::Error::other(e)),
+		}
+	}
+
+	/// Unmount the volume
+	///
+	/// Like mounting, unmounting usually requires root privileges. The
+	/// operation fails with `io::ErrorKind::ResourceBusy` when there are still
+	/// open files on the volume.
+	pub fn unmount(self) -> io::Result<()> {
+		let mount_point = self.mount_point()?;
+		let path = path_to_cstring(&mount_point)?;
+		let status = unsafe { fs_unmount_volume(path.as_ptr(), 0) };
+		if status != B_OK {
+			return Err(volume_error(status));
+		}
+		Ok(())
+	}
+
+	fn stat(&self) -> io::Result<fs_info> {
+		let mut info: fs_info = unsafe { mem::zeroed() };
+		if unsafe { fs_stat_dev(self.device, &mut info) } != 0 {
+			return Err(io::Error::last_os_error());
+		}
+		Ok(info)
+	}
+}
+
+/// Mount the file system on a `device` to the directory `at`
+///
+/// The `fs` is the name of the file system, like `bfs`. The `flags` can be a
+/// combination of `B_MOUNT_READ_ONLY`, and `B_MOUNT_VIRTUAL_DEVICE` when the
+/// `device` is an image file.
+///
+/// Mounting usually requires root privileges. If the user does not have
+/// them, the error has the kind `io::ErrorKind::PermissionDenied`. When the
+/// device is busy, the error has the kind `io::ErrorKind::ResourceBusy`, and
+/// if the file system cannot be mounted on a read-only device, the kind is
+/// `io::ErrorKind::ReadOnlyFilesystem`.
+pub fn mount(device: &Path, at: &Path, fs: &str, flags: u32) -> io::Result<Volume> {
+	let device = path_to_cstring(device)?;
+	let at = path_to_cstring(at)?;
+	let fs = match CString::new(fs) {
+		Ok(fs) => fs,
+		Err(_) => {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"the file system name contains a nul character",
+			))
+		}
+	};
+	let result = unsafe {
+		fs_mount_volume(
+			at.as_ptr(),
+			device.as_ptr(),
+			fs.as_ptr(),
+			flags,
+			ptr::null(),
+		)
+	};
+	if result < 0 {
+		return Err(volume_error(result));
+	}
+	Ok(Volume { device: result })
+}
+
+fn path_to_cstring(path: &Path) -> io::Result<CString> {
+	CString::new(path.as_os_str().as_bytes()).map_err(|_| {
+		io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"the path contains a nul character",
+		)
+	})
+}
+
+fn volume_error(status: status_t) -> io::Error {
+	match status {
+		B_BUSY => io::Error::new(io::ErrorKind::ResourceBusy, "the volume is busy"),
+		B_READ_ONLY_DEVICE => {
+			io::Error::new(io::ErrorKind::ReadOnlyFilesystem, "the device is read-only")
+		}
+		B_PERMISSION_DENIED | B_NOT_ALLOWED => io::Error::new(
+			io::ErrorKind::PermissionDenied,
+			"not allowed to mount or unmount the volume",
+		),
+		_ => io::Error::from_raw_os_error(status),
+	}
+}
+
+#[test]
+fn test_volume() {
+	use std::fs::metadata;
+	use std::os::unix::fs::MetadataExt;
+
+	let device = metadata("/boot").unwrap().dev();
+	let volume = Volume::from_device(device as dev_t).unwrap();
+	assert_eq!(volume.device() as u64, device);
+	assert!(!volume.name().unwrap().is_empty());
+	assert_eq!(volume.mount_point().unwrap(), PathBuf::from("/boot"));
+	assert!(Volume::from_device(dev_t::MAX).is_err());
+}
+
+#[test]
+fn test_mount_invalid_path() {
+	let error = mount(
+		Path::new("/boot/home/image\0"),
+		Path::new("/boot/home/mnt"),
+		"bfs",
+		0,
+	)
+	.err()
+	.unwrap();
+	assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_mount_image() {
+	use std::env;
+
+	use libc::B_MOUNT_VIRTUAL_DEVICE;
+
+	// Mounting requires a BFS image, and the privileges to mount it
+	let image = match env::var("HAIKU_RS_TEST_BFS_IMAGE") {
+		Ok(image) => PathBuf::from(image),
+		Err(_) => return,
+	};
+	let mount_point = env::temp_dir().join("haiku-rs-mount-test");
+	std::fs::create_dir_all(&mount_point).unwrap();
+	let volume = mount(&image, &mount_point, "bfs", B_MOUNT_VIRTUAL_DEVICE).unwrap();
+	assert_eq!(volume.mount_point().unwrap(), mount_point);
+	volume.unmount().unwrap();
+	std::fs::remove_dir(&mount_point).unwrap();
+}
@@ -0,0 +1,81 @@
+//
+// Copyright 2024, Niels Sascha Reedijk <niels.reedijk@gmail.com>
+// All rights reserved. Distributed under the terms of the MIT License.
+//
+
+use std::mem;
+
+use libc::{
+	B_BOOL_TYPE, B_CHAR_TYPE, B_DOUBLE_TYPE, B_FLOAT_TYPE, B_INT16_TYPE, B_INT32_TYPE,
+	B_INT64_TYPE, B_INT8_TYPE, B_OFF_T_TYPE, B_POINT_TYPE, B_RECT_TYPE, B_SIZE_T_TYPE,
+	B_SSIZE_T_TYPE, B_TIME_TYPE, B_UINT16_TYPE, B_UINT32_TYPE, B_UINT64_TYPE, B_UINT8_TYPE,
+};
+
+use crate::support::{ErrorKind, HaikuError, Result};
+
+/// Swap the byte order of flattened data
+///
+/// This is the equivalent of Haiku's `swap_data()`. The `data` contains one
+/// or more values of the type `type_code`, and the bytes of each value are
+/// reversed in place. This converts data from a little endian system to a
+/// big endian system, and back.
+///
+/// The numeric types, and the types that consist of numbers, like
+/// `B_POINT_TYPE` and `B_RECT_TYPE`, are supported. Single byte types are
+/// left as they are. For other types, an error with `ErrorKind::InvalidInput`
+/// is returned, because the function cannot know how they are laid out. An
+/// error with `ErrorKind::InvalidData` is returned if the size of the `data`
+/// is not a multiple of the size of the values.
+pub fn swap_data(type_code: u32, data: &mut [u8]) -> Result<()> {
+	let size = match type_code {
+		B_BOOL_TYPE | B_CHAR_TYPE | B_INT8_TYPE | B_UINT8_TYPE => return Ok(()),
+		B_INT16_TYPE | B_UINT16_TYPE => 2,
+		B_INT32_TYPE | B_UINT32_TYPE | B_FLOAT_TYPE | B_POINT_TYPE | B_RECT_TYPE => 4,
+		B_INT64_TYPE | B_UINT64_TYPE | B_DOUBLE_TYPE | B_OFF_T_TYPE | B_TIME_TYPE => 8,
+		B_SIZE_T_TYPE | B_SSIZE_T_TYPE => mem::size_of::<usize>(),
+		_ => {
+			return Err(HaikuError::new(
+				ErrorKind::InvalidInput,
+				"the byte order of this type cannot be swapped",
+			))
+		}
+	};
+	if !data.len().is_multiple_of(size) {
+		return Err(HaikuError::new(
+			ErrorKind::InvalidData,
+			"the size of the data does not match the type",
+		));
+	}
+	for value in data.chunks_exact_mut(size) {
+		value.reverse();
+	}
+	Ok(())
+}
+
+#[test]
+fn test_swap_data() {
+	use libc::{B_RAW_TYPE, B_STRING_TYPE};
+
+	let mut data = 0x1234i16.to_le_bytes();
+	swap_data(B_INT16_TYPE, &mut data).unwrap();
+	assert_eq!(data, 0x1234i16.to_be_bytes());
+
+	let mut data: Vec<u8> = [1.5f32, -2.0]
+		.iter()
+		.flat_map(|v| v.to_be_bytes())
+		.collect();
+	swap_data(B_POINT_TYPE, &mut data).unwrap();
+	assert_eq!(f32::from_le_bytes(data[0..4].try_into().unwrap()), 1.5);
+	assert_eq!(f32::from_le_bytes(data[4..8].try_into().unwrap()), -2.0);
+
+	let mut data = 42u64.to_be_bytes();
+	swap_data(B_TIME_TYPE, &mut data).unwrap();
+	assert_eq!(u64::from_le_bytes(data), 42);
+
+	let mut data = [1u8, 2, 3];
+	swap_data(B_UINT8_TYPE, &mut data).unwrap();
+	assert_eq!(data, [1, 2, 3]);
+	assert!(swap_data(B_INT32_TYPE, &mut data).is_err());
+	assert!(swap_data(B_STRING_TYPE, &mut data).is_err());
+	assert!(swap_data(B_RAW_TYPE, &mut data).is_err());
+}
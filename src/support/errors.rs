@@ -8,8 +8,10 @@ use std::{error, fmt, result, str};
 
 use libc::{
 	c_char, c_int, size_t, status_t, B_BAD_DATA, B_BAD_INDEX, B_BAD_TYPE, B_BAD_VALUE,
-	B_DONT_DO_THAT, B_INTERRUPTED, B_MISMATCHED_VALUES, B_NAME_IN_USE, B_NAME_NOT_FOUND,
-	B_NOT_ALLOWED, B_TIMED_OUT,
+	B_DONT_DO_THAT, B_INTERRUPTED, B_LAUNCH_FAILED_APP_IN_TRASH, B_LAUNCH_FAILED_APP_NOT_FOUND,
+	B_LAUNCH_FAILED_EXECUTABLE, B_LAUNCH_FAILED_FILES_APP_NOT_FOUND,
+	B_LAUNCH_FAILED_NO_PREFERRED_APP, B_LAUNCH_FAILED_NO_RESOLVE_LINK, B_MISMATCHED_VALUES,
+	B_NAME_IN_USE, B_NAME_NOT_FOUND, B_NOT_ALLOWED, B_TIMED_OUT,
 };
 
 /// This is a shortened version for a standard Rust result that returns a
@@ -162,8 +164,8 @@ impl fmt::Debug for Repr {
 			Repr::Os(code) => fmt
 				.debug_struct("Os")
 				.field("code", &code)
-				.field("kind", &ErrorKind::Other)
-				.field("message", &"message")
+				.field("kind", &decode_error_kind(code))
+				.field("message", &error_string(code))
 				.finish(),
 			Repr::Simple(kind) => fmt.debug_tuple("Kind").field(&kind).finish(),
 			Repr::Custom(ref c) => fmt::Debug::fmt(&c, fmt),
@@ -191,7 +193,14 @@ impl error::Error for HaikuError {
 }
 
 // Shamelessly taken from libstd/sys/unix/os.rs
+//
+// The Haiku specific codes are looked up in a table first, as strerror_r only
+// knows the POSIX error numbers.
 fn error_string(errno: status_t) -> String {
+	if let Some(description) = haiku_error_description(errno) {
+		return String::from(description);
+	}
+
 	extern "C" {
 		fn strerror_r(errnum: c_int, buf: *mut c_char, buflen: size_t) -> c_int;
 	}
@@ -211,6 +220,143 @@ fn error_string(errno: status_t) -> String {
 	}
 }
 
+// The descriptions of the error codes of the kits, as they are in Haiku's
+// strerror(). The storage kit codes are the POSIX error numbers on Haiku,
+// but they are kept here so that they are described on every platform.
+fn haiku_error_description(code: status_t) -> Option<&'static str> {
+	use libc::*;
+
+	let description = match code {
+		// General errors
+		B_NO_MEMORY => "Out of memory",
+		B_IO_ERROR => "General I/O error",
+		B_PERMISSION_DENIED => "Permission denied",
+		B_BAD_INDEX => "Index not in range for the data set",
+		B_BAD_TYPE => "Bad argument type passed to function",
+		B_BAD_VALUE => "Invalid argument",
+		B_MISMATCHED_VALUES => "Mismatched values passed to function",
+		B_NAME_NOT_FOUND => "Name not found",
+		B_NAME_IN_USE => "Name in use",
+		B_TIMED_OUT => "Operation timed out",
+		B_INTERRUPTED => "Interrupted system call",
+		B_WOULD_BLOCK => "Operation would block",
+		B_CANCELED => "Operation canceled",
+		B_NO_INIT => "Initialization failed",
+		B_BUSY => "Device/File/Resource busy",
+		B_NOT_ALLOWED => "Operation not allowed",
+		B_BAD_DATA => "Bad data",
+		B_DONT_DO_THAT => "No can do",
+		B_ERROR => "General system error",
+
+		// Kernel kit errors
+		B_BAD_SEM_ID => "Bad semaphore ID",
+		B_NO_MORE_SEMS => "No more semaphores",
+		B_BAD_THREAD_ID => "Bad thread ID",
+		B_NO_MORE_THREADS => "No more threads",
+		B_BAD_THREAD_STATE => "Thread is in an inappropriate state",
+		B_BAD_TEAM_ID => "Operation on invalid team",
+		B_NO_MORE_TEAMS => "No more teams",
+		B_BAD_PORT_ID => "Bad port ID",
+		B_NO_MORE_PORTS => "No more ports available",
+		B_BAD_IMAGE_ID => "Bad image ID",
+		B_BAD_ADDRESS => "Bad address",
+		B_NOT_AN_EXECUTABLE => "Not an executable",
+		B_MISSING_LIBRARY => "Missing library",
+		B_MISSING_SYMBOL => "Symbol not found",
+		B_UNKNOWN_EXECUTABLE => "Unknown executable format",
+		B_LEGACY_EXECUTABLE => "Legacy executable",
+		B_DEBUGGER_ALREADY_INSTALLED => "Debugger already installed for this team",
+
+		// Application kit errors
+		B_BAD_REPLY => "Invalid or unwanted reply",
+		B_DUPLICATE_REPLY => "Duplicate reply",
+		B_MESSAGE_TO_SELF => "Can't send message to self",
+		B_BAD_HANDLER => "Bad handler",
+		B_ALREADY_RUNNING => "Already running",
+		B_LAUNCH_FAILED => "Launch failed",
+		B_AMBIGUOUS_APP_LAUNCH => "Ambiguous app launch",
+		B_UNKNOWN_MIME_TYPE => "Unknown MIME type",
+		B_BAD_SCRIPT_SYNTAX => "Bad script syntax",
+		B_LAUNCH_FAILED_NO_RESOLVE_LINK => "Could not resolve a link",
+		B_LAUNCH_FAILED_EXECUTABLE => "File is mistakenly marked as executable",
+		B_LAUNCH_FAILED_APP_NOT_FOUND => "Application could not be found",
+		B_LAUNCH_FAILED_APP_IN_TRASH => "Application is in the trash",
+		B_LAUNCH_FAILED_NO_PREFERRED_APP => {
+			"There is no preferred application for this type of file"
+		}
+		B_LAUNCH_FAILED_FILES_APP_NOT_FOUND => {
+			"This file has a preferred app, but it could not be found"
+		}
+		B_BAD_MIME_SNIFFER_RULE => "Bad sniffer rule",
+		B_NOT_A_MESSAGE => "Data is not a message",
+		B_SHUTDOWN_CANCELLED => "System shutdown cancelled",
+		B_SHUTTING_DOWN => "System shutting down",
+
+		// Storage kit errors
+		B_FILE_ERROR => "Bad file descriptor",
+		B_FILE_EXISTS => "File or directory already exists",
+		B_ENTRY_NOT_FOUND => "No such file or directory",
+		B_NAME_TOO_LONG => "File or directory name too long",
+		B_NOT_A_DIRECTORY => "Not a directory",
+		B_DIRECTORY_NOT_EMPTY => "Directory not empty",
+		B_DEVICE_FULL => "No space left on device",
+		B_READ_ONLY_DEVICE => "Read-only file system",
+		B_IS_A_DIRECTORY => "Is a directory",
+		B_NO_MORE_FDS => "Too many open files",
+		B_CROSS_DEVICE_LINK => "Cross-device link",
+		B_LINK_LIMIT => "Too many symbolic links",
+		B_BUSTED_PIPE => "Broken pipe",
+		B_UNSUPPORTED => "Operation not supported",
+		B_PARTITION_TOO_SMALL => "Partition too small to contain filesystem",
+		B_PARTIAL_READ => "Data read partially",
+		B_PARTIAL_WRITE => "Data written partially",
+
+		// Media kit errors
+		B_STREAM_NOT_FOUND => "Stream not found",
+		B_SERVER_NOT_FOUND => "Server not found",
+		B_RESOURCE_NOT_FOUND => "Resource not found",
+		B_RESOURCE_UNAVAILABLE => "Resource unavailable",
+		B_BAD_SUBSCRIBER => "Bad subscriber",
+		B_SUBSCRIBER_NOT_ENTERED => "Subscriber not entered",
+		B_BUFFER_NOT_AVAILABLE => "Buffer not available",
+		B_LAST_BUFFER_ERROR => "Last buffer",
+		B_MEDIA_SYSTEM_FAILURE => "System failure",
+		B_MEDIA_BAD_NODE => "Bad media node",
+		B_MEDIA_NODE_BUSY => "Media node busy",
+		B_MEDIA_BAD_FORMAT => "Bad media format",
+		B_MEDIA_BAD_BUFFER => "Bad buffer",
+		B_MEDIA_TOO_MANY_NODES => "Too many nodes",
+		B_MEDIA_TOO_MANY_BUFFERS => "Too many buffers",
+		B_MEDIA_NODE_ALREADY_EXISTS => "Media node already exists",
+		B_MEDIA_BUFFER_ALREADY_EXISTS => "Buffer already exists",
+		B_MEDIA_CANNOT_SEEK => "Cannot seek",
+		B_MEDIA_CANNOT_CHANGE_RUN_MODE => "Cannot change run mode",
+		B_MEDIA_APP_ALREADY_REGISTERED => "Application already registered",
+		B_MEDIA_APP_NOT_REGISTERED => "Application not registered",
+		B_MEDIA_CANNOT_RECLAIM_BUFFERS => "Cannot reclaim buffers",
+		B_MEDIA_BUFFERS_NOT_RECLAIMED => "Buffers not reclaimed",
+		B_MEDIA_TIME_SOURCE_STOPPED => "Time source stopped",
+		B_MEDIA_TIME_SOURCE_BUSY => "Time source busy",
+		B_MEDIA_BAD_SOURCE => "Bad source",
+		B_MEDIA_BAD_DESTINATION => "Bad destination",
+		B_MEDIA_ALREADY_CONNECTED => "Already connected",
+		B_MEDIA_NOT_CONNECTED => "Not connected",
+		B_MEDIA_BAD_CLIP_FORMAT => "Bad clipping format",
+		B_MEDIA_ADDON_FAILED => "Media addon failed",
+		B_MEDIA_ADDON_DISABLED => "Media addon disabled",
+		B_MEDIA_CHANGE_IN_PROGRESS => "Change in progress",
+		B_MEDIA_STALE_CHANGE_COUNT => "Stale change count",
+		B_MEDIA_ADDON_RESTRICTED => "Media addon restricted",
+		B_MEDIA_NO_HANDLER => "No handler",
+		B_MEDIA_DUPLICATE_FORMAT => "Duplicate format",
+		B_MEDIA_REALTIME_DISABLED => "Realtime disabled",
+		B_MEDIA_REALTIME_UNAVAILABLE => "Realtime unavailable",
+
+		_ => return None,
+	};
+	Some(description)
+}
+
 fn decode_error_kind(errno: status_t) -> ErrorKind {
 	match errno {
 		B_BAD_INDEX => ErrorKind::InvalidInput,
@@ -224,6 +370,39 @@ fn decode_error_kind(errno: status_t) -> ErrorKind {
 		B_DONT_DO_THAT => ErrorKind::InvalidInput,
 		B_NOT_ALLOWED => ErrorKind::NotAllowed,
 		B_TIMED_OUT => ErrorKind::TimedOut,
+		B_LAUNCH_FAILED_NO_RESOLVE_LINK => ErrorKind::NotFound,
+		B_LAUNCH_FAILED_EXECUTABLE => ErrorKind::InvalidData,
+		B_LAUNCH_FAILED_APP_NOT_FOUND => ErrorKind::NotFound,
+		B_LAUNCH_FAILED_APP_IN_TRASH => ErrorKind::NotAllowed,
+		B_LAUNCH_FAILED_NO_PREFERRED_APP => ErrorKind::NotFound,
+		B_LAUNCH_FAILED_FILES_APP_NOT_FOUND => ErrorKind::NotFound,
 		_ => ErrorKind::Other,
 	}
 }
+
+#[test]
+fn test_os_error_debug() {
+	let error = HaikuError::from_raw_os_error(B_TIMED_OUT);
+	let debug = format!("{:?}", error);
+	assert!(debug.contains("TimedOut"));
+	assert!(debug.contains(&format!("{:?}", error_string(B_TIMED_OUT))));
+	assert!(!debug.contains("\"message\""));
+}
+
+#[test]
+fn test_haiku_error_string() {
+	use libc::{B_BAD_PORT_ID, B_MEDIA_BAD_NODE, ENOSYS};
+
+	let message = HaikuError::from_raw_os_error(B_BAD_PORT_ID).to_string();
+	assert!(message.contains("port"));
+	assert!(!message.contains("Unknown"));
+	assert!(HaikuError::from_raw_os_error(B_MEDIA_BAD_NODE)
+		.to_string()
+		.contains("node"));
+
+	// POSIX errors are described by strerror_r
+	assert!(haiku_error_description(ENOSYS).is_none());
+	assert!(!HaikuError::from_raw_os_error(ENOSYS)
+		.to_string()
+		.contains("Unknown"));
+}
@@ -13,11 +13,12 @@
 //! trait.
 
 use std::ffi::{CStr, CString};
-use std::mem;
+use std::time::Duration;
 
 use libc::{
-	B_BOOL_TYPE, B_DOUBLE_TYPE, B_FLOAT_TYPE, B_INT16_TYPE, B_INT32_TYPE, B_INT64_TYPE,
-	B_INT8_TYPE, B_STRING_TYPE, B_UINT16_TYPE, B_UINT32_TYPE, B_UINT64_TYPE, B_UINT8_TYPE,
+	B_BOOL_TYPE, B_CHAR_TYPE, B_DOUBLE_TYPE, B_FLOAT_TYPE, B_INT16_TYPE, B_INT32_TYPE,
+	B_INT64_TYPE, B_INT8_TYPE, B_RAW_TYPE, B_STRING_TYPE, B_TIME_TYPE, B_UINT16_TYPE,
+	B_UINT32_TYPE, B_UINT64_TYPE, B_UINT8_TYPE,
 };
 
 use crate::support::{ErrorKind, HaikuError, Result};
@@ -28,6 +29,11 @@ pub trait Flattenable<T> {
 	fn type_code() -> u32;
 	/// Check if flattened objects of this type are always a fixed size
 	fn is_fixed_size() -> bool;
+	/// The size of every flattened object of this type, if it is fixed
+	///
+	/// Only types with a fixed flattened size can be used for the fields
+	/// of a derived Flattenable struct, other than the last one.
+	const FLATTENED_SIZE: Option<usize> = None;
 	/// Return the size of the flattened type
 	fn flattened_size(&self) -> usize;
 	/// Return a flattened version of this object
@@ -42,6 +48,8 @@ pub trait Flattenable<T> {
 }
 
 impl Flattenable<bool> for bool {
+	const FLATTENED_SIZE: Option<usize> = Some(1);
+
 	fn type_code() -> u32 {
 		B_BOOL_TYPE
 	}
@@ -62,18 +70,50 @@ impl Flattenable<bool> for bool {
 		}
 	}
 
+	// Some older messages store booleans as 4 bytes, so accept those too
 	fn unflatten(buffer: &[u8]) -> Result<bool> {
+		if buffer.len() != 1 && buffer.len() != 4 {
+			Err(HaikuError::from(ErrorKind::InvalidData))
+		} else {
+			Ok(buffer.iter().any(|&b| b != 0))
+		}
+	}
+}
+
+// A char is stored as a single byte, like a C char. Only the characters up to
+// U+00FF fit; for other characters only the lowest byte is stored. Use a
+// String to store text with other characters.
+impl Flattenable<char> for char {
+	const FLATTENED_SIZE: Option<usize> = Some(1);
+
+	fn type_code() -> u32 {
+		B_CHAR_TYPE
+	}
+
+	fn is_fixed_size() -> bool {
+		true
+	}
+
+	fn flattened_size(&self) -> usize {
+		1
+	}
+
+	fn flatten(&self) -> Vec<u8> {
+		vec![*self as u32 as u8]
+	}
+
+	fn unflatten(buffer: &[u8]) -> Result<char> {
 		if buffer.len() != 1 {
 			Err(HaikuError::from(ErrorKind::InvalidData))
-		} else if buffer[0] == 0 {
-			Ok(false)
 		} else {
-			Ok(true)
+			Ok(char::from(buffer[0]))
 		}
 	}
 }
 
 impl Flattenable<i8> for i8 {
+	const FLATTENED_SIZE: Option<usize> = Some(1);
+
 	fn type_code() -> u32 {
 		B_INT8_TYPE
 	}
@@ -100,6 +140,8 @@ impl Flattenable<i8> for i8 {
 }
 
 impl Flattenable<i16> for i16 {
+	const FLATTENED_SIZE: Option<usize> = Some(2);
+
 	fn type_code() -> u32 {
 		B_INT16_TYPE
 	}
@@ -113,20 +155,21 @@ impl Flattenable<i16> for i16 {
 	}
 
 	fn flatten(&self) -> Vec<u8> {
-		let data = unsafe { mem::transmute::<i16, [u8; 2]>(*self) };
-		data.to_vec()
+		self.to_le_bytes().to_vec()
 	}
 
 	fn unflatten(buffer: &[u8]) -> Result<i16> {
 		if buffer.len() != 2 {
 			Err(HaikuError::from(ErrorKind::InvalidData))
 		} else {
-			Ok(buffer.iter().rev().fold(0, |acc, &b| (acc << 8) | b as i16))
+			Ok(i16::from_le_bytes(buffer.try_into().unwrap()))
 		}
 	}
 }
 
 impl Flattenable<i32> for i32 {
+	const FLATTENED_SIZE: Option<usize> = Some(4);
+
 	fn type_code() -> u32 {
 		B_INT32_TYPE
 	}
@@ -140,20 +183,21 @@ impl Flattenable<i32> for i32 {
 	}
 
 	fn flatten(&self) -> Vec<u8> {
-		let data = unsafe { mem::transmute::<i32, [u8; 4]>(*self) };
-		data.to_vec()
+		self.to_le_bytes().to_vec()
 	}
 
 	fn unflatten(buffer: &[u8]) -> Result<i32> {
 		if buffer.len() != 4 {
 			Err(HaikuError::from(ErrorKind::InvalidData))
 		} else {
-			Ok(buffer.iter().rev().fold(0, |acc, &b| (acc << 8) | b as i32))
+			Ok(i32::from_le_bytes(buffer.try_into().unwrap()))
 		}
 	}
 }
 
 impl Flattenable<i64> for i64 {
+	const FLATTENED_SIZE: Option<usize> = Some(8);
+
 	fn type_code() -> u32 {
 		B_INT64_TYPE
 	}
@@ -167,20 +211,21 @@ impl Flattenable<i64> for i64 {
 	}
 
 	fn flatten(&self) -> Vec<u8> {
-		let data = unsafe { mem::transmute::<i64, [u8; 8]>(*self) };
-		data.to_vec()
+		self.to_le_bytes().to_vec()
 	}
 
 	fn unflatten(buffer: &[u8]) -> Result<i64> {
 		if buffer.len() != 8 {
 			Err(HaikuError::from(ErrorKind::InvalidData))
 		} else {
-			Ok(buffer.iter().rev().fold(0, |acc, &b| (acc << 8) | b as i64))
+			Ok(i64::from_le_bytes(buffer.try_into().unwrap()))
 		}
 	}
 }
 
 impl Flattenable<u8> for u8 {
+	const FLATTENED_SIZE: Option<usize> = Some(1);
+
 	fn type_code() -> u32 {
 		B_UINT8_TYPE
 	}
@@ -207,6 +252,8 @@ impl Flattenable<u8> for u8 {
 }
 
 impl Flattenable<u16> for u16 {
+	const FLATTENED_SIZE: Option<usize> = Some(2);
+
 	fn type_code() -> u32 {
 		B_UINT16_TYPE
 	}
@@ -220,20 +267,21 @@ impl Flattenable<u16> for u16 {
 	}
 
 	fn flatten(&self) -> Vec<u8> {
-		let data = unsafe { mem::transmute::<u16, [u8; 2]>(*self) };
-		data.to_vec()
+		self.to_le_bytes().to_vec()
 	}
 
 	fn unflatten(buffer: &[u8]) -> Result<u16> {
 		if buffer.len() != 2 {
 			Err(HaikuError::from(ErrorKind::InvalidData))
 		} else {
-			Ok(buffer.iter().rev().fold(0, |acc, &b| (acc << 8) | b as u16))
+			Ok(u16::from_le_bytes(buffer.try_into().unwrap()))
 		}
 	}
 }
 
 impl Flattenable<u32> for u32 {
+	const FLATTENED_SIZE: Option<usize> = Some(4);
+
 	fn type_code() -> u32 {
 		B_UINT32_TYPE
 	}
@@ -247,20 +295,21 @@ impl Flattenable<u32> for u32 {
 	}
 
 	fn flatten(&self) -> Vec<u8> {
-		let data = unsafe { mem::transmute::<u32, [u8; 4]>(*self) };
-		data.to_vec()
+		self.to_le_bytes().to_vec()
 	}
 
 	fn unflatten(buffer: &[u8]) -> Result<u32> {
 		if buffer.len() != 4 {
 			Err(HaikuError::from(ErrorKind::InvalidData))
 		} else {
-			Ok(buffer.iter().rev().fold(0, |acc, &b| (acc << 8) | b as u32))
+			Ok(u32::from_le_bytes(buffer.try_into().unwrap()))
 		}
 	}
 }
 
 impl Flattenable<u64> for u64 {
+	const FLATTENED_SIZE: Option<usize> = Some(8);
+
 	fn type_code() -> u32 {
 		B_UINT64_TYPE
 	}
@@ -274,20 +323,21 @@ impl Flattenable<u64> for u64 {
 	}
 
 	fn flatten(&self) -> Vec<u8> {
-		let data = unsafe { mem::transmute::<u64, [u8; 8]>(*self) };
-		data.to_vec()
+		self.to_le_bytes().to_vec()
 	}
 
 	fn unflatten(buffer: &[u8]) -> Result<u64> {
 		if buffer.len() != 8 {
 			Err(HaikuError::from(ErrorKind::InvalidData))
 		} else {
-			Ok(buffer.iter().rev().fold(0, |acc, &b| (acc << 8) | b as u64))
+			Ok(u64::from_le_bytes(buffer.try_into().unwrap()))
 		}
 	}
 }
 
 impl Flattenable<f32> for f32 {
+	const FLATTENED_SIZE: Option<usize> = Some(4);
+
 	fn type_code() -> u32 {
 		B_FLOAT_TYPE
 	}
@@ -301,22 +351,21 @@ impl Flattenable<f32> for f32 {
 	}
 
 	fn flatten(&self) -> Vec<u8> {
-		let data = unsafe { mem::transmute::<f32, [u8; 4]>(*self) };
-		data.to_vec()
+		self.to_le_bytes().to_vec()
 	}
 
 	fn unflatten(buffer: &[u8]) -> Result<f32> {
 		if buffer.len() != 4 {
 			Err(HaikuError::from(ErrorKind::InvalidData))
 		} else {
-			let tmp: u32 = buffer.iter().rev().fold(0, |acc, &b| (acc << 8) | b as u32);
-			let tmp: f32 = unsafe { mem::transmute::<u32, f32>(tmp) };
-			Ok(tmp)
+			Ok(f32::from_le_bytes(buffer.try_into().unwrap()))
 		}
 	}
 }
 
 impl Flattenable<f64> for f64 {
+	const FLATTENED_SIZE: Option<usize> = Some(8);
+
 	fn type_code() -> u32 {
 		B_DOUBLE_TYPE
 	}
@@ -330,17 +379,48 @@ impl Flattenable<f64> for f64 {
 	}
 
 	fn flatten(&self) -> Vec<u8> {
-		let data = unsafe { mem::transmute::<f64, [u8; 8]>(*self) };
-		data.to_vec()
+		self.to_le_bytes().to_vec()
 	}
 
 	fn unflatten(buffer: &[u8]) -> Result<f64> {
 		if buffer.len() != 8 {
 			Err(HaikuError::from(ErrorKind::InvalidData))
 		} else {
-			let tmp: u64 = buffer.iter().rev().fold(0, |acc, &b| (acc << 8) | b as u64);
-			let tmp: f64 = unsafe { mem::transmute::<u64, f64>(tmp) };
-			Ok(tmp)
+			Ok(f64::from_le_bytes(buffer.try_into().unwrap()))
+		}
+	}
+}
+
+// A duration is stored as a bigtime_t, which is a number of microseconds.
+// Durations that do not fit are stored as the longest possible duration.
+impl Flattenable<Duration> for Duration {
+	const FLATTENED_SIZE: Option<usize> = Some(8);
+
+	fn type_code() -> u32 {
+		B_TIME_TYPE
+	}
+
+	fn flattened_size(&self) -> usize {
+		8
+	}
+
+	fn is_fixed_size() -> bool {
+		true
+	}
+
+	fn flatten(&self) -> Vec<u8> {
+		i64::try_from(self.as_micros())
+			.unwrap_or(i64::MAX)
+			.flatten()
+	}
+
+	fn unflatten(buffer: &[u8]) -> Result<Duration> {
+		match u64::try_from(i64::unflatten(buffer)?) {
+			Ok(micros) => Ok(Duration::from_micros(micros)),
+			Err(_) => Err(HaikuError::new(
+				ErrorKind::InvalidData,
+				"a duration cannot be negative",
+			)),
 		}
 	}
 }
@@ -379,6 +459,29 @@ impl Flattenable<String> for String {
 	}
 }
 
+// A buffer of bytes is stored as is, as raw data
+impl Flattenable<Vec<u8>> for Vec<u8> {
+	fn type_code() -> u32 {
+		B_RAW_TYPE
+	}
+
+	fn is_fixed_size() -> bool {
+		false
+	}
+
+	fn flattened_size(&self) -> usize {
+		self.len()
+	}
+
+	fn flatten(&self) -> Vec<u8> {
+		self.clone()
+	}
+
+	fn unflatten(buffer: &[u8]) -> Result<Vec<u8>> {
+		Ok(buffer.to_vec())
+	}
+}
+
 #[test]
 fn test_flattenable_primitives() {
 	let value: u8 = 150;
@@ -396,3 +499,162 @@ fn test_flattenable_primitives() {
 	let unflattened_value = String::unflatten(&flattened_value).unwrap();
 	assert_eq!(value, unflattened_value);
 }
+
+#[test]
+fn test_flattenable_sign() {
+	// The data is little endian, and the sign is in the last byte
+	assert_eq!(i16::MIN.flatten(), vec![0x00, 0x80]);
+	assert_eq!(i16::unflatten(&i16::MIN.flatten()).unwrap(), i16::MIN);
+	assert_eq!(i16::unflatten(&[0xff, 0xff]).unwrap(), -1);
+	assert_eq!(i32::unflatten(&i32::MIN.flatten()).unwrap(), i32::MIN);
+	assert_eq!(i64::unflatten(&i64::MIN.flatten()).unwrap(), i64::MIN);
+	assert_eq!((-2.0f32).flatten(), vec![0x00, 0x00, 0x00, 0xc0]);
+	assert_eq!(f64::unflatten(&(-0.5f64).flatten()).unwrap(), -0.5);
+	assert!(i16::unflatten(&[0x00]).is_err());
+}
+
+#[test]
+fn test_flattenable_duration() {
+	let duration = Duration::from_millis(1500);
+	assert_eq!(duration.flatten(), 1_500_000i64.flatten());
+	assert_eq!(Duration::unflatten(&duration.flatten()).unwrap(), duration);
+	assert_eq!(Duration::type_code(), B_TIME_TYPE);
+	assert_eq!(Duration::MAX.flatten(), i64::MAX.flatten());
+	assert!(Duration::unflatten(&(-1i64).flatten()).is_err());
+}
+
+#[test]
+fn test_flattenable_bool() {
+	assert_eq!(true.flatten(), vec![1]);
+	assert_eq!(bool::unflatten(&true.flatten()).unwrap(), true);
+	assert_eq!(bool::unflatten(&false.flatten()).unwrap(), false);
+
+	// Legacy 4-byte encoding
+	assert_eq!(bool::unflatten(&[0, 0, 0, 0]).unwrap(), false);
+	assert_eq!(bool::unflatten(&1u32.flatten()).unwrap(), true);
+	assert_eq!(bool::unflatten(&[0, 0, 0, 1]).unwrap(), true);
+	assert!(bool::unflatten(&[1, 0]).is_err());
+}
+
+#[test]
+fn test_flattenable_char() {
+	assert_eq!('A'.flatten(), vec![b'A']);
+	assert_eq!(char::unflatten(&'A'.flatten()).unwrap(), 'A');
+	assert_eq!(char::unflatten(&'é'.flatten()).unwrap(), 'é');
+	assert_eq!(char::type_code(), B_CHAR_TYPE);
+	assert!(char::unflatten(&[]).is_err());
+	assert!(char::unflatten(&[b'A', b'B']).is_err());
+}
+
+#[test]
+fn test_derive_flattenable() {
+	use crate::haiku_constant;
+
+	#[derive(Debug, PartialEq, crate::support::Flattenable)]
+	#[flattenable(type_code = "xPNT")]
+	#[repr(C)]
+	struct Derived {
+		score: i32,
+		name: String,
+	}
+
+	// The same layout, implemented by hand
+	#[derive(Debug, PartialEq)]
+	struct Manual {
+		score: i32,
+		name: String,
+	}
+
+	impl Flattenable<Manual> for Manual {
+		fn type_code() -> u32 {
+			haiku_constant!('x', 'P', 'N', 'T')
+		}
+
+		fn is_fixed_size() -> bool {
+			false
+		}
+
+		fn flattened_size(&self) -> usize {
+			4 + self.name.flattened_size()
+		}
+
+		fn flatten(&self) -> Vec<u8> {
+			let mut data = self.score.flatten();
+			data.extend(self.name.flatten());
+			data
+		}
+
+		fn unflatten(buffer: &[u8]) -> Result<Manual> {
+			if buffer.len() < 4 {
+				return Err(HaikuError::from(ErrorKind::InvalidData));
+			}
+			Ok(Manual {
+				score: i32::unflatten(&buffer[0..4])?,
+				name: String::unflatten(&buffer[4..])?,
+			})
+		}
+	}
+
+	let derived = Derived {
+		score: -42,
+		name: String::from("Haiku"),
+	};
+	let manual = Manual {
+		score: -42,
+		name: String::from("Haiku"),
+	};
+	assert_eq!(Derived::type_code(), Manual::type_code());
+	assert_eq!(Derived::is_fixed_size(), Manual::is_fixed_size());
+	assert_eq!(derived.flattened_size(), manual.flattened_size());
+	assert_eq!(derived.flatten(), manual.flatten());
+	assert_eq!(Derived::unflatten(&manual.flatten()).unwrap(), derived);
+	assert!(Derived::unflatten(&manual.flatten()[0..2]).is_err());
+
+	// Without a type code, the data is raw data
+	#[derive(Debug, PartialEq, crate::support::Flattenable)]
+	struct Pair {
+		first: u16,
+		second: u16,
+	}
+	let pair = Pair {
+		first: 1,
+		second: 2,
+	};
+	assert_eq!(Pair::type_code(), B_RAW_TYPE);
+	assert!(Pair::is_fixed_size());
+	assert_eq!(pair.flatten(), vec![1, 0, 2, 0]);
+	assert_eq!(Pair::unflatten(&pair.flatten()).unwrap(), pair);
+	assert!(Pair::unflatten(&[1, 0, 2]).is_err());
+	assert_eq!(Pair::FLATTENED_SIZE, Some(4));
+	assert_eq!(Derived::FLATTENED_SIZE, None);
+
+	// The fields are read with their flattened size, which differs from
+	// the size in memory for a char, and for a struct with padding
+	#[derive(Debug, PartialEq, crate::support::Flattenable)]
+	struct Padded {
+		flag: u8,
+		value: u32,
+	}
+
+	#[derive(Debug, PartialEq, crate::support::Flattenable)]
+	struct Nested {
+		initial: char,
+		padded: Padded,
+		name: String,
+	}
+	assert_eq!(std::mem::size_of::<Padded>(), 8);
+	assert_eq!(Padded::FLATTENED_SIZE, Some(5));
+	let nested = Nested {
+		initial: 'H',
+		padded: Padded {
+			flag: 1,
+			value: 0x0a0b0c0d,
+		},
+		name: String::from("aiku"),
+	};
+	let data = nested.flatten();
+	assert_eq!(&data[0..6], &[b'H', 1, 0x0d, 0x0c, 0x0b, 0x0a]);
+	assert_eq!(data.len(), nested.flattened_size());
+	assert_eq!(Nested::unflatten(&data).unwrap(), nested);
+	assert!(Nested::unflatten(&data[0..4]).is_err());
+}
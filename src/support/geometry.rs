@@ -0,0 +1,12 @@
+//
+// Copyright 2024, Niels Sascha Reedijk <niels.reedijk@gmail.com>
+// All rights reserved. Distributed under the terms of the MIT License.
+//
+
+//! The geometry types that messages use for `B_POINT_TYPE` and `B_RECT_TYPE`
+//!
+//! These are the same types as `interface::Point` and `interface::Rect`. They
+//! are available here for code that only deals with messages, and does not
+//! otherwise use the interface kit.
+
+pub use crate::interface::{Point, Rect};
@@ -5,8 +5,16 @@
 
 //! The support kit provides a few fundamentals that are used in Haiku applications
 
+mod byteorder;
 mod errors;
 mod flattenable;
+pub mod geometry;
+mod retry;
 
+pub use self::byteorder::swap_data;
 pub use self::errors::{ErrorKind, HaikuError, Result};
 pub use self::flattenable::Flattenable;
+pub use self::retry::{is_transient, retry, retry_if};
+// The color type of messages, which is defined with the interface kit
+pub use crate::interface::RgbColor;
+pub use haiku_derive::Flattenable;
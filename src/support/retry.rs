@@ -0,0 +1,109 @@
+//
+// Copyright 2024, Niels Sascha Reedijk <niels.reedijk@gmail.com>
+// All rights reserved. Distributed under the terms of the MIT License.
+//
+
+use std::thread;
+use std::time::Duration;
+
+use libc::{B_BUSY, B_TIMED_OUT};
+
+use crate::support::{HaikuError, Result};
+
+/// Check whether an error is transient
+///
+/// An error is transient when the operation may succeed if it is tried
+/// again. This is the case when the other side is busy (`B_BUSY`), or when
+/// the operation timed out (`B_TIMED_OUT`). These are the errors that
+/// `retry()` retries on.
+pub fn is_transient(error: &HaikuError) -> bool {
+	matches!(error.raw_os_error(), Some(B_BUSY) | Some(B_TIMED_OUT))
+}
+
+/// Run an operation, and retry it when it fails with a transient error
+///
+/// The operation is run at most `attempts` times. After each failed attempt,
+/// the function sleeps for `backoff`, which is doubled for every next
+/// attempt. If the error is not transient (see `is_transient()`), or if the
+/// last attempt fails, the error is returned.
+///
+/// This is useful for requests to the system servers, which may time out or
+/// be busy during heavy system activity, like when the system is booting.
+pub fn retry<T, F>(attempts: u32, backoff: Duration, op: F) -> Result<T>
+where
+	F: FnMut() -> Result<T>,
+{
+	retry_if(attempts, backoff, is_transient, op)
+}
+
+/// Run an operation, and retry it when `should_retry` accepts the error
+///
+/// This function behaves like `retry()`, but the caller decides which errors
+/// are retried.
+pub fn retry_if<T, F, P>(attempts: u32, backoff: Duration, should_retry: P, mut op: F) -> Result<T>
+where
+	F: FnMut() -> Result<T>,
+	P: Fn(&HaikuError) -> bool,
+{
+	let mut backoff = backoff;
+	let mut attempt = 1;
+	loop {
+		match op() {
+			Err(e) if attempt < attempts && should_retry(&e) => {
+				thread::sleep(backoff);
+				backoff *= 2;
+				attempt += 1;
+			}
+			result => return result,
+		}
+	}
+}
+
+#[test]
+fn test_retry() {
+	use libc::B_BAD_VALUE;
+
+	// The operation fails twice, and then succeeds
+	let mut calls = 0;
+	let result = retry(3, Duration::from_millis(1), || {
+		calls += 1;
+		if calls < 3 {
+			Err(HaikuError::from_raw_os_error(B_BUSY))
+		} else {
+			Ok(calls)
+		}
+	});
+	assert_eq!(result.unwrap(), 3);
+
+	// The error of the last attempt is returned
+	let mut calls = 0;
+	let result: Result<()> = retry(2, Duration::ZERO, || {
+		calls += 1;
+		Err(HaikuError::from_raw_os_error(B_TIMED_OUT))
+	});
+	assert_eq!(result.unwrap_err().raw_os_error(), Some(B_TIMED_OUT));
+	assert_eq!(calls, 2);
+
+	// Errors that are not transient are not retried
+	let mut calls = 0;
+	let result: Result<()> = retry(3, Duration::ZERO, || {
+		calls += 1;
+		Err(HaikuError::from_raw_os_error(B_BAD_VALUE))
+	});
+	assert!(result.is_err());
+	assert_eq!(calls, 1);
+
+	// The retried errors can be chosen
+	let mut calls = 0;
+	let result: Result<()> = retry_if(
+		3,
+		Duration::ZERO,
+		|e| e.raw_os_error() == Some(B_BAD_VALUE),
+		|| {
+			calls += 1;
+			Err(HaikuError::from_raw_os_error(B_BAD_VALUE))
+		},
+	);
+	assert!(result.is_err());
+	assert_eq!(calls, 3);
+}